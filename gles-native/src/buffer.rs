@@ -1,8 +1,16 @@
-use gl_sys::{GLboolean, GLenum, GLint, GLintptr, GLsizei, GLsizeiptr, GLuint, GLvoid};
+use std::ops::Range;
+use std::ptr;
+use std::slice;
+
+use gl_sys::{GLboolean, GLenum, GLint, GLintptr, GLsizei, GLsizeiptr, GLuint, GLvoid, GL_INVALID_VALUE};
 
 use context::{self, HUB};
+use utilities::record_error;
 
+use destruction_queue::{self, HalObject};
+use hal;
 use hal_registry;
+use rendering;
 use {HalBuffer, HalMemory};
 
 // todo: auto increase value like C enum
@@ -18,12 +26,14 @@ pub struct Buffer {
     pub usage: GLenum,
     pub target: GLenum,
     pub size: GLsizeiptr,
-    // todo: remove data field?
+    /// Copy of the contents, read when client data or indices have to be inspected
     pub data: Option<Vec<u8>>,
     pub is_allocated: bool,
 
     pub memory: Option<HalMemory>,
     pub buffer: Option<HalBuffer>,
+    /// Last submission which used the hal buffer
+    pub last_use: u64,
 }
 
 //IndexBufferObject
@@ -32,7 +42,46 @@ pub struct Buffer {
 //UniformBufferObject
 //VertexBufferObject
 
-pub fn hal_buffer_release(buffer: &mut Buffer) {}
+/// Give the hal buffer and its memory to the destruction queue, commands in flight may still read them
+pub fn hal_buffer_release(buffer: &mut Buffer) {
+    let mut objects = Vec::new();
+    objects.extend(buffer.buffer.take().map(HalObject::Buffer));
+    objects.extend(buffer.memory.take().map(HalObject::Memory));
+    destruction_queue::release(objects, buffer.last_use);
+    buffer.is_allocated = false;
+}
+
+/// Copy `range` of the contents of `buffer` to its hal buffer. A hal buffer submitted commands may still read
+/// is replaced with a new one holding all the contents. Return false if no hal buffer could be created.
+fn hal_buffer_update(buffer: &mut Buffer, range: Range<usize>) -> bool {
+    if buffer.size == 0 {
+        return true;
+    }
+    // recorded commands find the buffer by name when they are submitted, they have to see the old contents
+    if buffer.last_use == destruction_queue::recording_submission() {
+        rendering::flush();
+    }
+
+    let is_in_flight = buffer.last_use > HUB.command_buffer_manager.lock().completed_submission();
+    let range = if !has_hal_buffer(buffer) || is_in_flight {
+        hal_buffer_release(buffer);
+        let usage = hal::buffer::Usage::VERTEX | hal::buffer::Usage::INDEX;
+        match hal_registry::hal_buffer_create(buffer.size as u64, usage) {
+            Some((raw, memory)) => {
+                buffer.buffer = Some(raw);
+                buffer.memory = Some(memory);
+                buffer.is_allocated = true;
+            }
+            None => return false,
+        }
+        0..buffer.size as usize
+    } else {
+        range
+    };
+
+    let data = &buffer.data.as_ref().unwrap()[range.clone()];
+    hal_registry::hal_memory_write(buffer.memory.as_ref().unwrap(), range.start as u64, data)
+}
 
 pub fn has_hal_buffer(buffer: &Buffer) -> bool {
     buffer.buffer.is_some()
@@ -106,20 +155,26 @@ pub extern "C" fn glBufferData(target: GLenum, size: GLsizeiptr, data: *const GL
         target, size, data, usage
     );
 
+    let active_buffer = context::get_active_buffer(target);
+    let name = active_buffer.name;
     context::object_upload_data(
         || validate_buffer_target(target) && validate_buffer_usage(usage) && validate_buffer_size(size),
-        context::get_active_buffer(target),
+        active_buffer,
         |object| {
             object.usage = usage;
-            let allocated_size = object.size;
-            let data_is_nullptr = context::is_nullptr(data as *mut Buffer, "");
-            if (!data_is_nullptr && has_hal_buffer(object)) || (data_is_nullptr && allocated_size > 0 && size != allocated_size) {
+            if size != object.size {
                 hal_buffer_release(object);
             }
+            object.size = size;
+            object.data = Some(if context::is_nullptr(data as *mut GLvoid, "") {
+                vec![0; size as usize]
+            } else {
+                unsafe { slice::from_raw_parts(data as *const u8, size as usize) }.to_vec()
+            });
             true
         },
-        |object| hal_registry::hal_buffer_allocate(), /* todo: missing process logic*/
-        || {},
+        |object| hal_buffer_update(object, 0..size as usize),
+        || HUB.index_cache.lock().invalidate_buffer(name),
     );
 }
 
@@ -130,6 +185,33 @@ pub extern "C" fn glBufferSubData(target: GLenum, offset: GLintptr, size: GLsize
         "glBufferSubData(target = {:?}, offset = {:?},size = {}, data = {:p})",
         target, offset, size, data
     );
+
+    let active_buffer = context::get_active_buffer(target);
+    let name = active_buffer.name;
+    let range = offset as usize..(offset as GLsizeiptr + size) as usize;
+    context::object_upload_data(
+        || {
+            validate_buffer_target(target)
+                && context::validate_invalid_value(offset, |&offset| offset < 0, "invalid offset:")
+                && validate_buffer_size(size)
+                && !context::is_nullptr(data as *mut GLvoid, "data is nullptr")
+        },
+        active_buffer,
+        |object| {
+            if offset as GLsizeiptr + size > object.size {
+                error!("range {}..{} is out of the buffer", offset, offset as GLsizeiptr + size);
+                record_error(GL_INVALID_VALUE);
+                return false;
+            }
+            if let Some(ref mut shadow) = object.data {
+                let source = unsafe { slice::from_raw_parts(data as *const u8, size as usize) };
+                shadow[range.clone()].copy_from_slice(source);
+            }
+            true
+        },
+        |object| hal_buffer_update(object, range.clone()),
+        || HUB.index_cache.lock().invalidate_buffer(name),
+    );
 }
 
 #[allow(non_snake_case)]
@@ -137,7 +219,19 @@ pub extern "C" fn glBufferSubData(target: GLenum, offset: GLintptr, size: GLsize
 pub extern "C" fn glDeleteBuffers(n: GLsizei, buffers: *const GLuint) {
     info!("glDeleteBuffers(n = {}, buffers = {:p})", n, buffers);
 
-//    context::delete_objects(n, buffers);
+    context::delete_objects(n, buffers, &HUB.buffer_pool, |name, object| {
+        for active_buffer in &HUB.active_buffer {
+            context::unbind_object(active_buffer, name, ptr::null_mut());
+        }
+        for attribute in HUB.vertex_attribute_state.lock().attributes.iter_mut() {
+            if attribute.buffer == name {
+                attribute.buffer = 0;
+            }
+        }
+        HUB.index_cache.lock().invalidate_buffer(name);
+        hal_buffer_release(object);
+        true
+    });
 }
 
 #[allow(non_snake_case)]
@@ -174,9 +268,11 @@ pub extern "C" fn glIsBuffer(buffer: GLuint) -> GLboolean {
 mod tests {
     use super::*;
     use gl_sys::*;
+    use utilities::glGetError;
 
     #[test]
     fn test_all_in_one() {
+        let _guard = context::TEST_LOCK.lock();
         &*HUB;
 
         let count = 1;
@@ -208,7 +304,26 @@ mod tests {
             println!("test block 2-> {:?}", texture_object);
         }
 
+        let indices = [1u8, 2, 3];
+        glBindBuffer(GL_ARRAY_BUFFER, 0);
+        glGetError();
+        glBufferSubData(GL_ARRAY_BUFFER, -1, 3, indices.as_ptr() as *const GLvoid);
+        assert_eq!(glGetError(), GL_INVALID_VALUE);
+
         glBindBuffer(GL_ARRAY_BUFFER, buffers[0]);
         glBufferData(GL_ARRAY_BUFFER, 100, std::ptr::null(), GL_STATIC_DRAW);
+        let data = context::get_active_buffer(GL_ARRAY_BUFFER).ptr;
+        assert_eq!(has_hal_buffer(unsafe { &*data }), hal_registry::hal_device_available());
+
+        glBufferSubData(GL_ARRAY_BUFFER, 97, 3, indices.as_ptr() as *const GLvoid);
+        assert_eq!(&unsafe { &*data }.data.as_ref().unwrap()[96..], &[0, 1, 2, 3]);
+        glGetError();
+        glBufferSubData(GL_ARRAY_BUFFER, 96, 1, std::ptr::null());
+        assert_eq!(glGetError(), GL_NO_ERROR);
+        assert_eq!(unsafe { &*data }.data.as_ref().unwrap()[96], 0);
+
+        glDeleteBuffers(count as GLsizei, buffers.as_ptr());
+        assert_eq!(context::get_active_buffer(GL_ARRAY_BUFFER).name, 0);
+        assert!(!HUB.buffer_pool.lock().has_object(buffers[0]));
     }
 }
@@ -0,0 +1,590 @@
+use std::collections::VecDeque;
+use std::mem;
+
+use hal::pso::{self, ColorValue, DepthBias, DescriptorRangeDesc, DescriptorType, Viewport};
+use hal::{buffer, image};
+
+use gl_sys::{GLbitfield, GLuint};
+
+use buffer::Buffer;
+use context::{self, HUB};
+use destruction_queue::HalObject;
+use frame_buffer::{LoadOps, Rect};
+use glsl::layout::UNIFORM_BLOCK_BINDING;
+use hal_registry;
+use object_pool::ObjectPool;
+use pipeline::{PipelineCache, ProgramLayout, RenderPassKey};
+use rendering::ClearValues;
+use upload::{DataSource, IndexData, UploadBuffer, VertexBinding};
+use viewport_transformation;
+use {HalBuffer, HalCommandBuffer, HalDescriptorPool, HalDescriptorSet, HalFence, HalFrameBuffer, HalMemory};
+
+/// Resources of the descriptor set of a program
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DescriptorSet {
+    /// Offset of the uniform block in the upload buffer
+    pub uniform_block: Option<usize>,
+    /// Sampler binding and the texture of each array element, 0 for the default texture
+    pub textures: Vec<(u32, Vec<GLuint>)>,
+}
+
+/// Commands recorded on the CPU side, translated into hal commands at submission time
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    BeginRenderPass {
+        frame_buffer: GLuint,
+        load_ops: LoadOps,
+        clear_values: ClearValues,
+    },
+    /// `glClear` issued after the render pass has already begun
+    ClearAttachments {
+        mask: GLbitfield,
+        clear_values: ClearValues,
+        rect: Option<Rect>,
+        color_mask: [bool; 4],
+    },
+    EndRenderPass,
+    /// Index into the pipeline cache
+    BindPipeline(usize),
+    BindVertexBuffers(Vec<VertexBinding>),
+    BindIndexBuffer(IndexData),
+    BindDescriptorSet(DescriptorSet),
+    SetDepthBias(DepthBias),
+    SetStencilReference {
+        front: u32,
+        back: u32,
+    },
+    SetBlendConstants(ColorValue),
+    SetViewport(Viewport),
+    SetScissor(pso::Rect),
+    Draw {
+        vertex_count: u32,
+    },
+    DrawIndexed {
+        index_count: u32,
+        vertex_offset: i32,
+    },
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CommandBuffer {
+    pub commands: Vec<Command>,
+}
+
+impl CommandBuffer {
+    #[inline]
+    pub fn record(&mut self, command: Command) {
+        trace!("record {:?}", command);
+        self.commands.push(command);
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.commands.clear();
+    }
+}
+
+/// Frame buffer a render pass draws into
+#[derive(Debug)]
+pub(crate) struct RenderTarget {
+    pub key: RenderPassKey,
+    pub frame_buffer: HalFrameBuffer,
+    pub area: pso::Rect,
+}
+
+/// Hal objects created for the commands of one submission, destroyed once it completed
+#[derive(Debug, Default)]
+pub(crate) struct Transients {
+    /// Copy of the upload buffer
+    pub upload_buffer: Option<HalBuffer>,
+    upload_memory: Option<HalMemory>,
+    /// Target of each `BeginRenderPass`, None if its frame buffer was deleted
+    pub render_targets: Vec<Option<RenderTarget>>,
+    descriptor_pool: Option<HalDescriptorPool>,
+    /// Set of each `BindDescriptorSet`, None if there is nothing to bind
+    pub descriptor_sets: Vec<Option<HalDescriptorSet>>,
+}
+
+impl Transients {
+    /// Create the hal objects the commands of `command_buffer` read besides those of GL objects, None if any of them
+    /// could not be created
+    fn new(command_buffer: &CommandBuffer, upload_buffer: &UploadBuffer, pipeline_cache: &mut PipelineCache) -> Option<Self> {
+        let mut transients = Self::default();
+        if transients.create_upload_buffer(upload_buffer)
+            && transients.create_render_targets(&command_buffer.commands, pipeline_cache)
+            && transients.create_descriptor_sets(&command_buffer.commands, pipeline_cache)
+        {
+            Some(transients)
+        } else {
+            transients
+                .into_objects()
+                .into_iter()
+                .for_each(hal_registry::hal_object_destroy);
+            None
+        }
+    }
+
+    fn create_upload_buffer(&mut self, upload_buffer: &UploadBuffer) -> bool {
+        if upload_buffer.data.is_empty() {
+            return true;
+        }
+        let usage = buffer::Usage::VERTEX | buffer::Usage::INDEX | buffer::Usage::UNIFORM;
+        let (raw, memory) = match hal_registry::hal_buffer_create(upload_buffer.data.len() as u64, usage) {
+            Some(created) => created,
+            None => return false,
+        };
+        let is_written = hal_registry::hal_memory_write(&memory, 0, &upload_buffer.data);
+        self.upload_buffer = Some(raw);
+        self.upload_memory = Some(memory);
+        is_written
+    }
+
+    fn create_render_targets(&mut self, commands: &[Command], pipeline_cache: &mut PipelineCache) -> bool {
+        for command in commands {
+            let (name, load_ops) = match *command {
+                Command::BeginRenderPass {
+                    frame_buffer, load_ops, ..
+                } => (frame_buffer, load_ops),
+                _ => continue,
+            };
+
+            let frame_buffer_pool = HUB.frame_buffer_pool.lock();
+            let default_frame_buffer = HUB.default_frame_buffer.lock();
+            let frame_buffer = if context::is_default_frame_buffer(name) {
+                &*default_frame_buffer
+            } else {
+                match frame_buffer_pool.get_object(name) {
+                    Some(frame_buffer) => frame_buffer,
+                    None => {
+                        warn!("frame buffer {} was deleted before its render pass was submitted", name);
+                        self.render_targets.push(None);
+                        continue;
+                    }
+                }
+            };
+            let key = frame_buffer.render_pass_key();
+            let raw = match pipeline_cache.render_pass(key, load_ops) {
+                Some(render_pass) => frame_buffer.create_hal_frame_buffer(render_pass),
+                None => None,
+            };
+            let raw = match raw {
+                Some(raw) => raw,
+                None => return false,
+            };
+            let area = Rect::new(0, 0, frame_buffer.dimensions.width, frame_buffer.dimensions.height);
+            self.render_targets.push(Some(RenderTarget {
+                key,
+                frame_buffer: raw,
+                area: viewport_transformation::hal_rect(&area),
+            }));
+        }
+        true
+    }
+
+    /// Allocate a descriptor set with the layout of the pipeline bound before each `BindDescriptorSet` and write
+    /// the uniform block and textures into it
+    fn create_descriptor_sets(&mut self, commands: &[Command], pipeline_cache: &PipelineCache) -> bool {
+        // layout of each descriptor set, None for programs without uniforms
+        let mut layout = None;
+        let sets = commands
+            .iter()
+            .filter_map(|command| match *command {
+                Command::BindPipeline(index) => {
+                    layout = pipeline_cache
+                        .pipeline(index)
+                        .and_then(|pipeline| pipeline_cache.layout(pipeline.key.program));
+                    None
+                }
+                Command::BindDescriptorSet(ref set) => {
+                    let is_empty = set.uniform_block.is_none() && set.textures.is_empty();
+                    Some((set, if is_empty { None } else { layout }))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let max_sets = sets.iter().filter(|&&(_, layout)| layout.is_some()).count();
+        if max_sets == 0 {
+            self.descriptor_sets = sets.iter().map(|_| None).collect();
+            return true;
+        }
+        let uniform_blocks = sets.iter().filter(|&&(set, _)| set.uniform_block.is_some()).count();
+        let samplers = sets
+            .iter()
+            .flat_map(|&(set, _)| set.textures.iter().map(|(_, names)| names.len()))
+            .sum::<usize>();
+        let ranges = [
+            DescriptorRangeDesc {
+                ty: DescriptorType::UniformBuffer,
+                count: uniform_blocks,
+            },
+            DescriptorRangeDesc {
+                ty: DescriptorType::CombinedImageSampler,
+                count: samplers,
+            },
+        ];
+        let ranges = ranges.iter().filter(|range| range.count > 0).cloned().collect::<Vec<_>>();
+        let mut pool = match hal_registry::hal_descriptor_pool_create(max_sets, &ranges) {
+            Some(pool) => pool,
+            None => return false,
+        };
+
+        let mut is_allocated = true;
+        self.descriptor_sets = sets
+            .iter()
+            .map(|&(_, layout)| {
+                let layout = layout?;
+                let set = hal_registry::hal_descriptor_set_allocate(&mut pool, &layout.descriptor_set_layout);
+                is_allocated &= set.is_some();
+                set
+            })
+            .collect();
+        self.descriptor_pool = Some(pool);
+        if !is_allocated {
+            return false;
+        }
+
+        let contents = sets
+            .iter()
+            .map(|&(set, layout)| (set, layout.map(|layout| layout.uniform_block_size)));
+        self.write_descriptor_sets(contents.collect())
+    }
+
+    /// Write the uniform block in the upload buffer and the textures of each descriptor set, creating the samplers
+    /// of the textures which have none yet
+    fn write_descriptor_sets(&self, contents: Vec<(&DescriptorSet, Option<u64>)>) -> bool {
+        let mut texture_pool = HUB.texture_pool.lock();
+        for &(set, _) in &contents {
+            for &name in set.textures.iter().flat_map(|(_, names)| names) {
+                if !texture_pool.has_object(name) {
+                    continue;
+                }
+                let texture = texture_pool.get_object_mut(name);
+                if texture.image_view.is_some() && texture.sampler.is_none() {
+                    texture.sampler = hal_registry::hal_sampler_create();
+                    if texture.sampler.is_none() {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        let mut writes = Vec::new();
+        for (raw, &(set, uniform_block_size)) in self.descriptor_sets.iter().zip(&contents) {
+            let (raw, uniform_block_size) = match (raw.as_ref(), uniform_block_size) {
+                (Some(raw), Some(uniform_block_size)) => (raw, uniform_block_size),
+                _ => continue,
+            };
+            if let (Some(offset), Some(upload_buffer)) = (set.uniform_block, self.upload_buffer.as_ref()) {
+                let offset = offset as u64;
+                writes.push(pso::DescriptorSetWrite {
+                    set: raw,
+                    binding: UNIFORM_BLOCK_BINDING,
+                    array_offset: 0,
+                    descriptors: vec![pso::Descriptor::Buffer(
+                        upload_buffer,
+                        Some(offset)..Some(offset + uniform_block_size),
+                    )],
+                });
+            }
+            for &(binding, ref names) in &set.textures {
+                for (element, &name) in names.iter().enumerate() {
+                    let texture = texture_pool.get_object(name);
+                    match texture.and_then(|texture| Some((texture.image_view.as_ref()?, texture.sampler.as_ref()?))) {
+                        Some((view, sampler)) => writes.push(pso::DescriptorSetWrite {
+                            set: raw,
+                            binding,
+                            array_offset: element,
+                            descriptors: vec![pso::Descriptor::CombinedImageSampler(view, image::Layout::General, sampler)],
+                        }),
+                        None => warn!("texture {} has no image to sample", name),
+                    }
+                }
+            }
+        }
+        hal_registry::hal_descriptor_sets_write(writes);
+        true
+    }
+
+    /// Hal objects to destroy, descriptor sets are freed with their pool
+    fn into_objects(self) -> Vec<HalObject> {
+        let mut objects = Vec::new();
+        objects.extend(self.descriptor_pool.map(HalObject::DescriptorPool));
+        objects.extend(
+            self.render_targets
+                .into_iter()
+                .filter_map(|target| target.map(|target| HalObject::FrameBuffer(target.frame_buffer))),
+        );
+        objects.extend(self.upload_buffer.map(HalObject::Buffer));
+        objects.extend(self.upload_memory.map(HalObject::Memory));
+        objects
+    }
+}
+
+/// Hal objects the commands of a submission refer to by GL name or index, looked up while they are encoded
+pub(crate) struct Resources<'a> {
+    pub pipeline_cache: &'a PipelineCache,
+    pub buffer_pool: &'a ObjectPool<Buffer>,
+    pub transients: &'a Transients,
+}
+
+impl<'a> Resources<'a> {
+    /// Hal buffer holding the data of `source`
+    pub fn buffer(&self, source: DataSource) -> Option<&'a HalBuffer> {
+        match source {
+            DataSource::Buffer(name) => self.buffer_pool.get_object(name)?.buffer.as_ref(),
+            DataSource::Upload => self.transients.upload_buffer.as_ref(),
+        }
+    }
+}
+
+/// Command buffer, the hal command buffer it was translated into and the transient data it reads, in flight until
+/// `fence` signals
+#[derive(Debug)]
+struct Submission {
+    id: u64,
+    command_buffer: CommandBuffer,
+    raw: HalCommandBuffer,
+    upload_buffer: UploadBuffer,
+    /// Hal objects created for the commands
+    transients: Vec<HalObject>,
+    fence: HalFence,
+}
+
+/// Submits recorded command buffers and recycles them, with their upload buffers, once the GPU completed them
+#[derive(Debug, Default)]
+pub(crate) struct CommandBufferManager {
+    free_command_buffers: Vec<CommandBuffer>,
+    free_upload_buffers: Vec<UploadBuffer>,
+    submissions: VecDeque<Submission>,
+    /// Id of the last submission, ids start at 1
+    last_submission: u64,
+    /// Every submission up to this id completed
+    completed_submission: u64,
+}
+
+impl CommandBufferManager {
+    /// Submit the commands recorded in `command_buffer` and the `upload_buffer` they read, replacing both with
+    /// recycled ones to record into. Commands which fail to submit are dropped along with their data.
+    pub fn submit(&mut self, command_buffer: &mut CommandBuffer, upload_buffer: &mut UploadBuffer) -> bool {
+        let fence = match hal_registry::hal_fence_create() {
+            Some(fence) => fence,
+            None => {
+                error!("failed to create a fence for {} commands", command_buffer.commands.len());
+                self.discard(command_buffer, upload_buffer);
+                return false;
+            }
+        };
+        let mut pipeline_cache = HUB.pipeline_cache.lock();
+        let raw = Transients::new(command_buffer, upload_buffer, &mut pipeline_cache).and_then(|transients| {
+            let resources = Resources {
+                pipeline_cache: &pipeline_cache,
+                buffer_pool: &HUB.buffer_pool.lock(),
+                transients: &transients,
+            };
+            let raw = hal_registry::hal_queue_submit(command_buffer, &resources, &fence);
+            let transients = transients.into_objects();
+            match raw {
+                Some(raw) => Some((raw, transients)),
+                None => {
+                    transients.into_iter().for_each(hal_registry::hal_object_destroy);
+                    None
+                }
+            }
+        });
+        drop(pipeline_cache);
+        let (raw, transients) = match raw {
+            Some(submitted) => submitted,
+            None => {
+                error!("failed to submit {} commands", command_buffer.commands.len());
+                hal_registry::hal_fence_release(fence);
+                self.discard(command_buffer, upload_buffer);
+                return false;
+            }
+        };
+
+        self.last_submission += 1;
+        let id = self.last_submission;
+        trace!("submit {} commands as submission {}", command_buffer.commands.len(), id);
+
+        let generation = upload_buffer.generation + 1;
+        let mut recycled_upload_buffer = self.free_upload_buffers.pop().unwrap_or_default();
+        recycled_upload_buffer.generation = generation;
+        self.submissions.push_back(Submission {
+            id,
+            command_buffer: mem::replace(command_buffer, self.free_command_buffers.pop().unwrap_or_default()),
+            raw,
+            upload_buffer: mem::replace(upload_buffer, recycled_upload_buffer),
+            transients,
+            fence,
+        });
+        true
+    }
+
+    /// Drop the commands recorded in `command_buffer` and the data they read. They take a submission id which
+    /// completes with the submissions in flight, objects they used are released then.
+    pub fn discard(&mut self, command_buffer: &mut CommandBuffer, upload_buffer: &mut UploadBuffer) {
+        command_buffer.reset();
+        upload_buffer.reset();
+        self.last_submission += 1;
+        if self.submissions.is_empty() {
+            self.completed_submission = self.last_submission;
+        }
+    }
+
+    /// Recycle the resources of the submissions whose fence signaled
+    pub fn poll(&mut self) {
+        while self
+            .submissions
+            .front()
+            .map_or(false, |submission| hal_registry::hal_fence_status(&submission.fence))
+        {
+            let submission = self.submissions.pop_front().unwrap();
+            self.recycle(submission);
+        }
+    }
+
+    /// Wait for every submission to complete and recycle their resources
+    pub fn wait_idle(&mut self) -> bool {
+        while let Some(submission) = self.submissions.pop_front() {
+            if !hal_registry::hal_fence_wait(&submission.fence) {
+                error!("failed to wait for submission {}", submission.id);
+                self.submissions.push_front(submission);
+                return false;
+            }
+            self.recycle(submission);
+        }
+        true
+    }
+
+    /// Id the next submission will get
+    #[inline]
+    pub fn next_submission(&self) -> u64 {
+        self.last_submission + 1
+    }
+
+    #[inline]
+    pub fn completed_submission(&self) -> u64 {
+        self.completed_submission
+    }
+
+    fn recycle(&mut self, submission: Submission) {
+        let Submission {
+            id,
+            mut command_buffer,
+            raw,
+            mut upload_buffer,
+            transients,
+            fence,
+        } = submission;
+        trace!("submission {} completed", id);
+        // discarded submissions after the last one in flight completed with it
+        self.completed_submission = if self.submissions.is_empty() {
+            self.last_submission
+        } else {
+            id
+        };
+        hal_registry::hal_fence_release(fence);
+        hal_registry::hal_command_buffer_release(raw);
+        transients.into_iter().for_each(hal_registry::hal_object_destroy);
+        command_buffer.reset();
+        upload_buffer.data.clear();
+        self.free_command_buffers.push(command_buffer);
+        self.free_upload_buffers.push(upload_buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_buffer_manager() {
+        let mut manager = CommandBufferManager::default();
+        let mut command_buffer = CommandBuffer::default();
+        let mut upload_buffer = UploadBuffer::default();
+        command_buffer.record(Command::EndRenderPass);
+        upload_buffer.push(&[1, 2, 3], 4);
+        assert_eq!(manager.next_submission(), 1);
+
+        let is_submitted = manager.submit(&mut command_buffer, &mut upload_buffer);
+        assert_eq!(is_submitted, hal_registry::hal_device_available());
+        assert!(command_buffer.is_empty());
+        assert!(upload_buffer.data.is_empty());
+        assert_eq!(upload_buffer.generation, 1);
+        if !is_submitted {
+            // the commands are dropped, their submission completes right away
+            assert_eq!((manager.next_submission(), manager.completed_submission()), (2, 1));
+            assert!(manager.submissions.is_empty());
+            return;
+        }
+        assert_eq!((manager.next_submission(), manager.completed_submission()), (2, 0));
+
+        assert!(manager.submit(&mut command_buffer, &mut upload_buffer));
+        // submissions are only recycled once their fence signaled
+        manager.poll();
+        if let Some(submission) = manager.submissions.front() {
+            assert!(!hal_registry::hal_fence_status(&submission.fence));
+            assert!(manager.completed_submission() < submission.id);
+        }
+        assert!(manager.wait_idle());
+        assert_eq!(manager.completed_submission(), 2);
+        assert_eq!(
+            (manager.free_command_buffers.len(), manager.free_upload_buffers.len()),
+            (2, 2)
+        );
+
+        // recycled upload buffers never repeat the generation of offsets taken before
+        assert!(manager.submit(&mut command_buffer, &mut upload_buffer));
+        assert_eq!(upload_buffer.generation, 3);
+
+        // discarded commands complete with the submissions in flight before them
+        command_buffer.record(Command::EndRenderPass);
+        manager.discard(&mut command_buffer, &mut upload_buffer);
+        assert!(command_buffer.is_empty());
+        assert_eq!(upload_buffer.generation, 4);
+        assert!(manager.completed_submission() < 4);
+        assert!(manager.wait_idle());
+        assert_eq!((manager.next_submission(), manager.completed_submission()), (5, 4));
+    }
+
+    #[test]
+    fn test_transients() {
+        use context::TEST_LOCK;
+        use gl_sys::{GLvoid, GL_ARRAY_BUFFER, GL_FALSE, GL_FLOAT, GL_TRIANGLES};
+        use program::tests::link_program;
+        use program::{glDeleteProgram, glUseProgram};
+        use rendering::{glDrawArrays, glFinish, glFlush};
+        use vertex_attribute::{glEnableVertexAttribArray, glVertexAttribPointer};
+
+        let _guard = TEST_LOCK.lock();
+        glFinish();
+        ::buffer::glBindBuffer(GL_ARRAY_BUFFER, 0);
+        let program = link_program(
+            "attribute vec4 a_position; void main() { gl_Position = a_position; }",
+            "precision mediump float; uniform vec4 u_color; void main() { gl_FragColor = u_color; }",
+        );
+        glUseProgram(program);
+        let positions = [0.0f32; 12];
+        glVertexAttribPointer(0, 4, GL_FLOAT, GL_FALSE, 0, positions.as_ptr() as *const GLvoid);
+        glEnableVertexAttribArray(0);
+        glDrawArrays(GL_TRIANGLES, 0, 3);
+        glUseProgram(0);
+        glFlush();
+
+        {
+            let manager = HUB.command_buffer_manager.lock();
+            match manager.submissions.back() {
+                // the upload buffer and its memory, the frame buffer of the render pass and the descriptor pool
+                Some(submission) => assert_eq!(submission.transients.len(), 4),
+                None => assert!(!hal_registry::hal_device_available()),
+            }
+        }
+        glFinish();
+        glDeleteProgram(program);
+    }
+}
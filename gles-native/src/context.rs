@@ -4,20 +4,30 @@ use std;
 use std::ptr;
 
 use gl_sys::{
-    GLboolean, GLenum, GLsizei, GLuint, GL_FALSE, GL_INVALID_ENUM, GL_INVALID_OPERATION, GL_INVALID_VALUE, GL_OUT_OF_MEMORY,
-    GL_TEXTURE_2D, GL_TEXTURE_CUBE_MAP, GL_TRUE,
+    GLboolean, GLchar, GLenum, GLsizei, GLuint, GL_FALSE, GL_INVALID_ENUM, GL_INVALID_OPERATION, GL_INVALID_VALUE,
+    GL_OUT_OF_MEMORY, GL_TEXTURE_2D, GL_TEXTURE_CUBE_MAP, GL_TRUE,
 };
 
 use buffer::{self, Buffer};
-use frame_buffer::FrameBuffer;
+use command_buffer::{CommandBuffer, CommandBufferManager};
+use depth_stencil_state::DepthStencilState;
+use destruction_queue::DestructionQueue;
+use fragment_state::FragmentState;
+use frame_buffer::{FrameBuffer, Rect};
+use hal_registry;
+use index_conversion::IndexCache;
+use pipeline::{FixedFunctionState, PipelineCache};
 use pixel_operations::PixelStorageState;
 use program::Program;
 use rasterization_state::RasterizationState;
 use render_buffer::RenderBuffer;
+use rendering::ClearValues;
 use shader::Shader;
 use texture::{self, Texture};
+use upload::UploadBuffer;
 use utilities::{self, record_error};
-use viewport_transformation::ViewportTransformation;
+use vertex_attribute::VertexAttributeState;
+use viewport_transformation::{self, ViewportTransformation};
 
 use active_object::{get_object_mut, ActiveObject};
 use object_pool::ObjectPool;
@@ -29,9 +39,10 @@ type MutexActiveObject<T> = Mutex<ActiveObject<T>>;
 #[derive(Debug, Default)]
 pub struct Context {
     pub(crate) error: Mutex<utilities::Error>,
+    pub(crate) hints: Mutex<utilities::Hints>,
 
     pub(crate) texture_pool: MutexObjectPool<Texture>,
-    pub(crate) active_texture: [[MutexActiveObject<Texture>; 32]; texture::TextureType::Total as usize], // todo: split to 8(vertex) + 32(fragment)
+    pub(crate) active_texture: [[MutexActiveObject<Texture>; texture::MAX_TEXTURE_UNITS]; texture::TextureType::Total as usize],
     pub(crate) active_texture_unit: Mutex<texture::TextureUnit>,
 
     pub(crate) buffer_pool: MutexObjectPool<Buffer>,
@@ -42,15 +53,31 @@ pub struct Context {
 
     pub(crate) frame_buffer_pool: MutexObjectPool<FrameBuffer>,
     pub(crate) active_frame_buffer: MutexActiveObject<FrameBuffer>,
+    pub(crate) default_frame_buffer: Mutex<FrameBuffer>,
+    /// Whether the context has been made current to a window surface yet
+    pub(crate) is_initialized: Mutex<bool>,
+
+    pub(crate) command_buffer: Mutex<CommandBuffer>,
+    pub(crate) command_buffer_manager: Mutex<CommandBufferManager>,
+    pub(crate) pipeline_cache: Mutex<PipelineCache>,
+    pub(crate) fixed_function_state: Mutex<FixedFunctionState>,
+    pub(crate) destruction_queue: Mutex<DestructionQueue>,
 
     pub(crate) shader_pool: MutexObjectPool<Shader>,
 
     pub(crate) program_pool: MutexObjectPool<Program>,
     pub(crate) active_program: MutexActiveObject<Program>,
 
+    pub(crate) vertex_attribute_state: Mutex<VertexAttributeState>,
+    pub(crate) upload_buffer: Mutex<UploadBuffer>,
+    pub(crate) index_cache: Mutex<IndexCache>,
+
     pub(crate) rasterization_state: Mutex<RasterizationState>,
+    pub(crate) depth_stencil_state: Mutex<DepthStencilState>,
+    pub(crate) fragment_state: Mutex<FragmentState>,
     pub(crate) pixel_storage_state: Mutex<PixelStorageState>,
     pub(crate) viewport_state: Mutex<ViewportTransformation>,
+    pub(crate) clear_values: Mutex<ClearValues>,
     //    pub(crate) input_assembly_State: Mutex<InputAssemblyState>,
 }
 
@@ -64,6 +91,12 @@ lazy_static! {
     };
 }
 
+/// Serializes tests which change bindings of the context shared by all tests
+#[cfg(test)]
+lazy_static! {
+    pub(crate) static ref TEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
 #[inline(always)]
 fn init_log() {
     let env = env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "trace");
@@ -84,39 +117,36 @@ pub fn generate_objects<T: Default>(count: GLsizei, objects_ptr: *mut GLuint, po
     }
 }
 
-pub(crate) fn delete_objects<T, UPS>(
-    count: GLsizei,
-    objects_ptr: *const GLuint,
-    pool: &MutexObjectPool<T>,
-    active_object: &mut MutexActiveObject<T>,
-    need_forward_finish_drawing: bool,
-    update_state: UPS,
-) where
+/// Delete the objects named in `objects_ptr`, `update_state` unbinds each one and releases its hal objects first.
+/// It returns false to keep an object still in use until it is released elsewhere.
+pub(crate) fn delete_objects<T, UPS>(count: GLsizei, objects_ptr: *const GLuint, pool: &MutexObjectPool<T>, update_state: UPS)
+where
     T: Default,
-    UPS: Fn(&mut T),
+    UPS: Fn(GLuint, &mut T) -> bool,
 {
     if !validate_objects_ptr(count, objects_ptr) {
         return;
     }
 
-    if need_forward_finish_drawing {
-        // todo: glFinish()
-    }
-
     let pool_guard = &mut *pool.lock();
     let object_names: &[GLuint] = unsafe { std::slice::from_raw_parts(objects_ptr, count as usize) };
     for &object_name in object_names {
-        if !pool_guard.has_object(object_name) {
+        if object_name == 0 || !pool_guard.has_object(object_name) {
             debug!("name {} that do not correspond to an existing object", object_name);
             continue;
         }
-        {
-            let object = pool_guard.get_object_mut(object_name);
-            update_state(object);
-            active_object.lock().name = 0;
-            active_object.lock().ptr = ptr::null_mut();
+        if update_state(object_name, pool_guard.get_object_mut(object_name)) {
+            pool_guard.deallocate(object_name);
         }
-        pool_guard.deallocate(object_name);
+    }
+}
+
+/// Reset `active_object` to the default object if it refers to `name`
+pub(crate) fn unbind_object<T>(active_object: &MutexActiveObject<T>, name: GLuint, default_object_ptr: *mut T) {
+    let mut guard = active_object.lock();
+    if guard.name == name {
+        guard.name = 0;
+        guard.ptr = default_object_ptr;
     }
 }
 
@@ -147,8 +177,45 @@ pub fn is_valid_object<T: Default>(object_name: GLuint, pool: &MutexObjectPool<T
     pool.lock().has_object(object_name).as_gl_bool_value()
 }
 
+/// Draw to a window surface of `width` x `height` pixels through the default frame buffer, the first surface
+/// also sets the viewport and the scissor box (ES 2.0 §2.12.1, §4.1.2). Return false if there is no device to
+/// render with.
+pub fn make_current(width: GLsizei, height: GLsizei) -> bool {
+    let dimensions = Rect::new(0, 0, width, height);
+    {
+        let mut frame_buffer = HUB.default_frame_buffer.lock();
+        frame_buffer.is_system_frame_buffer = true;
+        frame_buffer.dimensions = dimensions;
+    }
+
+    let mut is_initialized = HUB.is_initialized.lock();
+    if !*is_initialized {
+        viewport_transformation::initialize(&dimensions);
+        *is_initialized = true;
+    }
+
+    if !hal_registry::hal_device_available() {
+        error!("no device to render with");
+        return false;
+    }
+    true
+}
+
 pub(crate) fn get_default_frame_buffer(target: GLenum) -> *mut FrameBuffer {
-    ptr::null_mut()
+    let mut guard = HUB.default_frame_buffer.lock();
+    guard.is_system_frame_buffer = true;
+    &mut *guard as *mut FrameBuffer
+}
+
+/// Frame buffer object name 0 is the default frame buffer
+pub(crate) fn get_active_frame_buffer() -> ActiveObject<FrameBuffer> {
+    use gl_sys::GL_FRAMEBUFFER;
+
+    let mut active_object = HUB.active_frame_buffer.lock().clone();
+    if active_object.ptr.is_null() {
+        active_object.ptr = get_default_frame_buffer(GL_FRAMEBUFFER);
+    }
+    active_object
 }
 
 pub(crate) fn get_default_texture(target: GLenum) -> *mut Texture {
@@ -213,6 +280,11 @@ pub(crate) fn get_active_texture(target: GLenum) -> ActiveObject<Texture> {
         .clone()
 }
 
+#[inline]
+pub(crate) fn get_active_render_buffer() -> ActiveObject<RenderBuffer> {
+    HUB.active_render_buffer.lock().clone()
+}
+
 #[inline]
 pub(crate) fn get_active_buffer(target: GLenum) -> ActiveObject<Buffer> {
     HUB.active_buffer[buffer_target_to_index(target)].lock().clone()
@@ -254,6 +326,25 @@ pub(crate) fn bind_target_object_name<T, PRL, OPT, PLG>(
     set_active_state(object_ptr)
 }
 
+/// Copy at most `bufsize - 1` bytes of `value` and a null terminator to `buffer`, the number of bytes copied
+/// excluding the null terminator is written to `length` unless it is nullptr
+pub(crate) fn copy_to_gl_string(value: &str, bufsize: GLsizei, length: *mut GLsizei, buffer: *mut GLchar) {
+    let copied = if bufsize > 0 && !buffer.is_null() {
+        let copied = std::cmp::min(value.len(), bufsize as usize - 1);
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr() as *const GLchar, buffer, copied);
+            *buffer.offset(copied as isize) = 0;
+        }
+        copied
+    } else {
+        0
+    };
+
+    if !length.is_null() {
+        unsafe { *length = copied as GLsizei };
+    }
+}
+
 #[inline(always)]
 pub(crate) fn is_nullptr<T>(ptr: *mut T, message: &str) -> bool {
     if ptr == ptr::null_mut() {
@@ -334,7 +425,7 @@ pub(crate) fn find_value_in_slice<F>(value: GLenum, required_values: &[GLenum],
 where
     F: Fn(GLenum),
 {
-    if !required_values.into_iter().any(|&value| value == value) {
+    if !required_values.into_iter().any(|&required_value| required_value == value) {
         operation_when_false(value);
         false
     } else {
@@ -0,0 +1,434 @@
+#![allow(non_snake_case)]
+
+use std::hash::{Hash, Hasher};
+
+use hal::pso::{Comparison, DepthStencilDesc, DepthTest, State, StencilFace, StencilOp, StencilTest};
+
+use gl_sys::{
+    GLboolean, GLenum, GLint, GLuint, GL_ALWAYS, GL_BACK, GL_DECR, GL_DECR_WRAP, GL_DEPTH_FUNC, GL_DEPTH_TEST,
+    GL_DEPTH_WRITEMASK, GL_EQUAL, GL_FALSE, GL_FRONT, GL_FRONT_AND_BACK, GL_GEQUAL, GL_GREATER, GL_INCR, GL_INCR_WRAP, GL_INVERT,
+    GL_KEEP, GL_LEQUAL, GL_LESS, GL_NEVER, GL_NOTEQUAL, GL_REPLACE, GL_STENCIL_BACK_FAIL, GL_STENCIL_BACK_FUNC,
+    GL_STENCIL_BACK_PASS_DEPTH_FAIL, GL_STENCIL_BACK_PASS_DEPTH_PASS, GL_STENCIL_BACK_REF, GL_STENCIL_BACK_VALUE_MASK,
+    GL_STENCIL_BACK_WRITEMASK, GL_STENCIL_FAIL, GL_STENCIL_FUNC, GL_STENCIL_PASS_DEPTH_FAIL, GL_STENCIL_PASS_DEPTH_PASS,
+    GL_STENCIL_REF, GL_STENCIL_TEST, GL_STENCIL_VALUE_MASK, GL_STENCIL_WRITEMASK, GL_ZERO,
+};
+
+use active_object;
+use context::{self, HUB};
+use pipeline::{self, DIRTY_DEPTH_STENCIL};
+use utilities::StateValue;
+
+const COMPARISON_FUNCTIONS: [GLenum; 8] = [
+    GL_NEVER,
+    GL_LESS,
+    GL_EQUAL,
+    GL_LEQUAL,
+    GL_GREATER,
+    GL_NOTEQUAL,
+    GL_GEQUAL,
+    GL_ALWAYS,
+];
+const STENCIL_OPERATIONS: [GLenum; 8] = [
+    GL_KEEP,
+    GL_ZERO,
+    GL_REPLACE,
+    GL_INCR,
+    GL_DECR,
+    GL_INVERT,
+    GL_INCR_WRAP,
+    GL_DECR_WRAP,
+];
+
+/// Stencil state of one face, the reference value is dynamic state and does not take part in pipeline lookups
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StencilFaceState {
+    pub func: GLenum,
+    pub reference: GLint,
+    pub value_mask: GLuint,
+    pub write_mask: GLuint,
+    pub fail: GLenum,
+    pub pass_depth_fail: GLenum,
+    pub pass_depth_pass: GLenum,
+}
+
+impl Default for StencilFaceState {
+    fn default() -> Self {
+        Self {
+            func: GL_ALWAYS,
+            reference: 0,
+            value_mask: !0,
+            write_mask: !0,
+            fail: GL_KEEP,
+            pass_depth_fail: GL_KEEP,
+            pass_depth_pass: GL_KEEP,
+        }
+    }
+}
+
+impl PartialEq for StencilFaceState {
+    fn eq(&self, other: &Self) -> bool {
+        self.func == other.func
+            && self.value_mask == other.value_mask
+            && self.write_mask == other.write_mask
+            && self.fail == other.fail
+            && self.pass_depth_fail == other.pass_depth_fail
+            && self.pass_depth_pass == other.pass_depth_pass
+    }
+}
+
+impl Eq for StencilFaceState {}
+
+impl Hash for StencilFaceState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.func.hash(state);
+        self.value_mask.hash(state);
+        self.write_mask.hash(state);
+        self.fail.hash(state);
+        self.pass_depth_fail.hash(state);
+        self.pass_depth_pass.hash(state);
+    }
+}
+
+impl StencilFaceState {
+    fn stencil_face(&self) -> StencilFace {
+        StencilFace {
+            fun: comparison(self.func),
+            mask_read: State::Static(self.value_mask),
+            mask_write: State::Static(self.write_mask),
+            op_fail: stencil_op(self.fail),
+            op_depth_fail: stencil_op(self.pass_depth_fail),
+            op_pass: stencil_op(self.pass_depth_pass),
+            reference: State::Dynamic,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct DepthStencilState {
+    pub is_depth_test_enabled: bool,
+    pub depth_func: GLenum,
+    pub depth_mask: bool,
+    pub is_stencil_test_enabled: bool,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState,
+}
+
+impl Default for DepthStencilState {
+    fn default() -> Self {
+        Self {
+            is_depth_test_enabled: false,
+            depth_func: GL_LESS,
+            depth_mask: true,
+            is_stencil_test_enabled: false,
+            front: StencilFaceState::default(),
+            back: StencilFaceState::default(),
+        }
+    }
+}
+
+impl DepthStencilState {
+    /// Depth stencil description of a pipeline, a disabled depth test does not write depth values either
+    pub fn depth_stencil_desc(&self) -> DepthStencilDesc {
+        DepthStencilDesc {
+            depth: if self.is_depth_test_enabled {
+                DepthTest::On {
+                    fun: comparison(self.depth_func),
+                    write: self.depth_mask,
+                }
+            } else {
+                DepthTest::Off
+            },
+            depth_bounds: false,
+            stencil: if self.is_stencil_test_enabled {
+                StencilTest::On {
+                    front: self.front.stencil_face(),
+                    back: self.back.stencil_face(),
+                }
+            } else {
+                StencilTest::Off
+            },
+        }
+    }
+
+    /// Front and back values of the dynamic stencil reference for a stencil buffer of `stencil_bits`, `None` unless
+    /// the stencil test is enabled
+    pub fn stencil_reference(&self, stencil_bits: GLint) -> Option<[u32; 2]> {
+        if !self.is_stencil_test_enabled {
+            return None;
+        }
+        Some([
+            clamp_reference(self.front.reference, stencil_bits) as u32,
+            clamp_reference(self.back.reference, stencil_bits) as u32,
+        ])
+    }
+
+    /// Stencil states of the faces `face` selects
+    fn faces_mut(&mut self, face: GLenum) -> Vec<&mut StencilFaceState> {
+        match face {
+            GL_FRONT => vec![&mut self.front],
+            GL_BACK => vec![&mut self.back],
+            _ => vec![&mut self.front, &mut self.back],
+        }
+    }
+}
+
+fn comparison(func: GLenum) -> Comparison {
+    match func {
+        GL_NEVER => Comparison::Never,
+        GL_LESS => Comparison::Less,
+        GL_EQUAL => Comparison::Equal,
+        GL_LEQUAL => Comparison::LessEqual,
+        GL_GREATER => Comparison::Greater,
+        GL_NOTEQUAL => Comparison::NotEqual,
+        GL_GEQUAL => Comparison::GreaterEqual,
+        _ => Comparison::Always,
+    }
+}
+
+fn stencil_op(op: GLenum) -> StencilOp {
+    match op {
+        GL_ZERO => StencilOp::Zero,
+        GL_REPLACE => StencilOp::Replace,
+        GL_INCR => StencilOp::IncrementClamp,
+        GL_DECR => StencilOp::DecrementClamp,
+        GL_INVERT => StencilOp::Invert,
+        GL_INCR_WRAP => StencilOp::IncrementWrap,
+        GL_DECR_WRAP => StencilOp::DecrementWrap,
+        _ => StencilOp::Keep,
+    }
+}
+
+pub(crate) fn set_capability(cap: GLenum, is_enabled: bool) {
+    {
+        let mut state = HUB.depth_stencil_state.lock();
+        match cap {
+            GL_DEPTH_TEST => state.is_depth_test_enabled = is_enabled,
+            GL_STENCIL_TEST => state.is_stencil_test_enabled = is_enabled,
+            _ => unreachable!(),
+        }
+    }
+    pipeline::mark_dirty(DIRTY_DEPTH_STENCIL);
+}
+
+pub(crate) fn is_capability_enabled(cap: GLenum) -> bool {
+    let state = HUB.depth_stencil_state.lock();
+    match cap {
+        GL_DEPTH_TEST => state.is_depth_test_enabled,
+        GL_STENCIL_TEST => state.is_stencil_test_enabled,
+        _ => unreachable!(),
+    }
+}
+
+/// `reference` clamped to [0, 2^s - 1], the values a stencil buffer of `stencil_bits` s holds
+fn clamp_reference(reference: GLint, stencil_bits: GLint) -> GLint {
+    reference.max(0).min((1 << stencil_bits) - 1)
+}
+
+pub(crate) fn query_state(pname: GLenum) -> Option<StateValue> {
+    // references are reported clamped to the stencil buffer of the bound frame buffer
+    let stencil_bits = match pname {
+        GL_STENCIL_REF | GL_STENCIL_BACK_REF => {
+            let mut active_object = context::get_active_frame_buffer();
+            active_object::get_object_mut(&mut active_object).unwrap().stencil_bits()
+        }
+        _ => 0,
+    };
+    let state = HUB.depth_stencil_state.lock();
+    let enum_value = |value: GLenum| Some(StateValue::Integers(vec![value as GLint]));
+    let reference = |value: GLint| Some(StateValue::Integers(vec![clamp_reference(value, stencil_bits)]));
+    match pname {
+        GL_DEPTH_FUNC => enum_value(state.depth_func),
+        GL_DEPTH_WRITEMASK => Some(StateValue::Booleans(vec![state.depth_mask])),
+        GL_STENCIL_FUNC => enum_value(state.front.func),
+        GL_STENCIL_REF => reference(state.front.reference),
+        GL_STENCIL_VALUE_MASK => Some(StateValue::Integers(vec![state.front.value_mask as GLint])),
+        GL_STENCIL_WRITEMASK => Some(StateValue::Integers(vec![state.front.write_mask as GLint])),
+        GL_STENCIL_FAIL => enum_value(state.front.fail),
+        GL_STENCIL_PASS_DEPTH_FAIL => enum_value(state.front.pass_depth_fail),
+        GL_STENCIL_PASS_DEPTH_PASS => enum_value(state.front.pass_depth_pass),
+        GL_STENCIL_BACK_FUNC => enum_value(state.back.func),
+        GL_STENCIL_BACK_REF => reference(state.back.reference),
+        GL_STENCIL_BACK_VALUE_MASK => Some(StateValue::Integers(vec![state.back.value_mask as GLint])),
+        GL_STENCIL_BACK_WRITEMASK => Some(StateValue::Integers(vec![state.back.write_mask as GLint])),
+        GL_STENCIL_BACK_FAIL => enum_value(state.back.fail),
+        GL_STENCIL_BACK_PASS_DEPTH_FAIL => enum_value(state.back.pass_depth_fail),
+        GL_STENCIL_BACK_PASS_DEPTH_PASS => enum_value(state.back.pass_depth_pass),
+        _ => None,
+    }
+}
+
+#[inline]
+fn validate_face(face: GLenum) -> bool {
+    context::validate_invalid_enum(face, &[GL_FRONT, GL_BACK, GL_FRONT_AND_BACK], "invalid face")
+}
+
+#[no_mangle]
+pub extern "C" fn glDepthFunc(func: GLenum) {
+    info!("glDepthFunc(func = {})", func);
+
+    if !context::validate_invalid_enum(func, &COMPARISON_FUNCTIONS, "invalid depth function") {
+        return;
+    }
+    HUB.depth_stencil_state.lock().depth_func = func;
+    pipeline::mark_dirty(DIRTY_DEPTH_STENCIL);
+}
+
+#[no_mangle]
+pub extern "C" fn glDepthMask(flag: GLboolean) {
+    info!("glDepthMask(flag = {})", flag);
+
+    HUB.depth_stencil_state.lock().depth_mask = flag != GL_FALSE;
+    pipeline::mark_dirty(DIRTY_DEPTH_STENCIL);
+}
+
+#[no_mangle]
+pub extern "C" fn glStencilFunc(func: GLenum, ref_: GLint, mask: GLuint) {
+    info!("glStencilFunc(func = {}, ref = {}, mask = {})", func, ref_, mask);
+
+    glStencilFuncSeparate(GL_FRONT_AND_BACK, func, ref_, mask);
+}
+
+#[no_mangle]
+pub extern "C" fn glStencilFuncSeparate(face: GLenum, func: GLenum, ref_: GLint, mask: GLuint) {
+    info!(
+        "glStencilFuncSeparate(face = {}, func = {}, ref = {}, mask = {})",
+        face, func, ref_, mask
+    );
+
+    if !validate_face(face) || !context::validate_invalid_enum(func, &COMPARISON_FUNCTIONS, "invalid stencil function") {
+        return;
+    }
+    for state in HUB.depth_stencil_state.lock().faces_mut(face) {
+        state.func = func;
+        state.reference = ref_;
+        state.value_mask = mask;
+    }
+    pipeline::mark_dirty(DIRTY_DEPTH_STENCIL);
+}
+
+#[no_mangle]
+pub extern "C" fn glStencilMask(mask: GLuint) {
+    info!("glStencilMask(mask = {})", mask);
+
+    glStencilMaskSeparate(GL_FRONT_AND_BACK, mask);
+}
+
+#[no_mangle]
+pub extern "C" fn glStencilMaskSeparate(face: GLenum, mask: GLuint) {
+    info!("glStencilMaskSeparate(face = {}, mask = {})", face, mask);
+
+    if !validate_face(face) {
+        return;
+    }
+    for state in HUB.depth_stencil_state.lock().faces_mut(face) {
+        state.write_mask = mask;
+    }
+    pipeline::mark_dirty(DIRTY_DEPTH_STENCIL);
+}
+
+#[no_mangle]
+pub extern "C" fn glStencilOp(fail: GLenum, zfail: GLenum, zpass: GLenum) {
+    info!("glStencilOp(fail = {}, zfail = {}, zpass = {})", fail, zfail, zpass);
+
+    glStencilOpSeparate(GL_FRONT_AND_BACK, fail, zfail, zpass);
+}
+
+#[no_mangle]
+pub extern "C" fn glStencilOpSeparate(face: GLenum, fail: GLenum, zfail: GLenum, zpass: GLenum) {
+    info!(
+        "glStencilOpSeparate(face = {}, fail = {}, zfail = {}, zpass = {})",
+        face, fail, zfail, zpass
+    );
+
+    if !validate_face(face)
+        || [fail, zfail, zpass]
+            .iter()
+            .any(|&op| !context::validate_invalid_enum(op, &STENCIL_OPERATIONS, "invalid stencil operation"))
+    {
+        return;
+    }
+    for state in HUB.depth_stencil_state.lock().faces_mut(face) {
+        state.fail = fail;
+        state.pass_depth_fail = zfail;
+        state.pass_depth_pass = zpass;
+    }
+    pipeline::mark_dirty(DIRTY_DEPTH_STENCIL);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_stencil_desc() {
+        let mut state = DepthStencilState::default();
+        assert_eq!(state.depth_stencil_desc().depth, DepthTest::Off);
+        assert_eq!(state.stencil_reference(8), None);
+
+        state.is_depth_test_enabled = true;
+        state.depth_func = GL_LEQUAL;
+        state.depth_mask = false;
+        state.is_stencil_test_enabled = true;
+        state.back.pass_depth_pass = GL_INCR_WRAP;
+        state.back.reference = 3;
+        let desc = state.depth_stencil_desc();
+        assert_eq!(
+            desc.depth,
+            DepthTest::On {
+                fun: Comparison::LessEqual,
+                write: false
+            }
+        );
+        match desc.stencil {
+            StencilTest::On { front, back } => {
+                assert_eq!((front.op_pass, back.op_pass), (StencilOp::Keep, StencilOp::IncrementWrap));
+                assert_eq!(back.reference, State::Dynamic);
+            }
+            StencilTest::Off => unreachable!(),
+        }
+        assert_eq!(state.stencil_reference(8), Some([0, 3]));
+        // references are clamped to the values of the stencil buffer
+        state.front.reference = -1;
+        state.back.reference = 300;
+        assert_eq!(state.stencil_reference(8), Some([0, 255]));
+        assert_eq!(state.stencil_reference(0), Some([0, 0]));
+        state.back.reference = 3;
+
+        // the stencil reference is dynamic, changing it keeps the pipeline
+        let mut reference = state.clone();
+        reference.front.reference = 1;
+        assert_eq!(state, reference);
+    }
+
+    #[test]
+    fn test_depth_stencil_state() {
+        use gl_sys::{GL_STENCIL_BITS, GL_TRUE};
+        use utilities::{glDisable, glEnable, glGetIntegerv, glIsEnabled};
+
+        let _guard = context::TEST_LOCK.lock();
+        let mut stencil_bits = 0;
+        glGetIntegerv(GL_STENCIL_BITS, &mut stencil_bits);
+        let max_reference = (1 << stencil_bits) - 1;
+        glEnable(GL_STENCIL_TEST);
+        assert_eq!(glIsEnabled(GL_STENCIL_TEST), GL_TRUE);
+        glStencilFuncSeparate(GL_BACK, GL_EQUAL, 2, 0xf);
+        glStencilOp(GL_KEEP, GL_KEEP, GL_REPLACE);
+        let mut values = [0 as GLint; 3];
+        glGetIntegerv(GL_STENCIL_BACK_FUNC, &mut values[0]);
+        glGetIntegerv(GL_STENCIL_BACK_REF, &mut values[1]);
+        glGetIntegerv(GL_STENCIL_PASS_DEPTH_PASS, &mut values[2]);
+        assert_eq!(values, [GL_EQUAL as GLint, max_reference.min(2), GL_REPLACE as GLint]);
+        assert_eq!(HUB.depth_stencil_state.lock().stencil_reference(8), Some([0, 2]));
+
+        // the reference is reported clamped to the stencil buffer of the frame buffer
+        glStencilFuncSeparate(GL_BACK, GL_EQUAL, 0x1ff, 0xf);
+        glGetIntegerv(GL_STENCIL_BACK_REF, &mut values[1]);
+        assert_eq!(values[1], max_reference);
+        assert_eq!(HUB.depth_stencil_state.lock().back.reference, 0x1ff);
+
+        glDisable(GL_STENCIL_TEST);
+        glStencilFunc(GL_ALWAYS, 0, !0);
+        glStencilOp(GL_KEEP, GL_KEEP, GL_KEEP);
+        assert_eq!(*HUB.depth_stencil_state.lock(), DepthStencilState::default());
+    }
+}
@@ -0,0 +1,140 @@
+use context::HUB;
+use hal_registry;
+use texture::Texture;
+use {
+    HalBuffer, HalDescriptorPool, HalDescriptorSetLayout, HalFrameBuffer, HalGraphicsPipeline, HalImage, HalImageView, HalMemory,
+    HalPipelineLayout, HalRenderPass, HalSampler, HalShaderModule,
+};
+
+/// Hal object given up by the GL object owning it
+#[derive(Debug)]
+pub(crate) enum HalObject {
+    Buffer(HalBuffer),
+    Image(HalImage),
+    ImageView(HalImageView),
+    Sampler(HalSampler),
+    Memory(HalMemory),
+    ShaderModule(HalShaderModule),
+    GraphicsPipeline(HalGraphicsPipeline),
+    PipelineLayout(HalPipelineLayout),
+    DescriptorSetLayout(HalDescriptorSetLayout),
+    RenderPass(HalRenderPass),
+    FrameBuffer(HalFrameBuffer),
+    DescriptorPool(HalDescriptorPool),
+}
+
+/// Hal objects waiting for the last submission which used them to complete
+#[derive(Debug, Default)]
+pub(crate) struct DestructionQueue {
+    pending: Vec<(u64, HalObject)>,
+}
+
+impl DestructionQueue {
+    /// Destroy `object` once submission `submission` completed
+    #[inline]
+    pub fn push(&mut self, submission: u64, object: HalObject) {
+        self.pending.push((submission, object));
+    }
+
+    /// Destroy the objects whose submission completed, in the order they were pushed
+    pub fn collect(&mut self, completed_submission: u64) {
+        let (completed, pending) = self
+            .pending
+            .drain(..)
+            .partition::<Vec<_>, _>(|&(submission, _)| submission <= completed_submission);
+        self.pending = pending;
+        for (_, object) in completed {
+            hal_registry::hal_object_destroy(object);
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Submission the commands being recorded will be part of, objects they use are tagged with it
+pub(crate) fn recording_submission() -> u64 {
+    HUB.command_buffer_manager.lock().next_submission()
+}
+
+/// Destroy `objects` once submission `last_use`, the last one which used them, completed
+pub(crate) fn release(objects: Vec<HalObject>, last_use: u64) {
+    if objects.is_empty() {
+        return;
+    }
+
+    if last_use <= HUB.command_buffer_manager.lock().completed_submission() {
+        objects.into_iter().for_each(hal_registry::hal_object_destroy);
+        return;
+    }
+
+    let mut destruction_queue = HUB.destruction_queue.lock();
+    for object in objects {
+        trace!("destroy {:?} after submission {}", object, last_use);
+        destruction_queue.push(last_use, object);
+    }
+}
+
+/// Release the hal objects of `texture`
+pub(crate) fn release_texture(texture: &mut Texture) {
+    let last_use = texture.last_use;
+    release(texture_objects(texture), last_use);
+}
+
+/// Take the hal objects of `texture`, views before images and images before their memory
+fn texture_objects(texture: &mut Texture) -> Vec<HalObject> {
+    let mut objects = Vec::new();
+    objects.extend(texture.sampler.take().map(HalObject::Sampler));
+    objects.extend(texture.image_view.take().map(HalObject::ImageView));
+    objects.extend(texture.image.take().map(HalObject::Image));
+    objects.extend(texture.memory.take().map(HalObject::Memory));
+    objects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use back;
+    use command_buffer::Command;
+    use context::TEST_LOCK;
+    use rendering::{glFinish, glFlush};
+
+    #[test]
+    fn test_destruction_queue() {
+        let mut queue = DestructionQueue::default();
+        queue.push(2, HalObject::Memory(back::R));
+        queue.push(1, HalObject::Buffer(back::R));
+        queue.push(2, HalObject::Image(back::R));
+        queue.collect(0);
+        assert_eq!(queue.len(), 3);
+        // objects are tagged with their own last submission, not in submission order
+        queue.collect(1);
+        assert_eq!(queue.len(), 2);
+        queue.collect(3);
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn test_release() {
+        let _guard = TEST_LOCK.lock();
+        glFinish();
+        assert_eq!(HUB.destruction_queue.lock().len(), 0);
+
+        // objects used by the recording command buffer wait for its submission, others are destroyed right away
+        HUB.command_buffer.lock().record(Command::EndRenderPass);
+        let submission = recording_submission();
+        release(vec![HalObject::Buffer(back::R)], submission);
+        release(vec![HalObject::Buffer(back::R)], submission - 1);
+        assert_eq!(HUB.destruction_queue.lock().len(), 1);
+        glFlush();
+
+        // glFinish collects objects of a submission in flight with nothing left to record
+        release(vec![HalObject::Buffer(back::R)], submission);
+        let completed_submission = HUB.command_buffer_manager.lock().completed_submission();
+        assert_eq!(HUB.destruction_queue.lock().len() == 0, completed_submission >= submission);
+        glFinish();
+        assert_eq!(HUB.destruction_queue.lock().len(), 0);
+    }
+}
@@ -1,2 +1,380 @@
-#[derive(Debug, Default)]
-pub struct FragmentState {}
+#![allow(non_snake_case)]
+
+use std::hash::{Hash, Hasher};
+
+use hal::pso::{BlendOp, BlendState, ColorBlendDesc, ColorMask, ColorValue, Factor};
+
+use gl_sys::{
+    GLboolean, GLclampf, GLenum, GLint, GL_BLEND, GL_BLEND_COLOR, GL_BLEND_DST_ALPHA, GL_BLEND_DST_RGB, GL_BLEND_EQUATION_ALPHA,
+    GL_BLEND_EQUATION_RGB, GL_BLEND_SRC_ALPHA, GL_BLEND_SRC_RGB, GL_COLOR_WRITEMASK, GL_CONSTANT_ALPHA, GL_CONSTANT_COLOR,
+    GL_DITHER, GL_DST_ALPHA, GL_DST_COLOR, GL_FALSE, GL_FUNC_ADD, GL_FUNC_REVERSE_SUBTRACT, GL_FUNC_SUBTRACT, GL_MAX_EXT,
+    GL_MIN_EXT, GL_ONE, GL_ONE_MINUS_CONSTANT_ALPHA, GL_ONE_MINUS_CONSTANT_COLOR, GL_ONE_MINUS_DST_ALPHA, GL_ONE_MINUS_DST_COLOR,
+    GL_ONE_MINUS_SRC_ALPHA, GL_ONE_MINUS_SRC_COLOR, GL_SAMPLE_ALPHA_TO_COVERAGE, GL_SAMPLE_COVERAGE, GL_SAMPLE_COVERAGE_INVERT,
+    GL_SAMPLE_COVERAGE_VALUE, GL_SRC_ALPHA, GL_SRC_ALPHA_SATURATE, GL_SRC_COLOR, GL_ZERO,
+};
+
+use context::{self, HUB};
+use pipeline::{self, DIRTY_FRAGMENT};
+use utilities::{clamp_to_unit_range, StateValue};
+
+/// Factors valid as destination factors, source factors may also be `GL_SRC_ALPHA_SATURATE`
+const DST_FACTORS: [GLenum; 14] = [
+    GL_ZERO,
+    GL_ONE,
+    GL_SRC_COLOR,
+    GL_ONE_MINUS_SRC_COLOR,
+    GL_DST_COLOR,
+    GL_ONE_MINUS_DST_COLOR,
+    GL_SRC_ALPHA,
+    GL_ONE_MINUS_SRC_ALPHA,
+    GL_DST_ALPHA,
+    GL_ONE_MINUS_DST_ALPHA,
+    GL_CONSTANT_COLOR,
+    GL_ONE_MINUS_CONSTANT_COLOR,
+    GL_CONSTANT_ALPHA,
+    GL_ONE_MINUS_CONSTANT_ALPHA,
+];
+/// Equations of OpenGL ES 2.0 and `GL_EXT_blend_minmax`
+const EQUATIONS: [GLenum; 5] = [
+    GL_FUNC_ADD,
+    GL_FUNC_SUBTRACT,
+    GL_FUNC_REVERSE_SUBTRACT,
+    GL_MIN_EXT,
+    GL_MAX_EXT,
+];
+
+/// Blending, color mask and multisample fragment operations. The blend color is dynamic state, dithering is left to
+/// the backend and sample coverage has no effect on the single sampled frame buffers, none of them take part in
+/// pipeline lookups.
+#[derive(Clone, Debug)]
+pub struct FragmentState {
+    pub(crate) is_blend_enabled: bool,
+    pub(crate) src_rgb: GLenum,
+    pub(crate) dst_rgb: GLenum,
+    pub(crate) src_alpha: GLenum,
+    pub(crate) dst_alpha: GLenum,
+    pub(crate) equation_rgb: GLenum,
+    pub(crate) equation_alpha: GLenum,
+    pub(crate) blend_color: [GLclampf; 4],
+    pub(crate) color_mask: [bool; 4],
+    pub(crate) is_dither_enabled: bool,
+    pub(crate) is_sample_alpha_to_coverage_enabled: bool,
+    pub(crate) is_sample_coverage_enabled: bool,
+    pub(crate) sample_coverage_value: GLclampf,
+    pub(crate) sample_coverage_invert: bool,
+}
+
+impl Default for FragmentState {
+    fn default() -> Self {
+        Self {
+            is_blend_enabled: false,
+            src_rgb: GL_ONE,
+            dst_rgb: GL_ZERO,
+            src_alpha: GL_ONE,
+            dst_alpha: GL_ZERO,
+            equation_rgb: GL_FUNC_ADD,
+            equation_alpha: GL_FUNC_ADD,
+            blend_color: [0.0; 4],
+            color_mask: [true; 4],
+            is_dither_enabled: true,
+            is_sample_alpha_to_coverage_enabled: false,
+            is_sample_coverage_enabled: false,
+            sample_coverage_value: 1.0,
+            sample_coverage_invert: false,
+        }
+    }
+}
+
+impl PartialEq for FragmentState {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_blend_enabled == other.is_blend_enabled
+            && self.src_rgb == other.src_rgb
+            && self.dst_rgb == other.dst_rgb
+            && self.src_alpha == other.src_alpha
+            && self.dst_alpha == other.dst_alpha
+            && self.equation_rgb == other.equation_rgb
+            && self.equation_alpha == other.equation_alpha
+            && self.color_mask == other.color_mask
+    }
+}
+
+impl Eq for FragmentState {}
+
+impl Hash for FragmentState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.is_blend_enabled.hash(state);
+        self.src_rgb.hash(state);
+        self.dst_rgb.hash(state);
+        self.src_alpha.hash(state);
+        self.dst_alpha.hash(state);
+        self.equation_rgb.hash(state);
+        self.equation_alpha.hash(state);
+        self.color_mask.hash(state);
+    }
+}
+
+impl FragmentState {
+    /// Blend description of the color attachment of a pipeline
+    pub(crate) fn color_blend_desc(&self) -> ColorBlendDesc {
+        let channels = [ColorMask::RED, ColorMask::GREEN, ColorMask::BLUE, ColorMask::ALPHA];
+        let mask = channels
+            .iter()
+            .zip(&self.color_mask)
+            .filter(|&(_, &is_written)| is_written)
+            .fold(ColorMask::NONE, |mask, (&channel, _)| mask | channel);
+
+        let blend = if self.is_blend_enabled {
+            BlendState::On {
+                color: blend_op(self.equation_rgb, self.src_rgb, self.dst_rgb),
+                alpha: blend_op(self.equation_alpha, self.src_alpha, self.dst_alpha),
+            }
+        } else {
+            BlendState::Off
+        };
+        ColorBlendDesc(mask, blend)
+    }
+
+    /// Value of the dynamic blend constants, `None` unless an enabled blend function reads them
+    pub(crate) fn blend_constants(&self) -> Option<ColorValue> {
+        let is_constant = |factor| {
+            factor == GL_CONSTANT_COLOR
+                || factor == GL_ONE_MINUS_CONSTANT_COLOR
+                || factor == GL_CONSTANT_ALPHA
+                || factor == GL_ONE_MINUS_CONSTANT_ALPHA
+        };
+        let factors = [self.src_rgb, self.dst_rgb, self.src_alpha, self.dst_alpha];
+        if self.is_blend_enabled && factors.iter().any(|&factor| is_constant(factor)) {
+            Some(self.blend_color)
+        } else {
+            None
+        }
+    }
+}
+
+fn factor(factor: GLenum) -> Factor {
+    match factor {
+        GL_ZERO => Factor::Zero,
+        GL_SRC_COLOR => Factor::SrcColor,
+        GL_ONE_MINUS_SRC_COLOR => Factor::OneMinusSrcColor,
+        GL_DST_COLOR => Factor::DstColor,
+        GL_ONE_MINUS_DST_COLOR => Factor::OneMinusDstColor,
+        GL_SRC_ALPHA => Factor::SrcAlpha,
+        GL_ONE_MINUS_SRC_ALPHA => Factor::OneMinusSrcAlpha,
+        GL_DST_ALPHA => Factor::DstAlpha,
+        GL_ONE_MINUS_DST_ALPHA => Factor::OneMinusDstAlpha,
+        GL_CONSTANT_COLOR => Factor::ConstColor,
+        GL_ONE_MINUS_CONSTANT_COLOR => Factor::OneMinusConstColor,
+        GL_CONSTANT_ALPHA => Factor::ConstAlpha,
+        GL_ONE_MINUS_CONSTANT_ALPHA => Factor::OneMinusConstAlpha,
+        GL_SRC_ALPHA_SATURATE => Factor::SrcAlphaSaturate,
+        _ => Factor::One,
+    }
+}
+
+/// Min and max ignore the factors
+fn blend_op(equation: GLenum, src: GLenum, dst: GLenum) -> BlendOp {
+    let (src, dst) = (factor(src), factor(dst));
+    match equation {
+        GL_FUNC_SUBTRACT => BlendOp::Sub { src, dst },
+        GL_FUNC_REVERSE_SUBTRACT => BlendOp::RevSub { src, dst },
+        GL_MIN_EXT => BlendOp::Min,
+        GL_MAX_EXT => BlendOp::Max,
+        _ => BlendOp::Add { src, dst },
+    }
+}
+
+pub(crate) fn set_capability(cap: GLenum, is_enabled: bool) {
+    let mut state = HUB.fragment_state.lock();
+    match cap {
+        GL_BLEND => state.is_blend_enabled = is_enabled,
+        GL_DITHER => state.is_dither_enabled = is_enabled,
+        GL_SAMPLE_ALPHA_TO_COVERAGE => state.is_sample_alpha_to_coverage_enabled = is_enabled,
+        GL_SAMPLE_COVERAGE => state.is_sample_coverage_enabled = is_enabled,
+        _ => unreachable!(),
+    }
+    if cap == GL_BLEND {
+        drop(state);
+        pipeline::mark_dirty(DIRTY_FRAGMENT);
+    }
+}
+
+pub(crate) fn is_capability_enabled(cap: GLenum) -> bool {
+    let state = HUB.fragment_state.lock();
+    match cap {
+        GL_BLEND => state.is_blend_enabled,
+        GL_DITHER => state.is_dither_enabled,
+        GL_SAMPLE_ALPHA_TO_COVERAGE => state.is_sample_alpha_to_coverage_enabled,
+        GL_SAMPLE_COVERAGE => state.is_sample_coverage_enabled,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn query_state(pname: GLenum) -> Option<StateValue> {
+    let state = HUB.fragment_state.lock();
+    let enum_value = |value: GLenum| Some(StateValue::Integers(vec![value as GLint]));
+    match pname {
+        GL_BLEND_SRC_RGB => enum_value(state.src_rgb),
+        GL_BLEND_DST_RGB => enum_value(state.dst_rgb),
+        GL_BLEND_SRC_ALPHA => enum_value(state.src_alpha),
+        GL_BLEND_DST_ALPHA => enum_value(state.dst_alpha),
+        GL_BLEND_EQUATION_RGB => enum_value(state.equation_rgb),
+        GL_BLEND_EQUATION_ALPHA => enum_value(state.equation_alpha),
+        GL_BLEND_COLOR => Some(StateValue::NormalizedFloats(state.blend_color.to_vec())),
+        GL_COLOR_WRITEMASK => Some(StateValue::Booleans(state.color_mask.to_vec())),
+        GL_SAMPLE_COVERAGE_VALUE => Some(StateValue::Floats(vec![state.sample_coverage_value])),
+        GL_SAMPLE_COVERAGE_INVERT => Some(StateValue::Booleans(vec![state.sample_coverage_invert])),
+        _ => None,
+    }
+}
+
+#[inline]
+fn validate_factors(src: GLenum, dst: GLenum) -> bool {
+    let is_valid_src = src == GL_SRC_ALPHA_SATURATE || context::validate_invalid_enum(src, &DST_FACTORS, "invalid source factor");
+    is_valid_src && context::validate_invalid_enum(dst, &DST_FACTORS, "invalid destination factor")
+}
+
+#[no_mangle]
+pub extern "C" fn glBlendColor(red: GLclampf, green: GLclampf, blue: GLclampf, alpha: GLclampf) {
+    info!(
+        "glBlendColor(red = {}, green = {}, blue = {}, alpha = {})",
+        red, green, blue, alpha
+    );
+
+    HUB.fragment_state.lock().blend_color = [
+        clamp_to_unit_range(red),
+        clamp_to_unit_range(green),
+        clamp_to_unit_range(blue),
+        clamp_to_unit_range(alpha),
+    ];
+}
+
+#[no_mangle]
+pub extern "C" fn glBlendEquation(mode: GLenum) {
+    info!("glBlendEquation(mode = {})", mode);
+
+    glBlendEquationSeparate(mode, mode);
+}
+
+#[no_mangle]
+pub extern "C" fn glBlendEquationSeparate(mode_rgb: GLenum, mode_alpha: GLenum) {
+    info!(
+        "glBlendEquationSeparate(mode_rgb = {}, mode_alpha = {})",
+        mode_rgb, mode_alpha
+    );
+
+    if !context::validate_invalid_enum(mode_rgb, &EQUATIONS, "invalid blend equation")
+        || !context::validate_invalid_enum(mode_alpha, &EQUATIONS, "invalid blend equation")
+    {
+        return;
+    }
+    let mut state = HUB.fragment_state.lock();
+    state.equation_rgb = mode_rgb;
+    state.equation_alpha = mode_alpha;
+    drop(state);
+    pipeline::mark_dirty(DIRTY_FRAGMENT);
+}
+
+#[no_mangle]
+pub extern "C" fn glBlendFunc(sfactor: GLenum, dfactor: GLenum) {
+    info!("glBlendFunc(sfactor = {}, dfactor = {})", sfactor, dfactor);
+
+    glBlendFuncSeparate(sfactor, dfactor, sfactor, dfactor);
+}
+
+#[no_mangle]
+pub extern "C" fn glBlendFuncSeparate(src_rgb: GLenum, dst_rgb: GLenum, src_alpha: GLenum, dst_alpha: GLenum) {
+    info!(
+        "glBlendFuncSeparate(src_rgb = {}, dst_rgb = {}, src_alpha = {}, dst_alpha = {})",
+        src_rgb, dst_rgb, src_alpha, dst_alpha
+    );
+
+    if !validate_factors(src_rgb, dst_rgb) || !validate_factors(src_alpha, dst_alpha) {
+        return;
+    }
+    let mut state = HUB.fragment_state.lock();
+    state.src_rgb = src_rgb;
+    state.dst_rgb = dst_rgb;
+    state.src_alpha = src_alpha;
+    state.dst_alpha = dst_alpha;
+    drop(state);
+    pipeline::mark_dirty(DIRTY_FRAGMENT);
+}
+
+#[no_mangle]
+pub extern "C" fn glColorMask(red: GLboolean, green: GLboolean, blue: GLboolean, alpha: GLboolean) {
+    info!(
+        "glColorMask(red = {}, green = {}, blue = {}, alpha = {})",
+        red, green, blue, alpha
+    );
+
+    HUB.fragment_state.lock().color_mask = [red != GL_FALSE, green != GL_FALSE, blue != GL_FALSE, alpha != GL_FALSE];
+    pipeline::mark_dirty(DIRTY_FRAGMENT);
+}
+
+#[no_mangle]
+pub extern "C" fn glSampleCoverage(value: GLclampf, invert: GLboolean) {
+    info!("glSampleCoverage(value = {}, invert = {})", value, invert);
+
+    let mut state = HUB.fragment_state.lock();
+    state.sample_coverage_value = clamp_to_unit_range(value);
+    state.sample_coverage_invert = invert != GL_FALSE;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::GL_TRUE;
+    use utilities::{glDisable, glEnable, glGetIntegerv};
+
+    #[test]
+    fn test_color_blend_desc() {
+        let mut state = FragmentState::default();
+        assert_eq!(state.color_blend_desc(), ColorBlendDesc(ColorMask::ALL, BlendState::Off));
+
+        state.is_blend_enabled = true;
+        state.src_rgb = GL_SRC_ALPHA;
+        state.dst_rgb = GL_ONE_MINUS_CONSTANT_ALPHA;
+        state.equation_alpha = GL_MAX_EXT;
+        state.color_mask = [true, true, true, false];
+        state.blend_color = [0.0, 0.0, 0.0, 0.5];
+        assert_eq!(
+            state.color_blend_desc(),
+            ColorBlendDesc(
+                ColorMask::COLOR,
+                BlendState::On {
+                    color: BlendOp::Add {
+                        src: Factor::SrcAlpha,
+                        dst: Factor::OneMinusConstAlpha
+                    },
+                    alpha: BlendOp::Max,
+                }
+            )
+        );
+        assert_eq!(state.blend_constants(), Some([0.0, 0.0, 0.0, 0.5]));
+
+        // the blend color is dynamic, changing it keeps the pipeline
+        let mut blend_color = state.clone();
+        blend_color.blend_color = [1.0; 4];
+        assert_eq!(state, blend_color);
+    }
+
+    #[test]
+    fn test_blend_state() {
+        let _guard = context::TEST_LOCK.lock();
+        glEnable(GL_BLEND);
+        glBlendFunc(GL_SRC_ALPHA, GL_ONE_MINUS_SRC_ALPHA);
+        glBlendEquationSeparate(GL_FUNC_ADD, GL_MIN_EXT);
+        glColorMask(GL_TRUE, GL_TRUE, GL_TRUE, GL_FALSE);
+        let mut values = [0 as GLint; 2];
+        glGetIntegerv(GL_BLEND_DST_ALPHA, &mut values[0]);
+        glGetIntegerv(GL_BLEND_EQUATION_ALPHA, &mut values[1]);
+        assert_eq!(values, [GL_ONE_MINUS_SRC_ALPHA as GLint, GL_MIN_EXT as GLint]);
+        assert_eq!(HUB.fragment_state.lock().color_mask, [true, true, true, false]);
+
+        glDisable(GL_BLEND);
+        glBlendFunc(GL_ONE, GL_ZERO);
+        glBlendEquation(GL_FUNC_ADD);
+        glColorMask(GL_TRUE, GL_TRUE, GL_TRUE, GL_TRUE);
+        assert_eq!(*HUB.fragment_state.lock(), FragmentState::default());
+    }
+}
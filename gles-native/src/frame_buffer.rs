@@ -1,16 +1,29 @@
 use std::ptr;
 
 use hal;
+use hal::format::{Format, ImageFeature};
+use hal::image;
+use hal::pass::AttachmentLoadOp;
 
 use gl_sys::{
-    GLboolean, GLenum, GLint, GLsizei, GLuint, GL_COLOR_ATTACHMENT0, GL_DEPTH_ATTACHMENT, GL_FALSE,
-    GL_FRAMEBUFFER_ATTACHMENT_OBJECT_NAME, GL_FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE,
+    GLbitfield, GLboolean, GLenum, GLint, GLsizei, GLuint, GL_COLOR_ATTACHMENT0, GL_DEPTH24_STENCIL8_OES, GL_DEPTH_ATTACHMENT,
+    GL_DEPTH_STENCIL_ATTACHMENT, GL_FALSE, GL_FRAMEBUFFER_ATTACHMENT_OBJECT_NAME, GL_FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE,
     GL_FRAMEBUFFER_ATTACHMENT_TEXTURE_CUBE_MAP_FACE, GL_FRAMEBUFFER_ATTACHMENT_TEXTURE_LEVEL, GL_FRAMEBUFFER_COMPLETE,
     GL_FRAMEBUFFER_DEFAULT, GL_NONE, GL_RENDERBUFFER, GL_STENCIL_ATTACHMENT, GL_TEXTURE, GL_TEXTURE_2D, GL_TEXTURE_CUBE_MAP,
     GL_TEXTURE_CUBE_MAP_NEGATIVE_Z, GL_TEXTURE_CUBE_MAP_POSITIVE_X, GL_TRUE,
 };
 
+use active_object;
+use command_buffer::{Command, CommandBuffer};
 use context::{self, HUB};
+use destruction_queue;
+use hal_registry;
+use pipeline::RenderPassKey;
+use render_buffer::{self, FormatBits};
+use rendering::ClearValues;
+use texture::Texture;
+use utilities::record_error;
+use {HalFrameBuffer, HalRenderPass};
 
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
 pub(crate) struct Rect {
@@ -24,8 +37,17 @@ impl Rect {
     pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
         Self { x, y, width, height }
     }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && self.x + self.width >= other.x + other.width
+            && self.y + self.height >= other.y + other.height
+    }
 }
 
+/// `Idle`: no render pass, `Clear`: render pass pending with clear load operations,
+/// `ClearDraw`: render pass begun with clear load operations, `Draw`: render pass begun with load operations
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum State {
     Idle,
@@ -40,22 +62,360 @@ impl Default for State {
     }
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub(crate) struct LoadOps {
+    pub color: AttachmentLoadOp,
+    pub depth: AttachmentLoadOp,
+    pub stencil: AttachmentLoadOp,
+}
+
+impl Default for LoadOps {
+    fn default() -> Self {
+        Self {
+            color: AttachmentLoadOp::Load,
+            depth: AttachmentLoadOp::Load,
+            stencil: AttachmentLoadOp::Load,
+        }
+    }
+}
+
+/// `glClear` with the state captured at call time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ClearRequest {
+    pub mask: GLbitfield,
+    pub clear_values: ClearValues,
+    pub rect: Option<Rect>,
+    pub color_mask: [bool; 4],
+}
+
+/// `type_` is one of `GL_NONE`, `GL_TEXTURE` and `GL_RENDERBUFFER`
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Attachment {
+    pub type_: GLenum,
+    pub name: GLuint,
+    pub level: GLint,
+    pub cube_map_face: GLenum,
+}
+
+impl Attachment {
+    #[inline]
+    pub fn is_attached(&self, type_: GLenum, name: GLuint) -> bool {
+        self.type_ == type_ && self.name == name
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct FrameBuffer {
+    pub name: GLuint,
     pub dimensions: Rect,
     pub target: GLenum,
     pub write_buffer_index: u32,
     state: State,
+    load_ops: LoadOps,
+    clear_values: ClearValues,
     pub is_updated: bool,
     pub is_size_updated: bool,
     pub is_system_frame_buffer: bool,
     pub is_bound_to_texture: bool,
-    //    attachment_colors: Vec<Attachment>,
-    //    pub attachment_depth: Attachment,
-    //    pub attachment_stencil: Attachment,
+    pub attachment_color: Attachment,
+    pub attachment_depth: Attachment,
+    pub attachment_stencil: Attachment,
+    /// Combined image replacing separately attached depth and stencil images
+    pub merged_depth_stencil: Option<Texture>,
     //    pub command_buffer_manager: *mut CommandBufferManager,
 }
 
+impl FrameBuffer {
+    pub fn attachment_mut(&mut self, attachment: GLenum) -> Option<&mut Attachment> {
+        match attachment {
+            GL_COLOR_ATTACHMENT0 => Some(&mut self.attachment_color),
+            GL_DEPTH_ATTACHMENT => Some(&mut self.attachment_depth),
+            GL_STENCIL_ATTACHMENT => Some(&mut self.attachment_stencil),
+            _ => None,
+        }
+    }
+
+    pub fn has_attachment(&self, type_: GLenum, name: GLuint) -> bool {
+        self.attachment_color.is_attached(type_, name)
+            || self.attachment_depth.is_attached(type_, name)
+            || self.attachment_stencil.is_attached(type_, name)
+    }
+
+    #[inline]
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    #[inline]
+    pub fn load_ops(&self) -> LoadOps {
+        self.load_ops
+    }
+
+    /// Bits of the color, depth and stencil components drawn to, as reported by `GL_RED_BITS` and friends
+    pub fn format_bits(&self) -> FormatBits {
+        if self.is_system_frame_buffer {
+            return FormatBits {
+                red: 8,
+                green: 8,
+                blue: 8,
+                alpha: 8,
+                depth: 24,
+                stencil: 8,
+            };
+        }
+
+        let color = attachment_bits(&self.attachment_color);
+        FormatBits {
+            depth: attachment_bits(&self.attachment_depth).depth,
+            stencil: attachment_bits(&self.attachment_stencil).stencil,
+            ..color
+        }
+    }
+
+    #[inline]
+    pub fn stencil_bits(&self) -> GLint {
+        self.format_bits().stencil
+    }
+
+    /// Attachment formats pipelines drawing into this frame buffer have to be compatible with
+    pub fn render_pass_key(&self) -> RenderPassKey {
+        let depth_stencil = match self.merged_depth_stencil {
+            Some(ref texture) => texture.hal_format,
+            None => attachment_format(&self.attachment_depth).or_else(|| attachment_format(&self.attachment_stencil)),
+        };
+        RenderPassKey {
+            color: attachment_format(&self.attachment_color),
+            depth_stencil,
+        }
+    }
+
+    /// Create a hal frame buffer of the attachment images for `render_pass`, which has to be compatible with the
+    /// render pass of `render_pass_key`. None if an image has no view or the frame buffer could not be created.
+    pub fn create_hal_frame_buffer(&self, render_pass: &HalRenderPass) -> Option<HalFrameBuffer> {
+        let texture_pool = HUB.texture_pool.lock();
+        let render_buffer_pool = HUB.render_buffer_pool.lock();
+        let image = |attachment: &Attachment| {
+            let image = match attachment.type_ {
+                GL_RENDERBUFFER => render_buffer_pool
+                    .get_object(attachment.name)
+                    .and_then(|object| object.texture.as_ref()),
+                GL_TEXTURE => texture_pool.get_object(attachment.name),
+                _ => None,
+            };
+            image.filter(|image| image.hal_format.is_some())
+        };
+
+        // the same images in the same order as the attachments of the render pass
+        let depth_stencil = match self.merged_depth_stencil {
+            Some(ref texture) => texture.hal_format.map(|_| texture),
+            None => image(&self.attachment_depth).or_else(|| image(&self.attachment_stencil)),
+        };
+        let mut views = Vec::new();
+        for image in image(&self.attachment_color).into_iter().chain(depth_stencil) {
+            match image.image_view {
+                Some(ref view) => views.push(view),
+                None => {
+                    error!("attachment of frame buffer {} has no image view", self.name);
+                    return None;
+                }
+            }
+        }
+        let extent = image::Extent {
+            width: self.dimensions.width.max(1) as image::Size,
+            height: self.dimensions.height.max(1) as image::Size,
+            depth: 1,
+        };
+        hal_registry::hal_frame_buffer_create(render_pass, &views, extent)
+    }
+
+    /// Fold `request` into the load operations while nothing has been drawn yet,
+    /// otherwise record it as an attachment clear within the render pass.
+    pub fn clear(&mut self, request: &ClearRequest, command_buffer: &mut CommandBuffer) {
+        match self.state {
+            State::Delete => warn!("clear frame buffer {} marked for deletion", self.name),
+            State::Idle | State::Clear if self.is_load_op_clear(request) => {
+                self.merge_clear(request);
+                self.state = State::Clear;
+            }
+            _ => {
+                self.begin_render_pass(command_buffer);
+                command_buffer.record(Command::ClearAttachments {
+                    mask: request.mask,
+                    clear_values: request.clear_values,
+                    rect: request.rect,
+                    color_mask: request.color_mask,
+                });
+            }
+        }
+    }
+
+    /// Begin the render pass unless it is already recording.
+    pub fn begin_render_pass(&mut self, command_buffer: &mut CommandBuffer) {
+        if self.is_updated && !self.is_system_frame_buffer {
+            self.update_attachments();
+            self.is_updated = false;
+        }
+
+        self.state = match self.state {
+            State::Idle => State::Draw,
+            State::Clear => State::ClearDraw,
+            State::ClearDraw | State::Draw | State::Delete => return,
+        };
+
+        command_buffer.record(Command::BeginRenderPass {
+            frame_buffer: self.name,
+            load_ops: self.load_ops,
+            clear_values: self.clear_values,
+        });
+
+        let submission = destruction_queue::recording_submission();
+        for attachment in &[self.attachment_color, self.attachment_depth, self.attachment_stencil] {
+            with_attachment_image(attachment, |image| image.last_use = submission);
+        }
+        if let Some(ref mut texture) = self.merged_depth_stencil {
+            texture.last_use = submission;
+        }
+    }
+
+    /// End the render pass, a pending clear is still executed by an empty render pass.
+    pub fn end_render_pass(&mut self, command_buffer: &mut CommandBuffer) {
+        match self.state {
+            State::Idle | State::Delete => return,
+            State::Clear => self.begin_render_pass(command_buffer),
+            State::ClearDraw | State::Draw => {}
+        }
+
+        command_buffer.record(Command::EndRenderPass);
+        self.state = State::Idle;
+        self.load_ops = LoadOps::default();
+    }
+
+    fn update_attachments(&mut self) {
+        let is_size_updated = self.is_size_updated;
+        self.is_size_updated = false;
+
+        if let Some(dimensions) = [self.attachment_color, self.attachment_depth, self.attachment_stencil]
+            .iter()
+            .filter_map(attachment_dimensions)
+            .next()
+        {
+            self.dimensions = dimensions;
+        }
+
+        self.update_merged_depth_stencil(is_size_updated);
+    }
+
+    /// Depth and stencil attached from different objects are rendered into one combined image, subpasses of hal
+    /// take a single depth stencil attachment.
+    fn update_merged_depth_stencil(&mut self, is_size_updated: bool) {
+        let is_separate = self.attachment_depth.type_ != GL_NONE
+            && self.attachment_stencil.type_ != GL_NONE
+            && self.attachment_depth != self.attachment_stencil;
+        if !is_separate {
+            self.release_merged_depth_stencil();
+            return;
+        }
+        if self.merged_depth_stencil.is_some() && !is_size_updated {
+            return;
+        }
+        self.release_merged_depth_stencil();
+
+        let dimensions = match attachment_dimensions(&self.attachment_depth) {
+            Some(dimensions) => dimensions,
+            None => {
+                warn!("depth attachment of frame buffer {} has no storage", self.name);
+                return;
+            }
+        };
+
+        let mut texture = Texture::default();
+        texture.internal_format = GL_DEPTH24_STENCIL8_OES;
+        texture.hal_format = render_buffer::choose_hal_format(GL_DEPTH24_STENCIL8_OES, ImageFeature::empty());
+        texture.dimensions = dimensions;
+        self.merged_depth_stencil = if texture.hal_format.is_some()
+            && hal_registry::hal_texture_create(&mut texture, image::Usage::DEPTH_STENCIL_ATTACHMENT)
+        {
+            debug!("merge depth and stencil attachments of frame buffer {}", self.name);
+            Some(texture)
+        } else {
+            error!("failed to merge depth and stencil attachments of frame buffer {}", self.name);
+            None
+        };
+    }
+
+    fn release_merged_depth_stencil(&mut self) {
+        if let Some(mut texture) = self.merged_depth_stencil.take() {
+            destruction_queue::release_texture(&mut texture);
+        }
+    }
+
+    fn is_load_op_clear(&self, request: &ClearRequest) -> bool {
+        use gl_sys::GL_COLOR_BUFFER_BIT;
+
+        let is_full_rect = match request.rect {
+            None => true,
+            Some(ref rect) => self.dimensions.width > 0 && self.dimensions.height > 0 && rect.contains(&self.dimensions),
+        };
+        let is_full_color_mask = request.mask & GL_COLOR_BUFFER_BIT == 0 || request.color_mask.iter().all(|&mask| mask);
+
+        is_full_rect && is_full_color_mask
+    }
+
+    fn merge_clear(&mut self, request: &ClearRequest) {
+        use gl_sys::{GL_COLOR_BUFFER_BIT, GL_DEPTH_BUFFER_BIT, GL_STENCIL_BUFFER_BIT};
+
+        if request.mask & GL_COLOR_BUFFER_BIT != 0 {
+            self.load_ops.color = AttachmentLoadOp::Clear;
+            self.clear_values.color = request.clear_values.color;
+        }
+        if request.mask & GL_DEPTH_BUFFER_BIT != 0 {
+            self.load_ops.depth = AttachmentLoadOp::Clear;
+            self.clear_values.depth = request.clear_values.depth;
+        }
+        if request.mask & GL_STENCIL_BUFFER_BIT != 0 {
+            self.load_ops.stencil = AttachmentLoadOp::Clear;
+            self.clear_values.stencil = request.clear_values.stencil;
+        }
+    }
+}
+
+/// Mark every frame buffer object which `name` of `type_` is attached to as updated
+pub(crate) fn mark_attached_frame_buffers(type_: GLenum, name: GLuint, is_size_updated: bool) {
+    let mut pool_guard = HUB.frame_buffer_pool.lock();
+    for (_, frame_buffer) in pool_guard.get_objects_mut() {
+        if frame_buffer.has_attachment(type_, name) {
+            frame_buffer.is_updated = true;
+            frame_buffer.is_size_updated |= is_size_updated;
+        }
+    }
+}
+
+/// Detach `name` of `type_` from the bound frame buffer object when it is deleted, other frame buffer objects keep
+/// their attachments
+/// Detach the image of object `name` from the bound frame buffer, returning the number of attachments it was
+/// detached from. Frame buffers which are not bound keep it attached (ES 2.0 §4.4.5).
+pub(crate) fn detach_from_active_frame_buffer(type_: GLenum, name: GLuint) -> u32 {
+    let mut active_object = HUB.active_frame_buffer.lock().clone();
+    if context::is_default_frame_buffer(active_object.name) {
+        return 0;
+    }
+
+    let frame_buffer = active_object::get_object_mut(&mut active_object).unwrap();
+    let mut count = 0;
+    for attachment in &mut [
+        &mut frame_buffer.attachment_color,
+        &mut frame_buffer.attachment_depth,
+        &mut frame_buffer.attachment_stencil,
+    ] {
+        if attachment.is_attached(type_, name) {
+            **attachment = Attachment::default();
+            frame_buffer.is_updated = true;
+            count += 1;
+        }
+    }
+    count
+}
+
 pub(crate) fn check_frame_buffer_status(object: &mut FrameBuffer) -> GLenum {
     use gl_sys::{GL_FRAMEBUFFER_INCOMPLETE_DIMENSIONS, GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT};
 
@@ -69,6 +429,150 @@ fn validate_frame_buffer_target(target: GLenum) -> bool {
     context::validate_invalid_enum(target, &[GL_FRAMEBUFFER], "invalid framebuffer target")
 }
 
+#[inline]
+fn validate_frame_buffer_attachment(attachment: GLenum) -> bool {
+    context::validate_invalid_enum(
+        attachment,
+        &[
+            GL_COLOR_ATTACHMENT0,
+            GL_DEPTH_ATTACHMENT,
+            GL_STENCIL_ATTACHMENT,
+            GL_DEPTH_STENCIL_ATTACHMENT,
+        ],
+        "invalid attachment",
+    )
+}
+
+/// Attach `value` to the bound frame buffer object, `GL_DEPTH_STENCIL_ATTACHMENT` attaches it as both depth and stencil
+fn attach(attachment: GLenum, value: Attachment) {
+    use gl_sys::GL_INVALID_OPERATION;
+
+    let mut active_object = HUB.active_frame_buffer.lock().clone();
+    if context::is_default_frame_buffer(active_object.name) {
+        error!("default frame buffer is bound");
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+
+    let frame_buffer = active_object::get_object_mut(&mut active_object).unwrap();
+    let slots = if attachment == GL_DEPTH_STENCIL_ATTACHMENT {
+        vec![&mut frame_buffer.attachment_depth, &mut frame_buffer.attachment_stencil]
+    } else {
+        vec![frame_buffer.attachment_mut(attachment).unwrap()]
+    };
+    for slot in slots {
+        // retain first, the image may already be attached to `slot`
+        retain_attachment(&value);
+        release_attachment(slot);
+        *slot = value;
+    }
+    frame_buffer.is_updated = true;
+}
+
+/// Count one more attachment referring to the image of `attachment`
+fn retain_attachment(attachment: &Attachment) {
+    match attachment.type_ {
+        GL_RENDERBUFFER => {
+            let mut pool_guard = HUB.render_buffer_pool.lock();
+            if pool_guard.has_object(attachment.name) {
+                pool_guard.get_object_mut(attachment.name).attach_count += 1;
+            }
+        }
+        GL_TEXTURE => {
+            let mut pool_guard = HUB.texture_pool.lock();
+            if pool_guard.has_object(attachment.name) {
+                pool_guard.get_object_mut(attachment.name).attach_count += 1;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Count one attachment less referring to the image of `attachment`, deleting its object once the last one is
+/// gone if it was marked for deletion
+fn release_attachment(attachment: &Attachment) {
+    match attachment.type_ {
+        GL_RENDERBUFFER => {
+            let mut pool_guard = HUB.render_buffer_pool.lock();
+            if !pool_guard.has_object(attachment.name) {
+                return;
+            }
+            let object = pool_guard.get_object_mut(attachment.name);
+            object.attach_count -= 1;
+            if object.deletion_mark && object.attach_count == 0 {
+                debug!("render buffer {} deleted after detaching", attachment.name);
+                if let Some(ref mut texture) = object.texture {
+                    destruction_queue::release_texture(texture);
+                }
+                pool_guard.deallocate(attachment.name);
+            }
+        }
+        GL_TEXTURE => {
+            let mut pool_guard = HUB.texture_pool.lock();
+            if !pool_guard.has_object(attachment.name) {
+                return;
+            }
+            let object = pool_guard.get_object_mut(attachment.name);
+            object.attach_count -= 1;
+            if object.deletion_mark && object.attach_count == 0 {
+                debug!("texture {} deleted after detaching", attachment.name);
+                destruction_queue::release_texture(object);
+                pool_guard.deallocate(attachment.name);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Call `f` with the image `attachment` refers to, `None` when it refers to no object with storage
+fn with_attachment_image<T, F>(attachment: &Attachment, f: F) -> Option<T>
+where
+    F: FnOnce(&mut Texture) -> T,
+{
+    match attachment.type_ {
+        GL_RENDERBUFFER => {
+            let mut pool_guard = HUB.render_buffer_pool.lock();
+            if !pool_guard.has_object(attachment.name) {
+                return None;
+            }
+            pool_guard.get_object_mut(attachment.name).texture.as_mut().map(f)
+        }
+        GL_TEXTURE => {
+            let mut pool_guard = HUB.texture_pool.lock();
+            if !pool_guard.has_object(attachment.name) {
+                return None;
+            }
+            Some(f(pool_guard.get_object_mut(attachment.name)))
+        }
+        _ => None,
+    }
+}
+
+/// Format of the image `attachment` refers to, `None` when it has no storage
+fn attachment_format(attachment: &Attachment) -> Option<Format> {
+    with_attachment_image(attachment, |image| image.hal_format).and_then(|format| format)
+}
+
+/// Component bits of the image `attachment` refers to, all 0 when it has no storage
+fn attachment_bits(attachment: &Attachment) -> FormatBits {
+    if attachment.type_ == GL_RENDERBUFFER {
+        let mut pool_guard = HUB.render_buffer_pool.lock();
+        if !pool_guard.has_object(attachment.name) {
+            return FormatBits::default();
+        }
+        return pool_guard.get_object_mut(attachment.name).format_bits();
+    }
+    match attachment_format(attachment) {
+        Some(format) => render_buffer::hal_format_bits(format),
+        None => FormatBits::default(),
+    }
+}
+
+/// Dimensions of the image `attachment` refers to, `None` when it has no storage
+fn attachment_dimensions(attachment: &Attachment) -> Option<Rect> {
+    with_attachment_image(attachment, |image| image.dimensions).filter(|dimensions| dimensions.width > 0 && dimensions.height > 0)
+}
+
 #[inline]
 fn validate_is_default_frame_buffer_active() -> bool {
     use gl_sys::GL_INVALID_OPERATION;
@@ -98,9 +602,17 @@ pub extern "C" fn glBindFramebuffer(target: GLenum, framebuffer: GLuint) {
             if object.target == GL_INVALID_VALUE {
                 object.target = target;
             }
+            object.name = object_name;
             object as *mut FrameBuffer
         },
         |object_ptr| {
+            let mut active_object = context::get_active_frame_buffer();
+            if active_object.ptr != object_ptr {
+                if let Some(frame_buffer) = active_object::get_object_mut(&mut active_object) {
+                    frame_buffer.end_render_pass(&mut HUB.command_buffer.lock());
+                }
+            }
+
             let mut object_guard = HUB.active_frame_buffer.lock();
             object_guard.name = framebuffer;
             object_guard.ptr = object_ptr;
@@ -132,7 +644,25 @@ pub extern "C" fn glCheckFramebufferStatus(target: GLenum) -> GLenum {
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn glDeleteFramebuffers(n: GLsizei, framebuffers: *const GLuint) {
+    use gl_sys::GL_FRAMEBUFFER;
+
     info!("glDeleteFramebuffers(n = {}, framebuffers = {:p})", n, framebuffers);
+
+    context::delete_objects(n, framebuffers, &HUB.frame_buffer_pool, |name, object| {
+        if HUB.active_frame_buffer.lock().name == name {
+            object.end_render_pass(&mut HUB.command_buffer.lock());
+        }
+        context::unbind_object(
+            &HUB.active_frame_buffer,
+            name,
+            context::get_default_frame_buffer(GL_FRAMEBUFFER),
+        );
+        for attachment in &[object.attachment_color, object.attachment_depth, object.attachment_stencil] {
+            release_attachment(attachment);
+        }
+        object.release_merged_depth_stencil();
+        true
+    });
 }
 
 #[no_mangle]
@@ -147,6 +677,34 @@ pub extern "C" fn glFramebufferRenderbuffer(
         "glFramebufferRenderbuffer(target = {:?}, attachment = {:?}, renderbuffertarget = {:?}, renderbuffer = {})",
         target, attachment, renderbuffertarget, renderbuffer
     );
+
+    use gl_sys::GL_INVALID_OPERATION;
+
+    if !validate_frame_buffer_target(target)
+        || !context::validate_invalid_enum(renderbuffertarget, &[GL_RENDERBUFFER], "invalid renderbuffertarget")
+        || !validate_frame_buffer_attachment(attachment)
+    {
+        return;
+    }
+
+    if renderbuffer != 0 && !HUB.render_buffer_pool.lock().has_object(renderbuffer) {
+        error!("renderbuffer {} is not an existing object", renderbuffer);
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+
+    attach(
+        attachment,
+        if renderbuffer == 0 {
+            Attachment::default()
+        } else {
+            Attachment {
+                type_: GL_RENDERBUFFER,
+                name: renderbuffer,
+                ..Attachment::default()
+            }
+        },
+    );
 }
 
 #[no_mangle]
@@ -156,6 +714,73 @@ pub extern "C" fn glFramebufferTexture2D(target: GLenum, attachment: GLenum, tex
         "glFramebufferTexture2D(target = {:?}, attachment = {:?}, textarget = {:?}, texture = {}, level = {})",
         target, attachment, textarget, texture, level
     );
+
+    use gl_sys::{
+        GL_INVALID_OPERATION, GL_INVALID_VALUE, GL_TEXTURE_CUBE_MAP_NEGATIVE_X, GL_TEXTURE_CUBE_MAP_NEGATIVE_Y,
+        GL_TEXTURE_CUBE_MAP_POSITIVE_Y, GL_TEXTURE_CUBE_MAP_POSITIVE_Z,
+    };
+
+    if !validate_frame_buffer_target(target)
+        || !validate_frame_buffer_attachment(attachment)
+        || !context::validate_invalid_enum(
+            textarget,
+            &[
+                GL_TEXTURE_2D,
+                GL_TEXTURE_CUBE_MAP_POSITIVE_X,
+                GL_TEXTURE_CUBE_MAP_NEGATIVE_X,
+                GL_TEXTURE_CUBE_MAP_POSITIVE_Y,
+                GL_TEXTURE_CUBE_MAP_NEGATIVE_Y,
+                GL_TEXTURE_CUBE_MAP_POSITIVE_Z,
+                GL_TEXTURE_CUBE_MAP_NEGATIVE_Z,
+            ],
+            "invalid textarget",
+        )
+    {
+        return;
+    }
+
+    if texture == 0 {
+        attach(attachment, Attachment::default());
+        return;
+    }
+
+    if level != 0 {
+        error!("invalid level: {}", level);
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+
+    let texture_target = {
+        let mut pool_guard = HUB.texture_pool.lock();
+        if !pool_guard.has_object(texture) {
+            error!("texture {} is not an existing object", texture);
+            record_error(GL_INVALID_OPERATION);
+            return;
+        }
+        pool_guard.get_object_mut(texture).target
+    };
+    let is_cube_map_face = textarget != GL_TEXTURE_2D;
+    if texture_target
+        != if is_cube_map_face {
+            GL_TEXTURE_CUBE_MAP
+        } else {
+            GL_TEXTURE_2D
+        }
+    {
+        error!("textarget {:?} does not match the target of texture {}", textarget, texture);
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+
+    attach(
+        attachment,
+        Attachment {
+            type_: GL_TEXTURE,
+            name: texture,
+            level,
+            cube_map_face: if is_cube_map_face { textarget } else { GL_NONE },
+        },
+    );
 }
 
 #[no_mangle]
@@ -193,4 +818,145 @@ mod tests {
     fn test_all_in_one() {
         &*HUB;
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_clear_to_load_ops() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.dimensions = Rect::new(0, 0, 64, 64);
+        let mut command_buffer = CommandBuffer::default();
+
+        let mut request = ClearRequest {
+            mask: GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT,
+            clear_values: ClearValues::default(),
+            rect: None,
+            color_mask: [true; 4],
+        };
+        request.clear_values.color = [1.0, 0.0, 0.0, 1.0];
+        frame_buffer.clear(&request, &mut command_buffer);
+        assert_eq!(State::Clear, frame_buffer.state());
+        assert_eq!(AttachmentLoadOp::Clear, frame_buffer.load_ops().color);
+        assert_eq!(AttachmentLoadOp::Clear, frame_buffer.load_ops().depth);
+        assert_eq!(AttachmentLoadOp::Load, frame_buffer.load_ops().stencil);
+        assert!(command_buffer.is_empty());
+
+        frame_buffer.begin_render_pass(&mut command_buffer);
+        assert_eq!(State::ClearDraw, frame_buffer.state());
+
+        request.rect = Some(Rect::new(8, 8, 16, 16));
+        frame_buffer.clear(&request, &mut command_buffer);
+        frame_buffer.end_render_pass(&mut command_buffer);
+        assert_eq!(State::Idle, frame_buffer.state());
+        assert_eq!(LoadOps::default(), frame_buffer.load_ops());
+
+        match command_buffer.commands.as_slice() {
+            [Command::BeginRenderPass {
+                load_ops, clear_values, ..
+            }, Command::ClearAttachments { rect, .. }, Command::EndRenderPass] => {
+                assert_eq!(AttachmentLoadOp::Clear, load_ops.color);
+                assert_eq!([1.0, 0.0, 0.0, 1.0], clear_values.color);
+                assert_eq!(Some(Rect::new(8, 8, 16, 16)), *rect);
+            }
+            commands => panic!("unexpected commands {:?}", commands),
+        }
+    }
+
+    #[test]
+    fn test_merge_depth_stencil() {
+        let _guard = context::TEST_LOCK.lock();
+        let new_render_buffer = |internal_format| {
+            let mut pool_guard = HUB.render_buffer_pool.lock();
+            let name = pool_guard.allocate();
+            let object = pool_guard.get_object_mut(name);
+            let mut texture = Texture::default();
+            texture.dimensions = Rect::new(0, 0, 32, 32);
+            object.internal_format = internal_format;
+            object.texture = Some(texture);
+            name
+        };
+        let attachment = |name| Attachment {
+            type_: GL_RENDERBUFFER,
+            name,
+            ..Attachment::default()
+        };
+
+        let mut frame_buffer = FrameBuffer::default();
+        let mut command_buffer = CommandBuffer::default();
+        let (depth, stencil) = (new_render_buffer(GL_DEPTH_COMPONENT16), new_render_buffer(GL_STENCIL_INDEX8));
+        frame_buffer.attachment_depth = attachment(depth);
+        frame_buffer.attachment_stencil = attachment(stencil);
+        frame_buffer.is_updated = true;
+        frame_buffer.begin_render_pass(&mut command_buffer);
+        assert_eq!(Rect::new(0, 0, 32, 32), frame_buffer.dimensions);
+        let texture = frame_buffer.merged_depth_stencil.as_ref().unwrap();
+        assert_eq!(GL_DEPTH24_STENCIL8_OES, texture.internal_format);
+        assert_eq!(Rect::new(0, 0, 32, 32), texture.dimensions);
+        frame_buffer.end_render_pass(&mut command_buffer);
+
+        let depth_stencil = attachment(new_render_buffer(GL_DEPTH24_STENCIL8_OES));
+        frame_buffer.attachment_depth = depth_stencil;
+        frame_buffer.attachment_stencil = depth_stencil;
+        frame_buffer.is_updated = true;
+        frame_buffer.begin_render_pass(&mut command_buffer);
+        assert!(frame_buffer.merged_depth_stencil.is_none());
+
+        let mut pool_guard = HUB.render_buffer_pool.lock();
+        for name in &[depth, stencil, depth_stencil.name] {
+            pool_guard.deallocate(*name);
+        }
+    }
+    #[test]
+    fn test_delete_attached_image() {
+        use render_buffer::{glBindRenderbuffer, glDeleteRenderbuffers, glGenRenderbuffers, glRenderbufferStorage};
+
+        let _guard = context::TEST_LOCK.lock();
+        let mut frame_buffers = [0; 2];
+        glGenFramebuffers(2, frame_buffers.as_mut_ptr());
+        let mut render_buffer = 0;
+        glGenRenderbuffers(1, &mut render_buffer);
+        glBindRenderbuffer(GL_RENDERBUFFER, render_buffer);
+        glRenderbufferStorage(GL_RENDERBUFFER, GL_DEPTH24_STENCIL8_OES, 16, 16);
+        glBindRenderbuffer(GL_RENDERBUFFER, 0);
+        glBindFramebuffer(GL_FRAMEBUFFER, frame_buffers[0]);
+        glFramebufferRenderbuffer(GL_FRAMEBUFFER, GL_DEPTH_STENCIL_ATTACHMENT, GL_RENDERBUFFER, render_buffer);
+        glBindFramebuffer(GL_FRAMEBUFFER, frame_buffers[1]);
+        glFramebufferRenderbuffer(GL_FRAMEBUFFER, GL_DEPTH_ATTACHMENT, GL_RENDERBUFFER, render_buffer);
+        let attach_count = || {
+            let mut pool_guard = HUB.render_buffer_pool.lock();
+            if pool_guard.has_object(render_buffer) {
+                Some(pool_guard.get_object_mut(render_buffer).attach_count)
+            } else {
+                None
+            }
+        };
+        assert_eq!(attach_count(), Some(3));
+
+        // deletion detaches the image from the bound frame buffer, the others keep it until they let go of it
+        glDeleteRenderbuffers(1, &render_buffer);
+        assert_eq!(attach_count(), Some(2));
+        assert_eq!(
+            HUB.frame_buffer_pool
+                .lock()
+                .get_object_mut(frame_buffers[0])
+                .format_bits()
+                .stencil,
+            8
+        );
+        glBindFramebuffer(GL_FRAMEBUFFER, frame_buffers[0]);
+        glFramebufferRenderbuffer(GL_FRAMEBUFFER, GL_DEPTH_ATTACHMENT, GL_RENDERBUFFER, 0);
+        assert_eq!(attach_count(), Some(1));
+        glDeleteFramebuffers(1, &frame_buffers[0]);
+        assert_eq!(attach_count(), None);
+        assert_eq!(HUB.active_frame_buffer.lock().name, 0);
+        glDeleteFramebuffers(1, &frame_buffers[1]);
+
+        // attachments naming no object do not create one
+        let missing = Attachment {
+            type_: GL_TEXTURE,
+            name: 999,
+            ..Attachment::default()
+        };
+        assert_eq!(attachment_format(&missing), None);
+        assert_eq!(attachment_dimensions(&missing), None);
+        assert!(!HUB.texture_pool.lock().has_object(missing.name));
+    }
+}
@@ -269,6 +269,7 @@ pub const GL_DEPTH_FUNC: GLenum = 0x0B74;
 pub const GL_DEPTH_RANGE: GLenum = 0x0B70;
 pub const GL_DEPTH_STENCIL: GLenum = 0x84F9;
 pub const GL_DEPTH_STENCIL_ATTACHMENT: GLenum = 0x821A;
+pub const GL_DEPTH_STENCIL_OES: GLenum = 0x84F9;
 pub const GL_DEPTH_STENCIL_TEXTURE_MODE: GLenum = 0x90EA;
 pub const GL_DEPTH_TEST: GLenum = 0x0B71;
 pub const GL_DEPTH_WRITEMASK: GLenum = 0x0B72;
@@ -471,6 +472,7 @@ pub const GL_MAP_UNSYNCHRONIZED_BIT: GLenum = 0x0020;
 pub const GL_MAP_WRITE_BIT: GLenum = 0x0002;
 pub const GL_MATRIX_STRIDE: GLenum = 0x92FF;
 pub const GL_MAX: GLenum = 0x8008;
+pub const GL_MAX_EXT: GLenum = 0x8008;
 pub const GL_MAX_3D_TEXTURE_SIZE: GLenum = 0x8073;
 pub const GL_MAX_ARRAY_TEXTURE_LAYERS: GLenum = 0x88FF;
 pub const GL_MAX_ATOMIC_COUNTER_BUFFER_BINDINGS: GLenum = 0x92DC;
@@ -599,6 +601,7 @@ pub const GL_MAX_VIEWPORT_DIMS: GLenum = 0x0D3A;
 pub const GL_MEDIUM_FLOAT: GLenum = 0x8DF1;
 pub const GL_MEDIUM_INT: GLenum = 0x8DF4;
 pub const GL_MIN: GLenum = 0x8007;
+pub const GL_MIN_EXT: GLenum = 0x8007;
 pub const GL_MINOR_VERSION: GLenum = 0x821C;
 pub const GL_MIN_FRAGMENT_INTERPOLATION_OFFSET: GLenum = 0x8E5B;
 pub const GL_MIN_PROGRAM_TEXEL_OFFSET: GLenum = 0x8904;
@@ -0,0 +1,245 @@
+use super::types::{BaseType, Precision, Type};
+
+pub(crate) type SymbolId = usize;
+pub(crate) type FunctionId = usize;
+
+/// Nodes are annotated in place by the type checker
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TranslationUnit {
+    pub declarations: Vec<ExternalDeclaration>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum ExternalDeclaration {
+    Declaration(Declaration),
+    Function(FunctionDefinition),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum StorageQualifier {
+    None,
+    Const,
+    Attribute,
+    Uniform,
+    Varying,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ParameterQualifier {
+    In,
+    Out,
+    InOut,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum TypeSpecifierKind {
+    Builtin(BaseType),
+    Struct(StructSpecifier),
+    /// Name of a previously declared struct
+    TypeName(String),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TypeSpecifier {
+    pub kind: TypeSpecifierKind,
+    pub precision: Option<Precision>,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct StructSpecifier {
+    pub name: Option<String>,
+    pub fields: Vec<FieldDeclaration>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FieldDeclaration {
+    pub type_: TypeSpecifier,
+    pub declarators: Vec<(String, Option<Expr>)>,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Declarator {
+    pub name: String,
+    pub array_size: Option<Expr>,
+    pub initializer: Option<Expr>,
+    pub line: u32,
+    pub symbol: Option<SymbolId>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Declaration {
+    Variables {
+        is_invariant: bool,
+        qualifier: StorageQualifier,
+        type_: TypeSpecifier,
+        /// Empty for a lone struct definition
+        declarators: Vec<Declarator>,
+    },
+    Prototype(FunctionPrototype),
+    /// `precision mediump float;`
+    Precision {
+        precision: Precision,
+        type_: TypeSpecifier,
+    },
+    /// `invariant gl_Position;`
+    Invariant {
+        names: Vec<String>,
+        line: u32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Parameter {
+    pub is_const: bool,
+    pub qualifier: ParameterQualifier,
+    pub type_: TypeSpecifier,
+    pub name: Option<String>,
+    pub array_size: Option<Expr>,
+    pub symbol: Option<SymbolId>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FunctionPrototype {
+    pub return_type: TypeSpecifier,
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub line: u32,
+    pub function: Option<FunctionId>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FunctionDefinition {
+    pub prototype: FunctionPrototype,
+    pub body: Vec<Statement>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Statement {
+    pub kind: StatementKind,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum StatementKind {
+    Declaration(Declaration),
+    Expression(Option<Expr>),
+    Block(Vec<Statement>),
+    If {
+        condition: Expr,
+        then: Box<Statement>,
+        else_: Option<Box<Statement>>,
+    },
+    For {
+        init: Box<Statement>,
+        condition: Option<Expr>,
+        step: Option<Expr>,
+        body: Box<Statement>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Statement>,
+    },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expr,
+    },
+    Continue,
+    Break,
+    Return(Option<Expr>),
+    Discard,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum UnaryOp {
+    Plus,
+    Minus,
+    Not,
+    PreIncrement,
+    PreDecrement,
+    PostIncrement,
+    PostDecrement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    LogicalAnd,
+    LogicalOr,
+    LogicalXor,
+}
+
+/// Resolution of `.` by the type checker
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FieldAccess {
+    /// Index of a struct field
+    Struct(usize),
+    /// Component indices of a vector swizzle
+    Swizzle(Vec<u8>),
+}
+
+/// Resolution of a call by the type checker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Callee {
+    Constructor(Type),
+    /// Index in the built-in function table
+    Builtin(usize),
+    User(FunctionId),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum ExprKind {
+    IntConstant(i32),
+    FloatConstant(f32),
+    BoolConstant(bool),
+    Identifier {
+        name: String,
+        symbol: Option<SymbolId>,
+    },
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// Compound assignments carry their arithmetic operator
+    Assign(Option<BinaryOp>, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Field {
+        base: Box<Expr>,
+        name: String,
+        access: Option<FieldAccess>,
+    },
+    Call {
+        name: String,
+        arguments: Vec<Expr>,
+        callee: Option<Callee>,
+    },
+    /// Comma operator
+    Sequence(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Expr {
+    pub kind: ExprKind,
+    pub line: u32,
+    /// Filled in by the type checker
+    pub ty: Type,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, line: u32) -> Self {
+        Self {
+            kind,
+            line,
+            ty: Type::VOID,
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use super::types::{BaseType, Precision, ScalarKind, Type};
+use super::Stage;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BuiltinFunction {
+    pub name: &'static str,
+    pub return_type: Type,
+    pub parameters: Vec<Type>,
+    /// Stage the function is restricted to
+    pub stage: Option<Stage>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum BuiltinVariable {
+    Position,
+    PointSize,
+    FragCoord,
+    FrontFacing,
+    FragColor,
+    FragData,
+    PointCoord,
+}
+
+impl BuiltinVariable {
+    /// Inputs of the fragment shader can not be written
+    pub fn is_read_only(&self) -> bool {
+        match *self {
+            BuiltinVariable::FragCoord | BuiltinVariable::FrontFacing | BuiltinVariable::PointCoord => true,
+            _ => false,
+        }
+    }
+}
+
+/// Built-in constants set to the minimum values of OpenGL ES 2.0
+pub(crate) const BUILTIN_CONSTANTS: &[(&str, i32)] = &[
+    ("gl_MaxVertexAttribs", 8),
+    ("gl_MaxVertexUniformVectors", 128),
+    ("gl_MaxVaryingVectors", 8),
+    ("gl_MaxVertexTextureImageUnits", 0),
+    ("gl_MaxCombinedTextureImageUnits", 8),
+    ("gl_MaxTextureImageUnits", 8),
+    ("gl_MaxFragmentUniformVectors", 16),
+    ("gl_MaxDrawBuffers", 1),
+];
+
+pub(crate) fn builtin_variables(stage: Stage) -> Vec<(&'static str, Type, Precision, BuiltinVariable)> {
+    let vec4 = Type::vector(ScalarKind::Float, 4);
+    match stage {
+        Stage::Vertex => vec![
+            ("gl_Position", vec4, Precision::High, BuiltinVariable::Position),
+            ("gl_PointSize", Type::FLOAT, Precision::Medium, BuiltinVariable::PointSize),
+        ],
+        Stage::Fragment => vec![
+            ("gl_FragCoord", vec4, Precision::Medium, BuiltinVariable::FragCoord),
+            ("gl_FrontFacing", Type::BOOL, Precision::Low, BuiltinVariable::FrontFacing),
+            ("gl_FragColor", vec4, Precision::Medium, BuiltinVariable::FragColor),
+            ("gl_FragData", vec4.array(1), Precision::Medium, BuiltinVariable::FragData),
+            (
+                "gl_PointCoord",
+                Type::vector(ScalarKind::Float, 2),
+                Precision::Medium,
+                BuiltinVariable::PointCoord,
+            ),
+        ],
+    }
+}
+
+/// Signatures of the built-in functions, `T`, `I`, `B` and `M` stand for the float, int, bool vectors and matrices
+/// of every size in the given range, the size 1 being the scalar
+const SIGNATURES: &[(&str, &str, &str, (u8, u8), Option<Stage>)] = &[
+    ("radians", "T", "T", (1, 4), None),
+    ("degrees", "T", "T", (1, 4), None),
+    ("sin", "T", "T", (1, 4), None),
+    ("cos", "T", "T", (1, 4), None),
+    ("tan", "T", "T", (1, 4), None),
+    ("asin", "T", "T", (1, 4), None),
+    ("acos", "T", "T", (1, 4), None),
+    ("atan", "T", "T T", (1, 4), None),
+    ("atan", "T", "T", (1, 4), None),
+    ("pow", "T", "T T", (1, 4), None),
+    ("exp", "T", "T", (1, 4), None),
+    ("log", "T", "T", (1, 4), None),
+    ("exp2", "T", "T", (1, 4), None),
+    ("log2", "T", "T", (1, 4), None),
+    ("sqrt", "T", "T", (1, 4), None),
+    ("inversesqrt", "T", "T", (1, 4), None),
+    ("abs", "T", "T", (1, 4), None),
+    ("sign", "T", "T", (1, 4), None),
+    ("floor", "T", "T", (1, 4), None),
+    ("ceil", "T", "T", (1, 4), None),
+    ("fract", "T", "T", (1, 4), None),
+    ("mod", "T", "T f", (2, 4), None),
+    ("mod", "T", "T T", (1, 4), None),
+    ("min", "T", "T f", (2, 4), None),
+    ("min", "T", "T T", (1, 4), None),
+    ("max", "T", "T f", (2, 4), None),
+    ("max", "T", "T T", (1, 4), None),
+    ("clamp", "T", "T f f", (2, 4), None),
+    ("clamp", "T", "T T T", (1, 4), None),
+    ("mix", "T", "T T f", (2, 4), None),
+    ("mix", "T", "T T T", (1, 4), None),
+    ("step", "T", "f T", (2, 4), None),
+    ("step", "T", "T T", (1, 4), None),
+    ("smoothstep", "T", "f f T", (2, 4), None),
+    ("smoothstep", "T", "T T T", (1, 4), None),
+    ("length", "f", "T", (1, 4), None),
+    ("distance", "f", "T T", (1, 4), None),
+    ("dot", "f", "T T", (1, 4), None),
+    ("cross", "v3", "v3 v3", (1, 1), None),
+    ("normalize", "T", "T", (1, 4), None),
+    ("faceforward", "T", "T T T", (1, 4), None),
+    ("reflect", "T", "T T", (1, 4), None),
+    ("refract", "T", "T T f", (1, 4), None),
+    ("matrixCompMult", "M", "M M", (2, 4), None),
+    ("lessThan", "B", "T T", (2, 4), None),
+    ("lessThan", "B", "I I", (2, 4), None),
+    ("lessThanEqual", "B", "T T", (2, 4), None),
+    ("lessThanEqual", "B", "I I", (2, 4), None),
+    ("greaterThan", "B", "T T", (2, 4), None),
+    ("greaterThan", "B", "I I", (2, 4), None),
+    ("greaterThanEqual", "B", "T T", (2, 4), None),
+    ("greaterThanEqual", "B", "I I", (2, 4), None),
+    ("equal", "B", "T T", (2, 4), None),
+    ("equal", "B", "I I", (2, 4), None),
+    ("equal", "B", "B B", (2, 4), None),
+    ("notEqual", "B", "T T", (2, 4), None),
+    ("notEqual", "B", "I I", (2, 4), None),
+    ("notEqual", "B", "B B", (2, 4), None),
+    ("any", "b", "B", (2, 4), None),
+    ("all", "b", "B", (2, 4), None),
+    ("not", "B", "B", (2, 4), None),
+    ("texture2D", "v4", "s2 v2", (1, 1), None),
+    ("texture2D", "v4", "s2 v2 f", (1, 1), Some(Stage::Fragment)),
+    ("texture2DProj", "v4", "s2 v3", (1, 1), None),
+    ("texture2DProj", "v4", "s2 v4", (1, 1), None),
+    ("texture2DProj", "v4", "s2 v3 f", (1, 1), Some(Stage::Fragment)),
+    ("texture2DProj", "v4", "s2 v4 f", (1, 1), Some(Stage::Fragment)),
+    ("texture2DLod", "v4", "s2 v2 f", (1, 1), Some(Stage::Vertex)),
+    ("texture2DProjLod", "v4", "s2 v3 f", (1, 1), Some(Stage::Vertex)),
+    ("texture2DProjLod", "v4", "s2 v4 f", (1, 1), Some(Stage::Vertex)),
+    ("textureCube", "v4", "sc v3", (1, 1), None),
+    ("textureCube", "v4", "sc v3 f", (1, 1), Some(Stage::Fragment)),
+    ("textureCubeLod", "v4", "sc v3 f", (1, 1), Some(Stage::Vertex)),
+];
+
+fn signature_type(code: &str, size: u8) -> Type {
+    match code {
+        "T" => Type::vector(ScalarKind::Float, size),
+        "I" => Type::vector(ScalarKind::Int, size),
+        "B" => Type::vector(ScalarKind::Bool, size),
+        "M" => Type::matrix(size),
+        "f" => Type::FLOAT,
+        "b" => Type::BOOL,
+        "v2" => Type::vector(ScalarKind::Float, 2),
+        "v3" => Type::vector(ScalarKind::Float, 3),
+        "v4" => Type::vector(ScalarKind::Float, 4),
+        "s2" => Type::new(BaseType::Sampler2D),
+        "sc" => Type::new(BaseType::SamplerCube),
+        _ => unreachable!("invalid signature type {}", code),
+    }
+}
+
+lazy_static! {
+    pub(crate) static ref BUILTIN_FUNCTIONS: Vec<BuiltinFunction> = {
+        let mut functions = Vec::new();
+        for &(name, return_type, parameters, (min_size, max_size), stage) in SIGNATURES {
+            for size in min_size..=max_size {
+                functions.push(BuiltinFunction {
+                    name,
+                    return_type: signature_type(return_type, size),
+                    parameters: parameters
+                        .split(' ')
+                        .map(|parameter| signature_type(parameter, size))
+                        .collect(),
+                    stage,
+                });
+            }
+        }
+        functions
+    };
+}
+
+#[inline]
+pub(crate) fn is_builtin_function(name: &str) -> bool {
+    BUILTIN_FUNCTIONS.iter().any(|function| function.name == name)
+}
+
+#[inline]
+pub(crate) fn is_texture_function(name: &str) -> bool {
+    name.starts_with("texture")
+}
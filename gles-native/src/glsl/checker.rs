@@ -0,0 +1,1451 @@
+use std::collections::HashMap;
+
+use super::ast::*;
+use super::builtins::{self, BuiltinVariable, BUILTIN_FUNCTIONS};
+use super::types::{BaseType, Precision, ScalarKind, StructField, StructType, Type};
+use super::{Diagnostics, Stage};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Storage {
+    Global,
+    Local,
+    Parameter { qualifier: ParameterQualifier, is_const: bool },
+    Const,
+    Attribute,
+    Uniform,
+    Varying,
+    BuiltIn(BuiltinVariable),
+}
+
+/// Value of a scalar constant expression
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Constant {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Symbol {
+    pub name: String,
+    pub ty: Type,
+    pub precision: Option<Precision>,
+    pub storage: Storage,
+    pub is_invariant: bool,
+    /// Referenced by the shader, only used symbols are active
+    pub is_used: bool,
+    pub constant: Option<Constant>,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Function {
+    pub name: String,
+    pub return_type: Type,
+    pub parameters: Vec<(ParameterQualifier, Type)>,
+    pub is_defined: bool,
+    pub line: u32,
+}
+
+/// Type checked shader kept for linking
+#[derive(Clone, Debug)]
+pub(crate) struct CompiledShader {
+    pub stage: Stage,
+    pub unit: TranslationUnit,
+    pub symbols: Vec<Symbol>,
+    pub structs: Vec<StructType>,
+    pub functions: Vec<Function>,
+}
+
+impl CompiledShader {
+    fn symbols_with(&self, storage: Storage) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .filter(move |&(_, symbol)| symbol.storage == storage)
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols_with(Storage::Attribute)
+    }
+
+    pub fn uniforms(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols_with(Storage::Uniform)
+    }
+
+    pub fn varyings(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols_with(Storage::Varying)
+    }
+
+    pub fn main(&self) -> Option<FunctionId> {
+        self.functions
+            .iter()
+            .position(|function| function.name == "main" && function.is_defined)
+    }
+}
+
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<String, SymbolId>,
+    structs: HashMap<String, usize>,
+    precisions: Vec<(ScalarKind, Precision)>,
+    sampler_precisions: Vec<(BaseType, Precision)>,
+}
+
+struct Checker<'a> {
+    stage: Stage,
+    diagnostics: &'a mut Diagnostics,
+    symbols: Vec<Symbol>,
+    structs: Vec<StructType>,
+    functions: Vec<Function>,
+    function_names: HashMap<String, Vec<FunctionId>>,
+    scopes: Vec<Scope>,
+    /// Return type of the function being checked
+    return_type: Option<Type>,
+    loop_depth: u32,
+    /// Function being checked
+    function: Option<FunctionId>,
+    /// Calls between user functions, as caller, callee and line of the call
+    calls: Vec<(FunctionId, FunctionId, u32)>,
+}
+
+/// Resolve names and types of `unit` in place, reporting semantic errors
+pub(crate) fn check(mut unit: TranslationUnit, stage: Stage, diagnostics: &mut Diagnostics) -> CompiledShader {
+    let mut checker = Checker {
+        stage,
+        diagnostics,
+        symbols: Vec::new(),
+        structs: Vec::new(),
+        functions: Vec::new(),
+        function_names: HashMap::new(),
+        scopes: vec![Scope::default()],
+        return_type: None,
+        loop_depth: 0,
+        function: None,
+        calls: Vec::new(),
+    };
+    checker.declare_builtins();
+
+    for declaration in &mut unit.declarations {
+        match *declaration {
+            ExternalDeclaration::Declaration(ref mut declaration) => checker.declaration(declaration, true),
+            ExternalDeclaration::Function(ref mut definition) => checker.function_definition(definition),
+        }
+    }
+    checker.check_recursion();
+
+    CompiledShader {
+        stage,
+        unit,
+        symbols: checker.symbols,
+        structs: checker.structs,
+        functions: checker.functions,
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn declare_builtins(&mut self) {
+        for &(name, value) in builtins::BUILTIN_CONSTANTS {
+            self.declare_symbol(Symbol {
+                name: name.to_string(),
+                ty: Type::INT,
+                precision: Some(Precision::Medium),
+                storage: Storage::Const,
+                is_invariant: false,
+                is_used: false,
+                constant: Some(Constant::Int(value)),
+                line: 0,
+            });
+        }
+        for (name, ty, precision, variable) in builtins::builtin_variables(self.stage) {
+            self.declare_symbol(Symbol {
+                name: name.to_string(),
+                ty,
+                precision: Some(precision),
+                storage: Storage::BuiltIn(variable),
+                is_invariant: false,
+                is_used: false,
+                constant: None,
+                line: 0,
+            });
+        }
+
+        let scope = &mut self.scopes[0];
+        if self.stage == Stage::Vertex {
+            scope.precisions.push((ScalarKind::Float, Precision::High));
+            scope.precisions.push((ScalarKind::Int, Precision::High));
+        } else {
+            scope.precisions.push((ScalarKind::Int, Precision::Medium));
+        }
+        scope.sampler_precisions.push((BaseType::Sampler2D, Precision::Low));
+        scope.sampler_precisions.push((BaseType::SamplerCube, Precision::Low));
+    }
+
+    #[inline]
+    fn error(&mut self, line: u32, token: &str, message: &str) {
+        self.diagnostics.error(line, token, message);
+    }
+
+    fn type_name(&self, ty: &Type) -> String {
+        ty.name(&self.structs)
+    }
+
+    fn declare_symbol(&mut self, symbol: Symbol) -> SymbolId {
+        let id = self.symbols.len();
+        self.scopes.last_mut().unwrap().variables.insert(symbol.name.clone(), id);
+        self.symbols.push(symbol);
+        id
+    }
+
+    /// Report names already declared in the current scope
+    fn check_redefinition(&mut self, name: &str, line: u32) -> bool {
+        let is_global = self.scopes.len() == 1;
+        let scope = self.scopes.last().unwrap();
+        if scope.variables.contains_key(name)
+            || scope.structs.contains_key(name)
+            || (is_global && self.function_names.contains_key(name))
+        {
+            self.error(line, name, "redefinition");
+            return false;
+        }
+        if name.starts_with("gl_") {
+            self.error(line, name, "identifiers starting with 'gl_' are reserved");
+            return false;
+        }
+        true
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<SymbolId> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&id) = scope.variables.get(name) {
+                return Some(id);
+            }
+            if scope.structs.contains_key(name) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn lookup_struct(&self, name: &str) -> Option<usize> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&id) = scope.structs.get(name) {
+                return Some(id);
+            }
+            if scope.variables.contains_key(name) {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Apply the default precision of the scope when none is given, fragment shaders have no default for floats
+    fn resolve_precision(&mut self, ty: &Type, precision: Option<Precision>, line: u32) -> Option<Precision> {
+        if precision.is_some() {
+            return precision;
+        }
+
+        let default = match ty.base {
+            BaseType::Sampler2D | BaseType::SamplerCube => self
+                .scopes
+                .iter()
+                .rev()
+                .filter_map(|scope| {
+                    scope
+                        .sampler_precisions
+                        .iter()
+                        .rev()
+                        .find(|&&(base, _)| base == ty.base)
+                        .map(|&(_, precision)| precision)
+                })
+                .next(),
+            BaseType::Scalar(ScalarKind::Bool) | BaseType::Vector(ScalarKind::Bool, _) => return None,
+            BaseType::Struct(_) | BaseType::Void => return None,
+            _ => {
+                let kind = ty.scalar_kind().unwrap();
+                let default = self
+                    .scopes
+                    .iter()
+                    .rev()
+                    .filter_map(|scope| {
+                        scope
+                            .precisions
+                            .iter()
+                            .rev()
+                            .find(|&&(scalar, _)| scalar == kind)
+                            .map(|&(_, precision)| precision)
+                    })
+                    .next();
+                if default.is_none() {
+                    self.error(line, "", "No precision specified for (float)");
+                }
+                default
+            }
+        };
+        default
+    }
+
+    fn resolve_type_specifier(&mut self, type_: &TypeSpecifier) -> Option<Type> {
+        match type_.kind {
+            TypeSpecifierKind::Builtin(base) => Some(Type::new(base)),
+            TypeSpecifierKind::TypeName(ref name) => match self.lookup_struct(name) {
+                Some(id) => Some(Type::new(BaseType::Struct(id))),
+                None => {
+                    self.error(type_.line, name, "undeclared type");
+                    None
+                }
+            },
+            TypeSpecifierKind::Struct(ref specifier) => self.struct_specifier(specifier, type_.line),
+        }
+    }
+
+    fn struct_specifier(&mut self, specifier: &StructSpecifier, line: u32) -> Option<Type> {
+        let mut fields: Vec<StructField> = Vec::new();
+        for field in &specifier.fields {
+            let ty = match self.resolve_type_specifier(&field.type_) {
+                Some(ty) => ty,
+                None => continue,
+            };
+            if ty.is_void() {
+                self.error(field.line, "void", "illegal use of type 'void'");
+                continue;
+            }
+            let precision = self.resolve_precision(&ty, field.type_.precision, field.line);
+
+            for &(ref name, ref array_size) in &field.declarators {
+                let ty = match *array_size {
+                    Some(ref size) => match self.array_size(size) {
+                        Some(size) => ty.array(size),
+                        None => continue,
+                    },
+                    None => ty,
+                };
+                if fields.iter().any(|field| &field.name == name) {
+                    self.error(field.line, name, "duplicate field name in structure");
+                    continue;
+                }
+                fields.push(StructField {
+                    name: name.clone(),
+                    ty,
+                    precision,
+                });
+            }
+        }
+
+        if fields.is_empty() {
+            self.error(line, "struct", "structures must have at least one field");
+            return None;
+        }
+
+        let id = self.structs.len();
+        self.structs.push(StructType {
+            name: specifier.name.clone(),
+            fields,
+        });
+        if let Some(ref name) = specifier.name {
+            if self.check_redefinition(name, line) {
+                self.scopes.last_mut().unwrap().structs.insert(name.clone(), id);
+            }
+        }
+        Some(Type::new(BaseType::Struct(id)))
+    }
+
+    /// Array sizes are positive integral constant expressions
+    fn array_size(&mut self, size: &Expr) -> Option<u32> {
+        let mut size = size.clone();
+        self.expr(&mut size)?;
+        match self.evaluate(&size) {
+            Some(Constant::Int(value)) if value > 0 => Some(value as u32),
+            Some(Constant::Int(_)) => {
+                self.error(size.line, "", "array size must be a positive integer");
+                None
+            }
+            _ => {
+                self.error(size.line, "", "array size must be a constant integer expression");
+                None
+            }
+        }
+    }
+
+    fn declaration(&mut self, declaration: &mut Declaration, is_global: bool) {
+        match *declaration {
+            Declaration::Variables {
+                is_invariant,
+                qualifier,
+                ref type_,
+                ref mut declarators,
+            } => self.variables(is_global, is_invariant, qualifier, type_, declarators),
+            Declaration::Prototype(ref mut prototype) => {
+                if !is_global {
+                    self.error(
+                        prototype.line,
+                        &prototype.name,
+                        "function declarations must be at global scope",
+                    );
+                    return;
+                }
+                self.function_prototype(prototype, false);
+            }
+            Declaration::Precision { precision, ref type_ } => {
+                let line = type_.line;
+                match type_.kind {
+                    TypeSpecifierKind::Builtin(BaseType::Scalar(kind)) if kind != ScalarKind::Bool => {
+                        self.scopes.last_mut().unwrap().precisions.push((kind, precision));
+                    }
+                    TypeSpecifierKind::Builtin(base @ BaseType::Sampler2D)
+                    | TypeSpecifierKind::Builtin(base @ BaseType::SamplerCube) => {
+                        self.scopes.last_mut().unwrap().sampler_precisions.push((base, precision));
+                    }
+                    _ => self.error(
+                        line,
+                        "precision",
+                        "default precision can only be set for float, int and sampler types",
+                    ),
+                }
+            }
+            Declaration::Invariant { ref names, line } => {
+                if !is_global {
+                    self.error(line, "invariant", "only allowed at global scope");
+                    return;
+                }
+                for name in names {
+                    let id = match self.lookup_variable(name) {
+                        Some(id) => id,
+                        None => {
+                            self.error(line, name, "undeclared identifier");
+                            continue;
+                        }
+                    };
+                    let can_be_invariant = match self.symbols[id].storage {
+                        Storage::Varying => true,
+                        Storage::BuiltIn(variable) => {
+                            variable != BuiltinVariable::FragColor && variable != BuiltinVariable::FragData
+                        }
+                        _ => false,
+                    };
+                    if can_be_invariant {
+                        self.symbols[id].is_invariant = true;
+                    } else {
+                        self.error(line, name, "only varyings can be declared invariant");
+                    }
+                }
+            }
+        }
+    }
+
+    fn variables(
+        &mut self,
+        is_global: bool,
+        is_invariant: bool,
+        qualifier: StorageQualifier,
+        type_: &TypeSpecifier,
+        declarators: &mut [Declarator],
+    ) {
+        let line = type_.line;
+        let base_type = match self.resolve_type_specifier(type_) {
+            Some(ty) => ty,
+            None => return,
+        };
+        if declarators.is_empty() {
+            return;
+        }
+
+        let qualifier_name = match qualifier {
+            StorageQualifier::None => "",
+            StorageQualifier::Const => "const",
+            StorageQualifier::Attribute => "attribute",
+            StorageQualifier::Uniform => "uniform",
+            StorageQualifier::Varying => "varying",
+        };
+        let is_float = |ty: &Type| ty.scalar_kind() == Some(ScalarKind::Float);
+        match qualifier {
+            StorageQualifier::Attribute | StorageQualifier::Uniform | StorageQualifier::Varying if !is_global => {
+                return self.error(line, qualifier_name, "only allowed at global scope");
+            }
+            StorageQualifier::Attribute if self.stage == Stage::Fragment => {
+                return self.error(line, qualifier_name, "not allowed in a fragment shader");
+            }
+            StorageQualifier::Attribute | StorageQualifier::Varying if !is_float(&base_type) => {
+                return self.error(
+                    line,
+                    qualifier_name,
+                    &format!("can not be of type '{}'", self.type_name(&base_type)),
+                );
+            }
+            _ => {}
+        }
+        if is_invariant && qualifier != StorageQualifier::Varying {
+            return self.error(line, "invariant", "only varyings can be declared invariant");
+        }
+        if base_type.is_void() {
+            return self.error(line, "void", "illegal use of type 'void'");
+        }
+        if base_type.contains(&self.structs, &|ty: &Type| ty.is_sampler()) && qualifier != StorageQualifier::Uniform {
+            return self.error(line, &self.type_name(&base_type), "samplers must be uniform");
+        }
+        let precision = self.resolve_precision(&base_type, type_.precision, line);
+
+        let storage = match qualifier {
+            StorageQualifier::None if is_global => Storage::Global,
+            StorageQualifier::None => Storage::Local,
+            StorageQualifier::Const => Storage::Const,
+            StorageQualifier::Attribute => Storage::Attribute,
+            StorageQualifier::Uniform => Storage::Uniform,
+            StorageQualifier::Varying => Storage::Varying,
+        };
+
+        for declarator in declarators.iter_mut() {
+            let line = declarator.line;
+            let ty = match declarator.array_size {
+                Some(ref size) => {
+                    if qualifier == StorageQualifier::Attribute || qualifier == StorageQualifier::Const {
+                        self.error(
+                            line,
+                            &declarator.name,
+                            &format!("arrays can not be declared '{}'", qualifier_name),
+                        );
+                        continue;
+                    }
+                    match self.array_size(size) {
+                        Some(size) => base_type.array(size),
+                        None => continue,
+                    }
+                }
+                None => base_type,
+            };
+
+            // the variable is declared even if its initializer is invalid to avoid cascading errors
+            let constant = match declarator.initializer {
+                Some(ref mut initializer) => self.initializer(&declarator.name, line, qualifier, is_global, &ty, initializer),
+                None if qualifier == StorageQualifier::Const => {
+                    self.error(line, &declarator.name, "'const' variables must be initialized");
+                    None
+                }
+                None => None,
+            };
+
+            if !self.check_redefinition(&declarator.name, line) {
+                continue;
+            }
+            declarator.symbol = Some(self.declare_symbol(Symbol {
+                name: declarator.name.clone(),
+                ty,
+                precision,
+                storage,
+                is_invariant,
+                is_used: false,
+                constant,
+                line,
+            }));
+        }
+    }
+
+    /// Check the initializer of a variable, returning its value for scalar constants
+    fn initializer(
+        &mut self,
+        name: &str,
+        line: u32,
+        qualifier: StorageQualifier,
+        is_global: bool,
+        ty: &Type,
+        initializer: &mut Expr,
+    ) -> Option<Constant> {
+        match qualifier {
+            StorageQualifier::Attribute | StorageQualifier::Uniform | StorageQualifier::Varying => {
+                self.error(line, name, "attribute, uniform and varying variables can not be initialized");
+                return None;
+            }
+            _ => {}
+        }
+        if ty.is_array() {
+            self.error(line, name, "arrays can not be initialized");
+            return None;
+        }
+
+        let initializer_type = self.expr(initializer)?;
+        if initializer_type != *ty {
+            let message = format!(
+                "can not initialize a variable of type '{}' with an expression of type '{}'",
+                self.type_name(ty),
+                self.type_name(&initializer_type)
+            );
+            self.error(line, name, &message);
+            return None;
+        }
+        if (qualifier == StorageQualifier::Const || is_global) && !self.is_constant(initializer) {
+            self.error(line, name, "initializer must be a constant expression");
+            return None;
+        }
+
+        if qualifier == StorageQualifier::Const {
+            self.evaluate(initializer)
+        } else {
+            None
+        }
+    }
+
+    /// Register a function, returning its id if the prototype is valid
+    fn function_prototype(&mut self, prototype: &mut FunctionPrototype, is_definition: bool) -> Option<FunctionId> {
+        let line = prototype.line;
+        let name = prototype.name.clone();
+
+        let return_type = self.resolve_type_specifier(&prototype.return_type)?;
+        self.resolve_precision(&return_type, prototype.return_type.precision, line);
+        if return_type.contains(&self.structs, &|ty: &Type| ty.is_sampler()) {
+            self.error(line, &name, "functions can not return samplers");
+            return None;
+        }
+
+        let mut parameters = Vec::with_capacity(prototype.parameters.len());
+        for parameter in &prototype.parameters {
+            let mut ty = self.resolve_type_specifier(&parameter.type_)?;
+            if ty.is_void() {
+                self.error(line, "void", "illegal use of type 'void'");
+                return None;
+            }
+            if let Some(ref size) = parameter.array_size {
+                ty = ty.array(self.array_size(size)?);
+            }
+            if ty.contains(&self.structs, &|ty: &Type| ty.is_sampler()) && parameter.qualifier != ParameterQualifier::In {
+                self.error(line, &name, "samplers can only be input parameters");
+                return None;
+            }
+            if parameter.is_const && parameter.qualifier != ParameterQualifier::In {
+                self.error(line, &name, "'const' parameters can only be input parameters");
+                return None;
+            }
+            self.resolve_precision(&ty, parameter.type_.precision, line);
+            parameters.push((parameter.qualifier, ty));
+        }
+
+        if builtins::is_builtin_function(&name) {
+            self.error(line, &name, "can not redeclare or overload a built-in function");
+            return None;
+        }
+        if name.starts_with("gl_") {
+            self.error(line, &name, "identifiers starting with 'gl_' are reserved");
+            return None;
+        }
+        if self.scopes[0].variables.contains_key(&name) || self.scopes[0].structs.contains_key(&name) {
+            self.error(line, &name, "redefinition");
+            return None;
+        }
+        if name == "main" && (!return_type.is_void() || !parameters.is_empty()) {
+            self.error(line, &name, "function must be 'void main()'");
+            return None;
+        }
+
+        let overloads = self.function_names.get(&name).cloned().unwrap_or_default();
+        let existing = overloads.into_iter().find(|&id| {
+            let function = &self.functions[id];
+            function.parameters.len() == parameters.len()
+                && function.parameters.iter().zip(&parameters).all(|(lhs, rhs)| lhs.1 == rhs.1)
+        });
+
+        let id = match existing {
+            Some(id) => {
+                if self.functions[id].return_type != return_type {
+                    self.error(line, &name, "overloaded functions must have different parameter types");
+                    return None;
+                }
+                if self.functions[id].parameters != parameters {
+                    self.error(line, &name, "parameter qualifiers do not match the previous declaration");
+                    return None;
+                }
+                if is_definition && self.functions[id].is_defined {
+                    self.error(line, &name, "function already has a body");
+                    return None;
+                }
+                id
+            }
+            None => {
+                let id = self.functions.len();
+                self.functions.push(Function {
+                    name: name.clone(),
+                    return_type,
+                    parameters,
+                    is_defined: false,
+                    line,
+                });
+                self.function_names.entry(name).or_insert_with(Vec::new).push(id);
+                id
+            }
+        };
+        prototype.function = Some(id);
+        Some(id)
+    }
+
+    fn function_definition(&mut self, definition: &mut FunctionDefinition) {
+        let id = match self.function_prototype(&mut definition.prototype, true) {
+            Some(id) => id,
+            None => return,
+        };
+        self.functions[id].is_defined = true;
+        self.return_type = Some(self.functions[id].return_type);
+        self.function = Some(id);
+
+        // parameters and the outermost statements of the body share the same scope
+        self.push_scope();
+        for (index, parameter) in definition.prototype.parameters.iter_mut().enumerate() {
+            let name = match parameter.name {
+                Some(ref name) => name.clone(),
+                None => continue,
+            };
+            let line = definition.prototype.line;
+            if !self.check_redefinition(&name, line) {
+                continue;
+            }
+            let (qualifier, ty) = self.functions[id].parameters[index];
+            let precision = self.resolve_precision(&ty, parameter.type_.precision, line);
+            parameter.symbol = Some(self.declare_symbol(Symbol {
+                name,
+                ty,
+                precision,
+                storage: Storage::Parameter {
+                    qualifier,
+                    is_const: parameter.is_const,
+                },
+                is_invariant: false,
+                is_used: false,
+                constant: None,
+                line,
+            }));
+        }
+        for statement in &mut definition.body {
+            self.statement(statement);
+        }
+        self.pop_scope();
+        self.return_type = None;
+        self.function = None;
+    }
+
+    /// Report the calls closing a cycle of the call graph, recursion is not allowed (GLSL ES 1.00 §6.1)
+    fn check_recursion(&mut self) {
+        let mut callees = vec![Vec::new(); self.functions.len()];
+        for &(caller, callee, line) in &self.calls {
+            callees[caller].push((callee, line));
+        }
+        let mut states = vec![VisitState::Unvisited; self.functions.len()];
+        let mut recursive_calls = Vec::new();
+        for id in 0..self.functions.len() {
+            find_recursive_calls(id, &callees, &mut states, &mut recursive_calls);
+        }
+        for (callee, line) in recursive_calls {
+            let name = self.functions[callee].name.clone();
+            self.error(line, &name, "recursive function call");
+        }
+    }
+
+    /// Sub-statements of control flow introduce their own scope
+    fn scoped_statement(&mut self, statement: &mut Statement) {
+        self.push_scope();
+        self.statement(statement);
+        self.pop_scope();
+    }
+
+    fn condition(&mut self, condition: &mut Expr, token: &str) {
+        if let Some(ty) = self.expr(condition) {
+            if ty != Type::BOOL {
+                self.error(condition.line, token, "boolean expression expected");
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &mut Statement) {
+        let line = statement.line;
+        match statement.kind {
+            StatementKind::Declaration(ref mut declaration) => self.declaration(declaration, false),
+            StatementKind::Expression(ref mut expression) => {
+                if let Some(ref mut expression) = *expression {
+                    self.expr(expression);
+                }
+            }
+            StatementKind::Block(ref mut statements) => {
+                self.push_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.pop_scope();
+            }
+            StatementKind::If {
+                ref mut condition,
+                ref mut then,
+                ref mut else_,
+            } => {
+                self.condition(condition, "if");
+                self.scoped_statement(then);
+                if let Some(ref mut else_) = *else_ {
+                    self.scoped_statement(else_);
+                }
+            }
+            StatementKind::For {
+                ref mut init,
+                ref mut condition,
+                ref mut step,
+                ref mut body,
+            } => {
+                self.push_scope();
+                self.statement(init);
+                if let Some(ref mut condition) = *condition {
+                    self.condition(condition, "for");
+                }
+                if let Some(ref mut step) = *step {
+                    self.expr(step);
+                }
+                self.loop_depth += 1;
+                self.scoped_statement(body);
+                self.loop_depth -= 1;
+                self.pop_scope();
+            }
+            StatementKind::While {
+                ref mut condition,
+                ref mut body,
+            } => {
+                self.condition(condition, "while");
+                self.loop_depth += 1;
+                self.scoped_statement(body);
+                self.loop_depth -= 1;
+            }
+            StatementKind::DoWhile {
+                ref mut body,
+                ref mut condition,
+            } => {
+                self.loop_depth += 1;
+                self.scoped_statement(body);
+                self.loop_depth -= 1;
+                self.condition(condition, "while");
+            }
+            StatementKind::Continue if self.loop_depth == 0 => {
+                self.error(line, "continue", "continue statement only allowed in loops")
+            }
+            StatementKind::Break if self.loop_depth == 0 => self.error(line, "break", "break statement only allowed in loops"),
+            StatementKind::Continue | StatementKind::Break => {}
+            StatementKind::Discard if self.stage != Stage::Fragment => {
+                self.error(line, "discard", "only allowed in fragment shaders")
+            }
+            StatementKind::Discard => {}
+            StatementKind::Return(ref mut value) => {
+                let return_type = self.return_type.unwrap_or(Type::VOID);
+                match *value {
+                    Some(ref mut value) => {
+                        if let Some(ty) = self.expr(value) {
+                            if return_type.is_void() {
+                                self.error(line, "return", "void function can not return a value");
+                            } else if ty != return_type {
+                                self.error(line, "return", "function return is not matching type");
+                            }
+                        }
+                    }
+                    None if !return_type.is_void() => self.error(line, "return", "non-void function must return a value"),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Check an expression, returning `None` after an error has been reported
+    fn expr(&mut self, expr: &mut Expr) -> Option<Type> {
+        let ty = self.expr_kind(expr);
+        expr.ty = ty.unwrap_or(Type::VOID);
+        ty
+    }
+
+    fn expr_kind(&mut self, expr: &mut Expr) -> Option<Type> {
+        let line = expr.line;
+        match expr.kind {
+            ExprKind::IntConstant(_) => Some(Type::INT),
+            ExprKind::FloatConstant(_) => Some(Type::FLOAT),
+            ExprKind::BoolConstant(_) => Some(Type::BOOL),
+            ExprKind::Identifier {
+                ref name,
+                ref mut symbol,
+            } => match self.lookup_variable(name) {
+                Some(id) => {
+                    *symbol = Some(id);
+                    self.symbols[id].is_used = true;
+                    Some(self.symbols[id].ty)
+                }
+                None => {
+                    self.error(line, name, "undeclared identifier");
+                    None
+                }
+            },
+            ExprKind::Unary(operator, ref mut operand) => {
+                let ty = self.expr(operand)?;
+                match operator {
+                    UnaryOp::Not if ty == Type::BOOL => Some(ty),
+                    UnaryOp::Not => {
+                        self.error(line, "!", &format!("wrong operand type '{}'", self.type_name(&ty)));
+                        None
+                    }
+                    _ if !ty.is_numeric() => {
+                        self.error(
+                            line,
+                            unary_token(operator),
+                            &format!("wrong operand type '{}'", self.type_name(&ty)),
+                        );
+                        None
+                    }
+                    UnaryOp::Plus | UnaryOp::Minus => Some(ty),
+                    _ => {
+                        if self.check_lvalue(operand, unary_token(operator)) {
+                            Some(ty)
+                        } else {
+                            None
+                        }
+                    }
+                }
+            }
+            ExprKind::Binary(operator, ref mut lhs, ref mut rhs) => {
+                let lhs_type = self.expr(lhs);
+                let rhs_type = self.expr(rhs);
+                let (lhs_type, rhs_type) = (lhs_type?, rhs_type?);
+                let ty = binary_result_type(operator, &lhs_type, &rhs_type, &self.structs);
+                if ty.is_none() {
+                    let message = format!(
+                        "wrong operand types: no operation '{}' exists that takes a left-hand operand of type '{}' and a right operand of type '{}'",
+                        binary_token(operator),
+                        self.type_name(&lhs_type),
+                        self.type_name(&rhs_type)
+                    );
+                    self.error(line, binary_token(operator), &message);
+                }
+                ty
+            }
+            ExprKind::Assign(operator, ref mut lhs, ref mut rhs) => {
+                let lhs_type = self.expr(lhs);
+                let rhs_type = self.expr(rhs);
+                let (lhs_type, rhs_type) = (lhs_type?, rhs_type?);
+                let token = operator.map_or("=", assign_token);
+                let result = match operator {
+                    None if lhs_type.is_array() || lhs_type.contains(&self.structs, &|ty: &Type| ty.is_sampler()) => None,
+                    None => Some(rhs_type),
+                    Some(operator) => binary_result_type(operator, &lhs_type, &rhs_type, &self.structs),
+                };
+                if result != Some(lhs_type) {
+                    let message = format!(
+                        "can not convert from '{}' to '{}'",
+                        self.type_name(&rhs_type),
+                        self.type_name(&lhs_type)
+                    );
+                    self.error(line, token, &message);
+                    return None;
+                }
+                if !self.check_lvalue(lhs, token) {
+                    return None;
+                }
+                Some(lhs_type)
+            }
+            ExprKind::Ternary(ref mut condition, ref mut then, ref mut else_) => {
+                self.condition(condition, "?:");
+                let then_type = self.expr(then);
+                let else_type = self.expr(else_);
+                let (then_type, else_type) = (then_type?, else_type?);
+                if then_type != else_type || then_type.is_array() {
+                    let message = format!(
+                        "wrong operand types: no operation '?:' exists that takes '{}' and '{}'",
+                        self.type_name(&then_type),
+                        self.type_name(&else_type)
+                    );
+                    self.error(line, "?:", &message);
+                    return None;
+                }
+                Some(then_type)
+            }
+            ExprKind::Index(ref mut base, ref mut index) => {
+                let base_type = self.expr(base);
+                let index_type = self.expr(index);
+                let (base_type, index_type) = (base_type?, index_type?);
+                let element = match base_type.element() {
+                    Some(element) => element,
+                    None => {
+                        self.error(line, "[", &format!("'{}' can not be indexed", self.type_name(&base_type)));
+                        return None;
+                    }
+                };
+                if index_type != Type::INT {
+                    self.error(line, "[", "integer expression required");
+                    return None;
+                }
+                let size = base_type.array_size.unwrap_or(base_type.size() as u32);
+                if let Some(Constant::Int(value)) = self.evaluate(index) {
+                    if value < 0 || value as u32 >= size {
+                        self.error(line, "[", &format!("index out of range '{}'", value));
+                        return None;
+                    }
+                }
+                Some(element)
+            }
+            ExprKind::Field {
+                ref mut base,
+                ref name,
+                ref mut access,
+            } => {
+                let base_type = self.expr(base)?;
+                match base_type.base {
+                    BaseType::Struct(id) if !base_type.is_array() => match self.structs[id].field(name) {
+                        Some((index, field)) => {
+                            *access = Some(FieldAccess::Struct(index));
+                            Some(field.ty)
+                        }
+                        None => {
+                            self.error(line, name, "no such field in structure");
+                            None
+                        }
+                    },
+                    BaseType::Vector(kind, _) if !base_type.is_array() => match swizzle(name, base_type.size()) {
+                        Some(components) => {
+                            let size = components.len() as u8;
+                            *access = Some(FieldAccess::Swizzle(components));
+                            Some(Type::vector(kind, size))
+                        }
+                        None => {
+                            self.error(line, name, "illegal vector field selection");
+                            None
+                        }
+                    },
+                    _ => {
+                        self.error(line, name, &format!("'{}' has no fields", self.type_name(&base_type)));
+                        None
+                    }
+                }
+            }
+            ExprKind::Call {
+                ref name,
+                ref mut arguments,
+                ref mut callee,
+            } => {
+                let mut argument_types = Vec::with_capacity(arguments.len());
+                for argument in arguments.iter_mut() {
+                    argument_types.push(self.expr(argument));
+                }
+                let argument_types = argument_types.into_iter().collect::<Option<Vec<_>>>()?;
+                let (resolved, ty) = self.call(name, &argument_types, line)?;
+
+                // `out` and `inout` arguments are written back
+                if let Callee::User(id) = resolved {
+                    if let Some(caller) = self.function {
+                        self.calls.push((caller, id, line));
+                    }
+                    let qualifiers = self.functions[id]
+                        .parameters
+                        .iter()
+                        .map(|&(qualifier, _)| qualifier)
+                        .collect::<Vec<_>>();
+                    for (argument, qualifier) in arguments.iter().zip(qualifiers) {
+                        if qualifier != ParameterQualifier::In && !self.check_lvalue(argument, name) {
+                            return None;
+                        }
+                    }
+                }
+                *callee = Some(resolved);
+                Some(ty)
+            }
+            ExprKind::Sequence(ref mut lhs, ref mut rhs) => {
+                let lhs_type = self.expr(lhs);
+                let rhs_type = self.expr(rhs);
+                lhs_type?;
+                rhs_type
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Type], line: u32) -> Option<(Callee, Type)> {
+        if let Some(base) = super::types::builtin_type(name) {
+            let ty = Type::new(base);
+            return self.constructor(ty, arguments, line).map(|()| (Callee::Constructor(ty), ty));
+        }
+        if let Some(id) = self.lookup_struct(name) {
+            let ty = Type::new(BaseType::Struct(id));
+            return self.constructor(ty, arguments, line).map(|()| (Callee::Constructor(ty), ty));
+        }
+        // variables hide functions of the same name
+        if self.lookup_variable(name).is_some() {
+            self.error(line, name, "no matching overloaded function found");
+            return None;
+        }
+
+        if let Some(overloads) = self.function_names.get(name) {
+            for &id in overloads {
+                let function = &self.functions[id];
+                if function.parameters.len() == arguments.len()
+                    && function
+                        .parameters
+                        .iter()
+                        .zip(arguments)
+                        .all(|(&(_, ref ty), argument)| ty == argument)
+                {
+                    return Some((Callee::User(id), function.return_type));
+                }
+            }
+        }
+        for (index, function) in BUILTIN_FUNCTIONS.iter().enumerate() {
+            if function.name == name
+                && function.stage.map_or(true, |stage| stage == self.stage)
+                && function.parameters == arguments
+            {
+                return Some((Callee::Builtin(index), function.return_type));
+            }
+        }
+
+        self.error(line, name, "no matching overloaded function found");
+        None
+    }
+
+    fn constructor(&mut self, ty: Type, arguments: &[Type], line: u32) -> Option<()> {
+        let name = self.type_name(&ty);
+        if arguments.is_empty() {
+            self.error(line, &name, "constructor does not have any arguments");
+            return None;
+        }
+
+        if let BaseType::Struct(id) = ty.base {
+            let fields = &self.structs[id].fields;
+            if fields.len() != arguments.len() || fields.iter().zip(arguments).any(|(field, argument)| field.ty != *argument) {
+                self.error(line, &name, "wrong arguments for structure constructor");
+                return None;
+            }
+            return Some(());
+        }
+
+        match ty.base {
+            BaseType::Void | BaseType::Sampler2D | BaseType::SamplerCube => {
+                self.error(line, &name, "can not construct this type");
+                return None;
+            }
+            _ => {}
+        }
+        if arguments
+            .iter()
+            .any(|argument| argument.scalar_kind().is_none() || argument.is_array())
+        {
+            self.error(line, &name, "arguments must be scalars, vectors or matrices");
+            return None;
+        }
+        if arguments.len() == 1 && (arguments[0].is_scalar() || (ty.is_matrix() && arguments[0].is_matrix())) {
+            return Some(());
+        }
+        if ty.is_matrix() && arguments.iter().any(|argument| argument.is_matrix()) {
+            self.error(line, &name, "can not construct a matrix from a matrix and other arguments");
+            return None;
+        }
+
+        let required = ty.component_count();
+        let provided: u32 = arguments.iter().map(|argument| argument.component_count()).sum();
+        let last = arguments.last().unwrap().component_count();
+        if provided < required {
+            self.error(line, &name, "not enough data provided for construction");
+            return None;
+        }
+        if provided - last >= required {
+            self.error(line, &name, "too many arguments");
+            return None;
+        }
+        Some(())
+    }
+
+    /// Report expressions which can not be assigned
+    fn check_lvalue(&mut self, expr: &Expr, token: &str) -> bool {
+        match expr.kind {
+            ExprKind::Identifier {
+                symbol: Some(id),
+                ref name,
+                ..
+            } => {
+                let reason = match self.symbols[id].storage {
+                    Storage::Const | Storage::Parameter { is_const: true, .. } => Some("can't modify a const variable"),
+                    Storage::Uniform => Some("can't modify a uniform"),
+                    Storage::Attribute => Some("can't modify an attribute"),
+                    Storage::Varying if self.stage == Stage::Fragment => Some("can't modify a varying"),
+                    Storage::BuiltIn(variable) if variable.is_read_only() => Some("can't modify a shader input"),
+                    _ => None,
+                };
+                match reason {
+                    Some(reason) => {
+                        let message = format!("l-value required \"{}\" ({})", name, reason);
+                        self.error(expr.line, token, &message);
+                        false
+                    }
+                    None => true,
+                }
+            }
+            ExprKind::Index(ref base, _) => self.check_lvalue(base, token),
+            ExprKind::Field {
+                ref base,
+                access: Some(FieldAccess::Swizzle(ref components)),
+                ..
+            } => {
+                let mut seen = [false; 4];
+                for &component in components {
+                    if seen[component as usize] {
+                        self.error(expr.line, token, "l-value of swizzle can not have duplicate components");
+                        return false;
+                    }
+                    seen[component as usize] = true;
+                }
+                self.check_lvalue(base, token)
+            }
+            ExprKind::Field { ref base, .. } => self.check_lvalue(base, token),
+            _ => {
+                self.error(expr.line, token, "l-value required");
+                false
+            }
+        }
+    }
+
+    /// Constant expressions are made of literals, `const` variables, constructors and built-in functions
+    fn is_constant(&self, expr: &Expr) -> bool {
+        match expr.kind {
+            ExprKind::IntConstant(_) | ExprKind::FloatConstant(_) | ExprKind::BoolConstant(_) => true,
+            ExprKind::Identifier { symbol, .. } => symbol.map_or(false, |id| self.symbols[id].storage == Storage::Const),
+            ExprKind::Unary(operator, ref operand) => match operator {
+                UnaryOp::Plus | UnaryOp::Minus | UnaryOp::Not => self.is_constant(operand),
+                _ => false,
+            },
+            ExprKind::Binary(_, ref lhs, ref rhs) | ExprKind::Index(ref lhs, ref rhs) => {
+                self.is_constant(lhs) && self.is_constant(rhs)
+            }
+            ExprKind::Ternary(ref condition, ref then, ref else_) => {
+                self.is_constant(condition) && self.is_constant(then) && self.is_constant(else_)
+            }
+            ExprKind::Field { ref base, .. } => self.is_constant(base),
+            ExprKind::Call {
+                ref arguments, callee, ..
+            } => match callee {
+                Some(Callee::Constructor(_)) | Some(Callee::Builtin(_)) => {
+                    arguments.iter().all(|argument| self.is_constant(argument))
+                }
+                _ => false,
+            },
+            ExprKind::Assign(..) | ExprKind::Sequence(..) => false,
+        }
+    }
+
+    /// Fold scalar constant expressions
+    fn evaluate(&self, expr: &Expr) -> Option<Constant> {
+        match expr.kind {
+            ExprKind::IntConstant(value) => Some(Constant::Int(value)),
+            ExprKind::FloatConstant(value) => Some(Constant::Float(value)),
+            ExprKind::BoolConstant(value) => Some(Constant::Bool(value)),
+            ExprKind::Identifier { symbol: Some(id), .. } if self.symbols[id].storage == Storage::Const => {
+                self.symbols[id].constant
+            }
+            ExprKind::Unary(operator, ref operand) => match (operator, self.evaluate(operand)?) {
+                (UnaryOp::Plus, value) => Some(value),
+                (UnaryOp::Minus, Constant::Int(value)) => Some(Constant::Int(value.wrapping_neg())),
+                (UnaryOp::Minus, Constant::Float(value)) => Some(Constant::Float(-value)),
+                (UnaryOp::Not, Constant::Bool(value)) => Some(Constant::Bool(!value)),
+                _ => None,
+            },
+            ExprKind::Binary(operator, ref lhs, ref rhs) => evaluate_binary(operator, self.evaluate(lhs)?, self.evaluate(rhs)?),
+            ExprKind::Ternary(ref condition, ref then, ref else_) => match self.evaluate(condition)? {
+                Constant::Bool(true) => self.evaluate(then),
+                Constant::Bool(false) => self.evaluate(else_),
+                _ => None,
+            },
+            ExprKind::Call {
+                ref arguments,
+                callee: Some(Callee::Constructor(ty)),
+                ..
+            } if arguments.len() == 1 && ty.is_scalar() && arguments[0].ty.is_scalar() => {
+                let value = self.evaluate(&arguments[0])?;
+                let value = match (ty.base, value) {
+                    (BaseType::Scalar(ScalarKind::Int), Constant::Float(value)) => Constant::Int(value as i32),
+                    (BaseType::Scalar(ScalarKind::Int), Constant::Bool(value)) => Constant::Int(value as i32),
+                    (BaseType::Scalar(ScalarKind::Float), Constant::Int(value)) => Constant::Float(value as f32),
+                    (BaseType::Scalar(ScalarKind::Float), Constant::Bool(value)) => Constant::Float(value as i32 as f32),
+                    (BaseType::Scalar(ScalarKind::Bool), Constant::Int(value)) => Constant::Bool(value != 0),
+                    (BaseType::Scalar(ScalarKind::Bool), Constant::Float(value)) => Constant::Bool(value != 0.0),
+                    (_, value) => value,
+                };
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VisitState {
+    Unvisited,
+    /// On the path of calls being followed
+    Visiting,
+    Visited,
+}
+
+/// Depth first search of the call graph from `id`, collecting the calls back into the current path
+fn find_recursive_calls(
+    id: FunctionId,
+    callees: &[Vec<(FunctionId, u32)>],
+    states: &mut [VisitState],
+    recursive_calls: &mut Vec<(FunctionId, u32)>,
+) {
+    if states[id] != VisitState::Unvisited {
+        return;
+    }
+    states[id] = VisitState::Visiting;
+    for &(callee, line) in &callees[id] {
+        match states[callee] {
+            VisitState::Unvisited => find_recursive_calls(callee, callees, states, recursive_calls),
+            VisitState::Visiting => recursive_calls.push((callee, line)),
+            VisitState::Visited => {}
+        }
+    }
+    states[id] = VisitState::Visited;
+}
+
+fn evaluate_binary(operator: BinaryOp, lhs: Constant, rhs: Constant) -> Option<Constant> {
+    let value = match (lhs, rhs) {
+        (Constant::Int(lhs), Constant::Int(rhs)) => match operator {
+            BinaryOp::Add => Constant::Int(lhs.wrapping_add(rhs)),
+            BinaryOp::Sub => Constant::Int(lhs.wrapping_sub(rhs)),
+            BinaryOp::Mul => Constant::Int(lhs.wrapping_mul(rhs)),
+            BinaryOp::Div if rhs != 0 => Constant::Int(lhs.wrapping_div(rhs)),
+            BinaryOp::Less => Constant::Bool(lhs < rhs),
+            BinaryOp::Greater => Constant::Bool(lhs > rhs),
+            BinaryOp::LessEqual => Constant::Bool(lhs <= rhs),
+            BinaryOp::GreaterEqual => Constant::Bool(lhs >= rhs),
+            BinaryOp::Equal => Constant::Bool(lhs == rhs),
+            BinaryOp::NotEqual => Constant::Bool(lhs != rhs),
+            _ => return None,
+        },
+        (Constant::Float(lhs), Constant::Float(rhs)) => match operator {
+            BinaryOp::Add => Constant::Float(lhs + rhs),
+            BinaryOp::Sub => Constant::Float(lhs - rhs),
+            BinaryOp::Mul => Constant::Float(lhs * rhs),
+            BinaryOp::Div => Constant::Float(lhs / rhs),
+            BinaryOp::Less => Constant::Bool(lhs < rhs),
+            BinaryOp::Greater => Constant::Bool(lhs > rhs),
+            BinaryOp::LessEqual => Constant::Bool(lhs <= rhs),
+            BinaryOp::GreaterEqual => Constant::Bool(lhs >= rhs),
+            BinaryOp::Equal => Constant::Bool(lhs == rhs),
+            BinaryOp::NotEqual => Constant::Bool(lhs != rhs),
+            _ => return None,
+        },
+        (Constant::Bool(lhs), Constant::Bool(rhs)) => match operator {
+            BinaryOp::LogicalAnd => Constant::Bool(lhs && rhs),
+            BinaryOp::LogicalOr => Constant::Bool(lhs || rhs),
+            BinaryOp::LogicalXor => Constant::Bool(lhs != rhs),
+            BinaryOp::Equal => Constant::Bool(lhs == rhs),
+            BinaryOp::NotEqual => Constant::Bool(lhs != rhs),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Type of `lhs operator rhs`, there are no implicit conversions in GLSL ES 1.00
+pub(crate) fn binary_result_type(operator: BinaryOp, lhs: &Type, rhs: &Type, structs: &[StructType]) -> Option<Type> {
+    match operator {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+            if !lhs.is_numeric() || !rhs.is_numeric() || lhs.scalar_kind() != rhs.scalar_kind() {
+                return None;
+            }
+            if lhs == rhs {
+                Some(*lhs)
+            } else if lhs.is_scalar() {
+                Some(*rhs)
+            } else if rhs.is_scalar() {
+                Some(*lhs)
+            } else if operator == BinaryOp::Mul && lhs.is_matrix() && rhs.is_vector() && lhs.size() == rhs.size() {
+                Some(*rhs)
+            } else if operator == BinaryOp::Mul && lhs.is_vector() && rhs.is_matrix() && lhs.size() == rhs.size() {
+                Some(*lhs)
+            } else {
+                None
+            }
+        }
+        BinaryOp::Less | BinaryOp::Greater | BinaryOp::LessEqual | BinaryOp::GreaterEqual => {
+            if lhs == rhs && lhs.is_scalar() && lhs.is_numeric() {
+                Some(Type::BOOL)
+            } else {
+                None
+            }
+        }
+        BinaryOp::Equal | BinaryOp::NotEqual => {
+            let is_comparable =
+                !lhs.is_array() && !has_array_field(lhs, structs) && !lhs.contains(structs, &|ty: &Type| ty.is_sampler());
+            if lhs == rhs && is_comparable {
+                Some(Type::BOOL)
+            } else {
+                None
+            }
+        }
+        BinaryOp::LogicalAnd | BinaryOp::LogicalOr | BinaryOp::LogicalXor => {
+            if *lhs == Type::BOOL && *rhs == Type::BOOL {
+                Some(Type::BOOL)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn has_array_field(ty: &Type, structs: &[StructType]) -> bool {
+    match ty.base {
+        BaseType::Struct(id) => structs[id]
+            .fields
+            .iter()
+            .any(|field| field.ty.is_array() || has_array_field(&field.ty, structs)),
+        _ => false,
+    }
+}
+
+/// Component indices of a swizzle, all from the same set of names
+fn swizzle(name: &str, size: u8) -> Option<Vec<u8>> {
+    const SETS: [&str; 3] = ["xyzw", "rgba", "stpq"];
+    if name.is_empty() || name.len() > 4 {
+        return None;
+    }
+    let first = name.chars().next().unwrap();
+    let set = SETS.iter().find(|set| set.contains(first))?;
+    name.chars()
+        .map(|c| set.find(c).map(|index| index as u8).filter(|&index| index < size))
+        .collect()
+}
+
+fn unary_token(operator: UnaryOp) -> &'static str {
+    match operator {
+        UnaryOp::Plus => "+",
+        UnaryOp::Minus => "-",
+        UnaryOp::Not => "!",
+        UnaryOp::PreIncrement | UnaryOp::PostIncrement => "++",
+        UnaryOp::PreDecrement | UnaryOp::PostDecrement => "--",
+    }
+}
+
+fn binary_token(operator: BinaryOp) -> &'static str {
+    match operator {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Less => "<",
+        BinaryOp::Greater => ">",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::Equal => "==",
+        BinaryOp::NotEqual => "!=",
+        BinaryOp::LogicalAnd => "&&",
+        BinaryOp::LogicalOr => "||",
+        BinaryOp::LogicalXor => "^^",
+    }
+}
+
+fn assign_token(operator: BinaryOp) -> &'static str {
+    match operator {
+        BinaryOp::Add => "+=",
+        BinaryOp::Sub => "-=",
+        BinaryOp::Mul => "*=",
+        _ => "/=",
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use super::checker::CompiledShader;
+use super::types::{BaseType, StructType, Type};
+
+/// Descriptor set holding the resources of a program
+pub(crate) const DESCRIPTOR_SET: u32 = 0;
+/// Binding of the uniform block loose uniforms are packed into, samplers follow it
+pub(crate) const UNIFORM_BLOCK_BINDING: u32 = 0;
+
+/// Locations, uniform offsets and bindings shared by the stages of a program
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Layout {
+    pub attribute_locations: HashMap<String, u32>,
+    pub varying_locations: HashMap<String, u32>,
+    /// Byte offsets in the uniform block, laid out with the std140 rules
+    pub uniform_offsets: HashMap<String, u32>,
+    pub uniform_block_size: u32,
+    pub sampler_bindings: HashMap<String, u32>,
+}
+
+impl Layout {
+    /// Assign locations, offsets and bindings in declaration order, interface variables of the same name
+    /// share them across shaders. Attributes in `attribute_bindings` keep their location, the others take the
+    /// first free ones.
+    pub fn new(shaders: &[&CompiledShader], attribute_bindings: &HashMap<String, u32>) -> Self {
+        let mut layout = Layout::default();
+        let mut next_varying = 0;
+        let mut next_binding = UNIFORM_BLOCK_BINDING + 1;
+
+        let mut used_attributes = Vec::new();
+        for shader in shaders {
+            for (_, symbol) in shader.attributes() {
+                if let Some(&location) = attribute_bindings.get(&symbol.name) {
+                    let count = symbol.ty.location_count(&shader.structs);
+                    layout.attribute_locations.insert(symbol.name.clone(), location);
+                    used_attributes.extend(location..location + count);
+                }
+            }
+        }
+        for shader in shaders {
+            for (_, symbol) in shader.attributes() {
+                if !layout.attribute_locations.contains_key(&symbol.name) {
+                    let count = symbol.ty.location_count(&shader.structs);
+                    let location = (0..)
+                        .find(|&location| (location..location + count).all(|used| !used_attributes.contains(&used)))
+                        .unwrap();
+                    layout.attribute_locations.insert(symbol.name.clone(), location);
+                    used_attributes.extend(location..location + count);
+                }
+            }
+            for (_, symbol) in shader.varyings() {
+                if !layout.varying_locations.contains_key(&symbol.name) {
+                    layout.varying_locations.insert(symbol.name.clone(), next_varying);
+                    next_varying += symbol.ty.location_count(&shader.structs);
+                }
+            }
+            for (_, symbol) in shader.uniforms() {
+                if symbol.ty.is_sampler() {
+                    if !layout.sampler_bindings.contains_key(&symbol.name) {
+                        layout.sampler_bindings.insert(symbol.name.clone(), next_binding);
+                        next_binding += 1;
+                    }
+                } else if !layout.uniform_offsets.contains_key(&symbol.name)
+                    && !symbol.ty.contains(&shader.structs, &|ty: &Type| ty.is_sampler())
+                {
+                    let (alignment, size) = std140(&symbol.ty, &shader.structs);
+                    let offset = align_to(layout.uniform_block_size, alignment);
+                    layout.uniform_offsets.insert(symbol.name.clone(), offset);
+                    layout.uniform_block_size = offset + size;
+                }
+            }
+        }
+
+        layout.uniform_block_size = align_to(layout.uniform_block_size, 16);
+        layout
+    }
+}
+
+#[inline]
+pub(crate) fn align_to(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+/// Stride of the elements of an array of `element`
+pub(crate) fn std140_array_stride(element: &Type, structs: &[StructType]) -> u32 {
+    let (alignment, size) = std140(element, structs);
+    align_to(size, alignment.max(16))
+}
+
+/// Byte offsets of the fields of a struct
+pub(crate) fn std140_field_offsets(id: usize, structs: &[StructType]) -> Vec<u32> {
+    let mut offset = 0;
+    structs[id]
+        .fields
+        .iter()
+        .map(|field| {
+            let (alignment, size) = std140(&field.ty, structs);
+            let field_offset = align_to(offset, alignment);
+            offset = field_offset + size;
+            field_offset
+        })
+        .collect()
+}
+
+/// Base alignment and size of a type in a std140 block, booleans are stored as 32 bit integers
+pub(crate) fn std140(ty: &Type, structs: &[StructType]) -> (u32, u32) {
+    if let Some(length) = ty.array_size {
+        let element = Type::new(ty.base);
+        let stride = std140_array_stride(&element, structs);
+        return (std140(&element, structs).0.max(16), stride * length);
+    }
+
+    match ty.base {
+        BaseType::Scalar(_) => (4, 4),
+        BaseType::Vector(_, 2) => (8, 8),
+        BaseType::Vector(_, size) => (16, 4 * size as u32),
+        BaseType::Matrix(size) => (16, 16 * size as u32),
+        BaseType::Struct(id) => {
+            let alignment = structs[id]
+                .fields
+                .iter()
+                .map(|field| std140(&field.ty, structs).0)
+                .max()
+                .unwrap_or(0)
+                .max(16);
+            let size = structs[id]
+                .fields
+                .iter()
+                .zip(std140_field_offsets(id, structs))
+                .map(|(field, offset)| offset + std140(&field.ty, structs).1)
+                .max()
+                .unwrap_or(0);
+            (alignment, align_to(size, alignment))
+        }
+        BaseType::Void | BaseType::Sampler2D | BaseType::SamplerCube => (0, 0),
+    }
+}
+
+/// Scalars, vectors, matrices and samplers a variable of `ty` is made of, with the number of elements of each,
+/// fields of structs are separate variables
+pub(crate) fn flattened(ty: &Type, structs: &[StructType]) -> Vec<(Type, u32)> {
+    let count = ty.array_size.unwrap_or(1);
+    match ty.base {
+        BaseType::Struct(id) => structs[id]
+            .fields
+            .iter()
+            .flat_map(|field| flattened(&field.ty, structs))
+            .map(|(field, field_count)| (field, field_count * count))
+            .collect(),
+        base => vec![(Type::new(base), count)],
+    }
+}
+
+/// Return true if the variables fit in `max_rows` vectors when packed with the rules of GLSL ES 1.00
+/// Appendix A §7. A variable is given as its type and number of elements, matrices take a row per column and
+/// 2x2 matrices take whole rows.
+pub(crate) fn fits_packed(variables: &[(Type, u32)], max_rows: u32) -> bool {
+    // columns and rows of each variable, in packing order: mat4, mat2, vec4, mat3, vec3, vec2, then scalars,
+    // largest first within each type
+    let mut variables = variables
+        .iter()
+        .map(|&(ty, count)| match ty.base {
+            BaseType::Matrix(2) => (1, 4, 2 * count),
+            BaseType::Matrix(4) => (0, 4, 4 * count),
+            BaseType::Matrix(size) => (3, size as usize, size as u32 * count),
+            BaseType::Vector(_, 4) => (2, 4, count),
+            BaseType::Vector(_, size) => (7 - size as u32, size as usize, count),
+            _ => (6, 1, count),
+        })
+        .collect::<Vec<_>>();
+    variables.sort_by_key(|&(order, _, rows)| (order, !rows));
+
+    let max_rows = max_rows as usize;
+    let mut is_used = vec![[false; 4]; max_rows];
+    for &(_, columns, rows) in &variables {
+        let rows = rows as usize;
+        if rows > max_rows {
+            return false;
+        }
+        let is_free = |is_used: &[[bool; 4]], row: usize, column: usize| {
+            is_used[row..row + rows]
+                .iter()
+                .all(|used| !used[column..column + columns].iter().any(|&used| used))
+        };
+        let first_free_row =
+            |is_used: &[[bool; 4]], column: usize| (0..=max_rows - rows).find(|&row| is_free(is_used, row, column));
+        let position = match columns {
+            // scalars go to the column they leave the least space in, at its lowest free rows
+            1 => (0..4)
+                .filter_map(|column| {
+                    let row = first_free_row(&is_used, column)?;
+                    let space = is_used.iter().filter(|used| !used[column]).count() - rows;
+                    Some((space, row, column))
+                })
+                .min()
+                .map(|(_, row, column)| (row, column)),
+            // vectors of 2 move to the highest rows they fit in once there is no row left in the first column
+            2 => first_free_row(&is_used, 0).map(|row| (row, 0)).or_else(|| {
+                (0..=max_rows - rows)
+                    .rev()
+                    .flat_map(|row| vec![(row, 0), (row, 2)])
+                    .find(|&(row, column)| is_free(&is_used, row, column))
+            }),
+            _ => first_free_row(&is_used, 0).map(|row| (row, 0)),
+        };
+        let (row, column) = match position {
+            Some(position) => position,
+            None => return false,
+        };
+        for used in &mut is_used[row..row + rows] {
+            for used in &mut used[column..column + columns] {
+                *used = true;
+            }
+        }
+    }
+    true
+}
@@ -0,0 +1,232 @@
+use super::Diagnostics;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TokenKind {
+    /// Identifiers and keywords alike, keywords are told apart by the parser
+    Identifier(String),
+    IntConstant(i64),
+    FloatConstant(f32),
+    Punct(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    pub line: u32,
+    /// Whitespace precedes the token, tells `#define F(x)` apart from `#define F (x)`
+    pub space_before: bool,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, line: u32) -> Self {
+        Self {
+            kind,
+            line,
+            space_before: true,
+        }
+    }
+
+    #[inline]
+    pub fn is_punct(&self, punct: &str) -> bool {
+        match self.kind {
+            TokenKind::Punct(value) => value == punct,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub fn identifier(&self) -> Option<&str> {
+        match self.kind {
+            TokenKind::Identifier(ref name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self.kind {
+            TokenKind::Identifier(ref name) => name.clone(),
+            TokenKind::IntConstant(value) => value.to_string(),
+            TokenKind::FloatConstant(value) => format!("{:?}", value),
+            TokenKind::Punct(value) => value.to_string(),
+        }
+    }
+}
+
+/// Longest operators first so that the first match is the longest one
+const PUNCTUATORS: &[&str] = &[
+    "<<=", ">>=", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "^^", "*=", "/=", "+=", "-=", "%=", "&=", "^=",
+    "|=", "(", ")", "[", "]", "{", "}", ".", ",", ":", "=", ";", "!", "-", "~", "+", "*", "/", "%", "<", ">", "|", "^", "&", "?",
+    "#",
+];
+
+/// Replace comments by a single space, keeping the newlines of block comments so that line numbers are preserved
+pub(crate) fn strip_comments(source: &str, diagnostics: &mut Diagnostics) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+                output.push(' ');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let start_line = line;
+                let mut is_closed = false;
+                while let Some(next) = chars.next() {
+                    if next == '\n' {
+                        line += 1;
+                        output.push('\n');
+                    } else if next == '*' && chars.peek() == Some(&'/') {
+                        chars.next();
+                        is_closed = true;
+                        break;
+                    }
+                }
+                if !is_closed {
+                    diagnostics.error(start_line, "/*", "unterminated comment");
+                }
+                output.push(' ');
+            }
+            '\n' => {
+                line += 1;
+                output.push(c);
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// Tokenize a single line without comments
+pub(crate) fn tokenize_line(text: &str, line: u32, diagnostics: &mut Diagnostics) -> Vec<Token> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    let mut space_before = true;
+
+    while index < bytes.len() {
+        let c = bytes[index];
+        if c.is_ascii_whitespace() {
+            space_before = true;
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        let kind = if c.is_ascii_alphabetic() || c == b'_' {
+            while index < bytes.len() && (bytes[index].is_ascii_alphanumeric() || bytes[index] == b'_') {
+                index += 1;
+            }
+            TokenKind::Identifier(text[start..index].to_string())
+        } else if c.is_ascii_digit() || (c == b'.' && index + 1 < bytes.len() && bytes[index + 1].is_ascii_digit()) {
+            let (kind, end) = scan_number(text, start, line, diagnostics);
+            index = end;
+            kind
+        } else {
+            match PUNCTUATORS.iter().find(|punct| text[start..].starts_with(*punct)) {
+                Some(punct) => {
+                    index += punct.len();
+                    TokenKind::Punct(punct)
+                }
+                None => {
+                    let character = text[start..].chars().next().unwrap();
+                    diagnostics.error(line, &character.to_string(), "invalid character");
+                    index += character.len_utf8();
+                    continue;
+                }
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            line,
+            space_before,
+        });
+        space_before = false;
+    }
+    tokens
+}
+
+fn scan_number(text: &str, start: usize, line: u32, diagnostics: &mut Diagnostics) -> (TokenKind, usize) {
+    let bytes = text.as_bytes();
+    let mut index = start;
+
+    if bytes[index] == b'0' && index + 1 < bytes.len() && (bytes[index + 1] == b'x' || bytes[index + 1] == b'X') {
+        index += 2;
+        while index < bytes.len() && bytes[index].is_ascii_hexdigit() {
+            index += 1;
+        }
+        let digits = &text[start + 2..index];
+        let value = i64::from_str_radix(digits, 16).unwrap_or_else(|_| {
+            diagnostics.error(line, &text[start..index], "invalid hexadecimal constant");
+            0
+        });
+        return (TokenKind::IntConstant(value), end_of_number(text, index, line, diagnostics));
+    }
+
+    while index < bytes.len() && bytes[index].is_ascii_digit() {
+        index += 1;
+    }
+
+    let mut is_float = false;
+    if index < bytes.len() && bytes[index] == b'.' {
+        is_float = true;
+        index += 1;
+        while index < bytes.len() && bytes[index].is_ascii_digit() {
+            index += 1;
+        }
+    }
+    if index < bytes.len() && (bytes[index] == b'e' || bytes[index] == b'E') {
+        let mut exponent = index + 1;
+        if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+            exponent += 1;
+        }
+        if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+            is_float = true;
+            index = exponent;
+            while index < bytes.len() && bytes[index].is_ascii_digit() {
+                index += 1;
+            }
+        }
+    }
+
+    let literal = &text[start..index];
+    let kind = if is_float {
+        TokenKind::FloatConstant(literal.parse().unwrap_or_else(|_| {
+            diagnostics.error(line, literal, "invalid floating point constant");
+            0.0
+        }))
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        TokenKind::IntConstant(i64::from_str_radix(&literal[1..], 8).unwrap_or_else(|_| {
+            diagnostics.error(line, literal, "invalid octal constant");
+            0
+        }))
+    } else {
+        TokenKind::IntConstant(literal.parse().unwrap_or_else(|_| {
+            diagnostics.error(line, literal, "integer constant overflow");
+            0
+        }))
+    };
+    (kind, end_of_number(text, index, line, diagnostics))
+}
+
+/// Suffixes such as `1.0f` are not part of GLSL ES 1.00
+fn end_of_number(text: &str, index: usize, line: u32, diagnostics: &mut Diagnostics) -> usize {
+    let bytes = text.as_bytes();
+    let mut end = index;
+    while end < bytes.len() && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_') {
+        end += 1;
+    }
+    if end != index {
+        diagnostics.error(line, &text[index..end], "invalid suffix on numeric constant");
+    }
+    end
+}
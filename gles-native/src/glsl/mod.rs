@@ -0,0 +1,356 @@
+mod ast;
+mod builtins;
+mod checker;
+pub(crate) mod layout;
+mod lexer;
+mod parser;
+mod preprocessor;
+pub(crate) mod spirv;
+mod translate;
+mod types;
+
+pub(crate) use self::ast::*;
+pub(crate) use self::builtins::{BuiltinVariable, BUILTIN_FUNCTIONS};
+pub(crate) use self::checker::{CompiledShader, Constant, Function, Storage, Symbol};
+pub(crate) use self::layout::{Layout, DESCRIPTOR_SET, UNIFORM_BLOCK_BINDING};
+pub(crate) use self::translate::{clip_space_position, translate, FLIP_Y};
+pub(crate) use self::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Stage {
+    Vertex,
+    Fragment,
+}
+
+/// Errors and warnings in the format of the reference compiler, `<severity>: <string>:<line>: '<token>' : <message>`
+#[derive(Debug, Default)]
+pub(crate) struct Diagnostics {
+    messages: Vec<String>,
+    error_count: usize,
+}
+
+impl Diagnostics {
+    pub fn error(&mut self, line: u32, token: &str, message: &str) {
+        self.error_count += 1;
+        self.messages.push(format!("ERROR: 0:{}: '{}' : {}", line, token, message));
+    }
+
+    pub fn warning(&mut self, line: u32, token: &str, message: &str) {
+        self.messages.push(format!("WARNING: 0:{}: '{}' : {}", line, token, message));
+    }
+
+    #[inline]
+    pub fn has_errors(&self) -> bool {
+        self.error_count > 0
+    }
+
+    pub fn info_log(&self) -> String {
+        let mut log = String::new();
+        for message in &self.messages {
+            log.push_str(message);
+            log.push('\n');
+        }
+        if self.has_errors() {
+            log.push_str(&format!(
+                "ERROR: {} compilation errors.  No code generated.\n",
+                self.error_count
+            ));
+        }
+        log
+    }
+}
+
+/// Compile `source`, returning the checked shader on success along with the info log
+pub(crate) fn compile(source: &str, stage: Stage) -> (Option<CompiledShader>, String) {
+    let mut diagnostics = Diagnostics::default();
+
+    let tokens = preprocessor::preprocess(source, stage, &mut diagnostics);
+    let shader = if diagnostics.has_errors() {
+        None
+    } else {
+        parser::parse(&tokens, &mut diagnostics).map(|unit| checker::check(unit, stage, &mut diagnostics))
+    };
+
+    if diagnostics.has_errors() {
+        (None, diagnostics.info_log())
+    } else {
+        (shader, diagnostics.info_log())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn compile_errors(source: &str, stage: Stage) -> String {
+        let (shader, info_log) = compile(source, stage);
+        assert!(shader.is_none(), "{} compiled", source);
+        info_log
+    }
+
+    #[test]
+    fn test_compile() {
+        let vertex = "
+            #version 100
+            #define SCALE(x) ((x) * 2.0)
+            attribute vec4 a_position;
+            attribute vec2 a_uv;
+            uniform mat4 u_mvp;
+            varying vec2 v_uv;
+            struct Light { vec3 color; float power[2]; };
+            uniform Light u_lights[2];
+            const int COUNT = 2;
+
+            vec3 shade(in vec3 color, inout float total) {
+                total += 1.0;
+                return color * total;
+            }
+
+            void main() {
+                float total = 0.0;
+                vec3 color = vec3(0.0);
+                for (int i = 0; i < COUNT; i++) {
+                    color += shade(u_lights[i].color, total) * u_lights[i].power[1];
+                }
+                v_uv = SCALE(a_uv).yx;
+                gl_Position = u_mvp * vec4(a_position.xyz + color, 1.0);
+            }
+        ";
+        let (shader, info_log) = compile(vertex, Stage::Vertex);
+        assert_eq!(info_log, "");
+        let shader = shader.unwrap();
+        assert_eq!(shader.attributes().count(), 2);
+        assert_eq!(shader.uniforms().count(), 2);
+        assert_eq!(shader.varyings().count(), 1);
+        assert!(shader.main().is_some());
+
+        let fragment = "
+            precision mediump float;
+            varying vec2 v_uv;
+            uniform sampler2D u_texture;
+            void main() {
+                vec4 color = texture2D(u_texture, v_uv);
+                if (color.a < 0.5) discard;
+                gl_FragColor = color.bgra;
+            }
+        ";
+        let (shader, info_log) = compile(fragment, Stage::Fragment);
+        assert!(shader.is_some(), "{}", info_log);
+    }
+
+    #[test]
+    fn test_preprocessor() {
+        let source = "
+            #define A 1
+            #if defined(A) && A + 1 == 2
+            #define VALUE 1.0
+            #elif 1
+            #error unreachable
+            #else
+            #error unreachable
+            #endif
+            #ifndef GL_ES
+            #error GL_ES
+            #endif
+            void main() { gl_Position = vec4(VALUE); }
+        ";
+        let (shader, info_log) = compile(source, Stage::Vertex);
+        assert!(shader.is_some(), "{}", info_log);
+
+        let info_log = compile_errors("void main() {}\n#version 100\n", Stage::Vertex);
+        assert!(info_log.starts_with("ERROR: 0:2: 'version' : #version must occur before anything else\n"));
+        let info_log = compile_errors("#if 1\nvoid main() {}\n", Stage::Vertex);
+        assert!(info_log.contains("unterminated #if"));
+        let info_log = compile_errors("#extension GL_foo : require\nvoid main() {}\n", Stage::Vertex);
+        assert!(info_log.contains("'GL_foo' : extension is not supported"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let info_log = compile_errors("void main() {\n    float x = 1;\n    x = y;\n}\n", Stage::Vertex);
+        assert_eq!(
+            info_log,
+            "ERROR: 0:2: 'x' : can not initialize a variable of type 'float' with an expression of type 'int'\n\
+             ERROR: 0:3: 'y' : undeclared identifier\n\
+             ERROR: 2 compilation errors.  No code generated.\n"
+        );
+
+        let info_log = compile_errors("void main() {\n    int x = 5 % 2;\n}\n", Stage::Vertex);
+        assert!(info_log.starts_with("ERROR: 0:2: '%' : reserved operator\n"));
+        let info_log = compile_errors("void main() { float x = 1.0; gl_FragColor = vec4(x); }", Stage::Fragment);
+        assert!(info_log.contains("No precision specified for (float)"));
+        let info_log = compile_errors("uniform float u;\nvoid main() { u = 1.0; }", Stage::Vertex);
+        assert!(info_log.contains("'=' : l-value required \"u\" (can't modify a uniform)"));
+        let info_log = compile_errors("void main() { vec3 v = vec3(1.0).xyw; }", Stage::Vertex);
+        assert!(info_log.contains("'xyw' : illegal vector field selection"));
+        let info_log = compile_errors("int main() { return 0; }", Stage::Vertex);
+        assert!(info_log.contains("'main' : function must be 'void main()'"));
+        let info_log = compile_errors("void main() { vec4 v = vec4(1.0, 2.0, 3.0, 4.0, 5.0); }", Stage::Vertex);
+        assert!(info_log.contains("'vec4' : too many arguments"));
+        let info_log = compile_errors("void main() { discard; }", Stage::Vertex);
+        assert!(info_log.contains("'discard' : only allowed in fragment shaders"));
+
+        let info_log = compile_errors("float f(float x) {\n    return f(x);\n}\nvoid main() {}", Stage::Vertex);
+        assert!(info_log.contains("ERROR: 0:2: 'f' : recursive function call"));
+        let info_log = compile_errors(
+            "float g(float x);\nfloat f(float x) { return g(x); }\nfloat g(float x) { return f(x); }\nvoid main() {}",
+            Stage::Vertex,
+        );
+        assert!(info_log.contains("recursive function call"));
+    }
+
+    #[test]
+    fn test_nesting() {
+        let source = |depth: usize| {
+            format!(
+                "void main() {{ gl_Position = vec4({}1.0{}); }}",
+                "-(".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        let info_log = compile_errors(&source(200), Stage::Vertex);
+        assert!(info_log.contains("nesting too deep"), "{}", info_log);
+        translate_source(&source(30), Stage::Vertex);
+    }
+
+    fn translate_source(source: &str, stage: Stage) -> Vec<u32> {
+        let (shader, info_log) = compile(source, stage);
+        let shader = shader.expect(&info_log);
+        let words = translate(&shader, &Layout::new(&[&shader], &HashMap::new())).unwrap();
+        assert_eq!(spirv::validate(&words), Ok(()));
+        words
+    }
+
+    #[test]
+    fn test_translate() {
+        let vertex = "
+            attribute vec3 a_position;
+            attribute vec2 a_uv;
+            uniform mat4 u_mvp;
+            uniform bool u_flip;
+            uniform vec4 u_colors[3];
+            varying vec2 v_uv;
+            varying lowp vec4 v_color;
+            invariant gl_Position;
+
+            float flip(float y) {
+                return u_flip ? 1.0 - y : y;
+            }
+
+            void main() {
+                v_uv = vec2(a_uv.x, flip(a_uv.y));
+                v_color = vec4(0.0);
+                for (int i = 0; i < 3; i++) {
+                    if (i == 1 && !u_flip) continue;
+                    v_color += u_colors[i] * float(i);
+                }
+                gl_Position = u_mvp * vec4(a_position, 1.0);
+                gl_PointSize = 1.0;
+            }
+        ";
+        let words = translate_source(vertex, Stage::Vertex);
+        assert_eq!(&words[..2], &[spirv::MAGIC_NUMBER, spirv::VERSION]);
+
+        let fragment = "
+            precision mediump float;
+            struct Material { vec3 color; float shininess; };
+            uniform Material u_material;
+            uniform sampler2D u_texture;
+            uniform samplerCube u_environment;
+            varying vec2 v_uv;
+
+            void main() {
+                vec4 color = texture2D(u_texture, v_uv) * vec4(u_material.color, 1.0);
+                color.rgb = mix(color.rgb, textureCube(u_environment, vec3(v_uv, 1.0)).rgb, 0.5);
+                if (color.a < 0.1) {
+                    discard;
+                }
+                gl_FragColor = color * pow(max(dot(normalize(gl_FragCoord.xyz), vec3(0.0, 0.0, 1.0)), 0.0),
+                    u_material.shininess);
+            }
+        ";
+        translate_source(fragment, Stage::Fragment);
+    }
+
+    #[test]
+    fn test_clip_space_position() {
+        // depth moves from [-w, w] to [0, w]
+        let flipped = if FLIP_Y { -2.0 } else { 2.0 };
+        assert_eq!(clip_space_position([1.0, 2.0, -4.0, 4.0]), [1.0, flipped, 0.0, 4.0]);
+        assert_eq!(clip_space_position([1.0, 2.0, 4.0, 4.0]), [1.0, flipped, 4.0, 4.0]);
+
+        // every return of main adjusts gl_Position, other functions leave it alone
+        let source = "
+            uniform bool u_early;
+            void set(float x) { gl_Position = vec4(x); return; }
+            void main() {
+                set(1.0);
+                if (u_early) return;
+                gl_Position.x = 2.0;
+            }
+        ";
+        let words = translate_source(source, Stage::Vertex);
+        let count = |opcode: u16| {
+            let mut count = 0;
+            let mut position = 5;
+            while position < words.len() {
+                if words[position] & 0xffff == opcode as u32 {
+                    count += 1;
+                }
+                position += (words[position] >> 16) as usize;
+            }
+            count
+        };
+        assert_eq!(count(spirv::op::RETURN), 3);
+        assert_eq!(count(spirv::op::F_MUL), 2);
+        assert_eq!(count(spirv::op::F_NEGATE), if FLIP_Y { 2 } else { 0 });
+    }
+
+    #[test]
+    fn test_layout() {
+        let source = "
+            struct Light { vec3 position; float power; vec2 range; };
+            uniform float u_a;
+            uniform vec3 u_b;
+            uniform float u_c[2];
+            uniform Light u_light;
+            uniform mat3 u_d;
+            uniform bool u_e;
+            uniform sampler2D u_texture;
+            attribute vec4 a_position;
+            attribute mat2 a_matrix;
+            attribute vec4 a_color;
+            void main() { gl_Position = a_position; }
+        ";
+        let (shader, info_log) = compile(source, Stage::Vertex);
+        let shader = shader.expect(&info_log);
+        let layout = Layout::new(&[&shader], &HashMap::new());
+
+        let offset = |name: &str| layout.uniform_offsets[name];
+        assert_eq!((offset("u_a"), offset("u_b"), offset("u_c")), (0, 16, 32));
+        assert_eq!((offset("u_light"), offset("u_d"), offset("u_e")), (64, 96, 144));
+        assert_eq!(layout.uniform_block_size, 160);
+        assert_eq!(layout.sampler_bindings["u_texture"], UNIFORM_BLOCK_BINDING + 1);
+        assert_eq!(layout.attribute_locations["a_color"], 3);
+    }
+
+    #[test]
+    fn test_packing() {
+        use self::layout::fits_packed;
+
+        let (vec2, vec3, vec4) = (
+            Type::vector(ScalarKind::Float, 2),
+            Type::vector(ScalarKind::Float, 3),
+            Type::vector(ScalarKind::Float, 4),
+        );
+        assert!(fits_packed(&[(vec4, 8)], 8));
+        assert!(!fits_packed(&[(vec4, 9)], 8));
+        // scalars fill the column vectors of 3 leave, vectors of 2 pair up in a row
+        assert!(fits_packed(&[(vec3, 8), (Type::FLOAT, 8)], 8));
+        assert!(fits_packed(&vec![(vec2, 1); 16], 8));
+        assert!(!fits_packed(&[(vec2, 16)], 8));
+        // 2x2 matrices take whole rows
+        assert!(!fits_packed(&[(Type::matrix(2), 1), (vec3, 7)], 8));
+    }
+}
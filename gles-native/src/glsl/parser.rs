@@ -0,0 +1,808 @@
+use std::collections::HashSet;
+
+use super::ast::*;
+use super::lexer::{Token, TokenKind};
+use super::types::{builtin_type, Precision};
+use super::Diagnostics;
+
+/// Keywords of GLSL ES 1.00 which are not type names
+const KEYWORDS: &[&str] = &[
+    "attribute",
+    "const",
+    "uniform",
+    "varying",
+    "break",
+    "continue",
+    "do",
+    "for",
+    "while",
+    "if",
+    "else",
+    "in",
+    "out",
+    "inout",
+    "true",
+    "false",
+    "lowp",
+    "mediump",
+    "highp",
+    "precision",
+    "invariant",
+    "discard",
+    "return",
+    "struct",
+];
+
+/// Reserved for future use, using them is an error
+const RESERVED_WORDS: &[&str] = &[
+    "asm",
+    "class",
+    "union",
+    "enum",
+    "typedef",
+    "template",
+    "this",
+    "packed",
+    "goto",
+    "switch",
+    "default",
+    "inline",
+    "noinline",
+    "volatile",
+    "public",
+    "static",
+    "extern",
+    "external",
+    "interface",
+    "flat",
+    "long",
+    "short",
+    "double",
+    "half",
+    "fixed",
+    "unsigned",
+    "superp",
+    "input",
+    "output",
+    "hvec2",
+    "hvec3",
+    "hvec4",
+    "dvec2",
+    "dvec3",
+    "dvec4",
+    "fvec2",
+    "fvec3",
+    "fvec4",
+    "sampler1D",
+    "sampler3D",
+    "sampler1DShadow",
+    "sampler2DShadow",
+    "sampler2DRect",
+    "sampler3DRect",
+    "sampler2DRectShadow",
+    "sizeof",
+    "cast",
+    "namespace",
+    "using",
+];
+
+/// Operators of desktop GLSL which are reserved in GLSL ES 1.00
+const RESERVED_OPERATORS: &[&str] = &["%", "~", "<<", ">>", "&", "|", "^", "%=", "<<=", ">>=", "&=", "^=", "|="];
+
+type ParseResult<T> = Result<T, ()>;
+
+/// Statements and expressions nested deeper than this are an error rather than a stack overflow of the recursive
+/// descent, and of the passes walking the tree after it
+const MAX_NESTING_DEPTH: u32 = 64;
+
+/// Parse the preprocessed tokens, stopping at the first syntax error
+pub(crate) fn parse(tokens: &[Token], diagnostics: &mut Diagnostics) -> Option<TranslationUnit> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        depth: 0,
+        struct_names: HashSet::new(),
+        diagnostics,
+    };
+
+    let mut unit = TranslationUnit::default();
+    while parser.peek().is_some() {
+        match parser.external_declaration() {
+            Ok(declaration) => unit.declarations.push(declaration),
+            Err(()) => return None,
+        }
+    }
+    Some(unit)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    /// Nesting of the statement or expression being parsed
+    depth: u32,
+    /// Struct names are type names from their declaration on
+    struct_names: HashSet<String>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl<'a> Parser<'a> {
+    #[inline]
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    #[inline]
+    fn peek_at(&self, offset: usize) -> Option<&'a Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    fn line(&self) -> u32 {
+        self.peek().or_else(|| self.tokens.last()).map_or(1, |token| token.line)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        self.peek().map_or(false, |token| token.is_punct(punct))
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.peek().and_then(|token| token.identifier()) == Some(keyword)
+    }
+
+    fn accept_punct(&mut self, punct: &str) -> bool {
+        if self.is_punct(punct) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn syntax_error<T>(&mut self) -> ParseResult<T> {
+        let line = self.line();
+        match self.peek() {
+            Some(token) => {
+                let text = token.text();
+                if token.identifier().map_or(false, |name| RESERVED_WORDS.contains(&name)) {
+                    self.diagnostics.error(line, &text, "reserved word");
+                } else if RESERVED_OPERATORS.iter().any(|operator| token.is_punct(operator)) {
+                    self.diagnostics.error(line, &text, "reserved operator");
+                } else {
+                    self.diagnostics.error(line, &text, "syntax error");
+                }
+            }
+            None => self.diagnostics.error(line, "", "unexpected end of file"),
+        }
+        Err(())
+    }
+
+    /// Parse with `parse` one level deeper
+    fn nested<T, F: FnOnce(&mut Self) -> ParseResult<T>>(&mut self, parse: F) -> ParseResult<T> {
+        if self.depth == MAX_NESTING_DEPTH {
+            let line = self.line();
+            let text = self.peek().map_or_else(String::new, |token| token.text());
+            self.diagnostics.error(line, &text, "nesting too deep");
+            return Err(());
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> ParseResult<()> {
+        if self.accept_punct(punct) {
+            Ok(())
+        } else {
+            self.syntax_error()
+        }
+    }
+
+    /// Identifier which is neither a keyword, a reserved word nor a type name
+    fn expect_identifier(&mut self) -> ParseResult<String> {
+        let name = match self.peek().and_then(|token| token.identifier()) {
+            Some(name) if !KEYWORDS.contains(&name) && builtin_type(name).is_none() => name.to_string(),
+            _ => return self.syntax_error(),
+        };
+        if RESERVED_WORDS.contains(&name.as_str()) {
+            return self.syntax_error();
+        }
+        self.position += 1;
+        Ok(name)
+    }
+
+    fn is_identifier_at(&self, offset: usize) -> bool {
+        self.peek_at(offset)
+            .and_then(|token| token.identifier())
+            .map_or(false, |name| {
+                !KEYWORDS.contains(&name) && builtin_type(name).is_none() && !self.struct_names.contains(name)
+            })
+    }
+
+    fn is_type_name_at(&self, offset: usize) -> bool {
+        self.peek_at(offset)
+            .and_then(|token| token.identifier())
+            .map_or(false, |name| {
+                name == "struct" || builtin_type(name).is_some() || self.struct_names.contains(name)
+            })
+    }
+
+    fn precision_qualifier(&mut self) -> Option<Precision> {
+        let precision = match self.peek().and_then(|token| token.identifier()) {
+            Some("lowp") => Precision::Low,
+            Some("mediump") => Precision::Medium,
+            Some("highp") => Precision::High,
+            _ => return None,
+        };
+        self.position += 1;
+        Some(precision)
+    }
+
+    /// Tell declarations apart from expression statements
+    fn is_declaration_start(&self) -> bool {
+        let name = match self.peek().and_then(|token| token.identifier()) {
+            Some(name) => name,
+            None => return false,
+        };
+        match name {
+            "const" | "attribute" | "uniform" | "varying" | "invariant" | "precision" | "lowp" | "mediump" | "highp"
+            | "struct" => true,
+            // `vec4(...)` and `S(...)` are constructor calls
+            _ if builtin_type(name).is_some() => !self.peek_at(1).map_or(false, |token| token.is_punct("(")),
+            _ if self.struct_names.contains(name) => self.is_identifier_at(1),
+            _ => false,
+        }
+    }
+
+    fn external_declaration(&mut self) -> ParseResult<ExternalDeclaration> {
+        let declaration = self.declaration()?;
+        if let Declaration::Prototype(prototype) = declaration {
+            if self.accept_punct(";") {
+                return Ok(ExternalDeclaration::Declaration(Declaration::Prototype(prototype)));
+            }
+            let body = self.compound_statement_body()?;
+            return Ok(ExternalDeclaration::Function(FunctionDefinition { prototype, body }));
+        }
+        Ok(ExternalDeclaration::Declaration(declaration))
+    }
+
+    /// Parse a declaration up to its `;`, except for function prototypes which are left for the caller
+    fn declaration(&mut self) -> ParseResult<Declaration> {
+        let line = self.line();
+
+        if self.accept_keyword("precision") {
+            let precision = match self.precision_qualifier() {
+                Some(precision) => precision,
+                None => return self.syntax_error(),
+            };
+            let type_ = self.type_specifier(None)?;
+            self.expect_punct(";")?;
+            return Ok(Declaration::Precision { precision, type_ });
+        }
+
+        if self.is_keyword("invariant") && self.is_identifier_at(1) {
+            self.position += 1;
+            let mut names = vec![self.expect_identifier()?];
+            while self.accept_punct(",") {
+                names.push(self.expect_identifier()?);
+            }
+            self.expect_punct(";")?;
+            return Ok(Declaration::Invariant { names, line });
+        }
+
+        let is_invariant = self.accept_keyword("invariant");
+        let qualifier = if self.accept_keyword("const") {
+            StorageQualifier::Const
+        } else if self.accept_keyword("attribute") {
+            StorageQualifier::Attribute
+        } else if self.accept_keyword("uniform") {
+            StorageQualifier::Uniform
+        } else if self.accept_keyword("varying") {
+            StorageQualifier::Varying
+        } else {
+            StorageQualifier::None
+        };
+        let precision = self.precision_qualifier();
+        let type_ = self.type_specifier(precision)?;
+
+        if self.accept_punct(";") {
+            return Ok(Declaration::Variables {
+                is_invariant,
+                qualifier,
+                type_,
+                declarators: Vec::new(),
+            });
+        }
+
+        let name_line = self.line();
+        let name = self.expect_identifier()?;
+        if self.is_punct("(") {
+            if is_invariant || qualifier != StorageQualifier::None {
+                return self.syntax_error();
+            }
+            let parameters = self.parameters()?;
+            return Ok(Declaration::Prototype(FunctionPrototype {
+                return_type: type_,
+                name,
+                parameters,
+                line: name_line,
+                function: None,
+            }));
+        }
+
+        let mut declarators = vec![self.declarator(name, name_line)?];
+        while self.accept_punct(",") {
+            let name_line = self.line();
+            let name = self.expect_identifier()?;
+            declarators.push(self.declarator(name, name_line)?);
+        }
+        self.expect_punct(";")?;
+
+        Ok(Declaration::Variables {
+            is_invariant,
+            qualifier,
+            type_,
+            declarators,
+        })
+    }
+
+    fn declarator(&mut self, name: String, line: u32) -> ParseResult<Declarator> {
+        let array_size = self.array_size()?;
+        let initializer = if self.accept_punct("=") {
+            Some(self.assignment_expression()?)
+        } else {
+            None
+        };
+        Ok(Declarator {
+            name,
+            array_size,
+            initializer,
+            line,
+            symbol: None,
+        })
+    }
+
+    fn array_size(&mut self) -> ParseResult<Option<Expr>> {
+        if !self.accept_punct("[") {
+            return Ok(None);
+        }
+        let size = self.conditional_expression()?;
+        self.expect_punct("]")?;
+        Ok(Some(size))
+    }
+
+    fn type_specifier(&mut self, precision: Option<Precision>) -> ParseResult<TypeSpecifier> {
+        let line = self.line();
+        let name = match self.peek().and_then(|token| token.identifier()) {
+            Some(name) => name,
+            None => return self.syntax_error(),
+        };
+
+        let kind = if let Some(base) = builtin_type(name) {
+            self.position += 1;
+            TypeSpecifierKind::Builtin(base)
+        } else if name == "struct" {
+            self.position += 1;
+            TypeSpecifierKind::Struct(self.struct_specifier()?)
+        } else if self.struct_names.contains(name) {
+            self.position += 1;
+            TypeSpecifierKind::TypeName(name.to_string())
+        } else {
+            return self.syntax_error();
+        };
+
+        Ok(TypeSpecifier { kind, precision, line })
+    }
+
+    fn struct_specifier(&mut self) -> ParseResult<StructSpecifier> {
+        let name = if self.is_punct("{") {
+            None
+        } else {
+            Some(self.expect_identifier()?)
+        };
+        self.expect_punct("{")?;
+
+        let mut fields = Vec::new();
+        while !self.accept_punct("}") {
+            let line = self.line();
+            let precision = self.precision_qualifier();
+            let type_ = self.type_specifier(precision)?;
+            let mut declarators = Vec::new();
+            loop {
+                let name = self.expect_identifier()?;
+                declarators.push((name, self.array_size()?));
+                if !self.accept_punct(",") {
+                    break;
+                }
+            }
+            self.expect_punct(";")?;
+            fields.push(FieldDeclaration {
+                type_,
+                declarators,
+                line,
+            });
+        }
+
+        if let Some(ref name) = name {
+            self.struct_names.insert(name.clone());
+        }
+        Ok(StructSpecifier { name, fields })
+    }
+
+    fn parameters(&mut self) -> ParseResult<Vec<Parameter>> {
+        self.expect_punct("(")?;
+        let mut parameters = Vec::new();
+        if self.accept_punct(")") {
+            return Ok(parameters);
+        }
+        if self.is_keyword("void") && self.peek_at(1).map_or(false, |token| token.is_punct(")")) {
+            self.position += 2;
+            return Ok(parameters);
+        }
+
+        loop {
+            let is_const = self.accept_keyword("const");
+            let qualifier = if self.accept_keyword("in") {
+                ParameterQualifier::In
+            } else if self.accept_keyword("out") {
+                ParameterQualifier::Out
+            } else if self.accept_keyword("inout") {
+                ParameterQualifier::InOut
+            } else {
+                ParameterQualifier::In
+            };
+            let precision = self.precision_qualifier();
+            let type_ = self.type_specifier(precision)?;
+            let (name, array_size) = if self.is_punct(",") || self.is_punct(")") {
+                (None, None)
+            } else {
+                let name = self.expect_identifier()?;
+                (Some(name), self.array_size()?)
+            };
+            parameters.push(Parameter {
+                is_const,
+                qualifier,
+                type_,
+                name,
+                array_size,
+                symbol: None,
+            });
+
+            if self.accept_punct(")") {
+                return Ok(parameters);
+            }
+            self.expect_punct(",")?;
+        }
+    }
+
+    /// Statements between `{` and `}`
+    fn compound_statement_body(&mut self) -> ParseResult<Vec<Statement>> {
+        self.expect_punct("{")?;
+        let mut statements = Vec::new();
+        while !self.accept_punct("}") {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let line = self.line();
+        let kind = self.nested(Self::statement_kind)?;
+        Ok(Statement { kind, line })
+    }
+
+    fn statement_kind(&mut self) -> ParseResult<StatementKind> {
+        let kind = if self.is_punct("{") {
+            StatementKind::Block(self.compound_statement_body()?)
+        } else if self.accept_punct(";") {
+            StatementKind::Expression(None)
+        } else if self.accept_keyword("if") {
+            self.expect_punct("(")?;
+            let condition = self.expression()?;
+            self.expect_punct(")")?;
+            let then = Box::new(self.statement()?);
+            let else_ = if self.accept_keyword("else") {
+                Some(Box::new(self.statement()?))
+            } else {
+                None
+            };
+            StatementKind::If { condition, then, else_ }
+        } else if self.accept_keyword("for") {
+            self.expect_punct("(")?;
+            let init_line = self.line();
+            let init = if self.is_declaration_start() {
+                StatementKind::Declaration(self.local_declaration()?)
+            } else {
+                let expression = if self.is_punct(";") { None } else { Some(self.expression()?) };
+                self.expect_punct(";")?;
+                StatementKind::Expression(expression)
+            };
+            let condition = if self.is_punct(";") { None } else { Some(self.expression()?) };
+            self.expect_punct(";")?;
+            let step = if self.is_punct(")") { None } else { Some(self.expression()?) };
+            self.expect_punct(")")?;
+            StatementKind::For {
+                init: Box::new(Statement {
+                    kind: init,
+                    line: init_line,
+                }),
+                condition,
+                step,
+                body: Box::new(self.statement()?),
+            }
+        } else if self.accept_keyword("while") {
+            self.expect_punct("(")?;
+            let condition = self.expression()?;
+            self.expect_punct(")")?;
+            StatementKind::While {
+                condition,
+                body: Box::new(self.statement()?),
+            }
+        } else if self.accept_keyword("do") {
+            let body = Box::new(self.statement()?);
+            if !self.accept_keyword("while") {
+                return self.syntax_error();
+            }
+            self.expect_punct("(")?;
+            let condition = self.expression()?;
+            self.expect_punct(")")?;
+            self.expect_punct(";")?;
+            StatementKind::DoWhile { body, condition }
+        } else if self.accept_keyword("continue") {
+            self.expect_punct(";")?;
+            StatementKind::Continue
+        } else if self.accept_keyword("break") {
+            self.expect_punct(";")?;
+            StatementKind::Break
+        } else if self.accept_keyword("discard") {
+            self.expect_punct(";")?;
+            StatementKind::Discard
+        } else if self.accept_keyword("return") {
+            let value = if self.is_punct(";") { None } else { Some(self.expression()?) };
+            self.expect_punct(";")?;
+            StatementKind::Return(value)
+        } else if self.is_declaration_start() {
+            StatementKind::Declaration(self.local_declaration()?)
+        } else {
+            let expression = self.expression()?;
+            self.expect_punct(";")?;
+            StatementKind::Expression(Some(expression))
+        };
+        Ok(kind)
+    }
+
+    /// Function definitions can not be nested, prototypes are left to the type checker
+    fn local_declaration(&mut self) -> ParseResult<Declaration> {
+        let declaration = self.declaration()?;
+        if let Declaration::Prototype(_) = declaration {
+            self.expect_punct(";")?;
+        }
+        Ok(declaration)
+    }
+
+    fn expression(&mut self) -> ParseResult<Expr> {
+        let mut expression = self.assignment_expression()?;
+        while self.is_punct(",") {
+            let line = self.line();
+            self.position += 1;
+            let rhs = self.assignment_expression()?;
+            expression = Expr::new(ExprKind::Sequence(Box::new(expression), Box::new(rhs)), line);
+        }
+        Ok(expression)
+    }
+
+    /// Every nested expression but unary operands goes through here
+    fn assignment_expression(&mut self) -> ParseResult<Expr> {
+        self.nested(Self::assignment)
+    }
+
+    fn assignment(&mut self) -> ParseResult<Expr> {
+        let lhs = self.conditional_expression()?;
+        let line = self.line();
+        let operator = match self.peek().map(|token| &token.kind) {
+            Some(&TokenKind::Punct("=")) => None,
+            Some(&TokenKind::Punct("+=")) => Some(BinaryOp::Add),
+            Some(&TokenKind::Punct("-=")) => Some(BinaryOp::Sub),
+            Some(&TokenKind::Punct("*=")) => Some(BinaryOp::Mul),
+            Some(&TokenKind::Punct("/=")) => Some(BinaryOp::Div),
+            Some(&TokenKind::Punct(punct)) if RESERVED_OPERATORS.contains(&punct) => return self.syntax_error(),
+            _ => return Ok(lhs),
+        };
+        self.position += 1;
+        let rhs = self.assignment_expression()?;
+        Ok(Expr::new(ExprKind::Assign(operator, Box::new(lhs), Box::new(rhs)), line))
+    }
+
+    fn conditional_expression(&mut self) -> ParseResult<Expr> {
+        let condition = self.binary_expression(0)?;
+        if !self.is_punct("?") {
+            return Ok(condition);
+        }
+        let line = self.line();
+        self.position += 1;
+        let then = self.expression()?;
+        self.expect_punct(":")?;
+        let else_ = self.assignment_expression()?;
+        Ok(Expr::new(
+            ExprKind::Ternary(Box::new(condition), Box::new(then), Box::new(else_)),
+            line,
+        ))
+    }
+
+    fn binary_operator(&self) -> Option<(BinaryOp, u32)> {
+        let punct = match self.peek().map(|token| &token.kind) {
+            Some(&TokenKind::Punct(punct)) => punct,
+            _ => return None,
+        };
+        let operator = match punct {
+            "||" => (BinaryOp::LogicalOr, 1),
+            "^^" => (BinaryOp::LogicalXor, 2),
+            "&&" => (BinaryOp::LogicalAnd, 3),
+            "==" => (BinaryOp::Equal, 4),
+            "!=" => (BinaryOp::NotEqual, 4),
+            "<" => (BinaryOp::Less, 5),
+            ">" => (BinaryOp::Greater, 5),
+            "<=" => (BinaryOp::LessEqual, 5),
+            ">=" => (BinaryOp::GreaterEqual, 5),
+            "+" => (BinaryOp::Add, 6),
+            "-" => (BinaryOp::Sub, 6),
+            "*" => (BinaryOp::Mul, 7),
+            "/" => (BinaryOp::Div, 7),
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    /// Left associative binary operators by precedence climbing
+    fn binary_expression(&mut self, min_precedence: u32) -> ParseResult<Expr> {
+        let mut lhs = self.unary_expression()?;
+        loop {
+            if self.peek().map_or(false, |token| {
+                ["%", "<<", ">>", "&", "|", "^"]
+                    .iter()
+                    .any(|operator| token.is_punct(operator))
+            }) {
+                return self.syntax_error();
+            }
+            let (operator, precedence) = match self.binary_operator() {
+                Some((operator, precedence)) if precedence > min_precedence => (operator, precedence),
+                _ => return Ok(lhs),
+            };
+            let line = self.line();
+            self.position += 1;
+            let rhs = self.binary_expression(precedence)?;
+            lhs = Expr::new(ExprKind::Binary(operator, Box::new(lhs), Box::new(rhs)), line);
+        }
+    }
+
+    fn unary_expression(&mut self) -> ParseResult<Expr> {
+        let line = self.line();
+        let operator = match self.peek().map(|token| &token.kind) {
+            Some(&TokenKind::Punct("+")) => UnaryOp::Plus,
+            Some(&TokenKind::Punct("-")) => UnaryOp::Minus,
+            Some(&TokenKind::Punct("!")) => UnaryOp::Not,
+            Some(&TokenKind::Punct("++")) => UnaryOp::PreIncrement,
+            Some(&TokenKind::Punct("--")) => UnaryOp::PreDecrement,
+            Some(&TokenKind::Punct("~")) => return self.syntax_error(),
+            _ => return self.postfix_expression(),
+        };
+        self.position += 1;
+        let operand = self.nested(Self::unary_expression)?;
+        Ok(Expr::new(ExprKind::Unary(operator, Box::new(operand)), line))
+    }
+
+    fn postfix_expression(&mut self) -> ParseResult<Expr> {
+        let mut expression = self.primary_expression()?;
+        loop {
+            let line = self.line();
+            if self.accept_punct("[") {
+                let index = self.expression()?;
+                self.expect_punct("]")?;
+                expression = Expr::new(ExprKind::Index(Box::new(expression), Box::new(index)), line);
+            } else if self.accept_punct(".") {
+                let name = match self.peek().and_then(|token| token.identifier()) {
+                    Some(name) => name.to_string(),
+                    None => return self.syntax_error(),
+                };
+                self.position += 1;
+                expression = Expr::new(
+                    ExprKind::Field {
+                        base: Box::new(expression),
+                        name,
+                        access: None,
+                    },
+                    line,
+                );
+            } else if self.accept_punct("++") {
+                expression = Expr::new(ExprKind::Unary(UnaryOp::PostIncrement, Box::new(expression)), line);
+            } else if self.accept_punct("--") {
+                expression = Expr::new(ExprKind::Unary(UnaryOp::PostDecrement, Box::new(expression)), line);
+            } else {
+                return Ok(expression);
+            }
+        }
+    }
+
+    fn primary_expression(&mut self) -> ParseResult<Expr> {
+        let line = self.line();
+        let token = match self.peek() {
+            Some(token) => token,
+            None => return self.syntax_error(),
+        };
+
+        let kind = match token.kind {
+            TokenKind::IntConstant(value) => {
+                if value > u32::max_value() as i64 {
+                    self.diagnostics.error(line, &token.text(), "integer constant overflow");
+                }
+                self.position += 1;
+                ExprKind::IntConstant(value as u32 as i32)
+            }
+            TokenKind::FloatConstant(value) => {
+                self.position += 1;
+                ExprKind::FloatConstant(value)
+            }
+            TokenKind::Punct("(") => {
+                self.position += 1;
+                let expression = self.expression()?;
+                self.expect_punct(")")?;
+                return Ok(expression);
+            }
+            TokenKind::Identifier(ref name) if name == "true" || name == "false" => {
+                self.position += 1;
+                ExprKind::BoolConstant(name == "true")
+            }
+            TokenKind::Identifier(ref name) if self.is_type_name_at(0) || self.is_identifier_at(0) => {
+                let is_type_name = self.is_type_name_at(0);
+                if name == "struct" || name == "void" {
+                    return self.syntax_error();
+                }
+                self.position += 1;
+                if self.is_punct("(") {
+                    ExprKind::Call {
+                        name: name.clone(),
+                        arguments: self.arguments()?,
+                        callee: None,
+                    }
+                } else if is_type_name {
+                    // a type name is only valid as a constructor
+                    return self.syntax_error();
+                } else {
+                    ExprKind::Identifier {
+                        name: name.clone(),
+                        symbol: None,
+                    }
+                }
+            }
+            _ => return self.syntax_error(),
+        };
+        Ok(Expr::new(kind, line))
+    }
+
+    fn arguments(&mut self) -> ParseResult<Vec<Expr>> {
+        self.expect_punct("(")?;
+        let mut arguments = Vec::new();
+        if self.accept_punct(")") {
+            return Ok(arguments);
+        }
+        if self.is_keyword("void") && self.peek_at(1).map_or(false, |token| token.is_punct(")")) {
+            self.position += 2;
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.assignment_expression()?);
+            if self.accept_punct(")") {
+                return Ok(arguments);
+            }
+            self.expect_punct(",")?;
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+
+use super::lexer::{self, Token, TokenKind};
+use super::{Diagnostics, Stage};
+
+/// Extensions a shader may enable with `#extension`
+const SUPPORTED_EXTENSIONS: &[&str] = &[];
+
+#[derive(Clone, Debug, PartialEq)]
+struct Macro {
+    parameters: Option<Vec<String>>,
+    body: Vec<Token>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Conditional {
+    /// Lines of the current group are kept
+    is_active: bool,
+    /// One of the groups of this `#if` has been taken
+    is_taken: bool,
+    is_parent_active: bool,
+    has_else: bool,
+}
+
+/// Expand macros, evaluate conditionals and strip directives, returning the tokens left to be parsed
+pub(crate) fn preprocess(source: &str, stage: Stage, diagnostics: &mut Diagnostics) -> Vec<Token> {
+    let mut preprocessor = Preprocessor::new(stage);
+    let source = lexer::strip_comments(source, diagnostics);
+
+    let mut pending = Vec::new();
+    let mut line = 1;
+    for text in source.split('\n') {
+        let tokens = lexer::tokenize_line(text, line, diagnostics);
+        line += 1;
+
+        if tokens.first().map_or(false, |token| token.is_punct("#")) {
+            preprocessor.expand_into_output(&mut pending, diagnostics);
+            if let Some(next_line) = preprocessor.directive(&tokens, diagnostics) {
+                line = next_line;
+            }
+        } else if !tokens.is_empty() {
+            preprocessor.has_content = true;
+            if preprocessor.is_active() {
+                pending.extend(tokens);
+            }
+        }
+    }
+    preprocessor.expand_into_output(&mut pending, diagnostics);
+
+    if !preprocessor.conditionals.is_empty() {
+        diagnostics.error(line - 1, "", "unterminated #if, #ifdef or #ifndef");
+    }
+    preprocessor.output
+}
+
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    conditionals: Vec<Conditional>,
+    output: Vec<Token>,
+    /// Tokens or directives other than `#version` have been seen
+    has_content: bool,
+}
+
+impl Preprocessor {
+    fn new(stage: Stage) -> Self {
+        let mut preprocessor = Self {
+            macros: HashMap::new(),
+            conditionals: Vec::new(),
+            output: Vec::new(),
+            has_content: false,
+        };
+
+        preprocessor.define_constant("GL_ES", 1);
+        if stage == Stage::Fragment {
+            preprocessor.define_constant("GL_FRAGMENT_PRECISION_HIGH", 1);
+        }
+        preprocessor
+    }
+
+    fn define_constant(&mut self, name: &str, value: i64) {
+        self.macros.insert(
+            name.to_string(),
+            Macro {
+                parameters: None,
+                body: vec![Token::new(TokenKind::IntConstant(value), 0)],
+            },
+        );
+    }
+
+    #[inline]
+    fn is_active(&self) -> bool {
+        self.conditionals.last().map_or(true, |conditional| conditional.is_active)
+    }
+
+    fn expand_into_output(&mut self, pending: &mut Vec<Token>, diagnostics: &mut Diagnostics) {
+        if pending.is_empty() {
+            return;
+        }
+        let tokens = pending.split_off(0);
+        let expanded = self.expand(&tokens, &mut Vec::new(), diagnostics);
+        self.output.extend(expanded);
+    }
+
+    /// Return the number of the next line for `#line`
+    fn directive(&mut self, tokens: &[Token], diagnostics: &mut Diagnostics) -> Option<u32> {
+        let line = tokens[0].line;
+        let name = match tokens.get(1) {
+            None => return None,
+            Some(token) => match token.identifier() {
+                Some(name) => name.to_string(),
+                None => {
+                    diagnostics.error(line, &token.text(), "invalid directive");
+                    return None;
+                }
+            },
+        };
+        let arguments = &tokens[2..];
+
+        match name.as_str() {
+            "if" | "ifdef" | "ifndef" => {
+                self.has_content = true;
+                let is_parent_active = self.is_active();
+                let condition = is_parent_active
+                    && match name.as_str() {
+                        "if" => self.evaluate(arguments, line, diagnostics) != 0,
+                        "ifdef" => self.is_defined(arguments, line, diagnostics),
+                        _ => !self.is_defined(arguments, line, diagnostics),
+                    };
+                self.conditionals.push(Conditional {
+                    is_active: condition,
+                    is_taken: condition,
+                    is_parent_active,
+                    has_else: false,
+                });
+                return None;
+            }
+            "elif" | "else" => {
+                let is_else = name == "else";
+                let conditional = match self.conditionals.pop() {
+                    Some(conditional) => conditional,
+                    None => {
+                        diagnostics.error(line, &name, &format!("#{} without #if", name));
+                        return None;
+                    }
+                };
+                if conditional.has_else {
+                    diagnostics.error(line, &name, &format!("#{} after #else", name));
+                }
+                let condition = conditional.is_parent_active
+                    && !conditional.is_taken
+                    && (is_else || self.evaluate(arguments, line, diagnostics) != 0);
+                self.conditionals.push(Conditional {
+                    is_active: condition,
+                    is_taken: conditional.is_taken || condition,
+                    has_else: is_else,
+                    ..conditional
+                });
+                return None;
+            }
+            "endif" => {
+                if self.conditionals.pop().is_none() {
+                    diagnostics.error(line, "endif", "#endif without #if");
+                }
+                return None;
+            }
+            _ => {}
+        }
+
+        if !self.is_active() {
+            return None;
+        }
+
+        if name != "version" {
+            self.has_content = true;
+        }
+        match name.as_str() {
+            "define" => self.define(arguments, line, diagnostics),
+            "undef" => match arguments.first().and_then(|token| token.identifier()) {
+                Some(name) if name.starts_with("GL_") => diagnostics.error(line, name, "predefined macro can not be undefined"),
+                Some(name) => {
+                    self.macros.remove(name);
+                }
+                None => diagnostics.error(line, "undef", "missing macro name"),
+            },
+            "version" => {
+                if self.has_content {
+                    diagnostics.error(line, "version", "#version must occur before anything else");
+                }
+                match arguments.first().map(|token| &token.kind) {
+                    Some(&TokenKind::IntConstant(100)) => {}
+                    _ => diagnostics.error(line, "version", "version number not supported"),
+                }
+                self.has_content = true;
+            }
+            "extension" => self.extension(arguments, line, diagnostics),
+            "error" => {
+                let message = arguments.iter().map(|token| token.text()).collect::<Vec<_>>().join(" ");
+                diagnostics.error(line, "#error", &message);
+            }
+            "pragma" => {}
+            "line" => {
+                let arguments = self.expand(arguments, &mut Vec::new(), diagnostics);
+                match arguments.first().map(|token| &token.kind) {
+                    Some(&TokenKind::IntConstant(value)) if value >= 0 => return Some(value as u32),
+                    _ => diagnostics.error(line, "line", "invalid line number"),
+                }
+            }
+            _ => diagnostics.error(line, &name, "invalid directive"),
+        }
+        None
+    }
+
+    fn define(&mut self, arguments: &[Token], line: u32, diagnostics: &mut Diagnostics) {
+        let name = match arguments.first().and_then(|token| token.identifier()) {
+            Some(name) => name.to_string(),
+            None => return diagnostics.error(line, "define", "missing macro name"),
+        };
+        if name.starts_with("GL_") || name == "__LINE__" || name == "__FILE__" || name == "__VERSION__" {
+            return diagnostics.error(line, &name, "predefined macro can not be redefined");
+        }
+
+        let mut body_start = 1;
+        let parameters = match arguments.get(1) {
+            Some(token) if token.is_punct("(") && !token.space_before => {
+                let mut parameters = Vec::new();
+                let mut index = 2;
+                loop {
+                    match arguments.get(index) {
+                        Some(token) if token.is_punct(")") && parameters.is_empty() => break,
+                        Some(token) if token.identifier().is_some() => {
+                            parameters.push(token.identifier().unwrap().to_string());
+                        }
+                        _ => return diagnostics.error(line, &name, "invalid macro parameter list"),
+                    }
+                    match arguments.get(index + 1) {
+                        Some(token) if token.is_punct(",") => index += 2,
+                        Some(token) if token.is_punct(")") => {
+                            index += 1;
+                            break;
+                        }
+                        _ => return diagnostics.error(line, &name, "invalid macro parameter list"),
+                    }
+                }
+                body_start = index + 1;
+                Some(parameters)
+            }
+            _ => None,
+        };
+
+        let definition = Macro {
+            parameters,
+            body: arguments[body_start..].to_vec(),
+        };
+        if let Some(previous) = self.macros.get(&name) {
+            if !is_same_definition(previous, &definition) {
+                return diagnostics.error(line, &name, "macro redefined");
+            }
+        }
+        self.macros.insert(name, definition);
+    }
+
+    fn extension(&mut self, arguments: &[Token], line: u32, diagnostics: &mut Diagnostics) {
+        let (name, behavior) = match arguments {
+            [name, colon, behavior] if colon.is_punct(":") => match (name.identifier(), behavior.identifier()) {
+                (Some(name), Some(behavior)) => (name, behavior),
+                _ => return diagnostics.error(line, "extension", "invalid #extension directive"),
+            },
+            _ => return diagnostics.error(line, "extension", "invalid #extension directive"),
+        };
+
+        let is_supported = SUPPORTED_EXTENSIONS.contains(&name);
+        match behavior {
+            "require" if name == "all" => diagnostics.error(line, name, "extension 'all' can not be required"),
+            "enable" if name == "all" => diagnostics.error(line, name, "extension 'all' can not be enabled"),
+            "require" if !is_supported => diagnostics.error(line, name, "extension is not supported"),
+            "enable" | "warn" if !is_supported && name != "all" => diagnostics.warning(line, name, "extension is not supported"),
+            "require" | "enable" | "warn" | "disable" => {}
+            _ => diagnostics.error(line, behavior, "invalid extension behavior"),
+        }
+    }
+
+    fn is_defined(&self, arguments: &[Token], line: u32, diagnostics: &mut Diagnostics) -> bool {
+        match arguments.first().and_then(|token| token.identifier()) {
+            Some(name) => self.is_macro(name),
+            None => {
+                diagnostics.error(line, "", "missing macro name");
+                false
+            }
+        }
+    }
+
+    #[inline]
+    fn is_macro(&self, name: &str) -> bool {
+        self.macros.contains_key(name) || name == "__LINE__" || name == "__FILE__" || name == "__VERSION__"
+    }
+
+    /// Evaluate the expression of `#if` and `#elif`
+    fn evaluate(&mut self, arguments: &[Token], line: u32, diagnostics: &mut Diagnostics) -> i64 {
+        // `defined` is resolved before macro expansion
+        let mut resolved = Vec::new();
+        let mut index = 0;
+        while index < arguments.len() {
+            let token = &arguments[index];
+            if token.identifier() == Some("defined") {
+                let (name, next) = match (arguments.get(index + 1), arguments.get(index + 2), arguments.get(index + 3)) {
+                    (Some(open), Some(name), Some(close)) if open.is_punct("(") && close.is_punct(")") => (name, index + 4),
+                    (Some(name), _, _) => (name, index + 2),
+                    _ => {
+                        diagnostics.error(line, "defined", "missing macro name");
+                        return 0;
+                    }
+                };
+                let is_defined = name.identifier().map_or(false, |name| self.is_macro(name));
+                resolved.push(Token::new(TokenKind::IntConstant(is_defined as i64), line));
+                index = next;
+            } else {
+                resolved.push(token.clone());
+                index += 1;
+            }
+        }
+
+        let tokens = self.expand(&resolved, &mut Vec::new(), diagnostics);
+        if tokens.is_empty() {
+            diagnostics.error(line, "if", "missing expression");
+            return 0;
+        }
+
+        let mut evaluator = Evaluator {
+            tokens: &tokens,
+            position: 0,
+            line,
+            is_valid: true,
+        };
+        let value = evaluator.expression(0);
+        if evaluator.position != tokens.len() || !evaluator.is_valid {
+            diagnostics.error(line, "if", "invalid expression");
+            return 0;
+        }
+        value
+    }
+
+    /// Expand the macros of `tokens`, `disabled` holds the macros being expanded to stop recursion
+    fn expand(&self, tokens: &[Token], disabled: &mut Vec<String>, diagnostics: &mut Diagnostics) -> Vec<Token> {
+        let mut output = Vec::with_capacity(tokens.len());
+        let mut index = 0;
+
+        while index < tokens.len() {
+            let token = &tokens[index];
+            index += 1;
+
+            let name = match token.identifier() {
+                Some(name) if !disabled.iter().any(|disabled| disabled == name) => name,
+                _ => {
+                    output.push(token.clone());
+                    continue;
+                }
+            };
+
+            let builtin = match name {
+                "__LINE__" => Some(token.line as i64),
+                "__FILE__" => Some(0),
+                "__VERSION__" => Some(100),
+                _ => None,
+            };
+            if let Some(value) = builtin {
+                output.push(Token {
+                    kind: TokenKind::IntConstant(value),
+                    ..token.clone()
+                });
+                continue;
+            }
+
+            let definition = match self.macros.get(name) {
+                Some(definition) => definition,
+                None => {
+                    output.push(token.clone());
+                    continue;
+                }
+            };
+
+            let body = match definition.parameters {
+                None => definition.body.clone(),
+                Some(ref parameters) => {
+                    if !tokens.get(index).map_or(false, |token| token.is_punct("(")) {
+                        // a function-like macro name without arguments is not an invocation
+                        output.push(token.clone());
+                        continue;
+                    }
+
+                    let (arguments, next) = match collect_arguments(tokens, index) {
+                        Some(result) => result,
+                        None => {
+                            diagnostics.error(token.line, name, "unterminated macro invocation");
+                            return output;
+                        }
+                    };
+                    index = next;
+
+                    let is_empty_call = arguments.len() == 1 && arguments[0].is_empty();
+                    if arguments.len() != parameters.len() && !(parameters.is_empty() && is_empty_call) {
+                        diagnostics.error(token.line, name, "wrong number of macro arguments");
+                        continue;
+                    }
+
+                    let arguments = arguments
+                        .iter()
+                        .map(|argument| self.expand(argument, disabled, diagnostics))
+                        .collect::<Vec<_>>();
+                    let mut body = Vec::new();
+                    for body_token in &definition.body {
+                        match body_token
+                            .identifier()
+                            .and_then(|identifier| parameters.iter().position(|parameter| parameter == identifier))
+                        {
+                            Some(position) => body.extend(arguments[position].iter().cloned()),
+                            None => body.push(body_token.clone()),
+                        }
+                    }
+                    body
+                }
+            };
+
+            // expanded tokens are reported at the invocation
+            let body = body
+                .into_iter()
+                .map(|body_token| Token {
+                    line: token.line,
+                    ..body_token
+                })
+                .collect::<Vec<_>>();
+            disabled.push(name.to_string());
+            output.extend(self.expand(&body, disabled, diagnostics));
+            disabled.pop();
+        }
+        output
+    }
+}
+
+fn is_same_definition(lhs: &Macro, rhs: &Macro) -> bool {
+    lhs.parameters == rhs.parameters
+        && lhs.body.len() == rhs.body.len()
+        && lhs.body.iter().zip(rhs.body.iter()).all(|(lhs, rhs)| lhs.kind == rhs.kind)
+}
+
+/// Split the arguments of the invocation whose `(` is at `open`, return them with the index after `)`
+fn collect_arguments(tokens: &[Token], open: usize) -> Option<(Vec<Vec<Token>>, usize)> {
+    let mut arguments = vec![Vec::new()];
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate().skip(open + 1) {
+        if token.is_punct("(") {
+            depth += 1;
+        } else if token.is_punct(")") {
+            if depth == 0 {
+                return Some((arguments, index + 1));
+            }
+            depth -= 1;
+        } else if token.is_punct(",") && depth == 0 {
+            arguments.push(Vec::new());
+            continue;
+        }
+        arguments.last_mut().unwrap().push(token.clone());
+    }
+    None
+}
+
+/// Integer expression evaluator of `#if`, operators are those of C with their precedences
+struct Evaluator<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    line: u32,
+    is_valid: bool,
+}
+
+impl<'a> Evaluator<'a> {
+    fn binary_precedence(punct: &str) -> Option<u32> {
+        let precedence = match punct {
+            "||" => 1,
+            "&&" => 2,
+            "|" => 3,
+            "^" => 4,
+            "&" => 5,
+            "==" | "!=" => 6,
+            "<" | ">" | "<=" | ">=" => 7,
+            "<<" | ">>" => 8,
+            "+" | "-" => 9,
+            "*" | "/" | "%" => 10,
+            _ => return None,
+        };
+        Some(precedence)
+    }
+
+    fn expression(&mut self, min_precedence: u32) -> i64 {
+        let mut lhs = self.unary();
+        loop {
+            let punct = match self.tokens.get(self.position).map(|token| &token.kind) {
+                Some(&TokenKind::Punct(punct)) => punct,
+                _ => return lhs,
+            };
+            let precedence = match Self::binary_precedence(punct) {
+                Some(precedence) if precedence > min_precedence => precedence,
+                _ => return lhs,
+            };
+            self.position += 1;
+            let rhs = self.expression(precedence);
+            lhs = match punct {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => {
+                    self.is_valid = false;
+                    0
+                }
+                "/" => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> i64 {
+        let token = match self.tokens.get(self.position) {
+            Some(token) => token,
+            None => {
+                self.is_valid = false;
+                return 0;
+            }
+        };
+        self.position += 1;
+
+        match token.kind {
+            TokenKind::IntConstant(value) => value,
+            // identifiers left after expansion are undefined macros
+            TokenKind::Identifier(_) => 0,
+            TokenKind::Punct("(") => {
+                let value = self.expression(0);
+                if self.tokens.get(self.position).map_or(false, |token| token.is_punct(")")) {
+                    self.position += 1;
+                } else {
+                    self.is_valid = false;
+                }
+                value
+            }
+            TokenKind::Punct("+") => self.unary(),
+            TokenKind::Punct("-") => self.unary().wrapping_neg(),
+            TokenKind::Punct("~") => !self.unary(),
+            TokenKind::Punct("!") => (self.unary() == 0) as i64,
+            _ => {
+                debug!("unexpected token {:?} in #if at line {}", token, self.line);
+                self.is_valid = false;
+                0
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub(crate) const MAGIC_NUMBER: u32 = 0x0723_0203;
+/// SPIR-V 1.0, the version consumed by Vulkan 1.0
+pub(crate) const VERSION: u32 = 0x0001_0000;
+/// Directory the translated shaders are written to for inspection
+pub(crate) const DUMP_DIRECTORY_VARIABLE: &str = "GLES_NATIVE_SPIRV_DUMP_DIR";
+
+/// Opcodes used by the translator
+pub(crate) mod op {
+    pub const UNDEF: u16 = 1;
+    pub const NAME: u16 = 5;
+    pub const MEMBER_NAME: u16 = 6;
+    pub const EXT_INST_IMPORT: u16 = 11;
+    pub const EXT_INST: u16 = 12;
+    pub const MEMORY_MODEL: u16 = 14;
+    pub const ENTRY_POINT: u16 = 15;
+    pub const EXECUTION_MODE: u16 = 16;
+    pub const CAPABILITY: u16 = 17;
+    pub const TYPE_VOID: u16 = 19;
+    pub const TYPE_BOOL: u16 = 20;
+    pub const TYPE_INT: u16 = 21;
+    pub const TYPE_FLOAT: u16 = 22;
+    pub const TYPE_VECTOR: u16 = 23;
+    pub const TYPE_MATRIX: u16 = 24;
+    pub const TYPE_IMAGE: u16 = 25;
+    pub const TYPE_SAMPLED_IMAGE: u16 = 27;
+    pub const TYPE_ARRAY: u16 = 28;
+    pub const TYPE_STRUCT: u16 = 30;
+    pub const TYPE_POINTER: u16 = 32;
+    pub const TYPE_FUNCTION: u16 = 33;
+    pub const CONSTANT_TRUE: u16 = 41;
+    pub const CONSTANT_FALSE: u16 = 42;
+    pub const CONSTANT: u16 = 43;
+    pub const CONSTANT_COMPOSITE: u16 = 44;
+    pub const FUNCTION: u16 = 54;
+    pub const FUNCTION_PARAMETER: u16 = 55;
+    pub const FUNCTION_END: u16 = 56;
+    pub const FUNCTION_CALL: u16 = 57;
+    pub const VARIABLE: u16 = 59;
+    pub const LOAD: u16 = 61;
+    pub const STORE: u16 = 62;
+    pub const ACCESS_CHAIN: u16 = 65;
+    pub const DECORATE: u16 = 71;
+    pub const MEMBER_DECORATE: u16 = 72;
+    pub const VECTOR_EXTRACT_DYNAMIC: u16 = 77;
+    pub const VECTOR_SHUFFLE: u16 = 79;
+    pub const COMPOSITE_CONSTRUCT: u16 = 80;
+    pub const COMPOSITE_EXTRACT: u16 = 81;
+    pub const IMAGE_SAMPLE_IMPLICIT_LOD: u16 = 87;
+    pub const IMAGE_SAMPLE_EXPLICIT_LOD: u16 = 88;
+    pub const IMAGE_SAMPLE_PROJ_IMPLICIT_LOD: u16 = 91;
+    pub const IMAGE_SAMPLE_PROJ_EXPLICIT_LOD: u16 = 92;
+    pub const CONVERT_F_TO_S: u16 = 110;
+    pub const CONVERT_S_TO_F: u16 = 111;
+    pub const S_NEGATE: u16 = 126;
+    pub const F_NEGATE: u16 = 127;
+    pub const I_ADD: u16 = 128;
+    pub const F_ADD: u16 = 129;
+    pub const I_SUB: u16 = 130;
+    pub const F_SUB: u16 = 131;
+    pub const I_MUL: u16 = 132;
+    pub const F_MUL: u16 = 133;
+    pub const S_DIV: u16 = 135;
+    pub const F_DIV: u16 = 136;
+    pub const F_MOD: u16 = 141;
+    pub const VECTOR_TIMES_SCALAR: u16 = 142;
+    pub const MATRIX_TIMES_SCALAR: u16 = 143;
+    pub const VECTOR_TIMES_MATRIX: u16 = 144;
+    pub const MATRIX_TIMES_VECTOR: u16 = 145;
+    pub const MATRIX_TIMES_MATRIX: u16 = 146;
+    pub const DOT: u16 = 148;
+    pub const ANY: u16 = 154;
+    pub const ALL: u16 = 155;
+    pub const LOGICAL_EQUAL: u16 = 164;
+    pub const LOGICAL_NOT_EQUAL: u16 = 165;
+    pub const LOGICAL_AND: u16 = 167;
+    pub const LOGICAL_NOT: u16 = 168;
+    pub const SELECT: u16 = 169;
+    pub const I_EQUAL: u16 = 170;
+    pub const I_NOT_EQUAL: u16 = 171;
+    pub const S_GREATER_THAN: u16 = 173;
+    pub const S_GREATER_THAN_EQUAL: u16 = 175;
+    pub const S_LESS_THAN: u16 = 177;
+    pub const S_LESS_THAN_EQUAL: u16 = 179;
+    pub const F_ORD_EQUAL: u16 = 180;
+    pub const F_ORD_NOT_EQUAL: u16 = 182;
+    pub const F_ORD_LESS_THAN: u16 = 184;
+    pub const F_ORD_GREATER_THAN: u16 = 186;
+    pub const F_ORD_LESS_THAN_EQUAL: u16 = 188;
+    pub const F_ORD_GREATER_THAN_EQUAL: u16 = 190;
+    pub const PHI: u16 = 245;
+    pub const LOOP_MERGE: u16 = 246;
+    pub const SELECTION_MERGE: u16 = 247;
+    pub const LABEL: u16 = 248;
+    pub const BRANCH: u16 = 249;
+    pub const BRANCH_CONDITIONAL: u16 = 250;
+    pub const KILL: u16 = 252;
+    pub const RETURN: u16 = 253;
+    pub const RETURN_VALUE: u16 = 254;
+    pub const UNREACHABLE: u16 = 255;
+}
+
+pub(crate) mod storage_class {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const INPUT: u32 = 1;
+    pub const UNIFORM: u32 = 2;
+    pub const OUTPUT: u32 = 3;
+    pub const PRIVATE: u32 = 6;
+    pub const FUNCTION: u32 = 7;
+}
+
+pub(crate) mod decoration {
+    pub const BLOCK: u32 = 2;
+    pub const COL_MAJOR: u32 = 5;
+    pub const ARRAY_STRIDE: u32 = 6;
+    pub const MATRIX_STRIDE: u32 = 7;
+    pub const BUILT_IN: u32 = 11;
+    pub const INVARIANT: u32 = 18;
+    pub const LOCATION: u32 = 30;
+    pub const BINDING: u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET: u32 = 35;
+}
+
+pub(crate) mod built_in {
+    pub const POSITION: u32 = 0;
+    pub const POINT_SIZE: u32 = 1;
+    pub const FRAG_COORD: u32 = 15;
+    pub const POINT_COORD: u32 = 16;
+    pub const FRONT_FACING: u32 = 17;
+}
+
+/// Instructions of the `GLSL.std.450` extended instruction set
+pub(crate) mod glsl_std {
+    pub const NAME: &str = "GLSL.std.450";
+    pub const F_ABS: u32 = 4;
+    pub const F_SIGN: u32 = 6;
+    pub const FLOOR: u32 = 8;
+    pub const CEIL: u32 = 9;
+    pub const FRACT: u32 = 10;
+    pub const RADIANS: u32 = 11;
+    pub const DEGREES: u32 = 12;
+    pub const SIN: u32 = 13;
+    pub const COS: u32 = 14;
+    pub const TAN: u32 = 15;
+    pub const ASIN: u32 = 16;
+    pub const ACOS: u32 = 17;
+    pub const ATAN: u32 = 18;
+    pub const ATAN2: u32 = 25;
+    pub const POW: u32 = 26;
+    pub const EXP: u32 = 27;
+    pub const LOG: u32 = 28;
+    pub const EXP2: u32 = 29;
+    pub const LOG2: u32 = 30;
+    pub const SQRT: u32 = 31;
+    pub const INVERSE_SQRT: u32 = 32;
+    pub const F_MIN: u32 = 37;
+    pub const F_MAX: u32 = 40;
+    pub const F_CLAMP: u32 = 43;
+    pub const F_MIX: u32 = 46;
+    pub const STEP: u32 = 48;
+    pub const SMOOTH_STEP: u32 = 49;
+    pub const LENGTH: u32 = 66;
+    pub const DISTANCE: u32 = 67;
+    pub const CROSS: u32 = 68;
+    pub const NORMALIZE: u32 = 69;
+    pub const FACE_FORWARD: u32 = 70;
+    pub const REFLECT: u32 = 71;
+    pub const REFRACT: u32 = 72;
+}
+
+pub(crate) const CAPABILITY_SHADER: u32 = 1;
+pub(crate) const ADDRESSING_MODEL_LOGICAL: u32 = 0;
+pub(crate) const MEMORY_MODEL_GLSL450: u32 = 1;
+pub(crate) const EXECUTION_MODEL_VERTEX: u32 = 0;
+pub(crate) const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+pub(crate) const EXECUTION_MODE_ORIGIN_UPPER_LEFT: u32 = 7;
+pub(crate) const DIM_2D: u32 = 1;
+pub(crate) const DIM_CUBE: u32 = 3;
+pub(crate) const IMAGE_OPERANDS_BIAS: u32 = 0x1;
+pub(crate) const IMAGE_OPERANDS_LOD: u32 = 0x2;
+
+/// Append an instruction to `section`
+pub(crate) fn instruction(section: &mut Vec<u32>, opcode: u16, operands: &[u32]) {
+    section.push(((operands.len() as u32 + 1) << 16) | opcode as u32);
+    section.extend_from_slice(operands);
+}
+
+/// Null terminated UTF-8 string padded to whole words
+pub(crate) fn string_operand(value: &str) -> Vec<u32> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.push(0);
+    while bytes.len() % 4 != 0 {
+        bytes.push(0);
+    }
+    bytes
+        .chunks(4)
+        .map(|chunk| chunk[0] as u32 | (chunk[1] as u32) << 8 | (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24)
+        .collect()
+}
+
+/// Module under construction, kept in the sections of the logical layout so that they can be filled in any order
+#[derive(Debug, Default)]
+pub(crate) struct Builder {
+    bound: u32,
+    pub capabilities: Vec<u32>,
+    pub imports: Vec<u32>,
+    pub entry_points: Vec<u32>,
+    pub execution_modes: Vec<u32>,
+    pub names: Vec<u32>,
+    pub annotations: Vec<u32>,
+    /// Types, constants and global variables
+    pub globals: Vec<u32>,
+    pub functions: Vec<u32>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            bound: 1,
+            ..Self::default()
+        }
+    }
+
+    #[inline]
+    pub fn id(&mut self) -> u32 {
+        let id = self.bound;
+        self.bound += 1;
+        id
+    }
+
+    pub fn name(&mut self, target: u32, name: &str) {
+        let mut operands = vec![target];
+        operands.extend(string_operand(name));
+        instruction(&mut self.names, op::NAME, &operands);
+    }
+
+    pub fn member_name(&mut self, target: u32, member: u32, name: &str) {
+        let mut operands = vec![target, member];
+        operands.extend(string_operand(name));
+        instruction(&mut self.names, op::MEMBER_NAME, &operands);
+    }
+
+    pub fn decorate(&mut self, target: u32, decoration: u32, literals: &[u32]) {
+        let mut operands = vec![target, decoration];
+        operands.extend_from_slice(literals);
+        instruction(&mut self.annotations, op::DECORATE, &operands);
+    }
+
+    pub fn member_decorate(&mut self, target: u32, member: u32, decoration: u32, literals: &[u32]) {
+        let mut operands = vec![target, member, decoration];
+        operands.extend_from_slice(literals);
+        instruction(&mut self.annotations, op::MEMBER_DECORATE, &operands);
+    }
+
+    /// Emit an instruction of the globals section defining a new id
+    pub fn global(&mut self, opcode: u16, operands_before: &[u32], operands_after: &[u32]) -> u32 {
+        let id = self.id();
+        let mut operands = operands_before.to_vec();
+        operands.push(id);
+        operands.extend_from_slice(operands_after);
+        instruction(&mut self.globals, opcode, &operands);
+        id
+    }
+
+    pub fn finish(self) -> Vec<u32> {
+        let mut words = vec![MAGIC_NUMBER, VERSION, 0, self.bound, 0];
+        words.extend(self.capabilities);
+        words.extend(self.imports);
+        instruction(
+            &mut words,
+            op::MEMORY_MODEL,
+            &[ADDRESSING_MODEL_LOGICAL, MEMORY_MODEL_GLSL450],
+        );
+        words.extend(self.entry_points);
+        words.extend(self.execution_modes);
+        words.extend(self.names);
+        words.extend(self.annotations);
+        words.extend(self.globals);
+        words.extend(self.functions);
+        words
+    }
+}
+
+/// Instructions without a result id
+fn has_result(opcode: u16) -> bool {
+    match opcode {
+        op::NAME
+        | op::MEMBER_NAME
+        | op::MEMORY_MODEL
+        | op::ENTRY_POINT
+        | op::EXECUTION_MODE
+        | op::CAPABILITY
+        | op::FUNCTION_END
+        | op::STORE
+        | op::DECORATE
+        | op::MEMBER_DECORATE
+        | op::LOOP_MERGE
+        | op::SELECTION_MERGE
+        | op::BRANCH
+        | op::BRANCH_CONDITIONAL
+        | op::KILL
+        | op::RETURN
+        | op::RETURN_VALUE
+        | op::UNREACHABLE => false,
+        _ => true,
+    }
+}
+
+/// Instructions whose first operand is the result id instead of a result type
+fn has_result_type(opcode: u16) -> bool {
+    match opcode {
+        op::EXT_INST_IMPORT | op::LABEL => false,
+        op::TYPE_VOID..=op::TYPE_FUNCTION => false,
+        _ => has_result(opcode),
+    }
+}
+
+#[inline]
+fn is_terminator(opcode: u16) -> bool {
+    match opcode {
+        op::BRANCH | op::BRANCH_CONDITIONAL | op::KILL | op::RETURN | op::RETURN_VALUE | op::UNREACHABLE => true,
+        _ => false,
+    }
+}
+
+/// Check the structure of a module: header, instruction sizes, unique result ids within the bound, result types
+/// declared before use and functions made of terminated blocks
+pub(crate) fn validate(words: &[u32]) -> Result<(), String> {
+    if words.len() < 5 || words[0] != MAGIC_NUMBER || words[1] != VERSION {
+        return Err("invalid header".to_string());
+    }
+    let bound = words[3] as usize;
+    let mut is_defined = vec![false; bound];
+    let mut is_in_function = false;
+    let mut is_in_block = false;
+    let mut has_entry_point = false;
+
+    let mut position = 5;
+    while position < words.len() {
+        let word_count = (words[position] >> 16) as usize;
+        let opcode = words[position] as u16;
+        if word_count == 0 || position + word_count > words.len() {
+            return Err(format!("invalid word count of opcode {} at word {}", opcode, position));
+        }
+        let operands = &words[position + 1..position + word_count];
+        position += word_count;
+
+        if has_result(opcode) {
+            let result_index = if has_result_type(opcode) { 1 } else { 0 };
+            let result = match operands.get(result_index) {
+                Some(&result) => result as usize,
+                None => return Err(format!("missing result id of opcode {}", opcode)),
+            };
+            if result == 0 || result >= bound || is_defined[result] {
+                return Err(format!("invalid result id {} of opcode {}", result, opcode));
+            }
+            if result_index == 1 {
+                let result_type = operands[0] as usize;
+                if result_type >= bound || !is_defined[result_type] {
+                    return Err(format!("undefined result type {} of opcode {}", result_type, opcode));
+                }
+            }
+            is_defined[result] = true;
+        }
+
+        match opcode {
+            op::ENTRY_POINT => has_entry_point = true,
+            op::FUNCTION if is_in_function => return Err("nested function".to_string()),
+            op::FUNCTION => is_in_function = true,
+            op::FUNCTION_END => {
+                if is_in_block {
+                    return Err("unterminated block".to_string());
+                }
+                is_in_function = false;
+            }
+            op::LABEL if is_in_block => return Err("unterminated block".to_string()),
+            op::LABEL => is_in_block = true,
+            op::VARIABLE if is_in_function && operands.get(2) != Some(&storage_class::FUNCTION) => {
+                return Err("global variable in a function".to_string())
+            }
+            _ if is_terminator(opcode) && !is_in_block => return Err(format!("opcode {} outside of a block", opcode)),
+            _ if is_terminator(opcode) => is_in_block = false,
+            _ => {}
+        }
+    }
+
+    if is_in_function {
+        return Err("unterminated function".to_string());
+    }
+    if !has_entry_point {
+        return Err("missing entry point".to_string());
+    }
+    Ok(())
+}
+
+/// Write the module to the dump directory when it is set
+pub(crate) fn dump(name: &str, words: &[u32]) {
+    let directory = match env::var(DUMP_DIRECTORY_VARIABLE) {
+        Ok(directory) => directory,
+        Err(_) => return,
+    };
+
+    let mut bytes = Vec::with_capacity(words.len() * 4);
+    for word in words {
+        bytes.extend_from_slice(&[*word as u8, (*word >> 8) as u8, (*word >> 16) as u8, (*word >> 24) as u8]);
+    }
+    let path = Path::new(&directory).join(format!("{}.spv", name));
+    match fs::write(&path, &bytes) {
+        Ok(()) => debug!("SPIR-V dumped to {:?}", path),
+        Err(err) => warn!("failed to dump SPIR-V to {:?}: {}", path, err),
+    }
+}
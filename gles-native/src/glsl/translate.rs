@@ -0,0 +1,1671 @@
+use std::collections::HashMap;
+
+use super::ast::*;
+use super::builtins::{BuiltinVariable, BUILTIN_FUNCTIONS};
+use super::checker::{CompiledShader, Constant, Storage};
+use super::layout::{self, Layout};
+use super::spirv::{self, built_in, decoration, glsl_std, op, storage_class, Builder};
+use super::types::{BaseType, ScalarKind, Type};
+use super::Stage;
+
+type TranslateResult<T> = Result<T, String>;
+
+/// Translated vertex shaders negate the y of `gl_Position`. The backend's window origin is upper left, so with
+/// viewports and scissors moved to that origin (see `viewport_transformation`) images come out the way OpenGL
+/// renders them, mirrored vertically, which also swaps the winding of primitives
+pub(crate) const FLIP_Y: bool = true;
+
+/// Clip space position the translated vertex shader outputs for `gl_Position` at the end of `main`: y negated if
+/// `FLIP_Y` and z moved from the [-w, w] depth range of OpenGL to the [0, w] one of the backend
+pub(crate) fn clip_space_position(position: [f32; 4]) -> [f32; 4] {
+    let [x, y, z, w] = position;
+    [x, if FLIP_Y { -y } else { y }, (z + w) * 0.5, w]
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pointer {
+    id: u32,
+    storage: u32,
+    ty: Type,
+    /// The pointee has the explicit layout of the uniform block
+    is_layout: bool,
+}
+
+#[derive(Clone, Debug)]
+enum LValue {
+    Pointer(Pointer),
+    /// Components of the vector pointed to
+    Swizzle(Pointer, Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+    Pointer(Pointer),
+    /// Index of a member of the uniform block
+    UniformMember(u32),
+    Constant(Constant),
+}
+
+struct FunctionState {
+    variables: Vec<u32>,
+    body: Vec<u32>,
+    /// Block being emitted
+    label: u32,
+    is_terminated: bool,
+    /// Merge and continue targets of the enclosing loops
+    loops: Vec<(u32, u32)>,
+    /// The function is the entry point, `gl_Position` gets adjusted before it returns
+    is_main: bool,
+}
+
+/// Translate a compiled shader into a SPIR-V module, with the locations and bindings of `layout`
+pub(crate) fn translate(shader: &CompiledShader, layout: &Layout) -> TranslateResult<Vec<u32>> {
+    let mut translator = Translator {
+        shader,
+        layout,
+        builder: Builder::new(),
+        glsl_std: 0,
+        types: HashMap::new(),
+        uint_type: None,
+        image_types: HashMap::new(),
+        pointer_types: HashMap::new(),
+        function_types: HashMap::new(),
+        constants: HashMap::new(),
+        variables: HashMap::new(),
+        uniform_block: None,
+        function_ids: Vec::new(),
+        interface: Vec::new(),
+        position: None,
+        function: None,
+    };
+    translator.module()?;
+
+    let words = translator.builder.finish();
+    spirv::validate(&words)?;
+    Ok(words)
+}
+
+struct Translator<'a> {
+    shader: &'a CompiledShader,
+    layout: &'a Layout,
+    builder: Builder,
+    glsl_std: u32,
+    types: HashMap<(Type, bool), u32>,
+    uint_type: Option<u32>,
+    image_types: HashMap<u32, u32>,
+    pointer_types: HashMap<(u32, u32), u32>,
+    function_types: HashMap<Vec<u32>, u32>,
+    constants: HashMap<(u32, u32), u32>,
+    variables: HashMap<SymbolId, Variable>,
+    uniform_block: Option<u32>,
+    function_ids: Vec<u32>,
+    /// Input and output variables of the entry point
+    interface: Vec<u32>,
+    /// `gl_Position` of vertex shaders writing it
+    position: Option<Pointer>,
+    function: Option<FunctionState>,
+}
+
+impl<'a> Translator<'a> {
+    fn module(&mut self) -> TranslateResult<()> {
+        let main = self.shader.main().ok_or_else(|| "missing main function".to_string())?;
+
+        spirv::instruction(&mut self.builder.capabilities, op::CAPABILITY, &[spirv::CAPABILITY_SHADER]);
+        self.glsl_std = self.builder.id();
+        let mut operands = vec![self.glsl_std];
+        operands.extend(spirv::string_operand(glsl_std::NAME));
+        spirv::instruction(&mut self.builder.imports, op::EXT_INST_IMPORT, &operands);
+
+        for function in &self.shader.functions {
+            let id = self.builder.id();
+            self.builder.name(id, &function.name);
+            self.function_ids.push(id);
+        }
+        self.interface_variables()?;
+        if let Some(id) = self
+            .shader
+            .symbols
+            .iter()
+            .position(|symbol| symbol.storage == Storage::BuiltIn(BuiltinVariable::Position) && symbol.is_used)
+        {
+            if let Variable::Pointer(pointer) = self.variable(id)? {
+                self.position = Some(pointer);
+            }
+        }
+
+        // global initializers are constant expressions evaluated at the start of main
+        let mut initializers = Vec::new();
+        for declaration in &self.shader.unit.declarations {
+            if let ExternalDeclaration::Declaration(Declaration::Variables { ref declarators, .. }) = *declaration {
+                for declarator in declarators {
+                    if let (Some(symbol), Some(initializer)) = (declarator.symbol, declarator.initializer.as_ref()) {
+                        initializers.push((symbol, initializer));
+                    }
+                }
+            }
+        }
+
+        for declaration in &self.shader.unit.declarations {
+            if let ExternalDeclaration::Function(ref definition) = *declaration {
+                let id = definition
+                    .prototype
+                    .function
+                    .ok_or_else(|| "unresolved function".to_string())?;
+                let initializers = if id == main { &initializers[..] } else { &[] };
+                self.function(definition, id, initializers)?;
+            }
+        }
+
+        let (execution_model, name) = match self.shader.stage {
+            Stage::Vertex => (spirv::EXECUTION_MODEL_VERTEX, "main"),
+            Stage::Fragment => (spirv::EXECUTION_MODEL_FRAGMENT, "main"),
+        };
+        let mut operands = vec![execution_model, self.function_ids[main]];
+        operands.extend(spirv::string_operand(name));
+        operands.extend_from_slice(&self.interface);
+        spirv::instruction(&mut self.builder.entry_points, op::ENTRY_POINT, &operands);
+        if self.shader.stage == Stage::Fragment {
+            spirv::instruction(
+                &mut self.builder.execution_modes,
+                op::EXECUTION_MODE,
+                &[self.function_ids[main], spirv::EXECUTION_MODE_ORIGIN_UPPER_LEFT],
+            );
+        }
+        Ok(())
+    }
+
+    /// Declare attributes, varyings and uniforms
+    fn interface_variables(&mut self) -> TranslateResult<()> {
+        let shader = self.shader;
+        for (id, symbol) in shader.attributes() {
+            let location = *self
+                .layout
+                .attribute_locations
+                .get(&symbol.name)
+                .ok_or_else(|| format!("no location for attribute {}", symbol.name))?;
+            let pointer = self.global_variable(storage_class::INPUT, &symbol.ty, &symbol.name);
+            self.builder.decorate(pointer.id, decoration::LOCATION, &[location]);
+            self.interface.push(pointer.id);
+            self.variables.insert(id, Variable::Pointer(pointer));
+        }
+
+        let varying_storage = match shader.stage {
+            Stage::Vertex => storage_class::OUTPUT,
+            Stage::Fragment => storage_class::INPUT,
+        };
+        for (id, symbol) in shader.varyings() {
+            let location = *self
+                .layout
+                .varying_locations
+                .get(&symbol.name)
+                .ok_or_else(|| format!("no location for varying {}", symbol.name))?;
+            let pointer = self.global_variable(varying_storage, &symbol.ty, &symbol.name);
+            self.builder.decorate(pointer.id, decoration::LOCATION, &[location]);
+            if symbol.is_invariant && shader.stage == Stage::Vertex {
+                self.builder.decorate(pointer.id, decoration::INVARIANT, &[]);
+            }
+            self.interface.push(pointer.id);
+            self.variables.insert(id, Variable::Pointer(pointer));
+        }
+
+        let mut members = Vec::new();
+        for (id, symbol) in shader.uniforms() {
+            if symbol.ty.is_sampler() {
+                let binding = *self
+                    .layout
+                    .sampler_bindings
+                    .get(&symbol.name)
+                    .ok_or_else(|| format!("no binding for sampler {}", symbol.name))?;
+                let pointer = self.global_variable(storage_class::UNIFORM_CONSTANT, &symbol.ty, &symbol.name);
+                self.builder
+                    .decorate(pointer.id, decoration::DESCRIPTOR_SET, &[layout::DESCRIPTOR_SET]);
+                self.builder.decorate(pointer.id, decoration::BINDING, &[binding]);
+                self.variables.insert(id, Variable::Pointer(pointer));
+            } else if symbol.ty.contains(&shader.structs, &|ty: &Type| ty.is_sampler()) {
+                return Err(format!("samplers in structures are not supported: {}", symbol.name));
+            } else {
+                let offset = *self
+                    .layout
+                    .uniform_offsets
+                    .get(&symbol.name)
+                    .ok_or_else(|| format!("no offset for uniform {}", symbol.name))?;
+                members.push((offset, id));
+            }
+        }
+        if !members.is_empty() {
+            self.uniform_block(members);
+        }
+        Ok(())
+    }
+
+    /// Pack the loose uniforms into a block with explicit offsets
+    fn uniform_block(&mut self, mut members: Vec<(u32, SymbolId)>) {
+        let shader = self.shader;
+        members.sort();
+        let member_types = members
+            .iter()
+            .map(|&(_, id)| self.type_id(&shader.symbols[id].ty, true))
+            .collect::<Vec<_>>();
+        let block_type = self.builder.global(op::TYPE_STRUCT, &[], &member_types);
+        self.builder.name(block_type, "DefaultUniformBlock");
+        self.builder.decorate(block_type, decoration::BLOCK, &[]);
+
+        for (index, &(offset, id)) in members.iter().enumerate() {
+            let symbol = &shader.symbols[id];
+            self.builder.member_name(block_type, index as u32, &symbol.name);
+            self.builder
+                .member_decorate(block_type, index as u32, decoration::OFFSET, &[offset]);
+            if let BaseType::Matrix(_) = symbol.ty.base {
+                self.builder
+                    .member_decorate(block_type, index as u32, decoration::COL_MAJOR, &[]);
+                self.builder
+                    .member_decorate(block_type, index as u32, decoration::MATRIX_STRIDE, &[16]);
+            }
+            self.variables.insert(id, Variable::UniformMember(index as u32));
+        }
+
+        let pointer_type = self.pointer_type(storage_class::UNIFORM, block_type);
+        let block = self.builder.global(op::VARIABLE, &[pointer_type], &[storage_class::UNIFORM]);
+        self.builder
+            .decorate(block, decoration::DESCRIPTOR_SET, &[layout::DESCRIPTOR_SET]);
+        self.builder
+            .decorate(block, decoration::BINDING, &[layout::UNIFORM_BLOCK_BINDING]);
+        self.uniform_block = Some(block);
+    }
+
+    fn global_variable(&mut self, storage: u32, ty: &Type, name: &str) -> Pointer {
+        let type_id = self.type_id(ty, false);
+        let pointer_type = self.pointer_type(storage, type_id);
+        let id = self.builder.global(op::VARIABLE, &[pointer_type], &[storage]);
+        self.builder.name(id, name);
+        Pointer {
+            id,
+            storage,
+            ty: *ty,
+            is_layout: false,
+        }
+    }
+
+    fn builtin_variable(&mut self, variable: BuiltinVariable, ty: &Type, name: &str, is_invariant: bool) -> Pointer {
+        let (storage, built_in, location) = match variable {
+            BuiltinVariable::Position => (storage_class::OUTPUT, Some(built_in::POSITION), None),
+            BuiltinVariable::PointSize => (storage_class::OUTPUT, Some(built_in::POINT_SIZE), None),
+            BuiltinVariable::FragCoord => (storage_class::INPUT, Some(built_in::FRAG_COORD), None),
+            BuiltinVariable::FrontFacing => (storage_class::INPUT, Some(built_in::FRONT_FACING), None),
+            BuiltinVariable::PointCoord => (storage_class::INPUT, Some(built_in::POINT_COORD), None),
+            BuiltinVariable::FragColor | BuiltinVariable::FragData => (storage_class::OUTPUT, None, Some(0)),
+        };
+
+        let pointer = self.global_variable(storage, ty, name);
+        if let Some(built_in) = built_in {
+            self.builder.decorate(pointer.id, decoration::BUILT_IN, &[built_in]);
+        }
+        if let Some(location) = location {
+            self.builder.decorate(pointer.id, decoration::LOCATION, &[location]);
+        }
+        if is_invariant && storage == storage_class::OUTPUT {
+            self.builder.decorate(pointer.id, decoration::INVARIANT, &[]);
+        }
+        self.interface.push(pointer.id);
+        pointer
+    }
+
+    /// Scalar `const` variables are folded into constants
+    fn is_folded_constant(&self, id: SymbolId) -> bool {
+        let symbol = &self.shader.symbols[id];
+        symbol.storage == Storage::Const && symbol.constant.is_some() && symbol.ty.is_scalar()
+    }
+
+    fn variable(&mut self, id: SymbolId) -> TranslateResult<Variable> {
+        if let Some(&variable) = self.variables.get(&id) {
+            return Ok(variable);
+        }
+
+        let symbol = &self.shader.symbols[id];
+        let variable = match symbol.storage {
+            Storage::Const if self.is_folded_constant(id) => Variable::Constant(symbol.constant.unwrap()),
+            Storage::Global | Storage::Const => {
+                Variable::Pointer(self.global_variable(storage_class::PRIVATE, &symbol.ty, &symbol.name))
+            }
+            Storage::BuiltIn(variable) => {
+                Variable::Pointer(self.builtin_variable(variable, &symbol.ty, &symbol.name, symbol.is_invariant))
+            }
+            _ => return Err(format!("undeclared variable {}", symbol.name)),
+        };
+        self.variables.insert(id, variable);
+        Ok(variable)
+    }
+
+    fn type_id(&mut self, ty: &Type, is_layout: bool) -> u32 {
+        // explicit layouts only change aggregates and booleans, which are stored as integers
+        let is_layout = is_layout && (ty.is_array() || ty.is_struct() || ty.scalar_kind() == Some(ScalarKind::Bool));
+        if let Some(&id) = self.types.get(&(*ty, is_layout)) {
+            return id;
+        }
+
+        let structs = &self.shader.structs;
+        let id = if let Some(length) = ty.array_size {
+            let element = Type::new(ty.base);
+            let element_type = self.type_id(&element, is_layout);
+            let length = self.constant_u32(length);
+            let id = self.builder.global(op::TYPE_ARRAY, &[], &[element_type, length]);
+            if is_layout {
+                let stride = layout::std140_array_stride(&element, structs);
+                self.builder.decorate(id, decoration::ARRAY_STRIDE, &[stride]);
+            }
+            id
+        } else {
+            match ty.base {
+                BaseType::Void => self.builder.global(op::TYPE_VOID, &[], &[]),
+                BaseType::Scalar(ScalarKind::Bool) if is_layout => self.type_id(&Type::INT, false),
+                BaseType::Vector(ScalarKind::Bool, size) if is_layout => {
+                    self.type_id(&Type::vector(ScalarKind::Int, size), false)
+                }
+                BaseType::Scalar(ScalarKind::Bool) => self.builder.global(op::TYPE_BOOL, &[], &[]),
+                BaseType::Scalar(ScalarKind::Int) => self.builder.global(op::TYPE_INT, &[], &[32, 1]),
+                BaseType::Scalar(ScalarKind::Float) => self.builder.global(op::TYPE_FLOAT, &[], &[32]),
+                BaseType::Vector(kind, size) => {
+                    let component = self.type_id(&Type::scalar(kind), false);
+                    self.builder.global(op::TYPE_VECTOR, &[], &[component, size as u32])
+                }
+                BaseType::Matrix(size) => {
+                    let column = self.type_id(&Type::vector(ScalarKind::Float, size), false);
+                    self.builder.global(op::TYPE_MATRIX, &[], &[column, size as u32])
+                }
+                BaseType::Sampler2D => {
+                    let image = self.image_type(spirv::DIM_2D);
+                    self.builder.global(op::TYPE_SAMPLED_IMAGE, &[], &[image])
+                }
+                BaseType::SamplerCube => {
+                    let image = self.image_type(spirv::DIM_CUBE);
+                    self.builder.global(op::TYPE_SAMPLED_IMAGE, &[], &[image])
+                }
+                BaseType::Struct(struct_id) => self.struct_type(struct_id, is_layout),
+            }
+        };
+        self.types.insert((*ty, is_layout), id);
+        id
+    }
+
+    fn struct_type(&mut self, struct_id: usize, is_layout: bool) -> u32 {
+        let definition = &self.shader.structs[struct_id];
+        let members = definition
+            .fields
+            .iter()
+            .map(|field| self.type_id(&field.ty, is_layout))
+            .collect::<Vec<_>>();
+        let id = self.builder.global(op::TYPE_STRUCT, &[], &members);
+        if let Some(ref name) = definition.name {
+            self.builder.name(id, name);
+        }
+        for (index, field) in definition.fields.iter().enumerate() {
+            self.builder.member_name(id, index as u32, &field.name);
+        }
+
+        if is_layout {
+            let offsets = layout::std140_field_offsets(struct_id, &self.shader.structs);
+            for (index, field) in definition.fields.iter().enumerate() {
+                self.builder
+                    .member_decorate(id, index as u32, decoration::OFFSET, &[offsets[index]]);
+                if let BaseType::Matrix(_) = field.ty.base {
+                    self.builder.member_decorate(id, index as u32, decoration::COL_MAJOR, &[]);
+                    self.builder
+                        .member_decorate(id, index as u32, decoration::MATRIX_STRIDE, &[16]);
+                }
+            }
+        }
+        id
+    }
+
+    fn image_type(&mut self, dimension: u32) -> u32 {
+        if let Some(&id) = self.image_types.get(&dimension) {
+            return id;
+        }
+        let sampled_type = self.type_id(&Type::FLOAT, false);
+        let id = self
+            .builder
+            .global(op::TYPE_IMAGE, &[], &[sampled_type, dimension, 0, 0, 0, 1, 0]);
+        self.image_types.insert(dimension, id);
+        id
+    }
+
+    fn pointer_type(&mut self, storage: u32, pointee: u32) -> u32 {
+        if let Some(&id) = self.pointer_types.get(&(storage, pointee)) {
+            return id;
+        }
+        let id = self.builder.global(op::TYPE_POINTER, &[], &[storage, pointee]);
+        self.pointer_types.insert((storage, pointee), id);
+        id
+    }
+
+    fn function_type(&mut self, return_type: u32, parameters: &[u32]) -> u32 {
+        let mut key = vec![return_type];
+        key.extend_from_slice(parameters);
+        if let Some(&id) = self.function_types.get(&key) {
+            return id;
+        }
+        let id = self.builder.global(op::TYPE_FUNCTION, &[], &key);
+        self.function_types.insert(key, id);
+        id
+    }
+
+    fn constant(&mut self, constant: Constant) -> u32 {
+        let (type_id, bits) = match constant {
+            Constant::Int(value) => (self.type_id(&Type::INT, false), value as u32),
+            Constant::Float(value) => (self.type_id(&Type::FLOAT, false), value.to_bits()),
+            Constant::Bool(value) => (self.type_id(&Type::BOOL, false), value as u32),
+        };
+        if let Some(&id) = self.constants.get(&(type_id, bits)) {
+            return id;
+        }
+        let id = match constant {
+            Constant::Bool(true) => self.builder.global(op::CONSTANT_TRUE, &[type_id], &[]),
+            Constant::Bool(false) => self.builder.global(op::CONSTANT_FALSE, &[type_id], &[]),
+            _ => self.builder.global(op::CONSTANT, &[type_id], &[bits]),
+        };
+        self.constants.insert((type_id, bits), id);
+        id
+    }
+
+    /// Unsigned constants for array lengths
+    fn constant_u32(&mut self, value: u32) -> u32 {
+        let type_id = match self.uint_type {
+            Some(id) => id,
+            None => {
+                let id = self.builder.global(op::TYPE_INT, &[], &[32, 0]);
+                self.uint_type = Some(id);
+                id
+            }
+        };
+        if let Some(&id) = self.constants.get(&(type_id, value)) {
+            return id;
+        }
+        let id = self.builder.global(op::CONSTANT, &[type_id], &[value]);
+        self.constants.insert((type_id, value), id);
+        id
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.function.as_mut().expect("instruction outside of a function")
+    }
+
+    /// Emit an instruction in the current block, code following a terminator goes to an unreachable block
+    fn emit(&mut self, opcode: u16, operands: &[u32]) {
+        if self.state().is_terminated {
+            let label = self.builder.id();
+            self.label(label);
+        }
+        spirv::instruction(&mut self.state().body, opcode, operands);
+    }
+
+    fn emit_value(&mut self, opcode: u16, result_type: u32, operands: &[u32]) -> u32 {
+        let id = self.builder.id();
+        let mut all_operands = vec![result_type, id];
+        all_operands.extend_from_slice(operands);
+        self.emit(opcode, &all_operands);
+        id
+    }
+
+    fn label(&mut self, id: u32) {
+        let state = self.state();
+        spirv::instruction(&mut state.body, op::LABEL, &[id]);
+        state.label = id;
+        state.is_terminated = false;
+    }
+
+    fn terminate(&mut self, opcode: u16, operands: &[u32]) {
+        self.emit(opcode, operands);
+        self.state().is_terminated = true;
+    }
+
+    /// Branch to `target` unless the block is already terminated
+    fn branch(&mut self, target: u32) {
+        if !self.state().is_terminated {
+            self.terminate(op::BRANCH, &[target]);
+        }
+    }
+
+    fn local_variable(&mut self, ty: &Type, name: Option<&str>) -> Pointer {
+        let type_id = self.type_id(ty, false);
+        let pointer_type = self.pointer_type(storage_class::FUNCTION, type_id);
+        let id = self.builder.id();
+        if let Some(name) = name {
+            self.builder.name(id, name);
+        }
+        spirv::instruction(
+            &mut self.state().variables,
+            op::VARIABLE,
+            &[pointer_type, id, storage_class::FUNCTION],
+        );
+        Pointer {
+            id,
+            storage: storage_class::FUNCTION,
+            ty: *ty,
+            is_layout: false,
+        }
+    }
+
+    fn function(
+        &mut self,
+        definition: &FunctionDefinition,
+        id: FunctionId,
+        initializers: &[(SymbolId, &Expr)],
+    ) -> TranslateResult<()> {
+        let function = &self.shader.functions[id];
+        let return_type = self.type_id(&function.return_type, false);
+        let mut parameter_types = Vec::with_capacity(function.parameters.len());
+        for &(_, ref ty) in &function.parameters {
+            parameter_types.push(self.parameter_type(ty));
+        }
+        let function_type = self.function_type(return_type, &parameter_types);
+
+        let mut header = Vec::new();
+        spirv::instruction(
+            &mut header,
+            op::FUNCTION,
+            &[return_type, self.function_ids[id], 0, function_type],
+        );
+        let mut parameters = Vec::with_capacity(parameter_types.len());
+        for (index, &parameter_type) in parameter_types.iter().enumerate() {
+            let parameter_id = self.builder.id();
+            spirv::instruction(&mut header, op::FUNCTION_PARAMETER, &[parameter_type, parameter_id]);
+            parameters.push(parameter_id);
+
+            let parameter = &definition.prototype.parameters[index];
+            if let (Some(symbol), Some(name)) = (parameter.symbol, parameter.name.as_ref()) {
+                let ty = function.parameters[index].1;
+                let storage = if ty.is_sampler() {
+                    storage_class::UNIFORM_CONSTANT
+                } else {
+                    storage_class::FUNCTION
+                };
+                self.builder.name(parameter_id, name);
+                self.variables.insert(
+                    symbol,
+                    Variable::Pointer(Pointer {
+                        id: parameter_id,
+                        storage,
+                        ty,
+                        is_layout: false,
+                    }),
+                );
+            }
+        }
+
+        let entry = self.builder.id();
+        self.function = Some(FunctionState {
+            variables: Vec::new(),
+            body: Vec::new(),
+            label: entry,
+            is_terminated: false,
+            loops: Vec::new(),
+            is_main: self.shader.main() == Some(id),
+        });
+
+        for &(symbol, initializer) in initializers {
+            let value = self.expr(initializer)?;
+            if let Variable::Pointer(pointer) = self.variable(symbol)? {
+                self.store(&pointer, value);
+            }
+        }
+        for statement in &definition.body {
+            self.statement(statement)?;
+        }
+        if !self.state().is_terminated {
+            if function.return_type.is_void() {
+                self.return_void();
+            } else {
+                self.terminate(op::UNREACHABLE, &[]);
+            }
+        }
+
+        let state = self.function.take().unwrap();
+        let functions = &mut self.builder.functions;
+        functions.extend(header);
+        spirv::instruction(functions, op::LABEL, &[entry]);
+        functions.extend(state.variables);
+        functions.extend(state.body);
+        spirv::instruction(functions, op::FUNCTION_END, &[]);
+        Ok(())
+    }
+
+    /// Parameters are passed by pointer, to temporaries of the caller or to samplers
+    fn parameter_type(&mut self, ty: &Type) -> u32 {
+        let type_id = self.type_id(ty, false);
+        if ty.is_sampler() {
+            self.pointer_type(storage_class::UNIFORM_CONSTANT, type_id)
+        } else {
+            self.pointer_type(storage_class::FUNCTION, type_id)
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> TranslateResult<()> {
+        match statement.kind {
+            StatementKind::Declaration(Declaration::Variables { ref declarators, .. }) => {
+                for declarator in declarators {
+                    let symbol = declarator.symbol.ok_or_else(|| "unresolved variable".to_string())?;
+                    if self.is_folded_constant(symbol) {
+                        let constant = self.shader.symbols[symbol].constant.unwrap();
+                        self.variables.insert(symbol, Variable::Constant(constant));
+                        continue;
+                    }
+                    let ty = self.shader.symbols[symbol].ty;
+                    let pointer = self.local_variable(&ty, Some(&declarator.name));
+                    self.variables.insert(symbol, Variable::Pointer(pointer));
+                    if let Some(ref initializer) = declarator.initializer {
+                        let value = self.expr(initializer)?;
+                        self.store(&pointer, value);
+                    }
+                }
+            }
+            StatementKind::Declaration(_) => {}
+            StatementKind::Expression(ref expression) => {
+                if let Some(ref expression) = *expression {
+                    self.expr(expression)?;
+                }
+            }
+            StatementKind::Block(ref statements) => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+            }
+            StatementKind::If {
+                ref condition,
+                ref then,
+                ref else_,
+            } => {
+                let condition = self.expr(condition)?;
+                let then_label = self.builder.id();
+                let else_label = self.builder.id();
+                let merge = self.builder.id();
+                self.emit(op::SELECTION_MERGE, &[merge, 0]);
+                let false_label = if else_.is_some() { else_label } else { merge };
+                self.terminate(op::BRANCH_CONDITIONAL, &[condition, then_label, false_label]);
+
+                self.label(then_label);
+                self.statement(then)?;
+                self.branch(merge);
+                if let Some(ref else_) = *else_ {
+                    self.label(else_label);
+                    self.statement(else_)?;
+                    self.branch(merge);
+                }
+                self.label(merge);
+            }
+            StatementKind::For {
+                ref init,
+                ref condition,
+                ref step,
+                ref body,
+            } => {
+                self.statement(init)?;
+                self.repeat(condition.as_ref(), body, step.as_ref())?;
+            }
+            StatementKind::While { ref condition, ref body } => self.repeat(Some(condition), body, None)?,
+            StatementKind::DoWhile { ref body, ref condition } => {
+                let header = self.builder.id();
+                let body_label = self.builder.id();
+                let continue_label = self.builder.id();
+                let merge = self.builder.id();
+                self.branch(header);
+                self.label(header);
+                self.emit(op::LOOP_MERGE, &[merge, continue_label, 0]);
+                self.terminate(op::BRANCH, &[body_label]);
+
+                self.label(body_label);
+                self.state().loops.push((merge, continue_label));
+                self.statement(body)?;
+                self.state().loops.pop();
+                self.branch(continue_label);
+
+                self.label(continue_label);
+                let condition = self.expr(condition)?;
+                self.terminate(op::BRANCH_CONDITIONAL, &[condition, header, merge]);
+                self.label(merge);
+            }
+            StatementKind::Continue => {
+                let &(_, continue_label) = self.state().loops.last().ok_or("continue outside of a loop")?;
+                self.branch(continue_label);
+            }
+            StatementKind::Break => {
+                let &(merge, _) = self.state().loops.last().ok_or("break outside of a loop")?;
+                self.branch(merge);
+            }
+            StatementKind::Return(ref value) => match *value {
+                Some(ref value) => {
+                    let value = self.expr(value)?;
+                    self.terminate(op::RETURN_VALUE, &[value]);
+                }
+                None => self.return_void(),
+            },
+            StatementKind::Discard => self.terminate(op::KILL, &[]),
+        }
+        Ok(())
+    }
+
+    fn return_void(&mut self) {
+        if let (true, Some(position)) = (self.state().is_main, self.position) {
+            self.adjust_position(&position);
+        }
+        self.terminate(op::RETURN, &[]);
+    }
+
+    /// Store `clip_space_position` of `gl_Position` back into it
+    fn adjust_position(&mut self, position: &Pointer) {
+        let float_type = self.type_id(&Type::FLOAT, false);
+        let vector_type = self.type_id(&position.ty, false);
+        let value = self.load(position);
+        let mut components = (0..4)
+            .map(|index| self.emit_value(op::COMPOSITE_EXTRACT, float_type, &[value, index]))
+            .collect::<Vec<_>>();
+        if FLIP_Y {
+            components[1] = self.emit_value(op::F_NEGATE, float_type, &[components[1]]);
+        }
+        let half = self.constant(Constant::Float(0.5));
+        let sum = self.emit_value(op::F_ADD, float_type, &[components[2], components[3]]);
+        components[2] = self.emit_value(op::F_MUL, float_type, &[sum, half]);
+        let value = self.emit_value(op::COMPOSITE_CONSTRUCT, vector_type, &components);
+        self.store(position, value);
+    }
+
+    /// Structured loop testing `condition` before each iteration, used by `for` and `while`
+    fn repeat(&mut self, condition: Option<&Expr>, body: &Statement, step: Option<&Expr>) -> TranslateResult<()> {
+        let header = self.builder.id();
+        let condition_label = self.builder.id();
+        let body_label = self.builder.id();
+        let continue_label = self.builder.id();
+        let merge = self.builder.id();
+
+        self.branch(header);
+        self.label(header);
+        self.emit(op::LOOP_MERGE, &[merge, continue_label, 0]);
+        self.terminate(op::BRANCH, &[condition_label]);
+
+        self.label(condition_label);
+        match condition {
+            Some(condition) => {
+                let condition = self.expr(condition)?;
+                self.terminate(op::BRANCH_CONDITIONAL, &[condition, body_label, merge]);
+            }
+            None => self.terminate(op::BRANCH, &[body_label]),
+        }
+
+        self.label(body_label);
+        self.state().loops.push((merge, continue_label));
+        self.statement(body)?;
+        self.state().loops.pop();
+        self.branch(continue_label);
+
+        self.label(continue_label);
+        if let Some(step) = step {
+            self.expr(step)?;
+        }
+        self.terminate(op::BRANCH, &[header]);
+        self.label(merge);
+        Ok(())
+    }
+
+    fn load(&mut self, pointer: &Pointer) -> u32 {
+        let type_id = self.type_id(&pointer.ty, pointer.is_layout);
+        let value = self.emit_value(op::LOAD, type_id, &[pointer.id]);
+        if pointer.is_layout {
+            self.convert_from_layout(value, &pointer.ty)
+        } else {
+            value
+        }
+    }
+
+    #[inline]
+    fn store(&mut self, pointer: &Pointer, value: u32) {
+        self.emit(op::STORE, &[pointer.id, value]);
+    }
+
+    /// Rebuild a value loaded from the uniform block with the types used everywhere else
+    fn convert_from_layout(&mut self, value: u32, ty: &Type) -> u32 {
+        if let Some(length) = ty.array_size {
+            let element = Type::new(ty.base);
+            let element_type = self.type_id(&element, true);
+            let elements = (0..length)
+                .map(|index| {
+                    let element_value = self.emit_value(op::COMPOSITE_EXTRACT, element_type, &[value, index]);
+                    self.convert_from_layout(element_value, &element)
+                })
+                .collect::<Vec<_>>();
+            let type_id = self.type_id(ty, false);
+            return self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &elements);
+        }
+
+        match ty.base {
+            BaseType::Scalar(ScalarKind::Bool) | BaseType::Vector(ScalarKind::Bool, _) => {
+                let zero = self.constant(Constant::Int(0));
+                let zero = self.splat(zero, &Type::vector(ScalarKind::Int, ty.size()));
+                let type_id = self.type_id(ty, false);
+                self.emit_value(op::I_NOT_EQUAL, type_id, &[value, zero])
+            }
+            BaseType::Struct(id) => {
+                let fields = self.shader.structs[id].fields.clone();
+                let members = fields
+                    .iter()
+                    .enumerate()
+                    .map(|(index, field)| {
+                        let field_type = self.type_id(&field.ty, true);
+                        let member = self.emit_value(op::COMPOSITE_EXTRACT, field_type, &[value, index as u32]);
+                        self.convert_from_layout(member, &field.ty)
+                    })
+                    .collect::<Vec<_>>();
+                let type_id = self.type_id(ty, false);
+                self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &members)
+            }
+            _ => value,
+        }
+    }
+
+    /// Expressions designating storage, except folded constants
+    fn is_addressable(&self, expr: &Expr) -> bool {
+        match expr.kind {
+            ExprKind::Identifier { symbol: Some(id), .. } => !self.is_folded_constant(id),
+            ExprKind::Index(ref base, _) | ExprKind::Field { ref base, .. } => self.is_addressable(base),
+            _ => false,
+        }
+    }
+
+    fn access_chain(&mut self, base: &Pointer, ty: &Type, index: u32) -> Pointer {
+        let type_id = self.type_id(ty, base.is_layout);
+        let pointer_type = self.pointer_type(base.storage, type_id);
+        let id = self.emit_value(op::ACCESS_CHAIN, pointer_type, &[base.id, index]);
+        Pointer { id, ty: *ty, ..*base }
+    }
+
+    fn lvalue(&mut self, expr: &Expr) -> TranslateResult<LValue> {
+        match expr.kind {
+            ExprKind::Identifier { symbol: Some(id), .. } => match self.variable(id)? {
+                Variable::Pointer(pointer) => Ok(LValue::Pointer(pointer)),
+                Variable::UniformMember(index) => {
+                    let block = Pointer {
+                        id: self.uniform_block.unwrap(),
+                        storage: storage_class::UNIFORM,
+                        ty: Type::VOID,
+                        is_layout: true,
+                    };
+                    let index = self.constant(Constant::Int(index as i32));
+                    let ty = self.shader.symbols[id].ty;
+                    Ok(LValue::Pointer(self.access_chain(&block, &ty, index)))
+                }
+                Variable::Constant(_) => Err("constants are not l-values".to_string()),
+            },
+            ExprKind::Index(ref base, ref index) => {
+                let base = match self.lvalue(base)? {
+                    LValue::Pointer(pointer) => pointer,
+                    LValue::Swizzle(..) => return Err("indexing a swizzle is not supported".to_string()),
+                };
+                let index = self.expr(index)?;
+                Ok(LValue::Pointer(self.access_chain(&base, &expr.ty, index)))
+            }
+            ExprKind::Field {
+                ref base,
+                access: Some(ref access),
+                ..
+            } => {
+                let base = self.lvalue(base)?;
+                let (pointer, index) = match (access, base) {
+                    (&FieldAccess::Struct(index), LValue::Pointer(pointer)) => (pointer, index as u8),
+                    (&FieldAccess::Swizzle(ref components), LValue::Pointer(pointer)) if components.len() > 1 => {
+                        return Ok(LValue::Swizzle(pointer, components.clone()))
+                    }
+                    (&FieldAccess::Swizzle(ref components), LValue::Pointer(pointer)) => (pointer, components[0]),
+                    (&FieldAccess::Swizzle(ref components), LValue::Swizzle(pointer, ref base_components)) => {
+                        let components = components
+                            .iter()
+                            .map(|&component| base_components[component as usize])
+                            .collect::<Vec<_>>();
+                        if components.len() > 1 {
+                            return Ok(LValue::Swizzle(pointer, components));
+                        }
+                        (pointer, components[0])
+                    }
+                    (&FieldAccess::Struct(_), LValue::Swizzle(..)) => unreachable!(),
+                };
+                let index = self.constant(Constant::Int(index as i32));
+                Ok(LValue::Pointer(self.access_chain(&pointer, &expr.ty, index)))
+            }
+            _ => Err("expression is not an l-value".to_string()),
+        }
+    }
+
+    fn load_lvalue(&mut self, lvalue: &LValue) -> u32 {
+        match *lvalue {
+            LValue::Pointer(ref pointer) => self.load(pointer),
+            LValue::Swizzle(ref pointer, ref components) => {
+                let vector = self.load(pointer);
+                let kind = pointer.ty.scalar_kind().unwrap();
+                self.shuffle(vector, vector, kind, components)
+            }
+        }
+    }
+
+    fn store_lvalue(&mut self, lvalue: &LValue, value: u32) {
+        match *lvalue {
+            LValue::Pointer(ref pointer) => self.store(pointer, value),
+            LValue::Swizzle(ref pointer, ref components) => {
+                let vector = self.load(pointer);
+                let size = pointer.ty.size();
+                let selection = (0..size)
+                    .map(|index| match components.iter().position(|&component| component == index) {
+                        Some(position) => size + position as u8,
+                        None => index,
+                    })
+                    .collect::<Vec<_>>();
+                let kind = pointer.ty.scalar_kind().unwrap();
+                let vector = self.shuffle(vector, value, kind, &selection);
+                self.store(pointer, vector);
+            }
+        }
+    }
+
+    fn shuffle(&mut self, lhs: u32, rhs: u32, kind: ScalarKind, components: &[u8]) -> u32 {
+        let type_id = self.type_id(&Type::vector(kind, components.len() as u8), false);
+        if components.len() == 1 {
+            return self.emit_value(op::COMPOSITE_EXTRACT, type_id, &[lhs, components[0] as u32]);
+        }
+        let mut operands = vec![lhs, rhs];
+        operands.extend(components.iter().map(|&component| component as u32));
+        self.emit_value(op::VECTOR_SHUFFLE, type_id, &operands)
+    }
+
+    /// Replicate a scalar into a vector of type `ty`
+    fn splat(&mut self, scalar: u32, ty: &Type) -> u32 {
+        if !ty.is_vector() {
+            return scalar;
+        }
+        let type_id = self.type_id(ty, false);
+        let components = vec![scalar; ty.size() as usize];
+        self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &components)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> TranslateResult<u32> {
+        let ty = expr.ty;
+        let value = match expr.kind {
+            ExprKind::IntConstant(value) => self.constant(Constant::Int(value)),
+            ExprKind::FloatConstant(value) => self.constant(Constant::Float(value)),
+            ExprKind::BoolConstant(value) => self.constant(Constant::Bool(value)),
+            ExprKind::Identifier { symbol: Some(id), .. } if self.is_folded_constant(id) => {
+                let constant = self.shader.symbols[id].constant.unwrap();
+                self.constant(constant)
+            }
+            ExprKind::Identifier { .. } | ExprKind::Index(..) | ExprKind::Field { .. } if self.is_addressable(expr) => {
+                let lvalue = self.lvalue(expr)?;
+                self.load_lvalue(&lvalue)
+            }
+            ExprKind::Identifier { ref name, .. } => return Err(format!("unresolved identifier {}", name)),
+            ExprKind::Index(ref base, ref index) => {
+                let base_value = self.expr(base)?;
+                let index = self.expr(index)?;
+                let type_id = self.type_id(&ty, false);
+                if base.ty.is_vector() {
+                    self.emit_value(op::VECTOR_EXTRACT_DYNAMIC, type_id, &[base_value, index])
+                } else {
+                    // arrays and matrices are indexed dynamically through memory
+                    let temporary = self.local_variable(&base.ty, None);
+                    self.store(&temporary, base_value);
+                    let element = self.access_chain(&temporary, &ty, index);
+                    self.load(&element)
+                }
+            }
+            ExprKind::Field {
+                ref base, ref access, ..
+            } => {
+                let base_value = self.expr(base)?;
+                match *access {
+                    Some(FieldAccess::Struct(index)) => {
+                        let type_id = self.type_id(&ty, false);
+                        self.emit_value(op::COMPOSITE_EXTRACT, type_id, &[base_value, index as u32])
+                    }
+                    Some(FieldAccess::Swizzle(ref components)) => {
+                        let kind = base.ty.scalar_kind().unwrap();
+                        self.shuffle(base_value, base_value, kind, components)
+                    }
+                    None => return Err("unresolved field".to_string()),
+                }
+            }
+            ExprKind::Unary(operator, ref operand) => self.unary(operator, operand)?,
+            ExprKind::Binary(BinaryOp::LogicalAnd, ref lhs, ref rhs) => self.short_circuit(true, lhs, rhs)?,
+            ExprKind::Binary(BinaryOp::LogicalOr, ref lhs, ref rhs) => self.short_circuit(false, lhs, rhs)?,
+            ExprKind::Binary(operator, ref lhs, ref rhs) => {
+                let lhs_value = self.expr(lhs)?;
+                let rhs_value = self.expr(rhs)?;
+                self.binary(operator, lhs_value, &lhs.ty, rhs_value, &rhs.ty, &ty)
+            }
+            ExprKind::Assign(operator, ref lhs, ref rhs) => {
+                let rhs_value = self.expr(rhs)?;
+                let lvalue = self.lvalue(lhs)?;
+                let value = match operator {
+                    Some(operator) => {
+                        let lhs_value = self.load_lvalue(&lvalue);
+                        self.binary(operator, lhs_value, &lhs.ty, rhs_value, &rhs.ty, &lhs.ty)
+                    }
+                    None => rhs_value,
+                };
+                self.store_lvalue(&lvalue, value);
+                value
+            }
+            ExprKind::Ternary(ref condition, ref then, ref else_) => {
+                let condition = self.expr(condition)?;
+                let then_label = self.builder.id();
+                let else_label = self.builder.id();
+                let merge = self.builder.id();
+                self.emit(op::SELECTION_MERGE, &[merge, 0]);
+                self.terminate(op::BRANCH_CONDITIONAL, &[condition, then_label, else_label]);
+
+                self.label(then_label);
+                let then_value = self.expr(then)?;
+                let then_end = self.state().label;
+                self.branch(merge);
+                self.label(else_label);
+                let else_value = self.expr(else_)?;
+                let else_end = self.state().label;
+                self.branch(merge);
+
+                self.label(merge);
+                if ty.is_void() {
+                    return Ok(0);
+                }
+                let type_id = self.type_id(&ty, false);
+                self.emit_value(op::PHI, type_id, &[then_value, then_end, else_value, else_end])
+            }
+            ExprKind::Call {
+                ref arguments,
+                callee: Some(callee),
+                ..
+            } => match callee {
+                Callee::Constructor(ty) => self.constructor(&ty, arguments)?,
+                Callee::Builtin(index) => self.builtin_call(index, arguments)?,
+                Callee::User(id) => self.user_call(id, arguments)?,
+            },
+            ExprKind::Call { ref name, .. } => return Err(format!("unresolved function {}", name)),
+            ExprKind::Sequence(ref lhs, ref rhs) => {
+                self.expr(lhs)?;
+                self.expr(rhs)?
+            }
+        };
+        Ok(value)
+    }
+
+    fn unary(&mut self, operator: UnaryOp, operand: &Expr) -> TranslateResult<u32> {
+        let ty = operand.ty;
+        let value = match operator {
+            UnaryOp::Plus => self.expr(operand)?,
+            UnaryOp::Minus => {
+                let value = self.expr(operand)?;
+                self.negate(value, &ty)
+            }
+            UnaryOp::Not => {
+                let value = self.expr(operand)?;
+                let type_id = self.type_id(&ty, false);
+                self.emit_value(op::LOGICAL_NOT, type_id, &[value])
+            }
+            UnaryOp::PreIncrement | UnaryOp::PreDecrement | UnaryOp::PostIncrement | UnaryOp::PostDecrement => {
+                let lvalue = self.lvalue(operand)?;
+                let old = self.load_lvalue(&lvalue);
+                let kind = ty.scalar_kind().unwrap();
+                let one = if kind == ScalarKind::Float {
+                    self.constant(Constant::Float(1.0))
+                } else {
+                    self.constant(Constant::Int(1))
+                };
+                let operator_ = match operator {
+                    UnaryOp::PreIncrement | UnaryOp::PostIncrement => BinaryOp::Add,
+                    _ => BinaryOp::Sub,
+                };
+                let new = self.binary(operator_, old, &ty, one, &Type::scalar(kind), &ty);
+                self.store_lvalue(&lvalue, new);
+                match operator {
+                    UnaryOp::PreIncrement | UnaryOp::PreDecrement => new,
+                    _ => old,
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    fn negate(&mut self, value: u32, ty: &Type) -> u32 {
+        let type_id = self.type_id(ty, false);
+        match ty.base {
+            BaseType::Matrix(size) => {
+                let column_type = Type::vector(ScalarKind::Float, size);
+                let column_type_id = self.type_id(&column_type, false);
+                let columns = (0..size as u32)
+                    .map(|column| {
+                        let column = self.emit_value(op::COMPOSITE_EXTRACT, column_type_id, &[value, column]);
+                        self.emit_value(op::F_NEGATE, column_type_id, &[column])
+                    })
+                    .collect::<Vec<_>>();
+                self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &columns)
+            }
+            _ if ty.scalar_kind() == Some(ScalarKind::Float) => self.emit_value(op::F_NEGATE, type_id, &[value]),
+            _ => self.emit_value(op::S_NEGATE, type_id, &[value]),
+        }
+    }
+
+    /// `&&` and `||` evaluate their right operand only when needed
+    fn short_circuit(&mut self, is_and: bool, lhs: &Expr, rhs: &Expr) -> TranslateResult<u32> {
+        let lhs_value = self.expr(lhs)?;
+        let lhs_end = self.state().label;
+        let rhs_label = self.builder.id();
+        let merge = self.builder.id();
+        self.emit(op::SELECTION_MERGE, &[merge, 0]);
+        if is_and {
+            self.terminate(op::BRANCH_CONDITIONAL, &[lhs_value, rhs_label, merge]);
+        } else {
+            self.terminate(op::BRANCH_CONDITIONAL, &[lhs_value, merge, rhs_label]);
+        }
+
+        self.label(rhs_label);
+        let rhs_value = self.expr(rhs)?;
+        let rhs_end = self.state().label;
+        self.branch(merge);
+
+        self.label(merge);
+        let type_id = self.type_id(&Type::BOOL, false);
+        Ok(self.emit_value(op::PHI, type_id, &[lhs_value, lhs_end, rhs_value, rhs_end]))
+    }
+
+    fn binary(&mut self, operator: BinaryOp, lhs: u32, lhs_type: &Type, rhs: u32, rhs_type: &Type, ty: &Type) -> u32 {
+        let is_float = lhs_type.scalar_kind() == Some(ScalarKind::Float);
+        let bool_type = self.type_id(&Type::BOOL, false);
+        match operator {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+                self.arithmetic(operator, lhs, lhs_type, rhs, rhs_type, ty)
+            }
+            BinaryOp::Less | BinaryOp::Greater | BinaryOp::LessEqual | BinaryOp::GreaterEqual => {
+                let opcode = match (operator, is_float) {
+                    (BinaryOp::Less, true) => op::F_ORD_LESS_THAN,
+                    (BinaryOp::Less, false) => op::S_LESS_THAN,
+                    (BinaryOp::Greater, true) => op::F_ORD_GREATER_THAN,
+                    (BinaryOp::Greater, false) => op::S_GREATER_THAN,
+                    (BinaryOp::LessEqual, true) => op::F_ORD_LESS_THAN_EQUAL,
+                    (BinaryOp::LessEqual, false) => op::S_LESS_THAN_EQUAL,
+                    (_, true) => op::F_ORD_GREATER_THAN_EQUAL,
+                    (_, false) => op::S_GREATER_THAN_EQUAL,
+                };
+                self.emit_value(opcode, bool_type, &[lhs, rhs])
+            }
+            BinaryOp::Equal => self.equal(lhs, rhs, lhs_type),
+            BinaryOp::NotEqual => {
+                let equal = self.equal(lhs, rhs, lhs_type);
+                self.emit_value(op::LOGICAL_NOT, bool_type, &[equal])
+            }
+            BinaryOp::LogicalXor => self.emit_value(op::LOGICAL_NOT_EQUAL, bool_type, &[lhs, rhs]),
+            BinaryOp::LogicalAnd => self.emit_value(op::LOGICAL_AND, bool_type, &[lhs, rhs]),
+            BinaryOp::LogicalOr => {
+                // only reached for folded operands, short circuits are handled by `short_circuit`
+                let not_lhs = self.emit_value(op::LOGICAL_NOT, bool_type, &[lhs]);
+                let not_rhs = self.emit_value(op::LOGICAL_NOT, bool_type, &[rhs]);
+                let and = self.emit_value(op::LOGICAL_AND, bool_type, &[not_lhs, not_rhs]);
+                self.emit_value(op::LOGICAL_NOT, bool_type, &[and])
+            }
+        }
+    }
+
+    fn arithmetic(&mut self, operator: BinaryOp, lhs: u32, lhs_type: &Type, rhs: u32, rhs_type: &Type, ty: &Type) -> u32 {
+        let is_float = lhs_type.scalar_kind() == Some(ScalarKind::Float);
+        let opcode = match (operator, is_float) {
+            (BinaryOp::Add, true) => op::F_ADD,
+            (BinaryOp::Add, false) => op::I_ADD,
+            (BinaryOp::Sub, true) => op::F_SUB,
+            (BinaryOp::Sub, false) => op::I_SUB,
+            (BinaryOp::Mul, true) => op::F_MUL,
+            (BinaryOp::Mul, false) => op::I_MUL,
+            (_, true) => op::F_DIV,
+            (_, false) => op::S_DIV,
+        };
+        let type_id = self.type_id(ty, false);
+
+        if lhs_type.is_matrix() || rhs_type.is_matrix() {
+            if operator == BinaryOp::Mul {
+                if lhs_type.is_matrix() && rhs_type.is_matrix() {
+                    return self.emit_value(op::MATRIX_TIMES_MATRIX, type_id, &[lhs, rhs]);
+                } else if lhs_type.is_matrix() && rhs_type.is_vector() {
+                    return self.emit_value(op::MATRIX_TIMES_VECTOR, type_id, &[lhs, rhs]);
+                } else if lhs_type.is_vector() && rhs_type.is_matrix() {
+                    return self.emit_value(op::VECTOR_TIMES_MATRIX, type_id, &[lhs, rhs]);
+                } else if lhs_type.is_matrix() {
+                    return self.emit_value(op::MATRIX_TIMES_SCALAR, type_id, &[lhs, rhs]);
+                } else {
+                    return self.emit_value(op::MATRIX_TIMES_SCALAR, type_id, &[rhs, lhs]);
+                }
+            }
+
+            // component-wise operations apply to each column
+            let column_type = Type::vector(ScalarKind::Float, ty.size());
+            let column_type_id = self.type_id(&column_type, false);
+            let columns = (0..ty.size() as u32)
+                .map(|column| {
+                    let lhs_column = if lhs_type.is_matrix() {
+                        self.emit_value(op::COMPOSITE_EXTRACT, column_type_id, &[lhs, column])
+                    } else {
+                        self.splat(lhs, &column_type)
+                    };
+                    let rhs_column = if rhs_type.is_matrix() {
+                        self.emit_value(op::COMPOSITE_EXTRACT, column_type_id, &[rhs, column])
+                    } else {
+                        self.splat(rhs, &column_type)
+                    };
+                    self.emit_value(opcode, column_type_id, &[lhs_column, rhs_column])
+                })
+                .collect::<Vec<_>>();
+            return self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &columns);
+        }
+
+        if operator == BinaryOp::Mul && is_float {
+            if lhs_type.is_vector() && rhs_type.is_scalar() {
+                return self.emit_value(op::VECTOR_TIMES_SCALAR, type_id, &[lhs, rhs]);
+            } else if lhs_type.is_scalar() && rhs_type.is_vector() {
+                return self.emit_value(op::VECTOR_TIMES_SCALAR, type_id, &[rhs, lhs]);
+            }
+        }
+        let lhs = if lhs_type.is_scalar() { self.splat(lhs, ty) } else { lhs };
+        let rhs = if rhs_type.is_scalar() { self.splat(rhs, ty) } else { rhs };
+        self.emit_value(opcode, type_id, &[lhs, rhs])
+    }
+
+    /// Aggregates are equal when all their components are
+    fn equal(&mut self, lhs: u32, rhs: u32, ty: &Type) -> u32 {
+        let bool_type = self.type_id(&Type::BOOL, false);
+        match ty.base {
+            BaseType::Scalar(kind) | BaseType::Vector(kind, _) => {
+                let opcode = match kind {
+                    ScalarKind::Float => op::F_ORD_EQUAL,
+                    ScalarKind::Int => op::I_EQUAL,
+                    ScalarKind::Bool => op::LOGICAL_EQUAL,
+                };
+                let result_type = self.type_id(&Type::vector(ScalarKind::Bool, ty.size()), false);
+                let equal = self.emit_value(opcode, result_type, &[lhs, rhs]);
+                if ty.is_vector() {
+                    self.emit_value(op::ALL, bool_type, &[equal])
+                } else {
+                    equal
+                }
+            }
+            BaseType::Matrix(size) => {
+                let column_type = Type::vector(ScalarKind::Float, size);
+                let parts = (0..size as u32).map(|index| (index, column_type)).collect::<Vec<_>>();
+                self.equal_parts(lhs, rhs, &parts)
+            }
+            BaseType::Struct(id) => {
+                let parts = self.shader.structs[id]
+                    .fields
+                    .iter()
+                    .enumerate()
+                    .map(|(index, field)| (index as u32, field.ty))
+                    .collect::<Vec<_>>();
+                self.equal_parts(lhs, rhs, &parts)
+            }
+            _ => self.constant(Constant::Bool(false)),
+        }
+    }
+
+    fn equal_parts(&mut self, lhs: u32, rhs: u32, parts: &[(u32, Type)]) -> u32 {
+        let bool_type = self.type_id(&Type::BOOL, false);
+        let mut result = None;
+        for &(index, ref ty) in parts {
+            let type_id = self.type_id(ty, false);
+            let lhs_part = self.emit_value(op::COMPOSITE_EXTRACT, type_id, &[lhs, index]);
+            let rhs_part = self.emit_value(op::COMPOSITE_EXTRACT, type_id, &[rhs, index]);
+            let equal = self.equal(lhs_part, rhs_part, ty);
+            result = Some(match result {
+                Some(result) => self.emit_value(op::LOGICAL_AND, bool_type, &[result, equal]),
+                None => equal,
+            });
+        }
+        result.unwrap_or_else(|| self.constant(Constant::Bool(true)))
+    }
+
+    fn convert_scalar(&mut self, value: u32, from: ScalarKind, to: ScalarKind) -> u32 {
+        let type_id = self.type_id(&Type::scalar(to), false);
+        match (from, to) {
+            _ if from == to => value,
+            (ScalarKind::Int, ScalarKind::Float) => self.emit_value(op::CONVERT_S_TO_F, type_id, &[value]),
+            (ScalarKind::Float, ScalarKind::Int) => self.emit_value(op::CONVERT_F_TO_S, type_id, &[value]),
+            (ScalarKind::Bool, _) => {
+                let (one, zero) = if to == ScalarKind::Float {
+                    (Constant::Float(1.0), Constant::Float(0.0))
+                } else {
+                    (Constant::Int(1), Constant::Int(0))
+                };
+                let one = self.constant(one);
+                let zero = self.constant(zero);
+                self.emit_value(op::SELECT, type_id, &[value, one, zero])
+            }
+            (ScalarKind::Int, _) => {
+                let zero = self.constant(Constant::Int(0));
+                self.emit_value(op::I_NOT_EQUAL, type_id, &[value, zero])
+            }
+            (ScalarKind::Float, _) => {
+                let zero = self.constant(Constant::Float(0.0));
+                self.emit_value(op::F_ORD_NOT_EQUAL, type_id, &[value, zero])
+            }
+        }
+    }
+
+    /// Scalar components of a value in column major order
+    fn components(&mut self, value: u32, ty: &Type) -> Vec<(u32, ScalarKind)> {
+        let kind = ty.scalar_kind().unwrap();
+        let component_type = self.type_id(&Type::scalar(kind), false);
+        match ty.base {
+            BaseType::Scalar(_) => vec![(value, kind)],
+            BaseType::Vector(_, size) => (0..size as u32)
+                .map(|index| (self.emit_value(op::COMPOSITE_EXTRACT, component_type, &[value, index]), kind))
+                .collect(),
+            BaseType::Matrix(size) => {
+                let mut components = Vec::new();
+                for column in 0..size as u32 {
+                    for row in 0..size as u32 {
+                        let component = self.emit_value(op::COMPOSITE_EXTRACT, component_type, &[value, column, row]);
+                        components.push((component, kind));
+                    }
+                }
+                components
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn constructor(&mut self, ty: &Type, arguments: &[Expr]) -> TranslateResult<u32> {
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            values.push(self.expr(argument)?);
+        }
+        let type_id = self.type_id(ty, false);
+
+        let value = match ty.base {
+            BaseType::Struct(_) => self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &values),
+            BaseType::Scalar(kind) => {
+                let (component, from) = self.components(values[0], &arguments[0].ty)[0];
+                self.convert_scalar(component, from, kind)
+            }
+            BaseType::Vector(kind, size) => {
+                if arguments.len() == 1 && arguments[0].ty.is_scalar() {
+                    let from = arguments[0].ty.scalar_kind().unwrap();
+                    let component = self.convert_scalar(values[0], from, kind);
+                    self.splat(component, ty)
+                } else {
+                    let components = self.converted_components(&values, arguments, kind, size as usize);
+                    self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &components)
+                }
+            }
+            BaseType::Matrix(size) => {
+                let column_type = self.type_id(&Type::vector(ScalarKind::Float, size), false);
+                let zero = self.constant(Constant::Float(0.0));
+                let one = self.constant(Constant::Float(1.0));
+                let argument_type = arguments[0].ty;
+
+                let components = if arguments.len() == 1 && argument_type.is_scalar() {
+                    // diagonal matrix
+                    let from = argument_type.scalar_kind().unwrap();
+                    let diagonal = self.convert_scalar(values[0], from, ScalarKind::Float);
+                    (0..size * size)
+                        .map(|index| if index / size == index % size { diagonal } else { zero })
+                        .collect::<Vec<_>>()
+                } else if arguments.len() == 1 && argument_type.is_matrix() {
+                    // resized matrix, completed with the identity
+                    let source_size = argument_type.size();
+                    let float_type = self.type_id(&Type::FLOAT, false);
+                    (0..size * size)
+                        .map(|index| {
+                            let (column, row) = (index / size, index % size);
+                            if column < source_size && row < source_size {
+                                self.emit_value(op::COMPOSITE_EXTRACT, float_type, &[values[0], column as u32, row as u32])
+                            } else if column == row {
+                                one
+                            } else {
+                                zero
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                } else {
+                    self.converted_components(&values, arguments, ScalarKind::Float, (size * size) as usize)
+                };
+
+                let columns = components
+                    .chunks(size as usize)
+                    .map(|column| self.emit_value(op::COMPOSITE_CONSTRUCT, column_type, column))
+                    .collect::<Vec<_>>();
+                self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &columns)
+            }
+            _ => return Err(format!("can not construct {:?}", ty)),
+        };
+        Ok(value)
+    }
+
+    /// The first `count` scalar components of the arguments converted to `kind`
+    fn converted_components(&mut self, values: &[u32], arguments: &[Expr], kind: ScalarKind, count: usize) -> Vec<u32> {
+        let mut components = Vec::with_capacity(count);
+        for (&value, argument) in values.iter().zip(arguments) {
+            for (component, from) in self.components(value, &argument.ty) {
+                if components.len() < count {
+                    components.push(self.convert_scalar(component, from, kind));
+                }
+            }
+        }
+        components
+    }
+
+    fn ext_inst(&mut self, instruction: u32, ty: &Type, operands: &[u32]) -> u32 {
+        let type_id = self.type_id(ty, false);
+        let mut all_operands = vec![self.glsl_std, instruction];
+        all_operands.extend_from_slice(operands);
+        self.emit_value(op::EXT_INST, type_id, &all_operands)
+    }
+
+    fn builtin_call(&mut self, index: usize, arguments: &[Expr]) -> TranslateResult<u32> {
+        let function = &BUILTIN_FUNCTIONS[index];
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            values.push(self.expr(argument)?);
+        }
+        let ty = function.return_type;
+        let type_id = self.type_id(&ty, false);
+        let first_type = function.parameters[0];
+
+        let simple = match function.name {
+            "radians" => Some(glsl_std::RADIANS),
+            "degrees" => Some(glsl_std::DEGREES),
+            "sin" => Some(glsl_std::SIN),
+            "cos" => Some(glsl_std::COS),
+            "tan" => Some(glsl_std::TAN),
+            "asin" => Some(glsl_std::ASIN),
+            "acos" => Some(glsl_std::ACOS),
+            "atan" if values.len() == 2 => Some(glsl_std::ATAN2),
+            "atan" => Some(glsl_std::ATAN),
+            "pow" => Some(glsl_std::POW),
+            "exp" => Some(glsl_std::EXP),
+            "log" => Some(glsl_std::LOG),
+            "exp2" => Some(glsl_std::EXP2),
+            "log2" => Some(glsl_std::LOG2),
+            "sqrt" => Some(glsl_std::SQRT),
+            "inversesqrt" => Some(glsl_std::INVERSE_SQRT),
+            "abs" => Some(glsl_std::F_ABS),
+            "sign" => Some(glsl_std::F_SIGN),
+            "floor" => Some(glsl_std::FLOOR),
+            "ceil" => Some(glsl_std::CEIL),
+            "fract" => Some(glsl_std::FRACT),
+            "length" => Some(glsl_std::LENGTH),
+            "distance" => Some(glsl_std::DISTANCE),
+            "cross" => Some(glsl_std::CROSS),
+            "normalize" => Some(glsl_std::NORMALIZE),
+            "faceforward" => Some(glsl_std::FACE_FORWARD),
+            "reflect" => Some(glsl_std::REFLECT),
+            "refract" => Some(glsl_std::REFRACT),
+            _ => None,
+        };
+        if let Some(instruction) = simple {
+            return Ok(self.ext_inst(instruction, &ty, &values));
+        }
+
+        // scalar arguments of component-wise functions are replicated
+        let splatted = function
+            .parameters
+            .iter()
+            .zip(&values)
+            .map(|(parameter, &value)| (*parameter, value))
+            .collect::<Vec<_>>();
+        let splat_all = |translator: &mut Self| {
+            splatted
+                .iter()
+                .map(|&(ref parameter, value)| {
+                    if parameter.is_scalar() {
+                        translator.splat(value, &ty)
+                    } else {
+                        value
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let value = match function.name {
+            "mod" => {
+                let operands = splat_all(self);
+                self.emit_value(op::F_MOD, type_id, &operands)
+            }
+            "min" | "max" | "clamp" | "mix" | "step" | "smoothstep" => {
+                let instruction = match function.name {
+                    "min" => glsl_std::F_MIN,
+                    "max" => glsl_std::F_MAX,
+                    "clamp" => glsl_std::F_CLAMP,
+                    "mix" => glsl_std::F_MIX,
+                    "step" => glsl_std::STEP,
+                    _ => glsl_std::SMOOTH_STEP,
+                };
+                let operands = splat_all(self);
+                self.ext_inst(instruction, &ty, &operands)
+            }
+            "dot" if first_type.is_scalar() => self.emit_value(op::F_MUL, type_id, &values),
+            "dot" => self.emit_value(op::DOT, type_id, &values),
+            "matrixCompMult" => self.arithmetic_per_column(values[0], values[1], &ty),
+            "lessThan" | "lessThanEqual" | "greaterThan" | "greaterThanEqual" | "equal" | "notEqual" => {
+                let kind = first_type.scalar_kind().unwrap();
+                let opcode = match (function.name, kind) {
+                    ("lessThan", ScalarKind::Float) => op::F_ORD_LESS_THAN,
+                    ("lessThan", _) => op::S_LESS_THAN,
+                    ("lessThanEqual", ScalarKind::Float) => op::F_ORD_LESS_THAN_EQUAL,
+                    ("lessThanEqual", _) => op::S_LESS_THAN_EQUAL,
+                    ("greaterThan", ScalarKind::Float) => op::F_ORD_GREATER_THAN,
+                    ("greaterThan", _) => op::S_GREATER_THAN,
+                    ("greaterThanEqual", ScalarKind::Float) => op::F_ORD_GREATER_THAN_EQUAL,
+                    ("greaterThanEqual", _) => op::S_GREATER_THAN_EQUAL,
+                    ("equal", ScalarKind::Float) => op::F_ORD_EQUAL,
+                    ("equal", ScalarKind::Int) => op::I_EQUAL,
+                    ("equal", ScalarKind::Bool) => op::LOGICAL_EQUAL,
+                    (_, ScalarKind::Float) => op::F_ORD_NOT_EQUAL,
+                    (_, ScalarKind::Int) => op::I_NOT_EQUAL,
+                    (_, ScalarKind::Bool) => op::LOGICAL_NOT_EQUAL,
+                };
+                self.emit_value(opcode, type_id, &values)
+            }
+            "any" => self.emit_value(op::ANY, type_id, &values),
+            "all" => self.emit_value(op::ALL, type_id, &values),
+            "not" => self.emit_value(op::LOGICAL_NOT, type_id, &values),
+            name if name.starts_with("texture") => self.sample(name, &function.parameters, &values),
+            name => return Err(format!("unsupported built-in function {}", name)),
+        };
+        Ok(value)
+    }
+
+    fn arithmetic_per_column(&mut self, lhs: u32, rhs: u32, ty: &Type) -> u32 {
+        let type_id = self.type_id(ty, false);
+        let column_type = self.type_id(&Type::vector(ScalarKind::Float, ty.size()), false);
+        let columns = (0..ty.size() as u32)
+            .map(|column| {
+                let lhs_column = self.emit_value(op::COMPOSITE_EXTRACT, column_type, &[lhs, column]);
+                let rhs_column = self.emit_value(op::COMPOSITE_EXTRACT, column_type, &[rhs, column]);
+                self.emit_value(op::F_MUL, column_type, &[lhs_column, rhs_column])
+            })
+            .collect::<Vec<_>>();
+        self.emit_value(op::COMPOSITE_CONSTRUCT, type_id, &columns)
+    }
+
+    /// Texture lookups, vertex shaders have no implicit level of detail
+    fn sample(&mut self, name: &str, parameters: &[Type], values: &[u32]) -> u32 {
+        let type_id = self.type_id(&Type::vector(ScalarKind::Float, 4), false);
+        let is_projective = name.contains("Proj");
+        let is_lod = name.ends_with("Lod");
+        let has_bias = !is_lod && values.len() == 3;
+
+        let mut coordinate = values[1];
+        if is_projective && parameters[1].size() == 4 {
+            // the divisor of `texture2DProj(sampler2D, vec4)` is the last component
+            coordinate = self.shuffle(coordinate, coordinate, ScalarKind::Float, &[0, 1, 3]);
+        }
+
+        let mut operands = vec![values[0], coordinate];
+        let opcode = if is_lod || self.shader.stage == Stage::Vertex {
+            let lod = if is_lod {
+                values[2]
+            } else {
+                self.constant(Constant::Float(0.0))
+            };
+            operands.extend_from_slice(&[spirv::IMAGE_OPERANDS_LOD, lod]);
+            if is_projective {
+                op::IMAGE_SAMPLE_PROJ_EXPLICIT_LOD
+            } else {
+                op::IMAGE_SAMPLE_EXPLICIT_LOD
+            }
+        } else {
+            if has_bias {
+                operands.extend_from_slice(&[spirv::IMAGE_OPERANDS_BIAS, values[2]]);
+            }
+            if is_projective {
+                op::IMAGE_SAMPLE_PROJ_IMPLICIT_LOD
+            } else {
+                op::IMAGE_SAMPLE_IMPLICIT_LOD
+            }
+        };
+        self.emit_value(opcode, type_id, &operands)
+    }
+
+    /// Arguments are copied to temporaries which are copied back for `out` and `inout` parameters
+    fn user_call(&mut self, id: FunctionId, arguments: &[Expr]) -> TranslateResult<u32> {
+        let function = &self.shader.functions[id];
+        if !function.is_defined {
+            return Err(format!("function {} is not defined", function.name));
+        }
+
+        let mut operands = vec![self.function_ids[id]];
+        let mut write_backs = Vec::new();
+        for (argument, &(qualifier, ref ty)) in arguments.iter().zip(&function.parameters) {
+            if ty.is_sampler() {
+                match self.lvalue(argument)? {
+                    LValue::Pointer(ref pointer) if pointer.storage == storage_class::UNIFORM_CONSTANT => {
+                        operands.push(pointer.id)
+                    }
+                    _ => return Err("unsupported sampler argument".to_string()),
+                }
+                continue;
+            }
+
+            let temporary = self.local_variable(ty, None);
+            match qualifier {
+                ParameterQualifier::In => {
+                    let value = self.expr(argument)?;
+                    self.store(&temporary, value);
+                }
+                ParameterQualifier::Out => write_backs.push((self.lvalue(argument)?, temporary)),
+                ParameterQualifier::InOut => {
+                    let lvalue = self.lvalue(argument)?;
+                    let value = self.load_lvalue(&lvalue);
+                    self.store(&temporary, value);
+                    write_backs.push((lvalue, temporary));
+                }
+            }
+            operands.push(temporary.id);
+        }
+
+        let return_type = self.type_id(&function.return_type, false);
+        let result = self.emit_value(op::FUNCTION_CALL, return_type, &operands);
+        for (lvalue, temporary) in write_backs {
+            let value = self.load(&temporary);
+            self.store_lvalue(&lvalue, value);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt;
+
+pub(crate) type StructId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ScalarKind {
+    Float,
+    Int,
+    Bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum BaseType {
+    Void,
+    Scalar(ScalarKind),
+    /// Vector of 2 to 4 components
+    Vector(ScalarKind, u8),
+    /// Square float matrix of 2 to 4 columns
+    Matrix(u8),
+    Sampler2D,
+    SamplerCube,
+    Struct(StructId),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Precision {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Type {
+    pub base: BaseType,
+    /// Arrays are one dimensional in GLSL ES 1.00
+    pub array_size: Option<u32>,
+}
+
+impl Type {
+    pub const VOID: Type = Type::new(BaseType::Void);
+    pub const FLOAT: Type = Type::new(BaseType::Scalar(ScalarKind::Float));
+    pub const INT: Type = Type::new(BaseType::Scalar(ScalarKind::Int));
+    pub const BOOL: Type = Type::new(BaseType::Scalar(ScalarKind::Bool));
+
+    pub const fn new(base: BaseType) -> Self {
+        Self { base, array_size: None }
+    }
+
+    pub fn scalar(kind: ScalarKind) -> Self {
+        Self::new(BaseType::Scalar(kind))
+    }
+
+    /// Scalar for one component, vector otherwise
+    pub fn vector(kind: ScalarKind, size: u8) -> Self {
+        if size == 1 {
+            Self::scalar(kind)
+        } else {
+            Self::new(BaseType::Vector(kind, size))
+        }
+    }
+
+    pub fn matrix(size: u8) -> Self {
+        Self::new(BaseType::Matrix(size))
+    }
+
+    pub fn array(self, size: u32) -> Self {
+        Self {
+            array_size: Some(size),
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn is_array(&self) -> bool {
+        self.array_size.is_some()
+    }
+
+    #[inline]
+    pub fn is_void(&self) -> bool {
+        self.base == BaseType::Void && !self.is_array()
+    }
+
+    #[inline]
+    pub fn is_scalar(&self) -> bool {
+        !self.is_array()
+            && match self.base {
+                BaseType::Scalar(_) => true,
+                _ => false,
+            }
+    }
+
+    #[inline]
+    pub fn is_vector(&self) -> bool {
+        !self.is_array()
+            && match self.base {
+                BaseType::Vector(..) => true,
+                _ => false,
+            }
+    }
+
+    #[inline]
+    pub fn is_matrix(&self) -> bool {
+        !self.is_array()
+            && match self.base {
+                BaseType::Matrix(_) => true,
+                _ => false,
+            }
+    }
+
+    #[inline]
+    pub fn is_sampler(&self) -> bool {
+        match self.base {
+            BaseType::Sampler2D | BaseType::SamplerCube => true,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub fn is_struct(&self) -> bool {
+        match self.base {
+            BaseType::Struct(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Scalars, vectors and matrices which are not arrays
+    #[inline]
+    pub fn is_numeric(&self) -> bool {
+        self.scalar_kind().map_or(false, |kind| kind != ScalarKind::Bool) && !self.is_array()
+    }
+
+    pub fn scalar_kind(&self) -> Option<ScalarKind> {
+        match self.base {
+            BaseType::Scalar(kind) | BaseType::Vector(kind, _) => Some(kind),
+            BaseType::Matrix(_) => Some(ScalarKind::Float),
+            _ => None,
+        }
+    }
+
+    /// Number of scalar components of a scalar, vector or matrix
+    pub fn component_count(&self) -> u32 {
+        match self.base {
+            BaseType::Scalar(_) => 1,
+            BaseType::Vector(_, size) => size as u32,
+            BaseType::Matrix(size) => size as u32 * size as u32,
+            _ => 0,
+        }
+    }
+
+    /// Vector size, or column count of matrices
+    pub fn size(&self) -> u8 {
+        match self.base {
+            BaseType::Vector(_, size) | BaseType::Matrix(size) => size,
+            _ => 1,
+        }
+    }
+
+    /// Type produced by `[]` on this type
+    pub fn element(&self) -> Option<Type> {
+        if self.is_array() {
+            return Some(Type::new(self.base));
+        }
+        match self.base {
+            BaseType::Vector(kind, _) => Some(Type::scalar(kind)),
+            BaseType::Matrix(size) => Some(Type::vector(ScalarKind::Float, size)),
+            _ => None,
+        }
+    }
+
+    /// Number of uniform or varying vectors the type uses without packing
+    pub fn location_count(&self, structs: &[StructType]) -> u32 {
+        let count = match self.base {
+            BaseType::Matrix(size) => size as u32,
+            BaseType::Struct(id) => structs[id].fields.iter().map(|field| field.ty.location_count(structs)).sum(),
+            _ => 1,
+        };
+        count * self.array_size.unwrap_or(1)
+    }
+
+    /// Return true if the type or one of its fields is of the given kind
+    pub fn contains<F: Fn(&Type) -> bool>(&self, structs: &[StructType], predicate: &F) -> bool {
+        match self.base {
+            BaseType::Struct(id) => structs[id].fields.iter().any(|field| field.ty.contains(structs, predicate)),
+            _ => predicate(&Type::new(self.base)),
+        }
+    }
+
+    /// Name of the type as written in a shader, for messages
+    pub fn name(&self, structs: &[StructType]) -> String {
+        let base = match self.base {
+            BaseType::Struct(id) => structs[id].name.clone().unwrap_or_else(|| "<anonymous struct>".to_string()),
+            _ => self.base.to_string(),
+        };
+        match self.array_size {
+            Some(size) => format!("{}[{}]", base, size),
+            None => base,
+        }
+    }
+}
+
+impl fmt::Display for BaseType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = |kind: ScalarKind| match kind {
+            ScalarKind::Float => "",
+            ScalarKind::Int => "i",
+            ScalarKind::Bool => "b",
+        };
+        match *self {
+            BaseType::Void => write!(f, "void"),
+            BaseType::Scalar(ScalarKind::Float) => write!(f, "float"),
+            BaseType::Scalar(ScalarKind::Int) => write!(f, "int"),
+            BaseType::Scalar(ScalarKind::Bool) => write!(f, "bool"),
+            BaseType::Vector(kind, size) => write!(f, "{}vec{}", prefix(kind), size),
+            BaseType::Matrix(size) => write!(f, "mat{}", size),
+            BaseType::Sampler2D => write!(f, "sampler2D"),
+            BaseType::SamplerCube => write!(f, "samplerCube"),
+            BaseType::Struct(id) => write!(f, "struct #{}", id),
+        }
+    }
+}
+
+/// Keyword naming a built-in type
+pub(crate) fn builtin_type(name: &str) -> Option<BaseType> {
+    let base = match name {
+        "void" => BaseType::Void,
+        "float" => BaseType::Scalar(ScalarKind::Float),
+        "int" => BaseType::Scalar(ScalarKind::Int),
+        "bool" => BaseType::Scalar(ScalarKind::Bool),
+        "vec2" => BaseType::Vector(ScalarKind::Float, 2),
+        "vec3" => BaseType::Vector(ScalarKind::Float, 3),
+        "vec4" => BaseType::Vector(ScalarKind::Float, 4),
+        "ivec2" => BaseType::Vector(ScalarKind::Int, 2),
+        "ivec3" => BaseType::Vector(ScalarKind::Int, 3),
+        "ivec4" => BaseType::Vector(ScalarKind::Int, 4),
+        "bvec2" => BaseType::Vector(ScalarKind::Bool, 2),
+        "bvec3" => BaseType::Vector(ScalarKind::Bool, 3),
+        "bvec4" => BaseType::Vector(ScalarKind::Bool, 4),
+        "mat2" => BaseType::Matrix(2),
+        "mat3" => BaseType::Matrix(3),
+        "mat4" => BaseType::Matrix(4),
+        "sampler2D" => BaseType::Sampler2D,
+        "samplerCube" => BaseType::SamplerCube,
+        _ => return None,
+    };
+    Some(base)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct StructField {
+    pub name: String,
+    pub ty: Type,
+    pub precision: Option<Precision>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct StructType {
+    pub name: Option<String>,
+    pub fields: Vec<StructField>,
+}
+
+impl StructType {
+    pub fn field(&self, name: &str) -> Option<(usize, &StructField)> {
+        self.fields.iter().enumerate().find(|&(_, field)| field.name == name)
+    }
+}
@@ -1,60 +1,834 @@
-pub fn hal_buffer_allocate() -> bool {
-    debug!("hal_buffer_allocate: fake allocate failed");
-    true
+use parking_lot::Mutex;
+
+use std::ops::Range;
+use std::ptr;
+use std::slice;
+
+use back;
+use hal::command::{AttachmentClear, ClearColor, ClearDepthStencil, ClearValue, OneShot, RenderPassInlineEncoder};
+use hal::format::{Aspects, Format, ImageFeature, Swizzle};
+use hal::pass::{self, AttachmentLoadOp, AttachmentStoreOp};
+use hal::pool::CommandPoolCreateFlags;
+use hal::pso::{self, DescriptorPool, Face};
+use hal::{self, buffer, image, memory, Device, IndexType, Instance, MemoryTypeId, PhysicalDevice, QueueFamily};
+
+use command_buffer::{Command, CommandBuffer, RenderTarget, Resources};
+use destruction_queue::HalObject;
+use frame_buffer::LoadOps;
+use gl_sys::{GLbitfield, GL_COLOR_BUFFER_BIT, GL_DEPTH_BUFFER_BIT, GL_STENCIL_BUFFER_BIT, GL_UNSIGNED_SHORT};
+use pipeline::RenderPassKey;
+use rendering::ClearValues;
+use texture::Texture;
+use viewport_transformation;
+use {
+    HalBuffer, HalCommandBuffer, HalDescriptorPool, HalDescriptorSet, HalDescriptorSetLayout, HalFence, HalFrameBuffer,
+    HalGraphicsPipeline, HalImageView, HalMemory, HalPipelineLayout, HalRenderPass, HalSampler, HalShaderModule, B,
+};
+
+lazy_static! {
+    static ref ADAPTER: Mutex<Option<hal::Adapter<B>>> = {
+        let instance = back::Instance::create("hood", 1);
+        Mutex::new(instance.enumerate_adapters().into_iter().next())
+    };
+    static ref DEVICE: Mutex<Option<LogicalDevice>> = Mutex::new(ADAPTER.lock().as_ref().and_then(open_device));
 }
 
+/// Device opened on the adapter, with the queue commands are submitted to and the pool of their command buffers
+struct LogicalDevice {
+    device: <B as hal::Backend>::Device,
+    queue_group: hal::QueueGroup<B, hal::Graphics>,
+    command_pool: hal::CommandPool<B, hal::Graphics>,
+}
 
+fn open_device(adapter: &hal::Adapter<B>) -> Option<LogicalDevice> {
+    let (device, queue_group) = match adapter.open_with::<_, hal::Graphics>(1, |family| family.supports_graphics()) {
+        Ok(opened) => opened,
+        Err(err) => {
+            error!("failed to open a device: {:?}", err);
+            return None;
+        }
+    };
+    let command_pool = match unsafe { device.create_command_pool_typed(&queue_group, CommandPoolCreateFlags::TRANSIENT) } {
+        Ok(command_pool) => command_pool,
+        Err(err) => {
+            error!("failed to create a command pool: {:?}", err);
+            return None;
+        }
+    };
+    Some(LogicalDevice {
+        device,
+        queue_group,
+        command_pool,
+    })
+}
 
-pub(crate) fn hal_sampler_release() {}
-pub(crate) fn hal_image_view_release() {}
-pub(crate) fn hal_image_release() {}
-pub(crate) fn hal_memory_release() {}
+/// Return true if a device was opened, nothing can be submitted without one
+pub(crate) fn hal_device_available() -> bool {
+    DEVICE.lock().is_some()
+}
+
+/// Limits and features of the adapter, without an adapter the limits every Vulkan implementation guarantees
+pub(crate) fn hal_limits() -> (hal::Limits, hal::Features) {
+    match *ADAPTER.lock() {
+        Some(ref adapter) => (adapter.physical_device.limits(), adapter.physical_device.features()),
+        None => {
+            let limits = hal::Limits {
+                max_image_2d_size: 4096,
+                max_image_cube_size: 4096,
+                max_viewport_dimensions: [4096, 4096],
+                max_framebuffer_extent: hal::image::Extent {
+                    width: 4096,
+                    height: 4096,
+                    depth: 1,
+                },
+                max_vertex_input_attributes: 16,
+                max_vertex_output_components: 64,
+                max_per_stage_descriptor_samplers: 16,
+                max_uniform_buffer_range: 16384,
+                ..hal::Limits::default()
+            };
+            (limits, hal::Features::empty())
+        }
+    }
+}
 
-pub fn hal_texture_create() -> bool {
-    trace!("hal_texture_create()");
+/// Return true if images of `format` with optimal tiling support `feature`, assume so without an adapter
+pub(crate) fn hal_format_supported(format: Format, feature: ImageFeature) -> bool {
+    match *ADAPTER.lock() {
+        Some(ref adapter) => adapter
+            .physical_device
+            .format_properties(Some(format))
+            .optimal_tiling
+            .contains(feature),
+        None => true,
+    }
+}
 
-    hal_sampler_release();
-    hal_image_view_release();
-    hal_image_release();
-    hal_memory_release();
+/// Create an unsignaled fence, None without a device or on failure
+pub(crate) fn hal_fence_create() -> Option<HalFence> {
+    trace!("hal_fence_create()");
 
-    if !hal_image_create() {
-        return false;
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => device,
+        None => {
+            error!("no device to create a fence with");
+            return None;
+        }
+    };
+    match unsafe { device.device.create_fence(false) } {
+        Ok(fence) => Some(fence),
+        Err(err) => {
+            error!("failed to create a fence: {:?}", err);
+            None
+        }
     }
+}
+
+pub(crate) fn hal_fence_release(fence: HalFence) {
+    if let Some(ref device) = *DEVICE.lock() {
+        unsafe { device.device.destroy_fence(fence) };
+    }
+}
 
-    //        if mip_level_count > 1 {
-    //
-    //        }
+/// Return true if `fence` signaled
+pub(crate) fn hal_fence_status(fence: &HalFence) -> bool {
+    trace!("hal_fence_status(fence = {:?})", fence);
 
-    if !hal_memory_allocate() {
-        hal_image_release();
-        return false;
+    match *DEVICE.lock() {
+        Some(ref device) => match unsafe { device.device.get_fence_status(fence) } {
+            Ok(signaled) => signaled,
+            Err(err) => {
+                error!("failed to query fence {:?}: {:?}", fence, err);
+                false
+            }
+        },
+        None => false,
     }
+}
+
+/// Block until `fence` signaled, return false if waiting failed
+pub(crate) fn hal_fence_wait(fence: &HalFence) -> bool {
+    trace!("hal_fence_wait(fence = {:?})", fence);
 
-    if !hal_image_view_create() {
-//        image_release();
-//        memory_release();
-        return false;
+    match *DEVICE.lock() {
+        Some(ref device) => match unsafe { device.device.wait_for_fence(fence, !0) } {
+            Ok(signaled) => signaled,
+            Err(err) => {
+                error!("failed to wait for fence {:?}: {:?}", fence, err);
+                false
+            }
+        },
+        None => false,
     }
-    true
 }
 
+/// Translate the commands of `command_buffer` into a hal command buffer, finding the hal objects they use in
+/// `resources`, and submit it, signalling `fence` on completion. The hal command buffer has to be kept until then,
+/// None without a device
+pub(crate) fn hal_queue_submit(
+    command_buffer: &CommandBuffer,
+    resources: &Resources,
+    fence: &HalFence,
+) -> Option<HalCommandBuffer> {
+    trace!("hal_queue_submit({} commands)", command_buffer.commands.len());
 
+    let mut device = DEVICE.lock();
+    let device = match *device {
+        Some(ref mut device) => device,
+        None => {
+            error!("no device to submit {} commands to", command_buffer.commands.len());
+            return None;
+        }
+    };
+    let mut raw = device.command_pool.acquire_command_buffer::<OneShot>();
+    unsafe {
+        raw.begin();
+        encode(&mut raw, &command_buffer.commands, resources);
+        raw.finish();
+        device.queue_group.queues[0].submit_nosemaphores(Some(&raw), Some(fence));
+    }
+    Some(raw)
+}
 
+/// Return a submitted command buffer to its pool once its fence signaled
+pub(crate) fn hal_command_buffer_release(raw: HalCommandBuffer) {
+    if let Some(ref mut device) = *DEVICE.lock() {
+        unsafe { device.command_pool.free(Some(raw)) };
+    }
+}
 
+/// Record `commands` into `raw`, commands other than `BeginRenderPass` are only recorded within a render pass
+unsafe fn encode(raw: &mut HalCommandBuffer, commands: &[Command], resources: &Resources) {
+    let mut render_targets = resources.transients.render_targets.iter();
+    let mut descriptor_sets = resources.transients.descriptor_sets.iter();
+    let mut commands = commands.iter();
+    while let Some(command) = commands.next() {
+        let (load_ops, clear_values) = match *command {
+            Command::BeginRenderPass {
+                load_ops,
+                ref clear_values,
+                ..
+            } => (load_ops, clear_values),
+            _ => {
+                warn!("{:?} is outside of a render pass", command);
+                continue;
+            }
+        };
+        let render_pass = match render_targets.next() {
+            Some(Some(target)) => resources
+                .pipeline_cache
+                .find_render_pass(target.key, load_ops)
+                .map(|render_pass| (target, render_pass)),
+            _ => None,
+        };
+        match render_pass {
+            Some((target, render_pass)) => {
+                let clear_values = render_pass_clear_values(&target.key, clear_values);
+                let mut encoder = raw.begin_render_pass_inline(render_pass, &target.frame_buffer, target.area, &clear_values);
+                encode_subpass(&mut encoder, target, &mut commands, &mut descriptor_sets, resources);
+            }
+            None => {
+                warn!("skip a render pass without a frame buffer");
+                for command in &mut commands {
+                    match *command {
+                        Command::EndRenderPass => break,
+                        Command::BindDescriptorSet(_) => {
+                            descriptor_sets.next();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
 
-pub fn hal_memory_allocate() -> bool {
-    true
+/// Record the commands of a render pass drawing into `target` up to its end, draws without every object they read
+/// are skipped
+unsafe fn encode_subpass(
+    encoder: &mut RenderPassInlineEncoder<'_, B>,
+    target: &RenderTarget,
+    commands: &mut slice::Iter<Command>,
+    descriptor_sets: &mut slice::Iter<Option<HalDescriptorSet>>,
+    resources: &Resources,
+) {
+    let mut layout = None;
+    let mut is_drawable = false;
+    for command in commands {
+        match *command {
+            Command::EndRenderPass => return,
+            Command::ClearAttachments {
+                mask,
+                ref clear_values,
+                ref rect,
+                color_mask,
+            } => {
+                let clears = attachment_clears(&target.key, mask, clear_values, color_mask);
+                let rect = rect.as_ref().map_or(target.area, viewport_transformation::hal_rect);
+                if !clears.is_empty() {
+                    encoder.clear_attachments(clears, Some(pso::ClearRect { rect, layers: 0..1 }));
+                }
+            }
+            Command::BindPipeline(index) => match resources.pipeline_cache.pipeline(index) {
+                Some(pipeline) => {
+                    encoder.bind_graphics_pipeline(&pipeline.raw);
+                    layout = resources.pipeline_cache.layout(pipeline.key.program);
+                    is_drawable = true;
+                }
+                None => {
+                    warn!("pipeline {} was removed before it was submitted", index);
+                    layout = None;
+                    is_drawable = false;
+                }
+            },
+            Command::BindDescriptorSet(_) => {
+                if let (Some(Some(set)), Some(layout)) = (descriptor_sets.next(), layout) {
+                    encoder.bind_graphics_descriptor_sets(&layout.raw, 0, Some(set), &[] as &[u32]);
+                }
+            }
+            Command::BindVertexBuffers(ref bindings) => {
+                let buffers = bindings
+                    .iter()
+                    .map(|binding| Some((resources.buffer(binding.source)?, binding.offset as u64)))
+                    .collect::<Option<Vec<_>>>();
+                match buffers {
+                    Some(ref buffers) if buffers.is_empty() => {}
+                    Some(buffers) => encoder.bind_vertex_buffers(0, buffers),
+                    None => {
+                        warn!("vertex buffers of {:?} have no hal buffer", bindings);
+                        is_drawable = false;
+                    }
+                }
+            }
+            Command::BindIndexBuffer(index_data) => match resources.buffer(index_data.source) {
+                Some(buffer) => encoder.bind_index_buffer(buffer::IndexBufferView {
+                    buffer,
+                    offset: index_data.offset as u64,
+                    index_type: if index_data.type_ == GL_UNSIGNED_SHORT {
+                        IndexType::U16
+                    } else {
+                        IndexType::U32
+                    },
+                }),
+                None => {
+                    warn!("index buffer of {:?} has no hal buffer", index_data);
+                    is_drawable = false;
+                }
+            },
+            Command::SetDepthBias(depth_bias) => encoder.set_depth_bias(depth_bias),
+            Command::SetStencilReference { front, back } => {
+                encoder.set_stencil_reference(Face::FRONT, front);
+                encoder.set_stencil_reference(Face::BACK, back);
+            }
+            Command::SetBlendConstants(color) => encoder.set_blend_constants(color),
+            Command::SetViewport(ref viewport) => encoder.set_viewports(0, Some(viewport.clone())),
+            Command::SetScissor(rect) => encoder.set_scissors(0, Some(rect)),
+            Command::Draw { vertex_count } if is_drawable => encoder.draw(0..vertex_count, 0..1),
+            Command::DrawIndexed {
+                index_count,
+                vertex_offset,
+            } if is_drawable => encoder.draw_indexed(0..index_count, vertex_offset, 0..1),
+            Command::Draw { .. } | Command::DrawIndexed { .. } => debug!("skip {:?}", command),
+            Command::BeginRenderPass { .. } => warn!("{:?} is inside of a render pass", command),
+        }
+    }
 }
 
-pub fn hal_image_create() -> bool {
-    true
+/// Clear values of the attachments of a render pass of `key`, color first
+fn render_pass_clear_values(key: &RenderPassKey, clear_values: &ClearValues) -> Vec<ClearValue> {
+    let mut values = Vec::new();
+    if key.color.is_some() {
+        values.push(ClearValue::Color(ClearColor::Float(clear_values.color)));
+    }
+    if key.depth_stencil.is_some() {
+        values.push(ClearValue::DepthStencil(ClearDepthStencil(
+            clear_values.depth,
+            clear_values.stencil as u32,
+        )));
+    }
+    values
+}
+
+/// Clears of the attachments of a render pass of `key` selected by `mask`. Attachments can only be cleared
+/// entirely, a color clear with some components masked is dropped.
+fn attachment_clears(
+    key: &RenderPassKey,
+    mask: GLbitfield,
+    clear_values: &ClearValues,
+    color_mask: [bool; 4],
+) -> Vec<AttachmentClear> {
+    let mut clears = Vec::new();
+    if mask & GL_COLOR_BUFFER_BIT != 0 && key.color.is_some() {
+        if color_mask.iter().all(|&is_written| is_written) {
+            clears.push(AttachmentClear::Color {
+                index: 0,
+                value: ClearColor::Float(clear_values.color),
+            });
+        } else {
+            warn!("clearing color with mask {:?} is not supported", color_mask);
+        }
+    }
+    if let Some(format) = key.depth_stencil {
+        let aspects = format.surface_desc().aspects;
+        let depth = if mask & GL_DEPTH_BUFFER_BIT != 0 && aspects.contains(Aspects::DEPTH) {
+            Some(clear_values.depth)
+        } else {
+            None
+        };
+        let stencil = if mask & GL_STENCIL_BUFFER_BIT != 0 && aspects.contains(Aspects::STENCIL) {
+            Some(clear_values.stencil as u32)
+        } else {
+            None
+        };
+        if depth.is_some() || stencil.is_some() {
+            clears.push(AttachmentClear::DepthStencil { depth, stencil });
+        }
+    }
+    clears
+}
+
+/// Destroy `object`, nothing may use it anymore
+pub(crate) fn hal_object_destroy(object: HalObject) {
+    trace!("hal_object_destroy(object = {:?})", object);
+
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to destroy {:?} with", object);
+            return;
+        }
+    };
+    unsafe {
+        match object {
+            HalObject::Buffer(buffer) => device.destroy_buffer(buffer),
+            HalObject::Image(image) => device.destroy_image(image),
+            HalObject::ImageView(image_view) => device.destroy_image_view(image_view),
+            HalObject::Sampler(sampler) => device.destroy_sampler(sampler),
+            HalObject::Memory(memory) => device.free_memory(memory),
+            HalObject::ShaderModule(module) => device.destroy_shader_module(module),
+            HalObject::GraphicsPipeline(pipeline) => device.destroy_graphics_pipeline(pipeline),
+            HalObject::PipelineLayout(layout) => device.destroy_pipeline_layout(layout),
+            HalObject::DescriptorSetLayout(layout) => device.destroy_descriptor_set_layout(layout),
+            HalObject::RenderPass(render_pass) => device.destroy_render_pass(render_pass),
+            HalObject::FrameBuffer(frame_buffer) => device.destroy_framebuffer(frame_buffer),
+            HalObject::DescriptorPool(descriptor_pool) => device.destroy_descriptor_pool(descriptor_pool),
+        }
+    }
+}
+
+/// Create a buffer of `size` bytes bound to host visible and coherent memory
+pub(crate) fn hal_buffer_create(size: u64, usage: buffer::Usage) -> Option<(HalBuffer, HalMemory)> {
+    trace!("hal_buffer_create(size = {}, usage = {:?})", size, usage);
+
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a buffer with");
+            return None;
+        }
+    };
+
+    unsafe {
+        let mut buffer = match device.create_buffer(size, usage) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                error!("failed to create a buffer: {:?}", err);
+                return None;
+            }
+        };
+
+        let requirements = device.get_buffer_requirements(&buffer);
+        let properties = memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT;
+        let memory = match hal_memory_type(requirements.type_mask, properties) {
+            Some(memory_type) => device.allocate_memory(memory_type, requirements.size),
+            None => {
+                error!("no host visible memory type in {:#x}", requirements.type_mask);
+                device.destroy_buffer(buffer);
+                return None;
+            }
+        };
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                error!("failed to allocate {} bytes for a buffer: {:?}", requirements.size, err);
+                device.destroy_buffer(buffer);
+                return None;
+            }
+        };
+        if let Err(err) = device.bind_buffer_memory(&memory, 0, &mut buffer) {
+            error!("failed to bind the memory of a buffer: {:?}", err);
+            device.destroy_buffer(buffer);
+            device.free_memory(memory);
+            return None;
+        }
+        Some((buffer, memory))
+    }
 }
 
-pub fn hal_image_view_create() -> bool {
+/// Copy `bytes` to `offset` in host visible and coherent `memory`
+pub(crate) fn hal_memory_write(memory: &HalMemory, offset: u64, bytes: &[u8]) -> bool {
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to map memory with");
+            return false;
+        }
+    };
+
+    unsafe {
+        let pointer = match device.map_memory(memory, offset..offset + bytes.len() as u64) {
+            Ok(pointer) => pointer,
+            Err(err) => {
+                error!("failed to map memory: {:?}", err);
+                return false;
+            }
+        };
+        ptr::copy_nonoverlapping(bytes.as_ptr(), pointer, bytes.len());
+        device.unmap_memory(memory);
+    }
     true
 }
 
-pub fn hal_memory_create() -> bool {
+/// Memory type allowed by `type_mask` which has `properties`
+fn hal_memory_type(type_mask: u64, properties: memory::Properties) -> Option<MemoryTypeId> {
+    let adapter = ADAPTER.lock();
+    let memory_properties = adapter.as_ref()?.physical_device.memory_properties();
+    memory_properties
+        .memory_types
+        .iter()
+        .enumerate()
+        .position(|(id, memory_type)| type_mask & (1 << id) != 0 && memory_type.properties.contains(properties))
+        .map(MemoryTypeId)
+}
+
+/// Create the image of `texture` in device local memory with a view of all its levels and layers. The previous
+/// hal objects of `texture` have to be released first. Return false if any of them could not be created.
+pub(crate) fn hal_texture_create(texture: &mut Texture, usage: image::Usage) -> bool {
+    trace!(
+        "hal_texture_create(format = {:?}, dimensions = {:?}, usage = {:?})",
+        texture.hal_format,
+        texture.dimensions,
+        usage
+    );
+
+    let format = match texture.hal_format {
+        Some(format) => format,
+        None => {
+            error!("no format to create an image of");
+            return false;
+        }
+    };
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create an image with");
+            return false;
+        }
+    };
+
+    let levels = texture.mip_level_count as image::Level;
+    let layers = texture.layers_count as image::Layer;
+    let is_cube_map = texture.layers_count == Texture::TEXTURE_CUBE_MAP_LAYERS;
+    let kind = image::Kind::D2(
+        texture.dimensions.width as image::Size,
+        texture.dimensions.height as image::Size,
+        layers,
+        1,
+    );
+    let (view_kind, view_caps) = if is_cube_map {
+        (image::ViewKind::Cube, image::ViewCapabilities::KIND_CUBE)
+    } else {
+        (image::ViewKind::D2, image::ViewCapabilities::empty())
+    };
+    unsafe {
+        let mut image = match device.create_image(kind, levels, format, image::Tiling::Optimal, usage, view_caps) {
+            Ok(image) => image,
+            Err(err) => {
+                error!("failed to create an image: {:?}", err);
+                return false;
+            }
+        };
+
+        let requirements = device.get_image_requirements(&image);
+        let memory = match hal_memory_type(requirements.type_mask, memory::Properties::DEVICE_LOCAL) {
+            Some(memory_type) => device.allocate_memory(memory_type, requirements.size),
+            None => {
+                error!("no device local memory type in {:#x}", requirements.type_mask);
+                device.destroy_image(image);
+                return false;
+            }
+        };
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                error!("failed to allocate {} bytes for an image: {:?}", requirements.size, err);
+                device.destroy_image(image);
+                return false;
+            }
+        };
+        if let Err(err) = device.bind_image_memory(&memory, 0, &mut image) {
+            error!("failed to bind the memory of an image: {:?}", err);
+            device.destroy_image(image);
+            device.free_memory(memory);
+            return false;
+        }
+
+        let range = image::SubresourceRange {
+            aspects: format.surface_desc().aspects,
+            levels: 0..levels,
+            layers: 0..layers,
+        };
+        let image_view = match device.create_image_view(&image, view_kind, format, Swizzle::NO, range) {
+            Ok(image_view) => image_view,
+            Err(err) => {
+                error!("failed to create an image view: {:?}", err);
+                device.destroy_image(image);
+                device.free_memory(memory);
+                return false;
+            }
+        };
+
+        texture.image = Some(image);
+        texture.memory = Some(memory);
+        texture.image_view = Some(image_view);
+    }
     true
-}
\ No newline at end of file
+}
+
+/// Create a render pass drawing into attachments of the formats of `key`, loading them with `load_ops` and
+/// storing them. Render passes of the same formats are compatible whatever their operations.
+pub(crate) fn hal_render_pass_create(key: &RenderPassKey, load_ops: LoadOps) -> Option<HalRenderPass> {
+    trace!("hal_render_pass_create(key = {:?}, load_ops = {:?})", key, load_ops);
+
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a render pass with");
+            return None;
+        }
+    };
+
+    // attachments stay in the general layout between render passes, the contents of those not loaded are dropped
+    let initial_layout = |is_loaded| {
+        if is_loaded {
+            image::Layout::General
+        } else {
+            image::Layout::Undefined
+        }
+    };
+    let mut attachments = Vec::new();
+    let mut colors = Vec::new();
+    if let Some(format) = key.color {
+        colors.push((attachments.len(), image::Layout::ColorAttachmentOptimal));
+        attachments.push(pass::Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: pass::AttachmentOps::new(load_ops.color, AttachmentStoreOp::Store),
+            stencil_ops: pass::AttachmentOps::DONT_CARE,
+            layouts: initial_layout(load_ops.color == AttachmentLoadOp::Load)..image::Layout::General,
+        });
+    }
+    let depth_stencil = key.depth_stencil.map(|format| {
+        let aspects = format.surface_desc().aspects;
+        let is_loaded = (aspects.contains(Aspects::DEPTH) && load_ops.depth == AttachmentLoadOp::Load)
+            || (aspects.contains(Aspects::STENCIL) && load_ops.stencil == AttachmentLoadOp::Load);
+        attachments.push(pass::Attachment {
+            format: Some(format),
+            samples: 1,
+            ops: pass::AttachmentOps::new(load_ops.depth, AttachmentStoreOp::Store),
+            stencil_ops: pass::AttachmentOps::new(load_ops.stencil, AttachmentStoreOp::Store),
+            layouts: initial_layout(is_loaded)..image::Layout::General,
+        });
+        (attachments.len() - 1, image::Layout::DepthStencilAttachmentOptimal)
+    });
+    let subpass = pass::SubpassDesc {
+        colors: &colors,
+        depth_stencil: depth_stencil.as_ref(),
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    match unsafe { device.create_render_pass(&attachments, &[subpass], &[] as &[pass::SubpassDependency]) } {
+        Ok(render_pass) => Some(render_pass),
+        Err(err) => {
+            error!("failed to create a render pass: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Create a shader module from the SPIR-V words of a shader
+pub(crate) fn hal_shader_module_create(spirv: &[u32]) -> Option<HalShaderModule> {
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a shader module with");
+            return None;
+        }
+    };
+
+    let bytes = unsafe { slice::from_raw_parts(spirv.as_ptr() as *const u8, spirv.len() * 4) };
+    match unsafe { device.create_shader_module(bytes) } {
+        Ok(module) => Some(module),
+        Err(err) => {
+            error!("failed to create a shader module: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Create a pipeline layout with a single descriptor set of `bindings`
+pub(crate) fn hal_pipeline_layout_create(
+    bindings: &[pso::DescriptorSetLayoutBinding],
+) -> Option<(HalDescriptorSetLayout, HalPipelineLayout)> {
+    trace!("hal_pipeline_layout_create(bindings = {:?})", bindings);
+
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a pipeline layout with");
+            return None;
+        }
+    };
+
+    unsafe {
+        let descriptor_set_layout = match device.create_descriptor_set_layout(bindings, &[] as &[HalSampler]) {
+            Ok(layout) => layout,
+            Err(err) => {
+                error!("failed to create a descriptor set layout: {:?}", err);
+                return None;
+            }
+        };
+        let push_constants: &[(pso::ShaderStageFlags, Range<u32>)] = &[];
+        match device.create_pipeline_layout(Some(&descriptor_set_layout), push_constants) {
+            Ok(layout) => Some((descriptor_set_layout, layout)),
+            Err(err) => {
+                error!("failed to create a pipeline layout: {:?}", err);
+                device.destroy_descriptor_set_layout(descriptor_set_layout);
+                None
+            }
+        }
+    }
+}
+
+/// Create the graphics pipeline described by `desc`
+pub(crate) fn hal_graphics_pipeline_create(desc: &pso::GraphicsPipelineDesc<B>) -> Option<HalGraphicsPipeline> {
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a graphics pipeline with");
+            return None;
+        }
+    };
+
+    match unsafe { device.create_graphics_pipeline(desc, None) } {
+        Ok(pipeline) => Some(pipeline),
+        Err(err) => {
+            error!("failed to create a graphics pipeline: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Create a frame buffer of `views` for `render_pass`
+pub(crate) fn hal_frame_buffer_create(
+    render_pass: &HalRenderPass,
+    views: &[&HalImageView],
+    extent: image::Extent,
+) -> Option<HalFrameBuffer> {
+    trace!("hal_frame_buffer_create({} views, extent = {:?})", views.len(), extent);
+
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a frame buffer with");
+            return None;
+        }
+    };
+
+    match unsafe { device.create_framebuffer(render_pass, views.iter().cloned(), extent) } {
+        Ok(frame_buffer) => Some(frame_buffer),
+        Err(err) => {
+            error!("failed to create a frame buffer: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Create a sampler with the initial sampling parameters of textures, linear filtering and repeat wrapping
+pub(crate) fn hal_sampler_create() -> Option<HalSampler> {
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a sampler with");
+            return None;
+        }
+    };
+
+    let info = image::SamplerInfo::new(image::Filter::Linear, image::WrapMode::Tile);
+    match unsafe { device.create_sampler(info) } {
+        Ok(sampler) => Some(sampler),
+        Err(err) => {
+            error!("failed to create a sampler: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Create a pool of `max_sets` descriptor sets holding the descriptors of `ranges` between them
+pub(crate) fn hal_descriptor_pool_create(max_sets: usize, ranges: &[pso::DescriptorRangeDesc]) -> Option<HalDescriptorPool> {
+    trace!("hal_descriptor_pool_create(max_sets = {}, ranges = {:?})", max_sets, ranges);
+
+    let device = DEVICE.lock();
+    let device = match *device {
+        Some(ref device) => &device.device,
+        None => {
+            error!("no device to create a descriptor pool with");
+            return None;
+        }
+    };
+
+    match unsafe { device.create_descriptor_pool(max_sets, ranges) } {
+        Ok(pool) => Some(pool),
+        Err(err) => {
+            error!("failed to create a descriptor pool: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Allocate a descriptor set of `layout` from `pool`, it is freed with the pool
+pub(crate) fn hal_descriptor_set_allocate(
+    pool: &mut HalDescriptorPool,
+    layout: &HalDescriptorSetLayout,
+) -> Option<HalDescriptorSet> {
+    match unsafe { pool.allocate_set(layout) } {
+        Ok(set) => Some(set),
+        Err(err) => {
+            error!("failed to allocate a descriptor set: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Write the descriptors of `writes` into their descriptor sets
+pub(crate) fn hal_descriptor_sets_write<'a>(writes: Vec<pso::DescriptorSetWrite<'a, B, Vec<pso::Descriptor<'a, B>>>>) {
+    trace!("hal_descriptor_sets_write({} writes)", writes.len());
+
+    match *DEVICE.lock() {
+        Some(ref device) => unsafe { device.device.write_descriptor_sets(writes) },
+        None => error!("no device to write {} descriptor sets with", writes.len()),
+    }
+}
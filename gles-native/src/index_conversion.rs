@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use gl_sys::{
+    GLenum, GLuint, GL_LINE_LOOP, GL_LINE_STRIP, GL_NONE, GL_TRIANGLES, GL_TRIANGLE_FAN, GL_UNSIGNED_BYTE, GL_UNSIGNED_INT,
+    GL_UNSIGNED_SHORT,
+};
+
+use upload;
+
+/// Indices converted from `count` indices of `type_` at `offset` of element array `buffer`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ConversionKey {
+    pub buffer: GLuint,
+    pub offset: usize,
+    pub count: usize,
+    pub type_: GLenum,
+    pub mode: GLenum,
+}
+
+#[derive(Debug)]
+pub(crate) struct ConvertedIndices {
+    pub mode: GLenum,
+    pub type_: GLenum,
+    pub count: u32,
+    /// Vertices referenced by the indices
+    pub range: Range<u32>,
+    pub data: Vec<u8>,
+}
+
+/// Return true if drawing `mode` with indices of `type_` needs converted indices, `type_` is `GL_NONE` for
+/// non-indexed draws
+#[inline]
+pub(crate) fn needs_conversion(mode: GLenum, type_: GLenum) -> bool {
+    mode == GL_TRIANGLE_FAN || mode == GL_LINE_LOOP || type_ == GL_UNSIGNED_BYTE
+}
+
+/// Turn fans into triangle lists and loops into strips closed by the first index, other modes are kept
+fn convert_primitives(mode: GLenum, indices: &[u32]) -> (GLenum, Vec<u32>) {
+    match mode {
+        GL_TRIANGLE_FAN => {
+            let mut triangles = Vec::with_capacity(indices.len().saturating_sub(2) * 3);
+            for pair in indices.get(1..).unwrap_or(&[]).windows(2) {
+                triangles.extend_from_slice(&[indices[0], pair[0], pair[1]]);
+            }
+            (GL_TRIANGLES, triangles)
+        }
+        GL_LINE_LOOP => {
+            let mut strip = indices.to_vec();
+            if indices.len() > 1 {
+                strip.push(indices[0]);
+            }
+            (GL_LINE_STRIP, strip)
+        }
+        _ => (mode, indices.to_vec()),
+    }
+}
+
+/// Convert `count` indices of `type_` in `bytes`, or generate them if `type_` is `GL_NONE`, to 16 bit indices
+/// drawing `mode` as a primitive every backend supports. Indices past 16 bits are stored in 32 bits.
+pub(crate) fn convert(mode: GLenum, type_: GLenum, count: usize, bytes: &[u8]) -> ConvertedIndices {
+    let indices = match type_ {
+        GL_NONE => (0..count as u32).collect::<Vec<_>>(),
+        GL_UNSIGNED_BYTE => bytes.iter().map(|&index| index as u32).collect(),
+        _ => bytes
+            .chunks(2)
+            .map(|index| index[0] as u32 | (index[1] as u32) << 8)
+            .collect(),
+    };
+    let range = match type_ {
+        GL_NONE => 0..count as u32,
+        _ => upload::index_range(type_, bytes),
+    };
+
+    let (mode, indices) = convert_primitives(mode, &indices);
+    let (type_, data) = if range.end > 0x10000 {
+        let mut data = Vec::with_capacity(indices.len() * 4);
+        for &index in &indices {
+            data.extend_from_slice(&[index as u8, (index >> 8) as u8, (index >> 16) as u8, (index >> 24) as u8]);
+        }
+        (GL_UNSIGNED_INT, data)
+    } else {
+        let mut data = Vec::with_capacity(indices.len() * 2);
+        for &index in &indices {
+            data.extend_from_slice(&[index as u8, (index >> 8) as u8]);
+        }
+        (GL_UNSIGNED_SHORT, data)
+    };
+
+    ConvertedIndices {
+        mode,
+        type_,
+        count: indices.len() as u32,
+        range,
+        data,
+    }
+}
+
+/// Number of conversions the index cache keeps, it starts over once full
+const MAX_CACHED_CONVERSIONS: usize = 256;
+
+/// Converted indices of element array buffers, kept until the source buffer changes
+#[derive(Debug, Default)]
+pub(crate) struct IndexCache {
+    entries: HashMap<ConversionKey, ConvertedIndices>,
+}
+
+impl IndexCache {
+    /// Indices converted for `key`, converting `bytes` on a miss
+    pub fn get_or_convert(&mut self, key: ConversionKey, bytes: &[u8]) -> &ConvertedIndices {
+        if self.entries.len() >= MAX_CACHED_CONVERSIONS && !self.entries.contains_key(&key) {
+            debug!("index cache is full, drop {} conversions", self.entries.len());
+            self.entries.clear();
+        }
+        self.entries.entry(key).or_insert_with(|| {
+            debug!("convert indices for {:?}", key);
+            convert(key.mode, key.type_, key.count, bytes)
+        })
+    }
+
+    #[inline]
+    pub fn get(&self, key: &ConversionKey) -> Option<&ConvertedIndices> {
+        self.entries.get(key)
+    }
+
+    /// Drop the indices converted from `buffer` after its contents changed
+    pub fn invalidate_buffer(&mut self, buffer: GLuint) {
+        self.entries.retain(|key, _| key.buffer != buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::GL_POINTS;
+
+    fn indices(converted: &ConvertedIndices) -> Vec<u16> {
+        converted
+            .data
+            .chunks(2)
+            .map(|index| index[0] as u16 | (index[1] as u16) << 8)
+            .collect()
+    }
+
+    #[test]
+    fn test_convert() {
+        let fan = convert(GL_TRIANGLE_FAN, GL_UNSIGNED_BYTE, 5, &[4, 5, 6, 7, 8]);
+        assert_eq!((fan.mode, fan.type_, fan.count), (GL_TRIANGLES, GL_UNSIGNED_SHORT, 9));
+        assert_eq!(indices(&fan), vec![4, 5, 6, 4, 6, 7, 4, 7, 8]);
+        assert_eq!(fan.range, 4..9);
+
+        let fan = convert(GL_TRIANGLE_FAN, GL_NONE, 2, &[]);
+        assert_eq!(fan.count, 0);
+
+        let loop_ = convert(GL_LINE_LOOP, GL_UNSIGNED_SHORT, 3, &[2, 1, 0, 1, 5, 0]);
+        assert_eq!((loop_.mode, loop_.count), (GL_LINE_STRIP, 4));
+        assert_eq!(indices(&loop_), vec![258, 256, 5, 258]);
+
+        let points = convert(GL_POINTS, GL_NONE, 3, &[]);
+        assert_eq!((points.mode, indices(&points)), (GL_POINTS, vec![0, 1, 2]));
+
+        let large = convert(GL_LINE_LOOP, GL_NONE, 0x10001, &[]);
+        assert_eq!((large.type_, large.data.len()), (GL_UNSIGNED_INT, 0x10002 * 4));
+        assert_eq!(&large.data[0x10000 * 4..], &[0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_index_cache() {
+        let mut cache = IndexCache::default();
+        let key = ConversionKey {
+            buffer: 1,
+            offset: 4,
+            count: 3,
+            type_: GL_UNSIGNED_BYTE,
+            mode: GL_TRIANGLE_FAN,
+        };
+        assert_eq!(cache.get_or_convert(key, &[0, 1, 2]).count, 3);
+        // cached entries are not converted again
+        assert_eq!(cache.get_or_convert(key, &[]).count, 3);
+        cache.invalidate_buffer(2);
+        assert!(cache.get(&key).is_some());
+        cache.invalidate_buffer(1);
+        assert!(cache.get(&key).is_none());
+
+        // the cache is bounded
+        for offset in 0..MAX_CACHED_CONVERSIONS + 1 {
+            cache.get_or_convert(ConversionKey { offset, ..key }, &[0, 1, 2]);
+        }
+        assert_eq!(cache.entries.len(), 1);
+    }
+}
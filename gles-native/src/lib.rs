@@ -25,15 +25,22 @@ pub(crate) type HalSampler = <B as hal::Backend>::Sampler;
 pub(crate) type HalBuffer = <back::Backend as hal::Backend>::Buffer;
 pub(crate) type HalFrameBuffer = <back::Backend as hal::Backend>::Framebuffer;
 pub(crate) type HalFence = <back::Backend as hal::Backend>::Fence;
+pub(crate) type HalCommandBuffer = hal::command::CommandBuffer<B, hal::Graphics, hal::command::OneShot>;
 pub(crate) type HalRenderPass = <back::Backend as hal::Backend>::RenderPass;
 pub(crate) type HalPipelineLayout = <back::Backend as hal::Backend>::PipelineLayout;
 pub(crate) type HalPipelineCache = <back::Backend as hal::Backend>::PipelineCache;
+pub(crate) type HalGraphicsPipeline = <back::Backend as hal::Backend>::GraphicsPipeline;
+pub(crate) type HalShaderModule = <back::Backend as hal::Backend>::ShaderModule;
+pub(crate) type HalDescriptorSetLayout = <back::Backend as hal::Backend>::DescriptorSetLayout;
+pub(crate) type HalDescriptorPool = <back::Backend as hal::Backend>::DescriptorPool;
+pub(crate) type HalDescriptorSet = <back::Backend as hal::Backend>::DescriptorSet;
 
 /// OpenGL (ES) defined types, constants
 mod gl_sys;
 
 /// OpenGL (ES) defined functions implementation split into multiple modules
 mod buffer;
+mod depth_stencil_state;
 mod fragment_state;
 mod frame_buffer;
 mod pixel_operations;
@@ -43,29 +50,46 @@ mod render_buffer;
 mod rendering;
 mod shader;
 mod texture;
+mod uniform;
 mod utilities;
+mod vertex_attribute;
 mod viewport_transformation;
 
 /// Export OpenGL (ES) defined functions
 pub use buffer::*;
+pub use depth_stencil_state::*;
 pub use fragment_state::*;
 pub use frame_buffer::*;
 pub use pixel_operations::*;
-//pub use program::*;
-//pub use rasterization_state::*;
+pub use program::*;
+pub use rasterization_state::*;
 pub use render_buffer::*;
 pub use rendering::*;
 pub use shader::*;
 pub use texture::*;
+pub use uniform::*;
 /// NOTE: only OpenGL (ES) defined functions
 pub use utilities::*;
+pub use vertex_attribute::*;
 pub use viewport_transformation::*;
 
+/// Extensions of this implementation
+pub use pipeline::{glesGetPipelineStatistics, PipelineStatistics};
+
 /// Entry point to access states and resources
 mod context;
 
 /// Infrastructure
 mod active_object;
+mod command_buffer;
+mod destruction_queue;
+mod index_conversion;
+mod limits;
 mod object_pool;
+mod pipeline;
+mod upload;
+
+/// Shader compiler
+mod glsl;
 
 mod hal_registry;
@@ -0,0 +1,160 @@
+use std::cmp;
+
+use hal;
+
+use gl_sys::{
+    GLenum, GLfloat, GLint, GL_ALIASED_LINE_WIDTH_RANGE, GL_ALIASED_POINT_SIZE_RANGE, GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS,
+    GL_MAX_CUBE_MAP_TEXTURE_SIZE, GL_MAX_FRAGMENT_UNIFORM_VECTORS, GL_MAX_RENDERBUFFER_SIZE, GL_MAX_TEXTURE_IMAGE_UNITS,
+    GL_MAX_TEXTURE_SIZE, GL_MAX_VARYING_VECTORS, GL_MAX_VERTEX_ATTRIBS, GL_MAX_VERTEX_TEXTURE_IMAGE_UNITS,
+    GL_MAX_VERTEX_UNIFORM_VECTORS, GL_MAX_VIEWPORT_DIMS,
+};
+
+use hal_registry;
+use texture::MAX_TEXTURE_UNITS;
+use utilities::StateValue;
+use vertex_attribute::MAX_VERTEX_ATTRIBS;
+
+lazy_static! {
+    pub(crate) static ref LIMITS: Limits = {
+        let (limits, features) = hal_registry::hal_limits();
+        Limits::new(&limits, features)
+    };
+}
+
+/// Implementation dependent values of OpenGL ES 2.0
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Limits {
+    pub max_texture_size: GLint,
+    pub max_cube_map_texture_size: GLint,
+    pub max_renderbuffer_size: GLint,
+    pub max_viewport_dims: [GLint; 2],
+    pub max_vertex_attribs: GLint,
+    pub max_vertex_uniform_vectors: GLint,
+    pub max_fragment_uniform_vectors: GLint,
+    pub max_varying_vectors: GLint,
+    pub max_vertex_texture_image_units: GLint,
+    pub max_texture_image_units: GLint,
+    pub max_combined_texture_image_units: GLint,
+    pub aliased_line_width_range: [GLfloat; 2],
+    pub aliased_point_size_range: [GLfloat; 2],
+}
+
+impl Limits {
+    /// Limits of an adapter, raised to the minimums OpenGL ES 2.0 requires and lowered to what the context stores
+    pub fn new(limits: &hal::Limits, features: hal::Features) -> Self {
+        let at_least = |value: u64, minimum: GLint| cmp::max(cmp::min(value, GLint::max_value() as u64) as GLint, minimum);
+
+        let max_renderbuffer_size = at_least(
+            cmp::min(
+                limits.max_image_2d_size,
+                cmp::min(limits.max_framebuffer_extent.width, limits.max_framebuffer_extent.height),
+            ) as u64,
+            1,
+        );
+        // viewports of hal are limited to `i16` rectangles
+        let max_viewport_dimension =
+            |dimension: u32| cmp::min(at_least(dimension as u64, max_renderbuffer_size), i16::max_value() as GLint);
+        let max_texture_image_units = cmp::min(
+            at_least(limits.max_per_stage_descriptor_samplers as u64, 8),
+            MAX_TEXTURE_UNITS as GLint,
+        );
+        let max_vertex_texture_image_units = cmp::min(
+            at_least(limits.max_per_stage_descriptor_samplers as u64, 0),
+            MAX_TEXTURE_UNITS as GLint,
+        );
+        // a uniform vector is 16 bytes of the uniform buffer
+        let max_uniform_vectors = limits.max_uniform_buffer_range / 16;
+
+        Limits {
+            max_texture_size: at_least(limits.max_image_2d_size as u64, 64),
+            max_cube_map_texture_size: at_least(limits.max_image_cube_size as u64, 16),
+            max_renderbuffer_size,
+            max_viewport_dims: [
+                max_viewport_dimension(limits.max_viewport_dimensions[0]),
+                max_viewport_dimension(limits.max_viewport_dimensions[1]),
+            ],
+            max_vertex_attribs: cmp::min(
+                at_least(limits.max_vertex_input_attributes as u64, 8),
+                MAX_VERTEX_ATTRIBS as GLint,
+            ),
+            max_vertex_uniform_vectors: at_least(max_uniform_vectors, 128),
+            max_fragment_uniform_vectors: at_least(max_uniform_vectors, 16),
+            max_varying_vectors: at_least(limits.max_vertex_output_components as u64 / 4, 8),
+            max_vertex_texture_image_units,
+            max_texture_image_units,
+            max_combined_texture_image_units: cmp::min(
+                max_vertex_texture_image_units + max_texture_image_units,
+                MAX_TEXTURE_UNITS as GLint,
+            ),
+            aliased_line_width_range: if features.contains(hal::Features::WIDE_LINES) {
+                [limits.line_width_range[0].min(1.0), limits.line_width_range[1].max(1.0)]
+            } else {
+                [1.0, 1.0]
+            },
+            aliased_point_size_range: [1.0, 1.0],
+        }
+    }
+}
+
+pub(crate) fn query_state(pname: GLenum) -> Option<StateValue> {
+    let limits = &*LIMITS;
+    let integer = |value: GLint| Some(StateValue::Integers(vec![value]));
+    match pname {
+        GL_MAX_TEXTURE_SIZE => integer(limits.max_texture_size),
+        GL_MAX_CUBE_MAP_TEXTURE_SIZE => integer(limits.max_cube_map_texture_size),
+        GL_MAX_RENDERBUFFER_SIZE => integer(limits.max_renderbuffer_size),
+        GL_MAX_VIEWPORT_DIMS => Some(StateValue::Integers(limits.max_viewport_dims.to_vec())),
+        GL_MAX_VERTEX_ATTRIBS => integer(limits.max_vertex_attribs),
+        GL_MAX_VERTEX_UNIFORM_VECTORS => integer(limits.max_vertex_uniform_vectors),
+        GL_MAX_FRAGMENT_UNIFORM_VECTORS => integer(limits.max_fragment_uniform_vectors),
+        GL_MAX_VARYING_VECTORS => integer(limits.max_varying_vectors),
+        GL_MAX_VERTEX_TEXTURE_IMAGE_UNITS => integer(limits.max_vertex_texture_image_units),
+        GL_MAX_TEXTURE_IMAGE_UNITS => integer(limits.max_texture_image_units),
+        GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS => integer(limits.max_combined_texture_image_units),
+        GL_ALIASED_LINE_WIDTH_RANGE => Some(StateValue::Floats(limits.aliased_line_width_range.to_vec())),
+        GL_ALIASED_POINT_SIZE_RANGE => Some(StateValue::Floats(limits.aliased_point_size_range.to_vec())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        // a small adapter is raised to the minimums of OpenGL ES 2.0
+        let minimums = Limits::new(&hal::Limits::default(), hal::Features::empty());
+        assert_eq!(minimums.max_texture_size, 64);
+        assert_eq!(minimums.max_renderbuffer_size, 1);
+        assert_eq!(minimums.max_viewport_dims, [1, 1]);
+        assert_eq!(minimums.max_vertex_attribs, 8);
+        assert_eq!(
+            (minimums.max_vertex_uniform_vectors, minimums.max_fragment_uniform_vectors),
+            (128, 16)
+        );
+        assert_eq!(minimums.max_combined_texture_image_units, 8);
+        assert_eq!(minimums.aliased_line_width_range, [1.0, 1.0]);
+
+        // a large adapter is lowered to what the context can store
+        let limits = hal::Limits {
+            max_image_2d_size: 65536,
+            max_viewport_dimensions: [65536, 65536],
+            max_framebuffer_extent: hal::image::Extent {
+                width: 16384,
+                height: 16384,
+                depth: 1,
+            },
+            max_vertex_input_attributes: 32,
+            max_per_stage_descriptor_samplers: 1 << 20,
+            line_width_range: [0.5, 64.0],
+            ..hal::Limits::default()
+        };
+        let limits = Limits::new(&limits, hal::Features::WIDE_LINES);
+        assert_eq!(limits.max_renderbuffer_size, 16384);
+        assert_eq!(limits.max_viewport_dims, [i16::max_value() as GLint; 2]);
+        assert_eq!(limits.max_vertex_attribs, MAX_VERTEX_ATTRIBS as GLint);
+        assert_eq!(limits.max_combined_texture_image_units, MAX_TEXTURE_UNITS as GLint);
+        assert_eq!(limits.aliased_line_width_range, [0.5, 64.0]);
+    }
+}
@@ -33,6 +33,12 @@ impl<T: Default> ObjectPool<T> {
         }
     }
 
+    /// Object `index` if it exists, unlike `get_object_mut` it never creates one
+    #[inline]
+    pub fn get_object(&self, index: GLuint) -> Option<&T> {
+        self.map.get(&index).map(|object| &**object)
+    }
+
     #[inline]
     pub fn has_object(&self, index: GLuint) -> bool {
         self.map.contains_key(&index)
@@ -0,0 +1,561 @@
+#![allow(non_snake_case)]
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use hal::format::Format;
+use hal::{pass, pso, Primitive};
+
+use gl_sys::{
+    GLboolean, GLenum, GLint, GLuint, GL_BYTE, GL_FALSE, GL_FLOAT, GL_LINES, GL_LINE_STRIP, GL_POINTS, GL_SHORT,
+    GL_TRIANGLE_STRIP, GL_UNSIGNED_BYTE, GL_UNSIGNED_SHORT,
+};
+
+use context::HUB;
+use depth_stencil_state::DepthStencilState;
+use destruction_queue::{self, HalObject};
+use fragment_state::FragmentState;
+use frame_buffer::LoadOps;
+use glsl::layout::UNIFORM_BLOCK_BINDING;
+use hal_registry;
+use program::LinkedProgram;
+use rasterization_state::RasterizationState;
+use upload::VertexInput;
+use vertex_attribute::VertexAttribute;
+use {HalDescriptorSetLayout, HalGraphicsPipeline, HalPipelineLayout, HalRenderPass, HalShaderModule, B};
+
+/// Format of a vertex attribute read by a pipeline, constant attributes are read as 4 floats with a stride of 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct VertexAttributeDesc {
+    pub location: GLuint,
+    pub size: GLint,
+    pub type_: GLenum,
+    pub normalized: GLboolean,
+    pub stride: usize,
+}
+
+/// Attachment formats deciding render pass compatibility
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct RenderPassKey {
+    pub color: Option<Format>,
+    pub depth_stencil: Option<Format>,
+}
+
+/// Pieces of fixed function state baked into pipelines, marked dirty when they change
+pub(crate) const DIRTY_RASTERIZATION: u32 = 0x1;
+pub(crate) const DIRTY_DEPTH_STENCIL: u32 = 0x2;
+pub(crate) const DIRTY_FRAGMENT: u32 = 0x4;
+
+/// Copy of the fixed function state taken by the last draw, only dirty pieces are copied again
+#[derive(Debug, Default)]
+pub(crate) struct FixedFunctionState {
+    dirty: u32,
+    rasterization: RasterizationState,
+    depth_stencil: DepthStencilState,
+    fragment: FragmentState,
+}
+
+impl FixedFunctionState {
+    fn refresh(&mut self) {
+        if self.dirty & DIRTY_RASTERIZATION != 0 {
+            self.rasterization = HUB.rasterization_state.lock().clone();
+        }
+        if self.dirty & DIRTY_DEPTH_STENCIL != 0 {
+            self.depth_stencil = HUB.depth_stencil_state.lock().clone();
+        }
+        if self.dirty & DIRTY_FRAGMENT != 0 {
+            self.fragment = HUB.fragment_state.lock().clone();
+        }
+        self.dirty = 0;
+    }
+}
+
+/// Mark `pieces` of the fixed function state as changed, the state they were changed in must not be locked
+#[inline]
+pub(crate) fn mark_dirty(pieces: u32) {
+    HUB.fixed_function_state.lock().dirty |= pieces;
+}
+
+/// Every piece of GL state baked into a graphics pipeline
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
+    pub program: GLuint,
+    pub vertex_attributes: Vec<VertexAttributeDesc>,
+    pub primitive: GLenum,
+    pub rasterization: RasterizationState,
+    pub depth_stencil: DepthStencilState,
+    pub fragment: FragmentState,
+    pub render_pass: RenderPassKey,
+}
+
+impl PipelineKey {
+    /// Key of drawing `primitive` with the vertex bindings of `vertex_input` and the current fixed function state
+    pub fn new(
+        program: GLuint,
+        attributes: &[VertexAttribute],
+        vertex_input: &VertexInput,
+        primitive: GLenum,
+        render_pass: RenderPassKey,
+    ) -> Self {
+        let vertex_attributes = vertex_input
+            .bindings
+            .iter()
+            .map(|binding| {
+                let attribute = &attributes[binding.location as usize];
+                if binding.stride == 0 {
+                    VertexAttributeDesc {
+                        location: binding.location,
+                        size: 4,
+                        type_: GL_FLOAT,
+                        normalized: GL_FALSE,
+                        stride: 0,
+                    }
+                } else {
+                    VertexAttributeDesc {
+                        location: binding.location,
+                        size: attribute.size,
+                        type_: attribute.type_,
+                        normalized: attribute.normalized as GLboolean,
+                        stride: binding.stride,
+                    }
+                }
+            })
+            .collect();
+
+        let mut fixed_function_state = HUB.fixed_function_state.lock();
+        fixed_function_state.refresh();
+        Self {
+            program,
+            vertex_attributes,
+            primitive,
+            rasterization: fixed_function_state.rasterization.for_primitive(primitive),
+            depth_stencil: fixed_function_state.depth_stencil.clone(),
+            fragment: fixed_function_state.fragment.clone(),
+            render_pass,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Pipeline {
+    pub key: PipelineKey,
+    pub raw: HalGraphicsPipeline,
+    /// Last submission which bound the pipeline
+    pub last_use: u64,
+}
+
+/// Layout of the descriptor set of a program, shared by its pipelines
+#[derive(Debug)]
+pub(crate) struct ProgramLayout {
+    pub descriptor_set_layout: HalDescriptorSetLayout,
+    pub raw: HalPipelineLayout,
+    /// Size of the uniform block bound with the descriptor set
+    pub uniform_block_size: u64,
+    /// Last submission which bound a pipeline of the program
+    pub last_use: u64,
+}
+
+impl ProgramLayout {
+    fn new(program: &LinkedProgram) -> Option<Self> {
+        let stages = pso::ShaderStageFlags::VERTEX | pso::ShaderStageFlags::FRAGMENT;
+        let mut bindings = Vec::new();
+        if !program.uniforms.data.is_empty() {
+            bindings.push(pso::DescriptorSetLayoutBinding {
+                binding: UNIFORM_BLOCK_BINDING,
+                ty: pso::DescriptorType::UniformBuffer,
+                count: 1,
+                stage_flags: stages,
+                immutable_samplers: false,
+            });
+        }
+        for (&binding, units) in &program.uniforms.sampler_units {
+            bindings.push(pso::DescriptorSetLayoutBinding {
+                binding,
+                ty: pso::DescriptorType::CombinedImageSampler,
+                count: units.len(),
+                stage_flags: stages,
+                immutable_samplers: false,
+            });
+        }
+        bindings.sort_by_key(|binding| binding.binding);
+
+        let (descriptor_set_layout, raw) = hal_registry::hal_pipeline_layout_create(&bindings)?;
+        Some(Self {
+            descriptor_set_layout,
+            raw,
+            uniform_block_size: program.uniforms.data.len() as u64,
+            last_use: 0,
+        })
+    }
+}
+
+/// Pipeline creations and cache hits since the context was created
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PipelineStatistics {
+    pub creations: u64,
+    pub hits: u64,
+}
+
+impl PipelineStatistics {
+    /// Share of lookups served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.creations + self.hits;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PipelineCache {
+    pipelines: HashMap<usize, Pipeline>,
+    indices: HashMap<PipelineKey, usize>,
+    next_index: usize,
+    layouts: HashMap<GLuint, ProgramLayout>,
+    /// Render passes of each format and load operations, pipelines are created for those which load every attachment
+    render_passes: HashMap<(RenderPassKey, LoadOps), HalRenderPass>,
+    pub statistics: PipelineStatistics,
+}
+
+impl PipelineCache {
+    /// Index of the pipeline built from `key` and the shaders of `program`, building it on a miss. None if it could
+    /// not be built.
+    pub fn get_or_create(&mut self, key: PipelineKey, program: &LinkedProgram) -> Option<usize> {
+        let submission = destruction_queue::recording_submission();
+        if let Some(&index) = self.indices.get(&key) {
+            self.statistics.hits += 1;
+            self.pipelines.get_mut(&index).unwrap().last_use = submission;
+            self.layouts.get_mut(&key.program).unwrap().last_use = submission;
+            return Some(index);
+        }
+
+        let layout = match self.layouts.entry(key.program) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(ProgramLayout::new(program)?),
+        };
+        let render_pass = get_or_create_render_pass(&mut self.render_passes, key.render_pass, LoadOps::default())?;
+        let raw = create_pipeline(&key, program, &layout.raw, render_pass)?;
+        layout.last_use = submission;
+
+        self.statistics.creations += 1;
+        let index = self.next_index;
+        self.next_index += 1;
+        info!(
+            "create pipeline {} for program {}, hit rate {:.3}",
+            index,
+            key.program,
+            self.statistics.hit_rate()
+        );
+        self.indices.insert(key.clone(), index);
+        self.pipelines.insert(
+            index,
+            Pipeline {
+                key,
+                raw,
+                last_use: submission,
+            },
+        );
+        Some(index)
+    }
+
+    /// Pipeline `index`, None once its program was relinked or deleted
+    #[inline]
+    pub fn pipeline(&self, index: usize) -> Option<&Pipeline> {
+        self.pipelines.get(&index)
+    }
+
+    #[inline]
+    pub fn layout(&self, program: GLuint) -> Option<&ProgramLayout> {
+        self.layouts.get(&program)
+    }
+
+    /// Render pass drawing into attachments of the formats of `key` with `load_ops`, creating it on a miss
+    pub fn render_pass(&mut self, key: RenderPassKey, load_ops: LoadOps) -> Option<&HalRenderPass> {
+        get_or_create_render_pass(&mut self.render_passes, key, load_ops)
+    }
+
+    /// Render pass of `key` with `load_ops` if it was already created
+    #[inline]
+    pub fn find_render_pass(&self, key: RenderPassKey, load_ops: LoadOps) -> Option<&HalRenderPass> {
+        self.render_passes.get(&(key, load_ops))
+    }
+
+    /// Forget the pipelines of `program` after it was relinked or deleted, their indices are not reused. Their hal
+    /// objects are destroyed once the submissions which bound them completed.
+    pub fn remove_program(&mut self, program: GLuint) {
+        self.indices.retain(|key, _| key.program != program);
+        let indices = self
+            .pipelines
+            .iter()
+            .filter(|&(_, pipeline)| pipeline.key.program == program)
+            .map(|(&index, _)| index)
+            .collect::<Vec<_>>();
+        for index in indices {
+            let pipeline = self.pipelines.remove(&index).unwrap();
+            destruction_queue::release(vec![HalObject::GraphicsPipeline(pipeline.raw)], pipeline.last_use);
+        }
+        if let Some(layout) = self.layouts.remove(&program) {
+            let objects = vec![
+                HalObject::PipelineLayout(layout.raw),
+                HalObject::DescriptorSetLayout(layout.descriptor_set_layout),
+            ];
+            destruction_queue::release(objects, layout.last_use);
+        }
+    }
+}
+
+fn get_or_create_render_pass(
+    render_passes: &mut HashMap<(RenderPassKey, LoadOps), HalRenderPass>,
+    key: RenderPassKey,
+    load_ops: LoadOps,
+) -> Option<&HalRenderPass> {
+    match render_passes.entry((key, load_ops)) {
+        Entry::Occupied(entry) => Some(entry.into_mut()),
+        Entry::Vacant(entry) => Some(entry.insert(hal_registry::hal_render_pass_create(&key, load_ops)?)),
+    }
+}
+
+/// Format the components of vertex attributes described by `desc` are read with, None for `GL_FIXED`
+fn vertex_format(desc: &VertexAttributeDesc) -> Option<Format> {
+    use hal::format::Format::*;
+
+    let formats = match (desc.type_, desc.normalized != GL_FALSE) {
+        (GL_FLOAT, _) => [R32Float, Rg32Float, Rgb32Float, Rgba32Float],
+        (GL_BYTE, true) => [R8Snorm, Rg8Snorm, Rgb8Snorm, Rgba8Snorm],
+        (GL_BYTE, false) => [R8Sscaled, Rg8Sscaled, Rgb8Sscaled, Rgba8Sscaled],
+        (GL_UNSIGNED_BYTE, true) => [R8Unorm, Rg8Unorm, Rgb8Unorm, Rgba8Unorm],
+        (GL_UNSIGNED_BYTE, false) => [R8Uscaled, Rg8Uscaled, Rgb8Uscaled, Rgba8Uscaled],
+        (GL_SHORT, true) => [R16Snorm, Rg16Snorm, Rgb16Snorm, Rgba16Snorm],
+        (GL_SHORT, false) => [R16Sscaled, Rg16Sscaled, Rgb16Sscaled, Rgba16Sscaled],
+        (GL_UNSIGNED_SHORT, true) => [R16Unorm, Rg16Unorm, Rgb16Unorm, Rgba16Unorm],
+        (GL_UNSIGNED_SHORT, false) => [R16Uscaled, Rg16Uscaled, Rgb16Uscaled, Rgba16Uscaled],
+        _ => return None,
+    };
+    formats.get(desc.size as usize - 1).cloned()
+}
+
+/// Primitive topology of `mode`, fans and loops are converted to lists and strips before drawing
+fn primitive(mode: GLenum) -> Primitive {
+    match mode {
+        GL_POINTS => Primitive::PointList,
+        GL_LINES => Primitive::LineList,
+        GL_LINE_STRIP => Primitive::LineStrip,
+        GL_TRIANGLE_STRIP => Primitive::TriangleStrip,
+        _ => Primitive::TriangleList,
+    }
+}
+
+#[inline]
+fn entry_point(module: &HalShaderModule) -> pso::EntryPoint<'_, B> {
+    pso::EntryPoint {
+        entry: "main",
+        module,
+        specialization: Default::default(),
+    }
+}
+
+/// Build the pipeline of `key` from the shaders of `program`, each vertex attribute is read from a buffer binding
+/// of its own
+fn create_pipeline(
+    key: &PipelineKey,
+    program: &LinkedProgram,
+    layout: &HalPipelineLayout,
+    render_pass: &HalRenderPass,
+) -> Option<HalGraphicsPipeline> {
+    let mut vertex_buffers = Vec::new();
+    let mut attributes = Vec::new();
+    for (binding, desc) in key.vertex_attributes.iter().enumerate() {
+        let format = match vertex_format(desc) {
+            Some(format) => format,
+            None => {
+                error!("no vertex format to read {:?} with", desc);
+                return None;
+            }
+        };
+        vertex_buffers.push(pso::VertexBufferDesc {
+            binding: binding as u32,
+            stride: desc.stride as u32,
+            rate: 0,
+        });
+        attributes.push(pso::AttributeDesc {
+            location: desc.location,
+            binding: binding as u32,
+            element: pso::Element { format, offset: 0 },
+        });
+    }
+
+    let vertex = hal_registry::hal_shader_module_create(&program.vertex_spirv)?;
+    let fragment = match hal_registry::hal_shader_module_create(&program.fragment_spirv) {
+        Some(fragment) => fragment,
+        None => {
+            hal_registry::hal_object_destroy(HalObject::ShaderModule(vertex));
+            return None;
+        }
+    };
+    let raw = {
+        let shaders = pso::GraphicsShaderSet {
+            vertex: entry_point(&vertex),
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(entry_point(&fragment)),
+        };
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+        let mut desc = pso::GraphicsPipelineDesc::new(
+            shaders,
+            primitive(key.primitive),
+            key.rasterization.rasterizer(key.primitive),
+            layout,
+            subpass,
+        );
+        desc.vertex_buffers = vertex_buffers;
+        desc.attributes = attributes;
+        desc.depth_stencil = key.depth_stencil.depth_stencil_desc();
+        if key.render_pass.color.is_some() {
+            desc.blender.targets.push(key.fragment.color_blend_desc());
+        }
+        hal_registry::hal_graphics_pipeline_create(&desc)
+    };
+    hal_registry::hal_object_destroy(HalObject::ShaderModule(vertex));
+    hal_registry::hal_object_destroy(HalObject::ShaderModule(fragment));
+    raw
+}
+
+/// Copy the pipeline cache statistics to `statistics`
+#[no_mangle]
+pub extern "C" fn glesGetPipelineStatistics(statistics: *mut PipelineStatistics) {
+    info!("glesGetPipelineStatistics(statistics = {:p})", statistics);
+
+    if statistics.is_null() {
+        return;
+    }
+    unsafe { *statistics = HUB.pipeline_cache.lock().statistics };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::{GL_TRIANGLES, GL_TRIANGLE_STRIP, GL_UNSIGNED_BYTE};
+    use upload::{DataSource, VertexBinding};
+
+    #[test]
+    fn test_pipeline_cache() {
+        use context::TEST_LOCK;
+        use program::{glDeleteProgram, tests::link_program};
+
+        let _guard = TEST_LOCK.lock();
+        let program = link_program(
+            "attribute vec4 a_position; attribute vec3 a_color; varying vec3 v_color; \
+             void main() { v_color = a_color; gl_Position = a_position; }",
+            "precision mediump float; varying vec3 v_color; uniform sampler2D u_texture; \
+             void main() { gl_FragColor = vec4(v_color, 1.0) * texture2D(u_texture, vec2(0.5)); }",
+        );
+        let mut attributes = [VertexAttribute::default(); 2];
+        attributes[1].size = 3;
+        attributes[1].type_ = GL_UNSIGNED_BYTE;
+        let vertex_input = VertexInput {
+            bindings: vec![
+                VertexBinding {
+                    location: 0,
+                    source: DataSource::Upload,
+                    offset: 0,
+                    stride: 0,
+                },
+                VertexBinding {
+                    location: 1,
+                    source: DataSource::Buffer(1),
+                    offset: 0,
+                    stride: 4,
+                },
+            ],
+            first_vertex: 0,
+        };
+
+        let key = PipelineKey::new(program, &attributes, &vertex_input, GL_TRIANGLES, RenderPassKey::default());
+        assert_eq!(key.vertex_attributes[1].size, 3);
+        assert_eq!(
+            (key.vertex_attributes[0].type_, key.vertex_attributes[0].stride),
+            (GL_FLOAT, 0)
+        );
+        assert_eq!(vertex_format(&key.vertex_attributes[1]), Some(Format::Rgb8Uscaled));
+
+        let mut cache = PipelineCache::default();
+        let mut program_pool = HUB.program_pool.lock();
+        let linked = program_pool.get_object_mut(program).linked.as_ref().unwrap();
+        if !hal_registry::hal_device_available() {
+            // nothing to build pipelines with, failed creations are not counted
+            assert_eq!(cache.get_or_create(key, linked), None);
+            assert_eq!(cache.statistics, PipelineStatistics::default());
+            drop(program_pool);
+            glDeleteProgram(program);
+            return;
+        }
+
+        let index = cache.get_or_create(key.clone(), linked).unwrap();
+        assert_eq!(cache.get_or_create(key.clone(), linked), Some(index));
+        let mut strip = key.clone();
+        strip.primitive = GL_TRIANGLE_STRIP;
+        assert_ne!(cache.get_or_create(strip, linked), Some(index));
+        assert_eq!(cache.statistics, PipelineStatistics { creations: 2, hits: 1 });
+        assert!((cache.statistics.hit_rate() - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(cache.pipeline(index).unwrap().key, key);
+
+        cache.remove_program(program);
+        assert!(cache.pipelines.is_empty() && cache.layouts.is_empty());
+        assert!(cache.pipeline(index).is_none());
+        assert_ne!(cache.get_or_create(key, linked), Some(index));
+        assert_eq!(cache.statistics.creations, 3);
+        drop(program_pool);
+        cache.remove_program(program);
+        glDeleteProgram(program);
+    }
+
+    #[test]
+    fn test_capability_dirty_state() {
+        use context;
+        use gl_sys::{
+            GL_BLEND, GL_CULL_FACE, GL_DEPTH_TEST, GL_DITHER, GL_FALSE, GL_POLYGON_OFFSET_FILL, GL_SAMPLE_ALPHA_TO_COVERAGE,
+            GL_SAMPLE_COVERAGE, GL_SCISSOR_TEST, GL_STENCIL_TEST, GL_TRUE,
+        };
+        use utilities::{glDisable, glEnable, glIsEnabled};
+
+        let _guard = context::TEST_LOCK.lock();
+        assert_eq!(glIsEnabled(GL_DITHER), GL_TRUE);
+
+        let caps = [
+            GL_BLEND,
+            GL_CULL_FACE,
+            GL_DEPTH_TEST,
+            GL_POLYGON_OFFSET_FILL,
+            GL_SAMPLE_ALPHA_TO_COVERAGE,
+            GL_SAMPLE_COVERAGE,
+            GL_SCISSOR_TEST,
+            GL_STENCIL_TEST,
+        ];
+        for &cap in &caps {
+            glEnable(cap);
+            assert_eq!(glIsEnabled(cap), GL_TRUE);
+            glDisable(cap);
+            assert_eq!(glIsEnabled(cap), GL_FALSE);
+        }
+        glDisable(GL_DITHER);
+        assert_eq!(glIsEnabled(GL_DITHER), GL_FALSE);
+        glEnable(GL_DITHER);
+
+        // toggling a capability only invalidates the piece of state owning it
+        HUB.fixed_function_state.lock().refresh();
+        glEnable(GL_DEPTH_TEST);
+        assert_eq!(HUB.fixed_function_state.lock().dirty, DIRTY_DEPTH_STENCIL);
+        glEnable(GL_SCISSOR_TEST);
+        assert_eq!(HUB.fixed_function_state.lock().dirty, DIRTY_DEPTH_STENCIL);
+        glDisable(GL_SCISSOR_TEST);
+        glDisable(GL_DEPTH_TEST);
+        HUB.fixed_function_state.lock().refresh();
+    }
+}
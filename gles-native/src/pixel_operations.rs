@@ -8,10 +8,31 @@ use gl_sys::{
 
 use context::{self, HUB};
 use rendering::glFinish;
-use utilities::record_error;
+use utilities::{record_error, StateValue};
 
-#[derive(Debug, Default)]
-pub(crate) struct PixelStorageState {}
+#[derive(Debug)]
+pub(crate) struct PixelStorageState {
+    pub pack_alignment: GLint,
+    pub unpack_alignment: GLint,
+}
+
+impl Default for PixelStorageState {
+    fn default() -> Self {
+        Self {
+            pack_alignment: 4,
+            unpack_alignment: 4,
+        }
+    }
+}
+
+pub(crate) fn query_state(pname: GLenum) -> Option<StateValue> {
+    let state = HUB.pixel_storage_state.lock();
+    match pname {
+        GL_PACK_ALIGNMENT => Some(StateValue::Integers(vec![state.pack_alignment])),
+        GL_UNPACK_ALIGNMENT => Some(StateValue::Integers(vec![state.unpack_alignment])),
+        _ => None,
+    }
+}
 
 #[no_mangle]
 pub extern "C" fn glPixelStorei(pname: GLenum, param: GLint) {
@@ -26,6 +47,13 @@ pub extern "C" fn glPixelStorei(pname: GLenum, param: GLint) {
         record_error(GL_INVALID_VALUE);
         return;
     }
+
+    let mut state = HUB.pixel_storage_state.lock();
+    if pname == GL_PACK_ALIGNMENT {
+        state.pack_alignment = param;
+    } else {
+        state.unpack_alignment = param;
+    }
 }
 
 #[no_mangle]
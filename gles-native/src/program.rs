@@ -1,7 +1,677 @@
+use std::collections::HashMap;
+use std::ptr;
+
+use gl_sys::{GLboolean, GLchar, GLenum, GLint, GLsizei, GLuint, GL_INVALID_OPERATION, GL_INVALID_VALUE, GL_VERTEX_SHADER};
+
+use context::{self, generate_objects, is_nullptr, AsGlBoolValue, HUB};
+use glsl::{self, layout, spirv, CompiledShader, Layout, Storage};
+use limits::LIMITS;
+use object_pool::ObjectPool;
+use shader::Shader;
+use uniform::Uniforms;
+use utilities::record_error;
+
 #[derive(Debug, Default)]
 pub(crate) struct Program {
     pub deletion_mark: bool,
     pub validate_state: bool,
 
     pub update_descriptor_sets: bool,
+
+    /// Names of the attached shaders, 0 if none
+    pub vertex_shader: GLuint,
+    pub fragment_shader: GLuint,
+
+    /// Locations set with `glBindAttribLocation`, used by the next link
+    pub attribute_bindings: HashMap<String, GLuint>,
+
+    pub link_status: bool,
+    pub info_log: String,
+    /// Executable of the last successful link
+    pub linked: Option<LinkedProgram>,
+}
+
+#[derive(Debug)]
+pub(crate) struct LinkedProgram {
+    pub vertex: CompiledShader,
+    pub fragment: CompiledShader,
+    pub layout: Layout,
+    pub vertex_spirv: Vec<u32>,
+    pub fragment_spirv: Vec<u32>,
+    pub uniforms: Uniforms,
+    /// Sorted locations of the active attributes, matrices take one per column
+    pub attribute_locations: Vec<GLuint>,
+}
+
+impl Program {
+    #[inline]
+    fn attached_shaders(&self) -> impl Iterator<Item = GLuint> {
+        vec![self.vertex_shader, self.fragment_shader]
+            .into_iter()
+            .filter(|&shader| shader != 0)
+    }
+}
+
+/// Return true if `program` names a program object, otherwise record `GL_INVALID_OPERATION` for shader objects
+/// and `GL_INVALID_VALUE` for anything else
+pub(crate) fn validate_program_name(program: GLuint, programs: &ObjectPool<Program>, shaders: &ObjectPool<Shader>) -> bool {
+    if programs.has_object(program) {
+        return true;
+    }
+
+    if shaders.has_object(program) {
+        error!("{} is a shader object", program);
+        record_error(GL_INVALID_OPERATION);
+    } else {
+        error!("{} is not a program object", program);
+        record_error(GL_INVALID_VALUE);
+    }
+    false
+}
+
+/// Return true if `program` names a program object which is linked
+pub(crate) fn validate_linked_program(program: GLuint, programs: &mut ObjectPool<Program>, shaders: &ObjectPool<Shader>) -> bool {
+    if !validate_program_name(program, programs, shaders) {
+        return false;
+    }
+    if !programs.get_object_mut(program).link_status {
+        error!("program {} is not linked", program);
+        record_error(GL_INVALID_OPERATION);
+        return false;
+    }
+    true
+}
+
+/// Same as `shader::validate_shader_name` for callers already holding the program pool
+fn validate_shader_name(shader: GLuint, programs: &ObjectPool<Program>, shaders: &ObjectPool<Shader>) -> bool {
+    if shaders.has_object(shader) {
+        return true;
+    }
+
+    if programs.has_object(shader) {
+        error!("{} is a program object", shader);
+        record_error(GL_INVALID_OPERATION);
+    } else {
+        error!("{} is not a shader object", shader);
+        record_error(GL_INVALID_VALUE);
+    }
+    false
+}
+
+/// Release a shader detached from a program, deleting it if it was marked for deletion
+pub(crate) fn release_shader(shader: GLuint, shaders: &mut ObjectPool<Shader>) {
+    if !shaders.has_object(shader) {
+        return;
+    }
+
+    let object = shaders.get_object_mut(shader);
+    object.attach_count -= 1;
+    if object.deletion_mark && object.attach_count == 0 {
+        debug!("shader {} deleted after detaching", shader);
+        shaders.deallocate(shader);
+    }
+}
+
+/// Detach the shaders of `program` and free it
+fn delete_program(program: GLuint, programs: &mut ObjectPool<Program>, shaders: &mut ObjectPool<Shader>) {
+    let attached_shaders = programs.get_object_mut(program).attached_shaders().collect::<Vec<_>>();
+    for shader in attached_shaders {
+        release_shader(shader, shaders);
+    }
+    programs.deallocate(program);
+    HUB.pipeline_cache.lock().remove_program(program);
+    debug!("program {} deleted", program);
+}
+
+/// Check the interface between the stages, returning the link errors
+fn match_interfaces(vertex: &CompiledShader, fragment: &CompiledShader) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for (_, varying) in fragment.varyings().filter(|&(_, symbol)| symbol.is_used) {
+        match vertex.varyings().find(|&(_, symbol)| symbol.name == varying.name) {
+            None => errors.push(format!("Fragment varying {} does not match any vertex varying", varying.name)),
+            Some((_, output)) => {
+                if output.ty.name(&vertex.structs) != varying.ty.name(&fragment.structs) {
+                    errors.push(format!("Types of varying {} differ between shaders", varying.name));
+                } else if output.is_invariant != varying.is_invariant {
+                    errors.push(format!("Invariance of varying {} differs between shaders", varying.name));
+                }
+            }
+        }
+    }
+
+    for (_, uniform) in fragment.uniforms() {
+        if let Some((_, other)) = vertex.uniforms().find(|&(_, symbol)| symbol.name == uniform.name) {
+            let is_same_struct = match (uniform.ty.base, other.ty.base) {
+                (glsl::BaseType::Struct(id), glsl::BaseType::Struct(other_id)) => {
+                    fragment.structs[id] == vertex.structs[other_id]
+                }
+                _ => true,
+            };
+            if uniform.ty.name(&fragment.structs) != other.ty.name(&vertex.structs) || !is_same_struct {
+                errors.push(format!("Types of uniform {} differ between shaders", uniform.name));
+            } else if uniform.precision != other.precision {
+                errors.push(format!("Precisions of uniform {} differ between shaders", uniform.name));
+            }
+        }
+    }
+
+    let is_invariant_builtin = |shader: &CompiledShader, name: &str| {
+        shader.symbols.iter().any(|symbol| match symbol.storage {
+            Storage::BuiltIn(_) => symbol.name == name && symbol.is_invariant,
+            _ => false,
+        })
+    };
+    if is_invariant_builtin(fragment, "gl_FragCoord") && !is_invariant_builtin(vertex, "gl_Position") {
+        errors.push("gl_FragCoord is invariant but gl_Position is not".to_string());
+    }
+    if is_invariant_builtin(fragment, "gl_PointCoord") && !is_invariant_builtin(vertex, "gl_PointSize") {
+        errors.push("gl_PointCoord is invariant but gl_PointSize is not".to_string());
+    }
+
+    errors
+}
+
+/// Check that the active uniforms, samplers and varyings fit the limits when packed with the rules of GLSL ES 1.00
+/// Appendix A, returning the link errors
+fn check_packing(vertex: &CompiledShader, fragment: &CompiledShader) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for &(shader, max_uniform_vectors, max_samplers, stage) in &[
+        (
+            vertex,
+            LIMITS.max_vertex_uniform_vectors,
+            LIMITS.max_vertex_texture_image_units,
+            "vertex",
+        ),
+        (
+            fragment,
+            LIMITS.max_fragment_uniform_vectors,
+            LIMITS.max_texture_image_units,
+            "fragment",
+        ),
+    ] {
+        let (samplers, uniforms): (Vec<_>, Vec<_>) = shader
+            .uniforms()
+            .filter(|&(_, symbol)| symbol.is_used)
+            .flat_map(|(_, symbol)| layout::flattened(&symbol.ty, &shader.structs))
+            .partition(|&(ty, _)| ty.is_sampler());
+        if !layout::fits_packed(&uniforms, max_uniform_vectors as u32) {
+            errors.push(format!("Too many {} shader uniform vectors", stage));
+        }
+        if samplers.iter().map(|&(_, count)| count).sum::<u32>() > max_samplers as u32 {
+            errors.push(format!("Too many {} shader samplers", stage));
+        }
+    }
+
+    // varyings the fragment shader reads, including the built-in ones
+    let varyings = fragment
+        .symbols
+        .iter()
+        .filter(|symbol| {
+            symbol.is_used
+                && match symbol.storage {
+                    Storage::Varying => true,
+                    Storage::BuiltIn(_) => ["gl_FragCoord", "gl_FrontFacing", "gl_PointCoord"].contains(&symbol.name.as_str()),
+                    _ => false,
+                }
+        })
+        .flat_map(|symbol| layout::flattened(&symbol.ty, &fragment.structs))
+        .collect::<Vec<_>>();
+    if !layout::fits_packed(&varyings, LIMITS.max_varying_vectors as u32) {
+        errors.push("Too many varying vectors".to_string());
+    }
+
+    errors
+}
+
+/// Link the compiled stages of a program
+fn link(
+    program: GLuint,
+    vertex: Option<CompiledShader>,
+    fragment: Option<CompiledShader>,
+    attribute_bindings: &HashMap<String, GLuint>,
+) -> Result<LinkedProgram, Vec<String>> {
+    let (vertex, fragment) = match (vertex, fragment) {
+        (Some(vertex), Some(fragment)) => (vertex, fragment),
+        (None, _) => return Err(vec!["Vertex shader is not compiled".to_string()]),
+        (_, None) => return Err(vec!["Fragment shader is not compiled".to_string()]),
+    };
+
+    let mut errors = match_interfaces(&vertex, &fragment);
+    errors.extend(check_packing(&vertex, &fragment));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let layout = Layout::new(&[&vertex, &fragment], attribute_bindings);
+    let is_out_of_range = vertex.attributes().filter(|&(_, symbol)| symbol.is_used).any(|(_, symbol)| {
+        layout.attribute_locations[&symbol.name] + symbol.ty.location_count(&vertex.structs) > LIMITS.max_vertex_attribs as u32
+    });
+    if is_out_of_range {
+        return Err(vec!["Too many vertex attributes".to_string()]);
+    }
+    let vertex_spirv = glsl::translate(&vertex, &layout).map_err(|message| vec![message])?;
+    let fragment_spirv = glsl::translate(&fragment, &layout).map_err(|message| vec![message])?;
+    spirv::dump(&format!("program_{}.vert", program), &vertex_spirv);
+    spirv::dump(&format!("program_{}.frag", program), &fragment_spirv);
+
+    let mut attribute_locations = vertex
+        .attributes()
+        .filter(|&(_, symbol)| symbol.is_used)
+        .flat_map(|(_, symbol)| {
+            let location = layout.attribute_locations[&symbol.name];
+            location..location + symbol.ty.location_count(&vertex.structs)
+        })
+        .collect::<Vec<_>>();
+    attribute_locations.sort();
+
+    Ok(LinkedProgram {
+        attribute_locations,
+        uniforms: Uniforms::new(&vertex, &fragment, &layout),
+        vertex,
+        fragment,
+        layout,
+        vertex_spirv,
+        fragment_spirv,
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glAttachShader(program: GLuint, shader: GLuint) {
+    info!("glAttachShader(program = {}, shader = {})", program, shader);
+
+    let mut shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_program_name(program, &program_pool_guard, &shader_pool_guard)
+        || !validate_shader_name(shader, &program_pool_guard, &shader_pool_guard)
+    {
+        return;
+    }
+
+    let shader_object = shader_pool_guard.get_object_mut(shader);
+    let program_object = program_pool_guard.get_object_mut(program);
+    let slot = if shader_object.type_ == GL_VERTEX_SHADER {
+        &mut program_object.vertex_shader
+    } else {
+        &mut program_object.fragment_shader
+    };
+    if *slot != 0 {
+        error!(
+            "program {} already has a shader of type {:?} attached",
+            program, shader_object.type_
+        );
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+
+    *slot = shader;
+    shader_object.attach_count += 1;
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glCreateProgram() -> GLuint {
+    info!("glCreateProgram()");
+
+    let mut program = 0;
+    generate_objects(1, &mut program, &HUB.program_pool);
+    HUB.program_pool.lock().get_object_mut(program);
+    program
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glDeleteProgram(program: GLuint) {
+    info!("glDeleteProgram(program = {})", program);
+
+    if program == 0 {
+        return;
+    }
+
+    let mut shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_program_name(program, &program_pool_guard, &shader_pool_guard) {
+        return;
+    }
+
+    if HUB.active_program.lock().name == program {
+        debug!("program {} is in use, deletion deferred", program);
+        program_pool_guard.get_object_mut(program).deletion_mark = true;
+    } else {
+        delete_program(program, &mut program_pool_guard, &mut shader_pool_guard);
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glDetachShader(program: GLuint, shader: GLuint) {
+    info!("glDetachShader(program = {}, shader = {})", program, shader);
+
+    let mut shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_program_name(program, &program_pool_guard, &shader_pool_guard)
+        || !validate_shader_name(shader, &program_pool_guard, &shader_pool_guard)
+    {
+        return;
+    }
+
+    let program_object = program_pool_guard.get_object_mut(program);
+    if program_object.vertex_shader == shader {
+        program_object.vertex_shader = 0;
+    } else if program_object.fragment_shader == shader {
+        program_object.fragment_shader = 0;
+    } else {
+        error!("shader {} is not attached to program {}", shader, program);
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+    release_shader(shader, &mut shader_pool_guard);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetProgramiv(program: GLuint, pname: GLenum, params: *mut GLint) {
+    info!(
+        "glGetProgramiv(program = {}, pname = {:?}, params = {:?})",
+        program, pname, params
+    );
+
+    use gl_sys::{
+        GL_ACTIVE_ATTRIBUTES, GL_ACTIVE_ATTRIBUTE_MAX_LENGTH, GL_ACTIVE_UNIFORMS, GL_ACTIVE_UNIFORM_MAX_LENGTH,
+        GL_ATTACHED_SHADERS, GL_DELETE_STATUS, GL_INFO_LOG_LENGTH, GL_LINK_STATUS, GL_VALIDATE_STATUS,
+    };
+
+    if !context::validate_pname(
+        pname,
+        &[
+            GL_DELETE_STATUS,
+            GL_LINK_STATUS,
+            GL_VALIDATE_STATUS,
+            GL_INFO_LOG_LENGTH,
+            GL_ATTACHED_SHADERS,
+            GL_ACTIVE_ATTRIBUTES,
+            GL_ACTIVE_ATTRIBUTE_MAX_LENGTH,
+            GL_ACTIVE_UNIFORMS,
+            GL_ACTIVE_UNIFORM_MAX_LENGTH,
+        ],
+    ) {
+        return;
+    }
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_program_name(program, &program_pool_guard, &shader_pool_guard) || is_nullptr(params, "params is nullptr") {
+        return;
+    }
+
+    let object = program_pool_guard.get_object_mut(program);
+    let active_attributes = || {
+        object
+            .linked
+            .iter()
+            .flat_map(|linked| linked.vertex.attributes())
+            .filter(|&(_, symbol)| symbol.is_used)
+            .map(|(_, symbol)| symbol)
+    };
+    let value = match pname {
+        GL_DELETE_STATUS => object.deletion_mark.as_gl_bool_value() as GLint,
+        GL_LINK_STATUS => object.link_status.as_gl_bool_value() as GLint,
+        GL_VALIDATE_STATUS => object.validate_state.as_gl_bool_value() as GLint,
+        GL_INFO_LOG_LENGTH if object.info_log.is_empty() => 0,
+        GL_INFO_LOG_LENGTH => object.info_log.len() as GLint + 1,
+        GL_ATTACHED_SHADERS => object.attached_shaders().count() as GLint,
+        GL_ACTIVE_ATTRIBUTES => active_attributes().count() as GLint,
+        GL_ACTIVE_ATTRIBUTE_MAX_LENGTH => active_attributes()
+            .map(|symbol| symbol.name.len() as GLint + 1)
+            .max()
+            .unwrap_or(0),
+        GL_ACTIVE_UNIFORMS => object
+            .linked
+            .as_ref()
+            .map_or(0, |linked| linked.uniforms.active.len() as GLint),
+        GL_ACTIVE_UNIFORM_MAX_LENGTH => object
+            .linked
+            .iter()
+            .flat_map(|linked| &linked.uniforms.active)
+            .map(|uniform| uniform.name.len() as GLint + if uniform.is_array { 4 } else { 1 })
+            .max()
+            .unwrap_or(0),
+        _ => unreachable!(),
+    };
+    unsafe { *params = value };
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetProgramInfoLog(program: GLuint, bufsize: GLsizei, length: *mut GLsizei, infolog: *mut GLchar) {
+    info!(
+        "glGetProgramInfoLog(program = {}, bufsize = {}, length = {:?}, infolog = {:?})",
+        program, bufsize, length, infolog
+    );
+
+    if bufsize < 0 {
+        error!("invalid bufsize {}", bufsize);
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_program_name(program, &program_pool_guard, &shader_pool_guard) {
+        return;
+    }
+
+    context::copy_to_gl_string(&program_pool_guard.get_object_mut(program).info_log, bufsize, length, infolog);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glIsProgram(program: GLuint) -> GLboolean {
+    info!("glIsProgram(program = {})", program);
+
+    context::is_valid_object(program, &HUB.program_pool)
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glLinkProgram(program: GLuint) {
+    info!("glLinkProgram(program = {})", program);
+
+    let mut shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_program_name(program, &program_pool_guard, &shader_pool_guard) {
+        return;
+    }
+
+    let object = program_pool_guard.get_object_mut(program);
+    let result = match (object.vertex_shader, object.fragment_shader) {
+        (0, _) => Err(vec!["No vertex shader attached".to_string()]),
+        (_, 0) => Err(vec!["No fragment shader attached".to_string()]),
+        (vertex, fragment) => link(
+            program,
+            shader_pool_guard.get_object_mut(vertex).compiled.clone(),
+            shader_pool_guard.get_object_mut(fragment).compiled.clone(),
+            &object.attribute_bindings,
+        ),
+    };
+
+    object.info_log.clear();
+    match result {
+        Ok(linked) => {
+            debug!("program {} linked, {:?}", program, linked.layout);
+            object.link_status = true;
+            object.linked = Some(linked);
+            object.update_descriptor_sets = true;
+            HUB.pipeline_cache.lock().remove_program(program);
+        }
+        Err(errors) => {
+            debug!("program {} failed to link: {:?}", program, errors);
+            for error in errors {
+                object.info_log.push_str(&error);
+                object.info_log.push('\n');
+            }
+            object.link_status = false;
+            // the program in use keeps its executable until it is replaced
+            if HUB.active_program.lock().name != program {
+                object.linked = None;
+            }
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glUseProgram(program: GLuint) {
+    info!("glUseProgram(program = {})", program);
+
+    let mut shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    let object_ptr = if program == 0 {
+        ptr::null_mut()
+    } else {
+        if !validate_program_name(program, &program_pool_guard, &shader_pool_guard) {
+            return;
+        }
+        let object = program_pool_guard.get_object_mut(program);
+        if !object.link_status {
+            error!("program {} is not linked", program);
+            record_error(GL_INVALID_OPERATION);
+            return;
+        }
+        object.update_descriptor_sets = true;
+        object as *mut Program
+    };
+
+    let mut active_program = HUB.active_program.lock();
+    let previous = active_program.name;
+    active_program.name = program;
+    active_program.ptr = object_ptr;
+
+    if previous != program && program_pool_guard.has_object(previous) && program_pool_guard.get_object_mut(previous).deletion_mark
+    {
+        delete_program(previous, &mut program_pool_guard, &mut shader_pool_guard);
+    }
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use gl_sys::*;
+    use shader::{glCompileShader, glCreateShader, glDeleteShader, glIsShader, glShaderSource};
+    use std::ffi::CStr;
+
+    /// Create a program from the sources of its stages and link it
+    pub(crate) fn link_program(vertex: &str, fragment: &str) -> GLuint {
+        let program = glCreateProgram();
+        for &(type_, source) in &[(GL_VERTEX_SHADER, vertex), (GL_FRAGMENT_SHADER, fragment)] {
+            let shader = compiled_shader(type_, &format!("{}\0", source));
+            glAttachShader(program, shader);
+        }
+        glLinkProgram(program);
+        program
+    }
+
+    fn compiled_shader(type_: GLenum, source: &str) -> GLuint {
+        let shader = glCreateShader(type_);
+        let source = source.as_ptr() as *const GLchar;
+        let length = source_length(source);
+        glShaderSource(shader, 1, &source, &length);
+        glCompileShader(shader);
+        shader
+    }
+
+    fn source_length(source: *const GLchar) -> GLint {
+        unsafe { CStr::from_ptr(source) }.to_bytes().len() as GLint
+    }
+
+    fn program_info_log(program: GLuint) -> String {
+        let mut buffer = vec![0 as GLchar; 1024];
+        glGetProgramInfoLog(program, buffer.len() as GLsizei, ptr::null_mut(), buffer.as_mut_ptr());
+        unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap().to_string()
+    }
+
+    fn program_parameter(program: GLuint, pname: GLenum) -> GLint {
+        let mut value = -1;
+        glGetProgramiv(program, pname, &mut value);
+        value
+    }
+
+    #[test]
+    fn test_link_program() {
+        let _guard = context::TEST_LOCK.lock();
+        let vertex = compiled_shader(
+            GL_VERTEX_SHADER,
+            "attribute vec4 a_position;\nvarying vec2 v_uv;\nvoid main() { v_uv = a_position.xy; gl_Position = a_position; }\0",
+        );
+        let fragment = compiled_shader(
+            GL_FRAGMENT_SHADER,
+            "precision mediump float;\nvarying vec2 v_uv;\nvoid main() { gl_FragColor = vec4(v_uv, 0.0, 1.0); }\0",
+        );
+        let program = glCreateProgram();
+        assert_eq!(glIsProgram(program), GL_TRUE);
+        glAttachShader(program, vertex);
+        glAttachShader(program, fragment);
+        assert_eq!(program_parameter(program, GL_ATTACHED_SHADERS), 2);
+
+        glLinkProgram(program);
+        assert_eq!(
+            program_parameter(program, GL_LINK_STATUS),
+            GL_TRUE as GLint,
+            "{}",
+            program_info_log(program)
+        );
+        assert_eq!(program_parameter(program, GL_ACTIVE_ATTRIBUTES), 1);
+        assert_eq!(program_parameter(program, GL_ACTIVE_ATTRIBUTE_MAX_LENGTH), 11);
+
+        // a fragment varying the vertex shader does not write fails to link
+        let other = compiled_shader(
+            GL_FRAGMENT_SHADER,
+            "precision mediump float;\nvarying vec3 v_uv;\nvoid main() { gl_FragColor = vec4(v_uv, 1.0); }\0",
+        );
+        glDetachShader(program, fragment);
+        glAttachShader(program, other);
+        glLinkProgram(program);
+        assert_eq!(program_parameter(program, GL_LINK_STATUS), GL_FALSE as GLint);
+        assert_eq!(program_info_log(program), "Types of varying v_uv differ between shaders\n");
+
+        // so does a shader without main, which compiles on its own
+        let no_main = compiled_shader(GL_FRAGMENT_SHADER, "precision mediump float;\nvoid color() {}\0");
+        glDetachShader(program, other);
+        glAttachShader(program, no_main);
+        glLinkProgram(program);
+        assert_eq!(program_info_log(program), "missing main function\n");
+        glDetachShader(program, no_main);
+        glDeleteShader(no_main);
+        glAttachShader(program, other);
+
+        // uniforms are packed into the vectors the limits count
+        let uniform_count = LIMITS.max_vertex_uniform_vectors + 1;
+        let large = link_program(
+            &format!(
+                "uniform vec4 u_values[{}]; void main() {{ gl_Position = u_values[0] + u_values[{}]; }}",
+                uniform_count,
+                uniform_count - 1
+            ),
+            "void main() { gl_FragColor = vec4(1.0); }",
+        );
+        assert_eq!(program_info_log(large), "Too many vertex shader uniform vectors\n");
+        glDeleteProgram(large);
+
+        // shaders and programs in use are deleted once released
+        glDeleteShader(vertex);
+        assert_eq!(glIsShader(vertex), GL_TRUE);
+        glDetachShader(program, other);
+        glAttachShader(program, fragment);
+        glLinkProgram(program);
+        glUseProgram(program);
+        glDeleteProgram(program);
+        assert_eq!(program_parameter(program, GL_DELETE_STATUS), GL_TRUE as GLint);
+        glUseProgram(0);
+        assert_eq!(glIsProgram(program), GL_FALSE);
+        assert_eq!(glIsShader(vertex), GL_FALSE);
+        assert_eq!(glIsShader(fragment), GL_TRUE);
+    }
 }
@@ -1,2 +1,288 @@
-#[derive(Debug, Default)]
-pub(crate) struct RasterizationState {}
+#![allow(non_snake_case)]
+
+use std::hash::{Hash, Hasher};
+
+use hal::pso::{DepthBias, Face, FrontFace, PolygonMode, Rasterizer, State};
+
+use gl_sys::{
+    GLenum, GLfloat, GL_BACK, GL_CCW, GL_CULL_FACE, GL_CW, GL_FRONT, GL_FRONT_AND_BACK, GL_INVALID_VALUE, GL_LINES, GL_LINE_LOOP,
+    GL_LINE_STRIP, GL_POLYGON_OFFSET_FILL,
+};
+
+use context::{self, HUB};
+use limits::LIMITS;
+use pipeline::{self, DIRTY_RASTERIZATION};
+use utilities::record_error;
+
+/// Rasterizer state, the polygon offset is dynamic state and does not take part in pipeline lookups
+#[derive(Clone, Debug)]
+pub(crate) struct RasterizationState {
+    pub is_cull_face_enabled: bool,
+    pub cull_face: GLenum,
+    pub front_face: GLenum,
+    pub line_width: GLfloat,
+    pub is_polygon_offset_fill_enabled: bool,
+    pub polygon_offset_factor: GLfloat,
+    pub polygon_offset_units: GLfloat,
+}
+
+impl Default for RasterizationState {
+    fn default() -> Self {
+        Self {
+            is_cull_face_enabled: false,
+            cull_face: GL_BACK,
+            front_face: GL_CCW,
+            line_width: 1.0,
+            is_polygon_offset_fill_enabled: false,
+            polygon_offset_factor: 0.0,
+            polygon_offset_units: 0.0,
+        }
+    }
+}
+
+impl PartialEq for RasterizationState {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_cull_face_enabled == other.is_cull_face_enabled
+            && self.cull_face == other.cull_face
+            && self.front_face == other.front_face
+            && self.line_width.to_bits() == other.line_width.to_bits()
+            && self.is_polygon_offset_fill_enabled == other.is_polygon_offset_fill_enabled
+    }
+}
+
+impl Eq for RasterizationState {}
+
+impl Hash for RasterizationState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.is_cull_face_enabled.hash(state);
+        self.cull_face.hash(state);
+        self.front_face.hash(state);
+        self.line_width.to_bits().hash(state);
+        self.is_polygon_offset_fill_enabled.hash(state);
+    }
+}
+
+impl RasterizationState {
+    /// State drawing `primitive` is baked with, the line width of other primitives is irrelevant
+    pub fn for_primitive(&self, primitive: GLenum) -> Self {
+        let mut state = self.clone();
+        if !is_line_primitive(primitive) {
+            state.line_width = 1.0;
+        }
+        state
+    }
+
+    /// Rasterizer of a pipeline drawing `primitive`. Hal determines the winding in frame buffer coordinates with
+    /// an upper-left origin, images are mirrored vertically into those (see `glsl::FLIP_Y`), so primitives keep the
+    /// winding they have in the lower-left origin window coordinates of GL
+    pub fn rasterizer(&self, primitive: GLenum) -> Rasterizer {
+        let cull_face = match self.cull_face {
+            _ if !self.is_cull_face_enabled => Face::NONE,
+            GL_FRONT => Face::FRONT,
+            GL_BACK => Face::BACK,
+            _ => Face::FRONT | Face::BACK,
+        };
+        let front_face = if self.front_face == GL_CCW {
+            FrontFace::CounterClockwise
+        } else {
+            FrontFace::Clockwise
+        };
+
+        Rasterizer {
+            polygon_mode: if is_line_primitive(primitive) {
+                PolygonMode::Line(self.line_width)
+            } else {
+                PolygonMode::Fill
+            },
+            cull_face,
+            front_face,
+            depth_clamping: false,
+            depth_bias: if self.is_polygon_offset_fill_enabled {
+                Some(State::Dynamic)
+            } else {
+                None
+            },
+            conservative: false,
+        }
+    }
+
+    /// Value of the dynamic depth bias, `None` unless polygon offset is enabled
+    pub fn depth_bias(&self) -> Option<DepthBias> {
+        if !self.is_polygon_offset_fill_enabled {
+            return None;
+        }
+        Some(DepthBias {
+            const_factor: self.polygon_offset_units,
+            clamp: 0.0,
+            slope_factor: self.polygon_offset_factor,
+        })
+    }
+}
+
+#[inline]
+fn is_line_primitive(primitive: GLenum) -> bool {
+    primitive == GL_LINES || primitive == GL_LINE_LOOP || primitive == GL_LINE_STRIP
+}
+
+pub(crate) fn set_capability(cap: GLenum, is_enabled: bool) {
+    {
+        let mut state = HUB.rasterization_state.lock();
+        match cap {
+            GL_CULL_FACE => state.is_cull_face_enabled = is_enabled,
+            GL_POLYGON_OFFSET_FILL => state.is_polygon_offset_fill_enabled = is_enabled,
+            _ => unreachable!(),
+        }
+    }
+    pipeline::mark_dirty(DIRTY_RASTERIZATION);
+}
+
+pub(crate) fn is_capability_enabled(cap: GLenum) -> bool {
+    let state = HUB.rasterization_state.lock();
+    match cap {
+        GL_CULL_FACE => state.is_cull_face_enabled,
+        GL_POLYGON_OFFSET_FILL => state.is_polygon_offset_fill_enabled,
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn glCullFace(mode: GLenum) {
+    info!("glCullFace(mode = {})", mode);
+
+    if !context::validate_invalid_enum(mode, &[GL_FRONT, GL_BACK, GL_FRONT_AND_BACK], "invalid cull face mode") {
+        return;
+    }
+    HUB.rasterization_state.lock().cull_face = mode;
+    pipeline::mark_dirty(DIRTY_RASTERIZATION);
+}
+
+#[no_mangle]
+pub extern "C" fn glFrontFace(mode: GLenum) {
+    info!("glFrontFace(mode = {})", mode);
+
+    if !context::validate_invalid_enum(mode, &[GL_CW, GL_CCW], "invalid front face mode") {
+        return;
+    }
+    HUB.rasterization_state.lock().front_face = mode;
+    pipeline::mark_dirty(DIRTY_RASTERIZATION);
+}
+
+#[no_mangle]
+pub extern "C" fn glLineWidth(width: GLfloat) {
+    info!("glLineWidth(width = {})", width);
+
+    if width.is_nan() || width <= 0.0 {
+        error!("line width {} is not positive", width);
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+    let [min, max] = LIMITS.aliased_line_width_range;
+    HUB.rasterization_state.lock().line_width = width.max(min).min(max);
+    pipeline::mark_dirty(DIRTY_RASTERIZATION);
+}
+
+#[no_mangle]
+pub extern "C" fn glPolygonOffset(factor: GLfloat, units: GLfloat) {
+    info!("glPolygonOffset(factor = {}, units = {})", factor, units);
+
+    let mut state = HUB.rasterization_state.lock();
+    state.polygon_offset_factor = factor;
+    state.polygon_offset_units = units;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::GL_TRIANGLES;
+
+    #[test]
+    fn test_rasterizer() {
+        let mut state = RasterizationState {
+            is_cull_face_enabled: true,
+            line_width: 4.0,
+            ..RasterizationState::default()
+        };
+        let rasterizer = state.rasterizer(GL_TRIANGLES);
+        assert_eq!(rasterizer.cull_face, Face::BACK);
+        assert_eq!(rasterizer.front_face, FrontFace::CounterClockwise);
+        assert_eq!(rasterizer.polygon_mode, PolygonMode::Fill);
+        assert_eq!(state.rasterizer(GL_LINE_STRIP).polygon_mode, PolygonMode::Line(4.0));
+        assert_eq!(
+            state.for_primitive(GL_TRIANGLES),
+            RasterizationState {
+                is_cull_face_enabled: true,
+                ..RasterizationState::default()
+            }
+        );
+
+        // polygon offset values are dynamic, only enabling it changes the pipeline
+        state.is_polygon_offset_fill_enabled = true;
+        state.polygon_offset_units = 2.0;
+        let mut offset = state.clone();
+        offset.polygon_offset_units = 3.0;
+        assert_eq!(state, offset);
+        assert_eq!(state.rasterizer(GL_TRIANGLES).depth_bias, Some(State::Dynamic));
+        assert_eq!(state.depth_bias().unwrap().const_factor, 2.0);
+    }
+
+    #[test]
+    fn test_front_face() {
+        use frame_buffer::Rect;
+        use viewport_transformation::tests::window_position;
+        use viewport_transformation::ViewportTransformation;
+
+        // counter-clockwise in clip space and in the window coordinates of GL
+        let triangle = [[-0.5f32, -0.5], [0.5, -0.5], [0.0, 0.5]];
+        let dimensions = Rect::new(0, 0, 64, 32);
+        let mut state = ViewportTransformation::default();
+        state.viewport_rectangle = dimensions;
+        let viewport = state.viewport(&dimensions);
+        let corners = triangle
+            .iter()
+            .map(|&[x, y]| window_position(&viewport, [x, y, 0.0, 1.0]))
+            .collect::<Vec<_>>();
+        // area in the frame buffer as hal computes it, positive for counter-clockwise primitives
+        let sum = (0..3)
+            .map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                a[0] * b[1] - b[0] * a[1]
+            })
+            .sum::<f32>();
+        let area = -0.5 * sum;
+        let is_front = |front_face: GLenum| {
+            let state = RasterizationState {
+                front_face,
+                ..RasterizationState::default()
+            };
+            (state.rasterizer(GL_TRIANGLES).front_face == FrontFace::CounterClockwise) == (area > 0.0)
+        };
+        assert!(is_front(GL_CCW));
+        assert!(!is_front(GL_CW));
+    }
+
+    #[test]
+    fn test_rasterization_state() {
+        use gl_sys::{GLint, GL_CULL_FACE_MODE, GL_LINE_WIDTH, GL_TRUE};
+        use utilities::{glDisable, glEnable, glGetFloatv, glGetIntegerv, glIsEnabled};
+
+        let _guard = context::TEST_LOCK.lock();
+        glCullFace(GL_FRONT);
+        glEnable(GL_CULL_FACE);
+        assert_eq!(glIsEnabled(GL_CULL_FACE), GL_TRUE);
+        let mut mode = 0 as GLint;
+        glGetIntegerv(GL_CULL_FACE_MODE, &mut mode);
+        assert_eq!(mode as GLenum, GL_FRONT);
+
+        // wide lines are clamped to the aliased line width range
+        let [_, max] = LIMITS.aliased_line_width_range;
+        glLineWidth(max + 1.0);
+        let mut width = 0.0;
+        glGetFloatv(GL_LINE_WIDTH, &mut width);
+        assert_eq!(width, max);
+
+        glDisable(GL_CULL_FACE);
+        glCullFace(GL_BACK);
+        glLineWidth(1.0);
+        assert_eq!(*HUB.rasterization_state.lock(), RasterizationState::default());
+    }
+}
@@ -1,16 +1,28 @@
+use std::cell::Cell;
+use std::ptr;
+
 use gl_sys::{
-    GLboolean, GLenum, GLint, GLsizei, GLuint, GL_COLOR_ATTACHMENT0, GL_DEPTH_COMPONENT16, GL_FALSE, GL_INVALID_ENUM,
-    GL_INVALID_OPERATION, GL_MAX_RENDERBUFFER_SIZE, GL_OUT_OF_MEMORY, GL_RENDERBUFFER, GL_RENDERBUFFER_ALPHA_SIZE,
-    GL_RENDERBUFFER_BLUE_SIZE, GL_RENDERBUFFER_DEPTH_SIZE, GL_RENDERBUFFER_GREEN_SIZE, GL_RENDERBUFFER_HEIGHT,
-    GL_RENDERBUFFER_INTERNAL_FORMAT, GL_RENDERBUFFER_RED_SIZE, GL_RENDERBUFFER_STENCIL_SIZE, GL_RENDERBUFFER_WIDTH, GL_RGB565,
-    GL_RGB5_A1, GL_RGBA4, GL_STENCIL_INDEX8, GL_TRUE,
+    GLboolean, GLenum, GLint, GLsizei, GLuint, GL_COLOR_ATTACHMENT0, GL_DEPTH24_STENCIL8_OES, GL_DEPTH_COMPONENT16,
+    GL_DEPTH_COMPONENT24_OES, GL_DEPTH_COMPONENT32_OES, GL_FALSE, GL_INVALID_ENUM, GL_INVALID_OPERATION,
+    GL_MAX_RENDERBUFFER_SIZE, GL_OUT_OF_MEMORY, GL_RENDERBUFFER, GL_RENDERBUFFER_ALPHA_SIZE, GL_RENDERBUFFER_BLUE_SIZE,
+    GL_RENDERBUFFER_DEPTH_SIZE, GL_RENDERBUFFER_GREEN_SIZE, GL_RENDERBUFFER_HEIGHT, GL_RENDERBUFFER_INTERNAL_FORMAT,
+    GL_RENDERBUFFER_RED_SIZE, GL_RENDERBUFFER_STENCIL_SIZE, GL_RENDERBUFFER_WIDTH, GL_RGB565, GL_RGB5_A1, GL_RGBA4,
+    GL_STENCIL_INDEX8, GL_TRUE,
 };
 
+use hal::format::{Aspects, Format, ImageFeature};
+use hal::image;
+
+use active_object;
 use context::{self, HUB};
+use destruction_queue;
+use frame_buffer::{self, Rect};
+use hal_registry;
+use limits::LIMITS;
 use texture::Texture;
 use utilities::record_error;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub(crate) struct RenderBuffer {
     pub internal_format: GLenum,
     pub target: GLenum,
@@ -18,32 +30,190 @@ pub(crate) struct RenderBuffer {
     pub texture: Option<Texture>,
     pub index: u32,
 
-    pub attached_frame_buffer_index: u32,
+    pub deletion_mark: bool,
+    /// Number of frame buffer attachments referring to the render buffer, deletion is deferred until they are
+    /// detached
+    pub attach_count: u32,
     pub component_size: GLint,
 }
 
+impl Default for RenderBuffer {
+    fn default() -> Self {
+        use gl_sys::GL_INVALID_VALUE;
+
+        Self {
+            internal_format: GL_RGBA4,
+            target: GL_INVALID_VALUE,
+            texture: None,
+            index: 0,
+            deletion_mark: false,
+            attach_count: 0,
+            component_size: 0,
+        }
+    }
+}
+
 pub(crate) fn init_texture(object: &mut RenderBuffer) {
     object.texture = Some(Texture::default());
     //    object.texture.as_mut().unwrap().init_state();
 }
 
-//pub fn allocate(&mut self, width: i32, height: i32, render_internal_format: RenderBufferInterFormat) -> bool {
-//    false
-//}
-//
-//pub fn set_texture(&mut self, texture: Texture) -> &mut Self {
-//    self.texture = Some(texture);
-//    self
-//}
-//
-//pub fn is_allocated(&self) -> bool {
-//    if self.texture.is_none() {
-//        return false;
-//    } else {
-//        //todo: texture is allocated?
-//        unimplemented!()
-//    }
-//}
+/// Backing formats ordered by preference, the first one supported by the adapter is used
+fn hal_format_candidates(internal_format: GLenum) -> &'static [Format] {
+    match internal_format {
+        GL_RGBA4 => &[Format::Rgba4Unorm, Format::Bgra4Unorm, Format::Rgba8Unorm],
+        GL_RGB565 => &[Format::R5g6b5Unorm, Format::B5g6r5Unorm, Format::Rgba8Unorm],
+        GL_RGB5_A1 => &[
+            Format::R5g5b5a1Unorm,
+            Format::B5g5r5a1Unorm,
+            Format::A1r5g5b5Unorm,
+            Format::Rgba8Unorm,
+        ],
+        GL_DEPTH_COMPONENT16 => &[Format::D16Unorm, Format::X8D24Unorm, Format::D32Float],
+        GL_DEPTH_COMPONENT24_OES => &[Format::X8D24Unorm, Format::D24UnormS8Uint, Format::D32Float],
+        GL_DEPTH_COMPONENT32_OES => &[Format::D32Float, Format::D32FloatS8Uint],
+        GL_STENCIL_INDEX8 => &[Format::S8Uint, Format::D24UnormS8Uint, Format::D32FloatS8Uint],
+        GL_DEPTH24_STENCIL8_OES => &[Format::D24UnormS8Uint, Format::D32FloatS8Uint],
+        _ => &[],
+    }
+}
+
+#[inline]
+pub(crate) fn is_depth_stencil_format(internal_format: GLenum) -> bool {
+    match internal_format {
+        GL_DEPTH_COMPONENT16
+        | GL_DEPTH_COMPONENT24_OES
+        | GL_DEPTH_COMPONENT32_OES
+        | GL_STENCIL_INDEX8
+        | GL_DEPTH24_STENCIL8_OES => true,
+        _ => false,
+    }
+}
+
+/// Bit sizes of the components of a format
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct FormatBits {
+    pub red: GLint,
+    pub green: GLint,
+    pub blue: GLint,
+    pub alpha: GLint,
+    pub depth: GLint,
+    pub stencil: GLint,
+}
+
+impl FormatBits {
+    fn new(red: GLint, green: GLint, blue: GLint, alpha: GLint, depth: GLint, stencil: GLint) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            alpha,
+            depth,
+            stencil,
+        }
+    }
+}
+
+pub(crate) fn hal_format_bits(format: Format) -> FormatBits {
+    match format {
+        Format::Rgba4Unorm | Format::Bgra4Unorm => FormatBits::new(4, 4, 4, 4, 0, 0),
+        Format::R5g6b5Unorm | Format::B5g6r5Unorm => FormatBits::new(5, 6, 5, 0, 0, 0),
+        Format::R5g5b5a1Unorm | Format::B5g5r5a1Unorm | Format::A1r5g5b5Unorm => FormatBits::new(5, 5, 5, 1, 0, 0),
+        Format::Rgba8Unorm | Format::Bgra8Unorm => FormatBits::new(8, 8, 8, 8, 0, 0),
+        Format::Rgb8Unorm => FormatBits::new(8, 8, 8, 0, 0, 0),
+        Format::D16Unorm => FormatBits::new(0, 0, 0, 0, 16, 0),
+        Format::X8D24Unorm => FormatBits::new(0, 0, 0, 0, 24, 0),
+        Format::D32Float => FormatBits::new(0, 0, 0, 0, 32, 0),
+        Format::S8Uint => FormatBits::new(0, 0, 0, 0, 0, 8),
+        Format::D16UnormS8Uint => FormatBits::new(0, 0, 0, 0, 16, 8),
+        Format::D24UnormS8Uint => FormatBits::new(0, 0, 0, 0, 24, 8),
+        Format::D32FloatS8Uint => FormatBits::new(0, 0, 0, 0, 32, 8),
+        _ => {
+            warn!("unexpected render buffer format {:?}", format);
+            FormatBits::default()
+        }
+    }
+}
+
+impl RenderBuffer {
+    /// Components missing from `internal_format` are reported as 0 even if the backing format has them
+    pub fn format_bits(&self) -> FormatBits {
+        let bits = match self.texture.as_ref().and_then(|texture| texture.hal_format) {
+            Some(format) => hal_format_bits(format),
+            None => return FormatBits::default(),
+        };
+
+        match self.internal_format {
+            GL_RGB565 => FormatBits {
+                alpha: 0,
+                depth: 0,
+                stencil: 0,
+                ..bits
+            },
+            GL_RGBA4 | GL_RGB5_A1 => FormatBits {
+                depth: 0,
+                stencil: 0,
+                ..bits
+            },
+            GL_DEPTH_COMPONENT16 | GL_DEPTH_COMPONENT24_OES | GL_DEPTH_COMPONENT32_OES => {
+                FormatBits::new(0, 0, 0, 0, bits.depth, 0)
+            }
+            GL_STENCIL_INDEX8 => FormatBits::new(0, 0, 0, 0, 0, bits.stencil),
+            GL_DEPTH24_STENCIL8_OES => FormatBits::new(0, 0, 0, 0, bits.depth, bits.stencil),
+            _ => bits,
+        }
+    }
+}
+
+/// Pick the backing format of the sized `internal_format` for images used as attachments and with `feature`
+pub(crate) fn choose_hal_format(internal_format: GLenum, feature: ImageFeature) -> Option<Format> {
+    let feature = feature
+        | if is_depth_stencil_format(internal_format) {
+            ImageFeature::DEPTH_STENCIL_ATTACHMENT
+        } else {
+            ImageFeature::COLOR_ATTACHMENT
+        };
+
+    hal_format_candidates(internal_format)
+        .iter()
+        .cloned()
+        .find(|&format| hal_registry::hal_format_supported(format, feature))
+}
+
+fn render_buffer_allocate(object: &mut RenderBuffer, format: Format, width: GLsizei, height: GLsizei) -> bool {
+    trace!(
+        "render_buffer_allocate(format = {:?}, width = {}, height = {})",
+        format,
+        width,
+        height
+    );
+
+    if object.texture.is_none() {
+        init_texture(object);
+    }
+
+    let internal_format = object.internal_format;
+    let texture = object.texture.as_mut().unwrap();
+    destruction_queue::release_texture(texture);
+    texture.internal_format = internal_format;
+    texture.hal_format = Some(format);
+    texture.dimensions = Rect::new(0, 0, width, height);
+    if !hal_registry::hal_texture_create(texture, attachment_usage(format)) {
+        texture.hal_format = None;
+        texture.dimensions = Rect::default();
+        return false;
+    }
+    true
+}
+
+/// Usage of images of `format` rendered to as attachments
+pub(crate) fn attachment_usage(format: Format) -> image::Usage {
+    if format.surface_desc().aspects.contains(Aspects::COLOR) {
+        image::Usage::COLOR_ATTACHMENT
+    } else {
+        image::Usage::DEPTH_STENCIL_ATTACHMENT
+    }
+}
 
 fn validate_render_buffer_target(target: GLenum) -> bool {
     if target != GL_RENDERBUFFER {
@@ -85,15 +255,76 @@ pub extern "C" fn glBindRenderbuffer(target: GLenum, renderbuffer: GLuint) {
 #[allow(non_snake_case)]
 pub extern "C" fn glDeleteRenderbuffers(n: GLsizei, renderbuffers: *const GLuint) {
     info!("glDeleteRenderbuffers(n = {}, renderbuffers = {:p}", n, renderbuffers);
+
+    context::delete_objects(n, renderbuffers, &HUB.render_buffer_pool, |name, object| {
+        context::unbind_object(&HUB.active_render_buffer, name, ptr::null_mut());
+        object.attach_count -= frame_buffer::detach_from_active_frame_buffer(GL_RENDERBUFFER, name);
+        if object.attach_count > 0 {
+            debug!("render buffer {} is attached, deletion deferred", name);
+            object.deletion_mark = true;
+            return false;
+        }
+        if let Some(ref mut texture) = object.texture {
+            destruction_queue::release_texture(texture);
+        }
+        true
+    });
 }
 
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn glGetRenderbufferParameteriv(target: GLenum, pname: GLenum, params: *mut GLint) {
     info!(
-        "glRenderbufferStorage(target = {:?}, pname = {:?}, params = {:p})",
+        "glGetRenderbufferParameteriv(target = {:?}, pname = {:?}, params = {:p})",
         target, pname, params
     );
+
+    if !validate_render_buffer_target(target)
+        || !context::validate_pname(
+            pname,
+            &[
+                GL_RENDERBUFFER_WIDTH,
+                GL_RENDERBUFFER_HEIGHT,
+                GL_RENDERBUFFER_INTERNAL_FORMAT,
+                GL_RENDERBUFFER_RED_SIZE,
+                GL_RENDERBUFFER_GREEN_SIZE,
+                GL_RENDERBUFFER_BLUE_SIZE,
+                GL_RENDERBUFFER_ALPHA_SIZE,
+                GL_RENDERBUFFER_DEPTH_SIZE,
+                GL_RENDERBUFFER_STENCIL_SIZE,
+            ],
+        )
+    {
+        return;
+    }
+
+    let mut active_object = context::get_active_render_buffer();
+    if 0 == active_object.name {
+        error!("current active object is default value");
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+
+    if context::is_nullptr(params, "params is nullptr") {
+        return;
+    }
+
+    let object = active_object::get_object_mut(&mut active_object).unwrap();
+    let dimensions = object.texture.as_ref().map(|texture| texture.dimensions).unwrap_or_default();
+    let bits = object.format_bits();
+    let value = match pname {
+        GL_RENDERBUFFER_WIDTH => dimensions.width,
+        GL_RENDERBUFFER_HEIGHT => dimensions.height,
+        GL_RENDERBUFFER_INTERNAL_FORMAT => object.internal_format as GLint,
+        GL_RENDERBUFFER_RED_SIZE => bits.red,
+        GL_RENDERBUFFER_GREEN_SIZE => bits.green,
+        GL_RENDERBUFFER_BLUE_SIZE => bits.blue,
+        GL_RENDERBUFFER_ALPHA_SIZE => bits.alpha,
+        GL_RENDERBUFFER_DEPTH_SIZE => bits.depth,
+        GL_RENDERBUFFER_STENCIL_SIZE => bits.stencil,
+        _ => unreachable!(),
+    };
+    unsafe { *params = value };
 }
 
 #[no_mangle]
@@ -111,6 +342,52 @@ pub extern "C" fn glRenderbufferStorage(target: GLenum, internalformat: GLenum,
         "glRenderbufferStorage(target = {:?}, internalformat = {:?}, width = {}, height = {})",
         target, internalformat, width, height
     );
+
+    let active_object = context::get_active_render_buffer();
+    let name = active_object.name;
+    let is_size_updated = Cell::new(false);
+
+    context::object_upload_data(
+        || {
+            validate_render_buffer_target(target)
+                && context::validate_invalid_enum(
+                    internalformat,
+                    &[
+                        GL_RGBA4,
+                        GL_RGB565,
+                        GL_RGB5_A1,
+                        GL_DEPTH_COMPONENT16,
+                        GL_STENCIL_INDEX8,
+                        GL_DEPTH_COMPONENT24_OES,
+                        GL_DEPTH_COMPONENT32_OES,
+                        GL_DEPTH24_STENCIL8_OES,
+                    ],
+                    "invalid internalformat",
+                )
+                && context::validate_invalid_value(
+                    (width, height),
+                    |&(width, height)| {
+                        width < 0 || height < 0 || width > LIMITS.max_renderbuffer_size || height > LIMITS.max_renderbuffer_size
+                    },
+                    "invalid size",
+                )
+        },
+        active_object,
+        |object| {
+            let dimensions = object.texture.as_ref().map(|texture| texture.dimensions).unwrap_or_default();
+            is_size_updated.set(dimensions.width != width || dimensions.height != height);
+            object.internal_format = internalformat;
+            true
+        },
+        |object| match choose_hal_format(internalformat, ImageFeature::empty()) {
+            Some(format) => render_buffer_allocate(object, format, width, height),
+            None => {
+                error!("no supported format for internalformat {:?}", internalformat);
+                false
+            }
+        },
+        || frame_buffer::mark_attached_frame_buffers(GL_RENDERBUFFER, name, is_size_updated.get()),
+    );
 }
 
 #[no_mangle]
@@ -131,4 +408,71 @@ mod tests {
     fn test_all_in_one() {
         &*HUB;
     }
+
+    #[test]
+    fn test_render_buffer_storage() {
+        use active_object;
+        use context::TEST_LOCK;
+        use frame_buffer::{glBindFramebuffer, glDeleteFramebuffers, glFramebufferRenderbuffer, glGenFramebuffers};
+        use utilities::glGetError;
+
+        let _guard = TEST_LOCK.lock();
+        let mut frame_buffer = 0;
+        glGenFramebuffers(1, &mut frame_buffer);
+        glBindFramebuffer(GL_FRAMEBUFFER, frame_buffer);
+
+        let mut render_buffer = 0;
+        glGenRenderbuffers(1, &mut render_buffer);
+        glBindRenderbuffer(GL_RENDERBUFFER, render_buffer);
+        glRenderbufferStorage(GL_RENDERBUFFER, GL_RGB565, 32, 16);
+        glFramebufferRenderbuffer(GL_FRAMEBUFFER, GL_COLOR_ATTACHMENT0, GL_RENDERBUFFER, render_buffer);
+
+        HUB.frame_buffer_pool.lock().get_object_mut(frame_buffer).is_updated = false;
+        glRenderbufferStorage(GL_RENDERBUFFER, GL_RGB565, 64, 16);
+        {
+            let mut pool_guard = HUB.frame_buffer_pool.lock();
+            let object = pool_guard.get_object_mut(frame_buffer);
+            assert!(object.is_updated && object.is_size_updated);
+        }
+
+        let mut active_object = context::get_active_render_buffer();
+        let object = active_object::get_object_mut(&mut active_object).unwrap();
+        let texture = object.texture.as_ref().unwrap();
+        assert_eq!(GL_RGB565, texture.internal_format);
+        assert_eq!(Some(Format::R5g6b5Unorm), texture.hal_format);
+        assert_eq!(Rect::new(0, 0, 64, 16), texture.dimensions);
+        assert!(texture.image.is_some() && texture.memory.is_some() && texture.image_view.is_some());
+
+        let query = |pname| {
+            let mut value = -1;
+            glGetRenderbufferParameteriv(GL_RENDERBUFFER, pname, &mut value);
+            value
+        };
+        assert_eq!(64, query(GL_RENDERBUFFER_WIDTH));
+        assert_eq!(16, query(GL_RENDERBUFFER_HEIGHT));
+        assert_eq!(GL_RGB565 as GLint, query(GL_RENDERBUFFER_INTERNAL_FORMAT));
+        assert_eq!(6, query(GL_RENDERBUFFER_GREEN_SIZE));
+        assert_eq!(0, query(GL_RENDERBUFFER_ALPHA_SIZE));
+
+        glRenderbufferStorage(GL_RENDERBUFFER, GL_STENCIL_INDEX8, 64, 16);
+        assert_eq!(0, query(GL_RENDERBUFFER_RED_SIZE));
+        assert_eq!(0, query(GL_RENDERBUFFER_DEPTH_SIZE));
+        assert_eq!(8, query(GL_RENDERBUFFER_STENCIL_SIZE));
+
+        // storage which cannot be allocated leaves the render buffer without any
+        glGetError();
+        glRenderbufferStorage(GL_RENDERBUFFER, GL_RGBA4, 4096, 4096);
+        match glGetError() {
+            GL_OUT_OF_MEMORY => assert_eq!(0, query(GL_RENDERBUFFER_WIDTH)),
+            error => {
+                assert_eq!(GL_NO_ERROR, error);
+                assert_eq!(4096, query(GL_RENDERBUFFER_WIDTH));
+            }
+        }
+
+        glBindFramebuffer(GL_FRAMEBUFFER, 0);
+        glBindRenderbuffer(GL_RENDERBUFFER, 0);
+        glDeleteFramebuffers(1, &frame_buffer);
+        glDeleteRenderbuffers(1, &render_buffer);
+    }
 }
@@ -1,15 +1,71 @@
 #![allow(non_snake_case)]
 
+use std::ops::Range;
 use std::ptr;
+use std::slice;
 
-use context::{is_nullptr, HUB};
-use frame_buffer::glCheckFramebufferStatus;
+use active_object;
+use command_buffer::{Command, DescriptorSet};
+use context::{self, is_nullptr, HUB};
+use destruction_queue;
+use frame_buffer::{glCheckFramebufferStatus, ClearRequest};
 use gl_sys::{
-    GLbitfield, GLenum, GLint, GLsizei, GLvoid, GL_COLOR_BUFFER_BIT, GL_DEPTH_BUFFER_BIT, GL_FRAMEBUFFER,
-    GL_FRAMEBUFFER_COMPLETE, GL_FRONT_AND_BACK, GL_INVALID_ENUM, GL_INVALID_FRAMEBUFFER_OPERATION, GL_INVALID_VALUE,
-    GL_STENCIL_BUFFER_BIT, GL_TRIANGLES, GL_TRIANGLE_FAN, GL_TRIANGLE_STRIP, GL_UNSIGNED_BYTE, GL_UNSIGNED_SHORT,
+    GLbitfield, GLclampf, GLenum, GLfloat, GLint, GLsizei, GLuint, GLvoid, GL_COLOR_BUFFER_BIT, GL_DEPTH_BUFFER_BIT,
+    GL_ELEMENT_ARRAY_BUFFER, GL_FRAMEBUFFER, GL_FRAMEBUFFER_COMPLETE, GL_FRONT_AND_BACK, GL_INVALID_ENUM,
+    GL_INVALID_FRAMEBUFFER_OPERATION, GL_INVALID_OPERATION, GL_INVALID_VALUE, GL_NONE, GL_OUT_OF_MEMORY, GL_STENCIL_BUFFER_BIT,
+    GL_TRIANGLES, GL_TRIANGLE_FAN, GL_TRIANGLE_STRIP, GL_UNSIGNED_BYTE, GL_UNSIGNED_SHORT,
 };
-use utilities::record_error;
+use hal_registry;
+use index_conversion::{self, ConversionKey, ConvertedIndices};
+use pipeline::PipelineKey;
+use upload::{self, DataSource, IndexData, VertexBinding};
+use utilities::{self, record_error};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct ClearValues {
+    pub color: [GLfloat; 4],
+    pub depth: GLfloat,
+    pub stencil: GLint,
+}
+
+impl Default for ClearValues {
+    fn default() -> Self {
+        Self {
+            color: [0.0, 0.0, 0.0, 0.0],
+            depth: 1.0,
+            stencil: 0,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn glClearColor(red: GLclampf, green: GLclampf, blue: GLclampf, alpha: GLclampf) {
+    info!(
+        "glClearColor(red = {}, green = {}, blue = {}, alpha = {})",
+        red, green, blue, alpha
+    );
+
+    HUB.clear_values.lock().color = [
+        utilities::clamp_to_unit_range(red),
+        utilities::clamp_to_unit_range(green),
+        utilities::clamp_to_unit_range(blue),
+        utilities::clamp_to_unit_range(alpha),
+    ];
+}
+
+#[no_mangle]
+pub extern "C" fn glClearDepthf(depth: GLclampf) {
+    info!("glClearDepthf(depth = {})", depth);
+
+    HUB.clear_values.lock().depth = utilities::clamp_to_unit_range(depth);
+}
+
+#[no_mangle]
+pub extern "C" fn glClearStencil(s: GLint) {
+    info!("glClearStencil(s = {})", s);
+
+    HUB.clear_values.lock().stencil = s;
+}
 
 #[no_mangle]
 pub extern "C" fn glFinish() {
@@ -18,6 +74,13 @@ pub extern "C" fn glFinish() {
     if !flush() {
         return;
     }
+    let mut command_buffer_manager = HUB.command_buffer_manager.lock();
+    if !command_buffer_manager.wait_idle() {
+        record_error(GL_OUT_OF_MEMORY);
+    }
+    HUB.destruction_queue
+        .lock()
+        .collect(command_buffer_manager.completed_submission());
 }
 
 #[no_mangle]
@@ -36,11 +99,11 @@ pub extern "C" fn glDrawArrays(mode: GLenum, first: GLint, count: GLsizei) {
         return;
     }
 
-    if count < 0 {
+    if first < 0 || count < 0 {
         record_error(GL_INVALID_VALUE);
         return;
     }
-    if 0 == count {
+    if !validate_program_in_use() || 0 == count {
         return;
     }
 
@@ -48,6 +111,18 @@ pub extern "C" fn glDrawArrays(mode: GLenum, first: GLint, count: GLsizei) {
         record_error(GL_INVALID_FRAMEBUFFER_OPERATION);
         return;
     }
+
+    // both are non-negative, so their sum can't overflow `u32`
+    let range = first as u32..first as u32 + count as u32;
+    if !index_conversion::needs_conversion(mode, GL_NONE) {
+        draw(mode, None, count as u32, range, 0);
+        return;
+    }
+
+    let indices = upload_converted(&index_conversion::convert(mode, GL_NONE, count as usize, &[]));
+    if indices.count > 0 {
+        draw(indices.mode, Some(indices.data), indices.count, range, first as u32);
+    }
 }
 
 #[no_mangle]
@@ -66,11 +141,7 @@ pub extern "C" fn glDrawElements(mode: GLenum, count: GLsizei, type_: GLenum, in
         record_error(GL_INVALID_VALUE);
         return;
     }
-    if 0 == count {
-        return;
-    }
-
-    if !is_nullptr(indices as *mut GLvoid, "indices is nullptr") {
+    if !validate_program_in_use() || 0 == count {
         return;
     }
 
@@ -78,28 +149,455 @@ pub extern "C" fn glDrawElements(mode: GLenum, count: GLsizei, type_: GLenum, in
         record_error(GL_INVALID_FRAMEBUFFER_OPERATION);
         return;
     }
+
+    if let Some(indices) = prepare_indices(mode, count as usize, type_, indices) {
+        if indices.count > 0 {
+            draw(indices.mode, Some(indices.data), indices.count, indices.range, 0);
+        }
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn glClear(mask: GLbitfield) {
     info!("glClear(mask = {})", mask);
 
-    let clearColorEnabled = (mask & GL_COLOR_BUFFER_BIT) == 0;
-    let clearDepthEnabled = (mask & GL_DEPTH_BUFFER_BIT) == 0;
-    let clearStencilEnabled = (mask & GL_STENCIL_BUFFER_BIT) == 0;
-
-    if !clearColorEnabled && !clearDepthEnabled && !clearStencilEnabled {
+    if mask & !(GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT | GL_STENCIL_BUFFER_BIT) != 0 {
         error!("invalid value {} for glClear", mask);
         record_error(GL_INVALID_VALUE);
         return;
     }
+    if 0 == mask {
+        return;
+    }
+
+    if glCheckFramebufferStatus(GL_FRAMEBUFFER) != GL_FRAMEBUFFER_COMPLETE {
+        record_error(GL_INVALID_FRAMEBUFFER_OPERATION);
+        return;
+    }
+
+    let mut active_object = context::get_active_frame_buffer();
+    let frame_buffer = active_object::get_object_mut(&mut active_object).unwrap();
+    let mut clear_values = *HUB.clear_values.lock();
+    // the stencil value is masked to the bitplanes of the cleared stencil buffer
+    clear_values.stencil &= (1 << frame_buffer.stencil_bits()) - 1;
+    let request = ClearRequest {
+        mask,
+        clear_values,
+        rect: HUB.viewport_state.lock().clear_rect(&frame_buffer.dimensions),
+        color_mask: HUB.fragment_state.lock().color_mask,
+    };
+    frame_buffer.clear(&request, &mut HUB.command_buffer.lock());
+}
+
+/// Indices of a draw after conversion
+struct Indices {
+    mode: GLenum,
+    data: IndexData,
+    count: u32,
+    /// Vertices referenced by the indices
+    range: Range<u32>,
+}
+
+/// Locate the indices of a draw, copying client indices to the upload buffer and converting those the backend
+/// can't draw directly, and the range of vertices they reference
+fn prepare_indices(mode: GLenum, count: usize, type_: GLenum, indices: *const GLvoid) -> Option<Indices> {
+    let size = count * upload::index_size(type_);
+    let mut element_buffer = context::get_active_buffer(GL_ELEMENT_ARRAY_BUFFER);
+    if 0 == element_buffer.name {
+        if is_nullptr(indices as *mut GLvoid, "indices is nullptr") {
+            return None;
+        }
+        let bytes = unsafe { slice::from_raw_parts(indices as *const u8, size) };
+        if index_conversion::needs_conversion(mode, type_) {
+            return Some(upload_converted(&index_conversion::convert(mode, type_, count, bytes)));
+        }
+        return Some(Indices {
+            mode,
+            data: IndexData {
+                source: DataSource::Upload,
+                offset: HUB.upload_buffer.lock().push(bytes, 4),
+                type_,
+            },
+            count: count as u32,
+            range: upload::index_range(type_, bytes),
+        });
+    }
+
+    let offset = indices as usize;
+    let key = ConversionKey {
+        buffer: element_buffer.name,
+        offset,
+        count,
+        type_,
+        mode,
+    };
+    let is_converted = index_conversion::needs_conversion(mode, type_);
+    if is_converted {
+        if let Some(converted) = HUB.index_cache.lock().get(&key) {
+            return Some(upload_converted(converted));
+        }
+    }
+
+    let buffer = active_object::get_object_mut(&mut element_buffer).unwrap();
+    let bytes = match buffer.data {
+        Some(ref data) if offset + size <= data.len() => &data[offset..offset + size],
+        _ => {
+            error!(
+                "indices {}..{} are out of element array buffer {}",
+                offset,
+                offset + size,
+                element_buffer.name
+            );
+            return None;
+        }
+    };
+    if is_converted {
+        return Some(upload_converted(HUB.index_cache.lock().get_or_convert(key, bytes)));
+    }
+    Some(Indices {
+        mode,
+        data: IndexData {
+            source: DataSource::Buffer(element_buffer.name),
+            offset,
+            type_,
+        },
+        count: count as u32,
+        range: upload::index_range(type_, bytes),
+    })
+}
+
+/// Indices of a draw reading a copy of `converted` in the upload buffer, the index cache entries may be gone by
+/// the time the recorded commands are submitted
+fn upload_converted(converted: &ConvertedIndices) -> Indices {
+    Indices {
+        mode: converted.mode,
+        data: IndexData {
+            source: DataSource::Upload,
+            offset: HUB.upload_buffer.lock().push(&converted.data, 4),
+            type_: converted.type_,
+        },
+        count: converted.count,
+        range: converted.range.clone(),
+    }
 }
 
-fn flush() -> bool {
-    unimplemented!()
+fn validate_program_in_use() -> bool {
+    if 0 == HUB.active_program.lock().name {
+        error!("no program in use");
+        record_error(GL_INVALID_OPERATION);
+        return false;
+    }
+    true
+}
+
+/// Record drawing `count` vertices or `indices` referencing the vertices in `range` into the render pass of the
+/// current frame buffer, with the pipeline and descriptor set of the current state. Index 0 refers to vertex
+/// `index_base`.
+fn draw(mode: GLenum, indices: Option<IndexData>, count: u32, range: Range<u32>, index_base: u32) {
+    let mut active_program = HUB.active_program.lock();
+    let program_name = active_program.name;
+    let program = active_object::get_object_mut(&mut active_program).unwrap();
+    let mut active_frame_buffer = context::get_active_frame_buffer();
+    let frame_buffer = active_object::get_object_mut(&mut active_frame_buffer).unwrap();
+    let mut upload_buffer = HUB.upload_buffer.lock();
+    let (vertex_input, key) = {
+        let linked = program.linked.as_ref().unwrap();
+        let vertex_attribute_state = HUB.vertex_attribute_state.lock();
+        let vertex_input = upload::prepare_vertices(
+            &vertex_attribute_state.attributes,
+            &linked.attribute_locations,
+            range,
+            &mut upload_buffer,
+        );
+        let render_pass = frame_buffer.render_pass_key();
+        let key = PipelineKey::new(
+            program_name,
+            &vertex_attribute_state.attributes,
+            &vertex_input,
+            mode,
+            render_pass,
+        );
+        (vertex_input, key)
+    };
+    let pipeline = match HUB.pipeline_cache.lock().get_or_create(key, program.linked.as_ref().unwrap()) {
+        Some(pipeline) => pipeline,
+        None => {
+            error!("no pipeline to draw with program {}", program_name);
+            return;
+        }
+    };
+    let descriptor_set = DescriptorSet {
+        uniform_block: program.upload_uniforms(&mut upload_buffer),
+        textures: program.linked.as_ref().unwrap().uniforms.textures(),
+    };
+    drop(upload_buffer);
+    mark_used(&vertex_input.bindings, indices, &descriptor_set.textures);
+
+    let mut command_buffer = HUB.command_buffer.lock();
+    frame_buffer.begin_render_pass(&mut command_buffer);
+    command_buffer.record(Command::BindPipeline(pipeline));
+    {
+        let viewport_state = HUB.viewport_state.lock();
+        command_buffer.record(Command::SetViewport(viewport_state.viewport(&frame_buffer.dimensions)));
+        command_buffer.record(Command::SetScissor(viewport_state.scissor(&frame_buffer.dimensions)));
+    }
+    command_buffer.record(Command::BindDescriptorSet(descriptor_set));
+    if let Some(depth_bias) = HUB.rasterization_state.lock().depth_bias() {
+        command_buffer.record(Command::SetDepthBias(depth_bias));
+    }
+    if let Some([front, back]) = HUB.depth_stencil_state.lock().stencil_reference(frame_buffer.stencil_bits()) {
+        command_buffer.record(Command::SetStencilReference { front, back });
+    }
+    if let Some(blend_constants) = HUB.fragment_state.lock().blend_constants() {
+        command_buffer.record(Command::SetBlendConstants(blend_constants));
+    }
+    command_buffer.record(Command::BindVertexBuffers(vertex_input.bindings));
+    match indices {
+        Some(index_data) => {
+            command_buffer.record(Command::BindIndexBuffer(index_data));
+            command_buffer.record(Command::DrawIndexed {
+                index_count: count,
+                vertex_offset: index_base as i32 - vertex_input.first_vertex as i32,
+            });
+        }
+        None => command_buffer.record(Command::Draw { vertex_count: count }),
+    }
+}
+
+/// Tag the buffers and textures a draw being recorded reads with the submission it will be part of
+fn mark_used(bindings: &[VertexBinding], indices: Option<IndexData>, textures: &[(u32, Vec<GLuint>)]) {
+    let submission = destruction_queue::recording_submission();
+    let mut buffer_pool = HUB.buffer_pool.lock();
+    for source in bindings
+        .iter()
+        .map(|binding| binding.source)
+        .chain(indices.map(|index_data| index_data.source))
+    {
+        if let DataSource::Buffer(name) = source {
+            if buffer_pool.has_object(name) {
+                buffer_pool.get_object_mut(name).last_use = submission;
+            }
+        }
+    }
+
+    let mut texture_pool = HUB.texture_pool.lock();
+    for &name in textures.iter().flat_map(|&(_, ref names)| names) {
+        if texture_pool.has_object(name) {
+            texture_pool.get_object_mut(name).last_use = submission;
+        }
+    }
+}
+
+/// End the render pass of the current frame buffer and submit the recorded commands
+pub(crate) fn flush() -> bool {
+    let mut active_frame_buffer = context::get_active_frame_buffer();
+    let mut command_buffer = HUB.command_buffer.lock();
+    active_object::get_object_mut(&mut active_frame_buffer)
+        .unwrap()
+        .end_render_pass(&mut command_buffer);
+
+    let mut command_buffer_manager = HUB.command_buffer_manager.lock();
+    command_buffer_manager.poll();
+    HUB.destruction_queue
+        .lock()
+        .collect(command_buffer_manager.completed_submission());
+    if command_buffer.is_empty() {
+        return true;
+    }
+    if !hal_registry::hal_device_available() {
+        // make_current already failed, there is nothing to render with
+        debug!("drop {} commands without a device", command_buffer.commands.len());
+        command_buffer_manager.discard(&mut command_buffer, &mut HUB.upload_buffer.lock());
+        HUB.destruction_queue
+            .lock()
+            .collect(command_buffer_manager.completed_submission());
+        return true;
+    }
+    if !command_buffer_manager.submit(&mut command_buffer, &mut HUB.upload_buffer.lock()) {
+        record_error(GL_OUT_OF_MEMORY);
+        return false;
+    }
+    true
 }
 
 #[inline]
 fn is_draw_mode_triangle(mode: GLenum) -> bool {
     mode == GL_TRIANGLE_STRIP || mode == GL_TRIANGLE_FAN || mode == GL_TRIANGLES
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::{glBindBuffer, glBufferData, glBufferSubData, glGenBuffers};
+    use context::TEST_LOCK;
+    use gl_sys::*;
+    use program::glUseProgram;
+    use program::tests::link_program;
+    use upload::DataSource;
+    use utilities::{glGetFloatv, glGetIntegerv};
+    use vertex_attribute::{glEnableVertexAttribArray, glVertexAttribPointer};
+
+    #[test]
+    fn test_clear_values() {
+        let _guard = TEST_LOCK.lock();
+        glClearColor(2.0, 0.5, -1.0, 1.0);
+        glClearDepthf(0.25);
+        glClearStencil(0x1ff);
+
+        let mut color = [0.0 as GLfloat; 4];
+        glGetFloatv(GL_COLOR_CLEAR_VALUE, color.as_mut_ptr());
+        assert_eq!([1.0, 0.5, 0.0, 1.0], color);
+
+        let mut depth = 0.0 as GLfloat;
+        glGetFloatv(GL_DEPTH_CLEAR_VALUE, &mut depth);
+        assert_eq!(0.25, depth);
+
+        let mut stencil = 0;
+        glGetIntegerv(GL_STENCIL_CLEAR_VALUE, &mut stencil);
+        assert_eq!(0x1ff, stencil);
+
+        let mut color = [0 as GLint; 4];
+        glGetIntegerv(GL_COLOR_CLEAR_VALUE, color.as_mut_ptr());
+        assert_eq!(GLint::max_value(), color[0]);
+        assert_eq!(0, color[2]);
+    }
+
+    #[test]
+    fn test_draw() {
+        let _guard = TEST_LOCK.lock();
+        glBindBuffer(GL_ARRAY_BUFFER, 0);
+        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, 0);
+        let program = link_program(
+            "attribute vec4 a_position; void main() { gl_Position = a_position; }",
+            "precision mediump float; uniform vec4 u_color; void main() { gl_FragColor = u_color; }",
+        );
+        glUseProgram(program);
+
+        let positions = [0.0f32; 12];
+        glVertexAttribPointer(0, 3, GL_FLOAT, GL_FALSE, 0, positions.as_ptr() as *const GLvoid);
+        glEnableVertexAttribArray(0);
+        HUB.command_buffer.lock().reset();
+        let indices = [2u16, 1, 3];
+        glDrawElements(GL_TRIANGLES, 3, GL_UNSIGNED_SHORT, indices.as_ptr() as *const GLvoid);
+        glDrawArrays(GL_TRIANGLES, 1, 3);
+        glUseProgram(0);
+
+        let commands = HUB.command_buffer.lock().commands.split_off(0);
+        let pipelines = commands
+            .iter()
+            .filter_map(|command| match *command {
+                Command::BindPipeline(pipeline) => Some(pipeline),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pipelines.len(), 2);
+        assert_eq!(pipelines[0], pipelines[1]);
+        match commands.iter().find(|command| match **command {
+            Command::BindVertexBuffers(_) => true,
+            _ => false,
+        }) {
+            Some(&Command::BindVertexBuffers(ref bindings)) => {
+                assert_eq!((bindings[0].source, bindings[0].stride), (DataSource::Upload, 12))
+            }
+            _ => panic!("no vertex buffers bound"),
+        }
+        assert!(commands.contains(&Command::DrawIndexed {
+            index_count: 3,
+            vertex_offset: -1
+        }));
+        assert_eq!(commands.last(), Some(&Command::Draw { vertex_count: 3 }));
+
+        // without a device the commands are dropped rather than submitted
+        glUseProgram(program);
+        glDrawArrays(GL_TRIANGLES, 0, 3);
+        glUseProgram(0);
+        glFinish();
+        assert!(HUB.command_buffer.lock().is_empty());
+        assert!(HUB.upload_buffer.lock().data.is_empty());
+        let command_buffer_manager = HUB.command_buffer_manager.lock();
+        assert_eq!(
+            command_buffer_manager.completed_submission() + 1,
+            command_buffer_manager.next_submission()
+        );
+    }
+
+    #[test]
+    fn test_draw_converted_indices() {
+        let _guard = TEST_LOCK.lock();
+        glBindBuffer(GL_ARRAY_BUFFER, 0);
+        let program = link_program(
+            "attribute vec4 a_position; void main() { gl_Position = a_position; }",
+            "void main() { gl_FragColor = vec4(1.0); }",
+        );
+        glUseProgram(program);
+        let positions = [0.0f32; 16];
+        glVertexAttribPointer(0, 4, GL_FLOAT, GL_FALSE, 0, positions.as_ptr() as *const GLvoid);
+        glEnableVertexAttribArray(0);
+
+        let mut buffer = 0;
+        glGenBuffers(1, &mut buffer);
+        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, buffer);
+        let indices = [9u8, 1, 2, 3, 0];
+        glBufferData(GL_ELEMENT_ARRAY_BUFFER, 5, indices.as_ptr() as *const GLvoid, GL_STATIC_DRAW);
+        HUB.command_buffer.lock().reset();
+        glDrawElements(GL_TRIANGLE_FAN, 4, GL_UNSIGNED_BYTE, 1 as *const GLvoid);
+        glDrawElements(GL_TRIANGLE_FAN, 4, GL_UNSIGNED_BYTE, 1 as *const GLvoid);
+        glDrawArrays(GL_LINE_LOOP, 1, 3);
+        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, 0);
+        glUseProgram(0);
+
+        let key = ConversionKey {
+            buffer,
+            offset: 1,
+            count: 4,
+            type_: GL_UNSIGNED_BYTE,
+            mode: GL_TRIANGLE_FAN,
+        };
+        assert_eq!(HUB.index_cache.lock().get(&key).unwrap().range, 0..4);
+        let commands = HUB.command_buffer.lock().commands.split_off(0);
+        let draws = commands
+            .iter()
+            .filter(|command| match **command {
+                Command::DrawIndexed { .. } => true,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            draws,
+            vec![
+                &Command::DrawIndexed {
+                    index_count: 6,
+                    vertex_offset: 0
+                },
+                &Command::DrawIndexed {
+                    index_count: 6,
+                    vertex_offset: 0
+                },
+                &Command::DrawIndexed {
+                    index_count: 4,
+                    vertex_offset: 0
+                },
+            ]
+        );
+        let index_data = commands
+            .iter()
+            .filter_map(|command| match *command {
+                Command::BindIndexBuffer(index_data) => Some(index_data),
+                _ => None,
+            })
+            .next()
+            .unwrap();
+        assert_eq!((index_data.source, index_data.type_), (DataSource::Upload, GL_UNSIGNED_SHORT));
+
+        // recorded draws keep their converted indices when the buffer changes before they are submitted
+        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, buffer);
+        glBufferSubData(GL_ELEMENT_ARRAY_BUFFER, 0, 1, indices.as_ptr() as *const GLvoid);
+        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, 0);
+        assert!(HUB.index_cache.lock().get(&key).is_none());
+        let upload_buffer = HUB.upload_buffer.lock();
+        assert_eq!(
+            &upload_buffer.data[index_data.offset..index_data.offset + 12],
+            &[1, 0, 2, 0, 3, 0, 1, 0, 3, 0, 0, 0]
+        );
+    }
+}
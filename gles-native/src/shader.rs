@@ -7,18 +7,89 @@ use gl_sys::{
     GL_INVALID_OPERATION, GL_INVALID_VALUE, GL_TRUE, GL_VERTEX_SHADER,
 };
 
-use context::{generate_objects, is_nullptr, is_valid_object, HUB};
+use context::{self, generate_objects, is_nullptr, is_valid_object, AsGlBoolValue, HUB};
+use glsl::{self, CompiledShader, Stage};
+use object_pool::ObjectPool;
 use utilities::record_error;
 
 #[derive(Debug, Default)]
 pub struct Shader {
     pub type_: GLenum,
+    pub source: String,
+
+    pub compile_status: bool,
+    pub info_log: String,
+    pub deletion_mark: bool,
+    /// Number of programs the shader is attached to, deletion is deferred until it is detached from all of them
+    pub attach_count: u32,
+
+    /// Result of the last successful compilation, used at link time
+    pub(crate) compiled: Option<CompiledShader>,
+}
+
+/// Return true if `shader` names a shader object, otherwise record `GL_INVALID_OPERATION` for program objects
+/// and `GL_INVALID_VALUE` for anything else
+pub(crate) fn validate_shader_name(shader: GLuint, pool: &ObjectPool<Shader>) -> bool {
+    if pool.has_object(shader) {
+        return true;
+    }
+
+    if HUB.program_pool.lock().has_object(shader) {
+        error!("{} is a program object", shader);
+        record_error(GL_INVALID_OPERATION);
+    } else {
+        error!("{} is not a shader object", shader);
+        record_error(GL_INVALID_VALUE);
+    }
+    false
+}
+
+/// Concatenate `count` strings, each one is null terminated if `length` is nullptr or its length is negative
+fn concatenate_source(count: GLsizei, string: *const *const GLchar, length: *const GLint) -> String {
+    let strings = unsafe { slice::from_raw_parts(string, count as usize) };
+    let mut source = Vec::new();
+    for (index, &chars) in strings.iter().enumerate() {
+        if chars.is_null() {
+            continue;
+        }
+
+        let chars_length = if length.is_null() {
+            -1
+        } else {
+            unsafe { *length.offset(index as isize) }
+        };
+        if chars_length < 0 {
+            source.extend_from_slice(unsafe { CStr::from_ptr(chars) }.to_bytes());
+        } else {
+            source.extend_from_slice(unsafe { slice::from_raw_parts(chars as *const u8, chars_length as usize) });
+        }
+    }
+    String::from_utf8_lossy(&source).into_owned()
 }
 
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn glCompileShader(shader: GLuint) {
     info!("glCompileShader(shader = {})", shader);
+
+    let mut pool_guard = HUB.shader_pool.lock();
+    if !validate_shader_name(shader, &pool_guard) {
+        return;
+    }
+
+    let object = pool_guard.get_object_mut(shader);
+    let stage = if object.type_ == GL_VERTEX_SHADER {
+        Stage::Vertex
+    } else {
+        Stage::Fragment
+    };
+    // translation needs the layout of the whole program, so it is left to link time along with the check for `main`
+    let (compiled, info_log) = glsl::compile(&object.source, stage);
+    debug!("shader {} compiled: {}", shader, compiled.is_some());
+
+    object.compile_status = compiled.is_some();
+    object.info_log = info_log;
+    object.compiled = compiled;
 }
 
 #[no_mangle]
@@ -44,6 +115,22 @@ pub extern "C" fn glCreateShader(r#type: GLenum) -> GLuint {
 #[allow(non_snake_case)]
 pub extern "C" fn glDeleteShader(shader: GLuint) {
     info!("glDeleteShader(shader = {})", shader);
+
+    if shader == 0 {
+        return;
+    }
+
+    let mut pool_guard = HUB.shader_pool.lock();
+    if !validate_shader_name(shader, &pool_guard) {
+        return;
+    }
+
+    if pool_guard.get_object_mut(shader).attach_count > 0 {
+        debug!("shader {} is attached, deletion deferred", shader);
+        pool_guard.get_object_mut(shader).deletion_mark = true;
+    } else {
+        pool_guard.deallocate(shader);
+    }
 }
 
 #[no_mangle]
@@ -77,10 +164,17 @@ pub extern "C" fn glShaderSource(shader: GLuint, count: GLsizei, string: *const
         return;
     }
 
-    if !is_nullptr(string as *mut GLchar, "string is not allowed to set nullptr") {
+    if is_nullptr(string as *mut *const GLchar, "string is not allowed to set nullptr") {
         record_error(GL_INVALID_VALUE);
         return;
     }
+
+    let mut pool_guard = HUB.shader_pool.lock();
+    if !validate_shader_name(shader, &pool_guard) {
+        return;
+    }
+
+    pool_guard.get_object_mut(shader).source = concatenate_source(count, string, length);
 }
 
 #[no_mangle]
@@ -90,6 +184,40 @@ pub extern "C" fn glGetShaderiv(shader: GLuint, pname: GLenum, params: *mut GLin
         "glGetShaderiv(shader = {:?}, pname = {:?}, params = {:?})",
         shader, pname, params
     );
+
+    use gl_sys::{GL_COMPILE_STATUS, GL_DELETE_STATUS, GL_INFO_LOG_LENGTH, GL_SHADER_SOURCE_LENGTH, GL_SHADER_TYPE};
+
+    if !context::validate_pname(
+        pname,
+        &[
+            GL_SHADER_TYPE,
+            GL_DELETE_STATUS,
+            GL_COMPILE_STATUS,
+            GL_INFO_LOG_LENGTH,
+            GL_SHADER_SOURCE_LENGTH,
+        ],
+    ) {
+        return;
+    }
+
+    let mut pool_guard = HUB.shader_pool.lock();
+    if !validate_shader_name(shader, &pool_guard) || is_nullptr(params, "params is nullptr") {
+        return;
+    }
+
+    // lengths include the null terminator, and are 0 for empty strings
+    let length_with_terminator = |value: &str| if value.is_empty() { 0 } else { value.len() as GLint + 1 };
+
+    let object = pool_guard.get_object_mut(shader);
+    let value = match pname {
+        GL_SHADER_TYPE => object.type_ as GLint,
+        GL_DELETE_STATUS => object.deletion_mark.as_gl_bool_value() as GLint,
+        GL_COMPILE_STATUS => object.compile_status.as_gl_bool_value() as GLint,
+        GL_INFO_LOG_LENGTH => length_with_terminator(&object.info_log),
+        GL_SHADER_SOURCE_LENGTH => length_with_terminator(&object.source),
+        _ => unreachable!(),
+    };
+    unsafe { *params = value };
 }
 
 #[no_mangle]
@@ -103,12 +231,15 @@ pub extern "C" fn glGetShaderInfoLog(shader: GLuint, bufsize: GLsizei, length: *
     if bufsize < 0 {
         error!("glGetShaderInfoLog: Invalid Value For Buffer Size= {:?}", bufsize);
         record_error(GL_INVALID_VALUE);
+        return;
     }
 
-    if !is_nullptr(infolog, "infolog is not allowed to set nullptr") {
-        record_error(GL_INVALID_VALUE);
+    let mut pool_guard = HUB.shader_pool.lock();
+    if !validate_shader_name(shader, &pool_guard) {
         return;
     }
+
+    context::copy_to_gl_string(&pool_guard.get_object_mut(shader).info_log, bufsize, length, infolog);
 }
 
 #[no_mangle]
@@ -139,10 +270,12 @@ pub extern "C" fn glGetShaderSource(shader: GLuint, bufsize: GLsizei, length: *m
         return;
     }
 
-    if !is_nullptr(source, "source is not allowed to set nullptr") {
-        record_error(GL_INVALID_OPERATION);
+    let mut pool_guard = HUB.shader_pool.lock();
+    if !validate_shader_name(shader, &pool_guard) {
         return;
     }
+
+    context::copy_to_gl_string(&pool_guard.get_object_mut(shader).source, bufsize, length, source);
 }
 
 #[no_mangle]
@@ -160,3 +293,73 @@ pub extern "C" fn glReleaseShaderCompiler() {
 
     unimplemented!()
 }
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::*;
+
+    #[test]
+    fn test_shader_source() {
+        let _guard = context::TEST_LOCK.lock();
+        let shader = glCreateShader(GL_VERTEX_SHADER);
+        let strings = [
+            b"void main() {ignored".as_ptr() as *const GLchar,
+            b" gl_Position = vec4(0.0); }\0".as_ptr() as *const GLchar,
+        ];
+        let lengths = [13, -1];
+        glShaderSource(shader, 2, strings.as_ptr(), lengths.as_ptr());
+
+        let expected = "void main() { gl_Position = vec4(0.0); }";
+        let mut source_length = 0;
+        glGetShaderiv(shader, GL_SHADER_SOURCE_LENGTH, &mut source_length);
+        assert_eq!(expected.len() as GLint + 1, source_length);
+
+        let mut buffer = vec![0 as GLchar; source_length as usize];
+        let mut length = 0;
+        glGetShaderSource(shader, source_length, &mut length, buffer.as_mut_ptr());
+        assert_eq!(expected.len() as GLsizei, length);
+        assert_eq!(expected, unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap());
+
+        glGetShaderSource(shader, 5, &mut length, buffer.as_mut_ptr());
+        assert_eq!(4, length);
+        assert_eq!("void", unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap());
+    }
+
+    #[test]
+    fn test_compile_shader() {
+        let _guard = context::TEST_LOCK.lock();
+        let shader = glCreateShader(GL_FRAGMENT_SHADER);
+        let source = b"precision mediump float;\nvoid main() {\n    gl_FragColor = vec3(1.0);\n}\n\0";
+        glShaderSource(shader, 1, &(source.as_ptr() as *const GLchar), ptr::null());
+        glCompileShader(shader);
+
+        let mut status = GL_TRUE as GLint;
+        glGetShaderiv(shader, GL_COMPILE_STATUS, &mut status);
+        assert_eq!(GL_FALSE as GLint, status);
+
+        let mut log_length = 0;
+        glGetShaderiv(shader, GL_INFO_LOG_LENGTH, &mut log_length);
+        let mut buffer = vec![0 as GLchar; log_length as usize];
+        glGetShaderInfoLog(shader, log_length, ptr::null_mut(), buffer.as_mut_ptr());
+        let info_log = unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap();
+        assert!(info_log.starts_with("ERROR: 0:3: '=' : can not convert from 'vec3' to 'vec4'\n"));
+
+        let source = b"precision mediump float;\nvoid main() {\n    gl_FragColor = vec4(1.0);\n}\n\0";
+        glShaderSource(shader, 1, &(source.as_ptr() as *const GLchar), ptr::null());
+        glCompileShader(shader);
+        glGetShaderiv(shader, GL_COMPILE_STATUS, &mut status);
+        assert_eq!(GL_TRUE as GLint, status);
+        glGetShaderiv(shader, GL_INFO_LOG_LENGTH, &mut log_length);
+        assert_eq!(0, log_length);
+
+        // a missing main is a link error
+        let source = b"precision mediump float;\nvoid color() {}\n\0";
+        glShaderSource(shader, 1, &(source.as_ptr() as *const GLchar), ptr::null());
+        glCompileShader(shader);
+        glGetShaderiv(shader, GL_COMPILE_STATUS, &mut status);
+        assert_eq!(GL_TRUE as GLint, status);
+        glDeleteShader(shader);
+    }
+}
@@ -2,6 +2,8 @@ use std::ptr;
 
 use back;
 use hal;
+use hal::format::ImageFeature;
+use hal::image;
 
 use gl_sys::{
     GLboolean, GLenum, GLfloat, GLint, GLintptr, GLsizei, GLsizeiptr, GLuint, GLvoid, GL_ALPHA, GL_CLAMP_TO_EDGE,
@@ -14,10 +16,15 @@ use gl_sys::{
 
 use active_object;
 use context::{self, HUB};
+use destruction_queue;
+use frame_buffer::{self, Rect};
+use render_buffer;
+use utilities::record_error;
 
 use {HalImage, HalImageView, HalMemory, HalSampler};
 
 use hal_registry;
+use limits::LIMITS;
 
 // todo: auto increase value like C enum
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
@@ -27,6 +34,10 @@ pub enum TextureType {
     Total = 2,
 }
 
+/// Number of texture units the context stores, shared by the vertex and fragment stages.
+/// `GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS` may be lower on adapters with fewer samplers.
+pub(crate) const MAX_TEXTURE_UNITS: usize = 32;
+
 #[derive(Debug)]
 pub struct TextureUnit {
     pub raw: GLenum,
@@ -53,15 +64,23 @@ pub struct Texture {
     pub explicit_type: GLenum,
     pub explicit_internal_format: GLenum,
 
-    //    pub dimensions: Rect,
+    pub dimensions: Rect,
     //    pub sampling_params: Sampler,
     pub data_updated: bool,
     pub data_no_inversion: bool,
 
+    /// Backing format, may differ from `internal_format` when the adapter lacks support for it
+    pub hal_format: Option<hal::format::Format>,
     pub memory: Option<HalMemory>,
     pub image: Option<HalImage>,
     pub image_view: Option<HalImageView>,
     pub sampler: Option<HalSampler>,
+    /// Last submission which used the hal objects
+    pub last_use: u64,
+
+    pub deletion_mark: bool,
+    /// Number of frame buffer attachments referring to the texture, deletion is deferred until they are detached
+    pub attach_count: u32,
 }
 
 impl Texture {
@@ -91,12 +110,16 @@ impl Texture {
             //            states: Vec::new(),
             explicit_type,
             explicit_internal_format,
-            //            dimensions: Rect::default(),
+            dimensions: Rect::default(),
             //            sampling_params: Sampler::default(),
+            hal_format: None,
             memory: None,
             image: None,
             image_view: None,
             sampler: None,
+            last_use: 0,
+            deletion_mark: false,
+            attach_count: 0,
             data_updated,
             data_no_inversion,
         }
@@ -122,16 +145,76 @@ impl Default for Texture {
 
 fn texture_allocate(texture: &mut Texture) -> bool {
     trace!("texture_allocate(texture: &mut Texture)");
-    if !hal_registry::hal_texture_create() {
-        return false;
-    }
-    true
+
+    destruction_queue::release_texture(texture);
+    let format = match texture.hal_format {
+        Some(format) => format,
+        None => {
+            debug!("no backing format for internal format {:?} yet", texture.internal_format);
+            return true;
+        }
+    };
+    let usage = image::Usage::SAMPLED | image::Usage::TRANSFER_DST | render_buffer::attachment_usage(format);
+    hal_registry::hal_texture_create(texture, usage)
 }
 
 
 
 pub fn generate_mip_maps(target: GLenum) {}
 
+#[inline]
+fn is_depth_texture_format(format: GLenum) -> bool {
+    use gl_sys::{GL_DEPTH_COMPONENT, GL_DEPTH_STENCIL_OES};
+    format == GL_DEPTH_COMPONENT || format == GL_DEPTH_STENCIL_OES
+}
+
+/// Return the sized internal format of a depth or packed depth stencil texture image,
+/// otherwise `None` and record GL error flag
+fn validate_depth_texture_image(
+    target: GLenum,
+    level: GLint,
+    internal_format: GLenum,
+    format: GLenum,
+    type_: GLenum,
+) -> Option<GLenum> {
+    use gl_sys::{
+        GL_DEPTH24_STENCIL8_OES, GL_DEPTH_COMPONENT, GL_DEPTH_COMPONENT16, GL_DEPTH_COMPONENT24_OES, GL_DEPTH_COMPONENT32_OES,
+        GL_DEPTH_STENCIL_OES, GL_TEXTURE_2D, GL_UNSIGNED_INT, GL_UNSIGNED_INT_24_8_OES, GL_UNSIGNED_SHORT,
+    };
+
+    let sized_internal_format = match (format, type_) {
+        (GL_DEPTH_COMPONENT, GL_UNSIGNED_SHORT) => GL_DEPTH_COMPONENT16,
+        (GL_DEPTH_COMPONENT, GL_UNSIGNED_INT) => GL_DEPTH_COMPONENT24_OES,
+        (GL_DEPTH_STENCIL_OES, GL_UNSIGNED_INT_24_8_OES) => GL_DEPTH24_STENCIL8_OES,
+        _ => {
+            error!("invalid type {:?} for format {:?}", type_, format);
+            record_error(GL_INVALID_OPERATION);
+            return None;
+        }
+    };
+
+    let internal_format = match (format, internal_format) {
+        (_, internal_format) if internal_format == format => sized_internal_format,
+        (GL_DEPTH_COMPONENT, GL_DEPTH_COMPONENT16)
+        | (GL_DEPTH_COMPONENT, GL_DEPTH_COMPONENT24_OES)
+        | (GL_DEPTH_COMPONENT, GL_DEPTH_COMPONENT32_OES)
+        | (GL_DEPTH_STENCIL_OES, GL_DEPTH24_STENCIL8_OES) => internal_format,
+        _ => {
+            error!("internalformat {:?} does not match format {:?}", internal_format, format);
+            record_error(GL_INVALID_OPERATION);
+            return None;
+        }
+    };
+
+    if target != GL_TEXTURE_2D || level != 0 {
+        error!("depth textures only support level 0 of GL_TEXTURE_2D");
+        record_error(GL_INVALID_OPERATION);
+        return None;
+    }
+
+    Some(internal_format)
+}
+
 /// Return true if (target, pname, params) meet all the rules, otherwise false and record GL error flag
 fn validate_texture_parameters<T>(target: GLenum, pname: GLenum, params: *mut T) -> bool {
     !validate_texture_target_parameter(target, pname) || !validate_texture_parameter_ptr(params)
@@ -146,6 +229,26 @@ fn validate_texture_target(target: GLenum) -> bool {
     context::validate_target(target, &[GL_TEXTURE_2D, GL_TEXTURE_CUBE_MAP])
 }
 
+/// Return true if an image of `level` fits into `GL_MAX_TEXTURE_SIZE` or `GL_MAX_CUBE_MAP_TEXTURE_SIZE`,
+/// otherwise false and record `GL_INVALID_VALUE`
+fn validate_texture_size(target: GLenum, level: GLint, width: GLsizei, height: GLsizei) -> bool {
+    use gl_sys::GL_TEXTURE_2D;
+
+    let max_size = if target == GL_TEXTURE_2D {
+        LIMITS.max_texture_size
+    } else {
+        LIMITS.max_cube_map_texture_size
+    };
+    let max_level = 31 - max_size.leading_zeros() as GLint;
+    context::validate_invalid_value(
+        (level, width, height),
+        |&(level, width, height)| {
+            level < 0 || level > max_level || width < 0 || height < 0 || width > max_size >> level || height > max_size >> level
+        },
+        "invalid texture size",
+    )
+}
+
 pub(crate) fn validate_texture_format(format: GLenum) -> bool {
     use gl_sys::{GL_ALPHA, GL_LUMINANCE, GL_LUMINANCE_ALPHA, GL_RGB, GL_RGBA};
     context::validate_invalid_enum(
@@ -173,8 +276,8 @@ pub(crate) fn validate_texture_type(r#type: GLenum) -> bool {
 pub(crate) fn validate_pixel_internal_format(internal_format: GLenum, required_values: &[GLenum], message: &str) -> bool {
     context::validate_invalid_value(
         internal_format,
-        |&internal_format| context::find_value_in_slice(internal_format, required_values, |_| {}),
-        "",
+        |&internal_format| !context::find_value_in_slice(internal_format, required_values, |_| {}),
+        message,
     )
 }
 
@@ -199,7 +302,16 @@ fn validate_texture_parameter_ptr<T>(params: *mut T) -> bool {
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn glActiveTexture(texture: GLenum) {
+    use gl_sys::GL_TEXTURE0;
+
     info!("glActiveTexture(texture = {:?})", texture);
+
+    if texture < GL_TEXTURE0 || texture >= GL_TEXTURE0 + LIMITS.max_combined_texture_image_units as GLenum {
+        error!("invalid texture unit {:?}", texture);
+        record_error(GL_INVALID_ENUM);
+        return;
+    }
+    HUB.active_texture_unit.lock().raw = texture;
 }
 
 #[no_mangle]
@@ -329,7 +441,30 @@ pub extern "C" fn glCopyTexSubImage2D(
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn glDeleteTextures(n: GLsizei, textures: *const GLuint) {
+    use gl_sys::{GL_TEXTURE, GL_TEXTURE_2D, GL_TEXTURE_CUBE_MAP};
+
     info!("glDeleteTextures(n = {}, textures = {:p})", n, textures);
+
+    context::delete_objects(n, textures, &HUB.texture_pool, |name, object| {
+        for (type_, units) in HUB.active_texture.iter().enumerate() {
+            let target = if type_ == TextureType::TwoD as usize {
+                GL_TEXTURE_2D
+            } else {
+                GL_TEXTURE_CUBE_MAP
+            };
+            for unit in units.iter() {
+                context::unbind_object(unit, name, context::get_default_texture(target));
+            }
+        }
+        object.attach_count -= frame_buffer::detach_from_active_frame_buffer(GL_TEXTURE, name);
+        if object.attach_count > 0 {
+            debug!("texture {} is attached, deletion deferred", name);
+            object.deletion_mark = true;
+            return false;
+        }
+        destruction_queue::release_texture(object);
+        true
+    });
 }
 
 #[no_mangle]
@@ -399,12 +534,43 @@ pub extern "C" fn glTexImage2D(
         target, level, internalformat, width, height, border, format, r#type, pixels
     );
 
+    if !validate_texture_size(target, level, width, height) {
+        return;
+    }
+
+    let depth_internal_format = if is_depth_texture_format(format) {
+        match validate_depth_texture_image(target, level, internalformat, format, r#type) {
+            Some(internal_format) => Some(internal_format),
+            None => return,
+        }
+    } else {
+        None
+    };
+
     //todo:refactor param check
     context::object_upload_data(
         || true,
         context::get_active_texture(target),
         |object| {
             debug!("enter operation block with texture object = {:?}", object);
+            object.internal_format = internalformat;
+            object.format = format;
+            object.type_ = r#type;
+            if level == 0 {
+                object.dimensions = Rect::new(0, 0, width, height);
+            }
+            object.hal_format = match depth_internal_format {
+                Some(internal_format) => {
+                    let hal_format = render_buffer::choose_hal_format(internal_format, ImageFeature::SAMPLED);
+                    if hal_format.is_none() {
+                        error!("no supported format for internalformat {:?}", internalformat);
+                        record_error(GL_INVALID_OPERATION);
+                        return false;
+                    }
+                    hal_format
+                }
+                None => None,
+            };
             true
         },
         |object| {
@@ -485,6 +651,7 @@ mod tests {
     #[test]
     fn test_all_in_one() {
         &*HUB;
+        let _guard = context::TEST_LOCK.lock();
 
         let count = 1;
         let mut buffers = Vec::<GLuint>::with_capacity(count);
@@ -0,0 +1,591 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
+use std::{cmp, slice};
+
+use gl_sys::{
+    GLboolean, GLchar, GLenum, GLfloat, GLint, GLsizei, GLuint, GL_BOOL, GL_BOOL_VEC2, GL_BOOL_VEC3, GL_BOOL_VEC4, GL_FALSE,
+    GL_FLOAT, GL_FLOAT_MAT2, GL_FLOAT_MAT3, GL_FLOAT_MAT4, GL_FLOAT_VEC2, GL_FLOAT_VEC3, GL_FLOAT_VEC4, GL_INT, GL_INT_VEC2,
+    GL_INT_VEC3, GL_INT_VEC4, GL_INVALID_OPERATION, GL_INVALID_VALUE, GL_SAMPLER_2D, GL_SAMPLER_CUBE,
+};
+
+use context::{self, is_nullptr, HUB};
+use glsl::{self, layout, BaseType, CompiledShader, Layout, ScalarKind, Type};
+use limits::LIMITS;
+use program::{self, LinkedProgram, Program};
+use texture;
+use upload::UploadBuffer;
+use utilities::record_error;
+
+/// Largest `minUniformBufferOffsetAlignment` Vulkan allows
+pub(crate) const UNIFORM_BUFFER_OFFSET_ALIGNMENT: usize = 256;
+
+/// Active uniform of a basic type, struct uniforms are split into one per field
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ActiveUniform {
+    /// Name without the array subscript
+    pub name: String,
+    pub type_: GLenum,
+    /// Number of array elements, 1 for non-arrays
+    pub size: GLint,
+    pub is_array: bool,
+    /// Location of the first element
+    pub location: GLint,
+    /// Byte offset and array stride in the uniform block
+    pub offset: u32,
+    pub stride: u32,
+    /// Descriptor binding of samplers
+    pub binding: Option<u32>,
+}
+
+/// Uniform values of a linked program
+#[derive(Debug, Default)]
+pub(crate) struct Uniforms {
+    pub active: Vec<ActiveUniform>,
+    /// Active uniform index and array element of each location
+    pub locations: Vec<(usize, u32)>,
+    /// Shadow of the uniform block, uploaded before the next draw
+    pub data: Vec<u8>,
+    /// Texture units of each sampler binding
+    pub sampler_units: HashMap<u32, Vec<GLint>>,
+    /// Upload buffer generation and offset of the last uploaded block
+    pub upload: Option<(u64, usize)>,
+}
+
+fn gl_type(ty: &Type) -> GLenum {
+    match ty.base {
+        BaseType::Scalar(ScalarKind::Float) => GL_FLOAT,
+        BaseType::Vector(ScalarKind::Float, 2) => GL_FLOAT_VEC2,
+        BaseType::Vector(ScalarKind::Float, 3) => GL_FLOAT_VEC3,
+        BaseType::Vector(ScalarKind::Float, _) => GL_FLOAT_VEC4,
+        BaseType::Scalar(ScalarKind::Int) => GL_INT,
+        BaseType::Vector(ScalarKind::Int, 2) => GL_INT_VEC2,
+        BaseType::Vector(ScalarKind::Int, 3) => GL_INT_VEC3,
+        BaseType::Vector(ScalarKind::Int, _) => GL_INT_VEC4,
+        BaseType::Scalar(ScalarKind::Bool) => GL_BOOL,
+        BaseType::Vector(ScalarKind::Bool, 2) => GL_BOOL_VEC2,
+        BaseType::Vector(ScalarKind::Bool, 3) => GL_BOOL_VEC3,
+        BaseType::Vector(ScalarKind::Bool, _) => GL_BOOL_VEC4,
+        BaseType::Matrix(2) => GL_FLOAT_MAT2,
+        BaseType::Matrix(3) => GL_FLOAT_MAT3,
+        BaseType::Matrix(_) => GL_FLOAT_MAT4,
+        BaseType::Sampler2D => GL_SAMPLER_2D,
+        BaseType::SamplerCube => GL_SAMPLER_CUBE,
+        BaseType::Void | BaseType::Struct(_) => unreachable!(),
+    }
+}
+
+/// Split `ty` into uniforms of basic types
+fn flatten(name: &str, ty: &Type, offset: u32, binding: Option<u32>, shader: &CompiledShader, uniforms: &mut Vec<ActiveUniform>) {
+    let element = Type::new(ty.base);
+    let stride = layout::std140_array_stride(&element, &shader.structs);
+    match ty.base {
+        BaseType::Struct(id) => {
+            let field_offsets = layout::std140_field_offsets(id, &shader.structs);
+            for index in 0..ty.array_size.unwrap_or(1) {
+                let prefix = match ty.array_size {
+                    Some(_) => format!("{}[{}]", name, index),
+                    None => name.to_string(),
+                };
+                for (field, &field_offset) in shader.structs[id].fields.iter().zip(&field_offsets) {
+                    let name = format!("{}.{}", prefix, field.name);
+                    flatten(
+                        &name,
+                        &field.ty,
+                        offset + index * stride + field_offset,
+                        None,
+                        shader,
+                        uniforms,
+                    );
+                }
+            }
+        }
+        _ => uniforms.push(ActiveUniform {
+            name: name.to_string(),
+            type_: gl_type(ty),
+            size: ty.array_size.unwrap_or(1) as GLint,
+            is_array: ty.is_array(),
+            location: 0,
+            offset,
+            stride,
+            binding,
+        }),
+    }
+}
+
+impl Uniforms {
+    /// Reflect the uniforms used by either stage, assigning locations in declaration order
+    pub fn new(vertex: &CompiledShader, fragment: &CompiledShader, layout: &Layout) -> Self {
+        let mut uniforms = Self {
+            data: vec![0; layout.uniform_block_size as usize],
+            ..Self::default()
+        };
+
+        let mut reflected = HashSet::new();
+        for &shader in &[vertex, fragment] {
+            for (_, symbol) in shader.uniforms() {
+                let is_used = [vertex, fragment]
+                    .iter()
+                    .any(|shader| shader.uniforms().any(|(_, other)| other.name == symbol.name && other.is_used));
+                if !is_used || !reflected.insert(symbol.name.clone()) {
+                    continue;
+                }
+
+                let binding = layout.sampler_bindings.get(&symbol.name).cloned();
+                let offset = layout.uniform_offsets.get(&symbol.name).cloned().unwrap_or(0);
+                flatten(&symbol.name, &symbol.ty, offset, binding, shader, &mut uniforms.active);
+            }
+        }
+
+        for (index, uniform) in uniforms.active.iter_mut().enumerate() {
+            uniform.location = uniforms.locations.len() as GLint;
+            for element in 0..uniform.size as u32 {
+                uniforms.locations.push((index, element));
+            }
+            if let Some(binding) = uniform.binding {
+                uniforms.sampler_units.insert(binding, vec![0; uniform.size as usize]);
+            }
+        }
+        uniforms
+    }
+
+    /// Textures bound to the units of each sampler binding
+    pub fn textures(&self) -> Vec<(u32, Vec<GLuint>)> {
+        self.active
+            .iter()
+            .filter_map(|uniform| {
+                let binding = uniform.binding?;
+                let target = if uniform.type_ == GL_SAMPLER_CUBE {
+                    texture::TextureType::CubeMap
+                } else {
+                    texture::TextureType::TwoD
+                };
+                let textures = self.sampler_units[&binding]
+                    .iter()
+                    .map(|&unit| HUB.active_texture[target as usize][unit as usize].lock().name)
+                    .collect();
+                Some((binding, textures))
+            })
+            .collect()
+    }
+
+    /// Location of `name`, which is a uniform name optionally followed by an array subscript
+    pub fn location(&self, name: &str) -> GLint {
+        let (base, element) = match name.rfind('[') {
+            Some(start) if name.ends_with(']') => match name[start + 1..name.len() - 1].parse::<u32>() {
+                Ok(element) => (&name[..start], element),
+                Err(_) => return -1,
+            },
+            _ => (name, 0),
+        };
+
+        let has_subscript = base.len() != name.len();
+        self.active
+            .iter()
+            .find(|uniform| uniform.name == base && (uniform.is_array || !has_subscript))
+            .filter(|uniform| element < uniform.size as u32)
+            .map_or(-1, |uniform| uniform.location + element as GLint)
+    }
+}
+
+impl Program {
+    /// Copy the shadow block to `upload` if values changed since the last draw or the block was recycled,
+    /// returning its offset
+    pub(crate) fn upload_uniforms(&mut self, upload: &mut UploadBuffer) -> Option<usize> {
+        let uniforms = match self.linked {
+            Some(ref mut linked) if !linked.uniforms.data.is_empty() => &mut linked.uniforms,
+            _ => return None,
+        };
+
+        match uniforms.upload {
+            Some((generation, offset)) if !self.update_descriptor_sets && generation == upload.generation => return Some(offset),
+            _ => {}
+        }
+        trace!("upload {} bytes of uniforms", uniforms.data.len());
+        let offset = upload.push(&uniforms.data, UNIFORM_BUFFER_OFFSET_ALIGNMENT);
+        uniforms.upload = Some((upload.generation, offset));
+        self.update_descriptor_sets = false;
+        Some(offset)
+    }
+}
+
+/// Components of a value passed to `glUniform*`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Components {
+    Float(u8),
+    Int(u8),
+    Matrix(u8),
+}
+
+/// Return true if values of the given components can be written to a uniform of `type_`
+fn is_compatible(type_: GLenum, components: Components) -> bool {
+    match components {
+        Components::Float(1) => type_ == GL_FLOAT || type_ == GL_BOOL,
+        Components::Float(2) => type_ == GL_FLOAT_VEC2 || type_ == GL_BOOL_VEC2,
+        Components::Float(3) => type_ == GL_FLOAT_VEC3 || type_ == GL_BOOL_VEC3,
+        Components::Float(_) => type_ == GL_FLOAT_VEC4 || type_ == GL_BOOL_VEC4,
+        Components::Int(1) => type_ == GL_INT || type_ == GL_BOOL || type_ == GL_SAMPLER_2D || type_ == GL_SAMPLER_CUBE,
+        Components::Int(2) => type_ == GL_INT_VEC2 || type_ == GL_BOOL_VEC2,
+        Components::Int(3) => type_ == GL_INT_VEC3 || type_ == GL_BOOL_VEC3,
+        Components::Int(_) => type_ == GL_INT_VEC4 || type_ == GL_BOOL_VEC4,
+        Components::Matrix(2) => type_ == GL_FLOAT_MAT2,
+        Components::Matrix(3) => type_ == GL_FLOAT_MAT3,
+        Components::Matrix(_) => type_ == GL_FLOAT_MAT4,
+    }
+}
+
+/// Write `count` elements of 32 bit `values` to the uniform at `location` of the current program
+fn set_uniform(location: GLint, count: GLsizei, components: Components, values: *const u32) {
+    if count < 0 {
+        error!("invalid count {}", count);
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+
+    let mut program_pool_guard = HUB.program_pool.lock();
+    let program = HUB.active_program.lock().name;
+    if program == 0 {
+        error!("no current program");
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+    if location == -1 {
+        return;
+    }
+
+    let object: &mut Program = program_pool_guard.get_object_mut(program);
+    let uniforms = match object.linked {
+        Some(LinkedProgram { ref mut uniforms, .. }) => uniforms,
+        None => unreachable!(),
+    };
+    if location < 0 {
+        error!("invalid location {}", location);
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+    let (index, element) = match uniforms.locations.get(location as usize) {
+        Some(&location) => location,
+        _ => {
+            error!("invalid location {}", location);
+            record_error(GL_INVALID_OPERATION);
+            return;
+        }
+    };
+
+    let uniform = uniforms.active[index].clone();
+    if !is_compatible(uniform.type_, components) || (count > 1 && !uniform.is_array) {
+        error!("{:?} x {} do not match uniform {:?}", components, count, uniform);
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+    if count == 0 || is_nullptr(values as *mut u32, "values is nullptr") {
+        return;
+    }
+
+    let count = cmp::min(count as u32, uniform.size as u32 - element) as usize;
+    let (columns, rows) = match components {
+        Components::Float(size) | Components::Int(size) => (1, size as usize),
+        Components::Matrix(size) => (size as usize, size as usize),
+    };
+    let values = unsafe { slice::from_raw_parts(values, count * columns * rows) };
+
+    if let Some(binding) = uniform.binding {
+        let units = &values[..count];
+        if units
+            .iter()
+            .any(|&unit| unit as GLint >= LIMITS.max_combined_texture_image_units || (unit as GLint) < 0)
+        {
+            error!("invalid texture units {:?}", units);
+            record_error(GL_INVALID_VALUE);
+            return;
+        }
+        let sampler_units = uniforms.sampler_units.get_mut(&binding).unwrap();
+        for (unit, &value) in sampler_units[element as usize..].iter_mut().zip(units) {
+            *unit = value as GLint;
+        }
+    } else {
+        let is_bool = match uniform.type_ {
+            GL_BOOL | GL_BOOL_VEC2 | GL_BOOL_VEC3 | GL_BOOL_VEC4 => true,
+            _ => false,
+        };
+        for (index, value) in values.chunks(columns * rows).enumerate() {
+            let offset = uniform.offset + (element + index as u32) * uniform.stride;
+            for (column, value) in value.chunks(rows).enumerate() {
+                // matrix columns are vectors aligned to 16 bytes
+                let start = offset as usize + column * 16;
+                for (component, &value) in value.iter().enumerate() {
+                    let value = match components {
+                        Components::Float(_) if is_bool => (f32::from_bits(value) != 0.0) as u32,
+                        Components::Int(_) if is_bool => (value != 0) as u32,
+                        _ => value,
+                    };
+                    let bytes = start + component * 4;
+                    uniforms.data[bytes..bytes + 4].copy_from_slice(&[
+                        value as u8,
+                        (value >> 8) as u8,
+                        (value >> 16) as u8,
+                        (value >> 24) as u8,
+                    ]);
+                }
+            }
+        }
+    }
+    object.update_descriptor_sets = true;
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetActiveUniform(
+    program: GLuint,
+    index: GLuint,
+    bufsize: GLsizei,
+    length: *mut GLsizei,
+    size: *mut GLint,
+    type_: *mut GLenum,
+    name: *mut GLchar,
+) {
+    info!(
+        "glGetActiveUniform(program = {}, index = {}, bufsize = {}, length = {:?}, size = {:?}, type = {:?}, name = {:?})",
+        program, index, bufsize, length, size, type_, name
+    );
+
+    if bufsize < 0 {
+        error!("invalid bufsize {}", bufsize);
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !program::validate_linked_program(program, &mut program_pool_guard, &shader_pool_guard) {
+        return;
+    }
+
+    let uniform = match program_pool_guard.get_object_mut(program).linked {
+        Some(ref linked) if (index as usize) < linked.uniforms.active.len() => linked.uniforms.active[index as usize].clone(),
+        _ => {
+            error!("invalid uniform index {}", index);
+            record_error(GL_INVALID_VALUE);
+            return;
+        }
+    };
+
+    let uniform_name = if uniform.is_array {
+        format!("{}[0]", uniform.name)
+    } else {
+        uniform.name
+    };
+    context::copy_to_gl_string(&uniform_name, bufsize, length, name);
+    if !size.is_null() {
+        unsafe { *size = uniform.size };
+    }
+    if !type_.is_null() {
+        unsafe { *type_ = uniform.type_ };
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetUniformLocation(program: GLuint, name: *const GLchar) -> GLint {
+    info!("glGetUniformLocation(program = {}, name = {:?})", program, name);
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !program::validate_linked_program(program, &mut program_pool_guard, &shader_pool_guard)
+        || is_nullptr(name as *mut GLchar, "name is nullptr")
+    {
+        return -1;
+    }
+
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    match program_pool_guard.get_object_mut(program).linked {
+        Some(ref linked) if !name.starts_with("gl_") => linked.uniforms.location(&name),
+        _ => -1,
+    }
+}
+
+macro_rules! uniform_functions {
+    ($($scalar:ident, $vector:ident, $type_:ty, $components:expr, [$($value:ident),+];)+) => {
+        $(
+            #[no_mangle]
+            #[allow(non_snake_case)]
+            pub extern "C" fn $scalar(location: GLint, $($value: $type_),+) {
+                info!(concat!(stringify!($scalar), "(location = {}", $(", ", stringify!($value), " = {}",)+ ")"), location, $($value),+);
+
+                let values = [$($value.to_bits_u32()),+];
+                set_uniform(location, 1, $components, values.as_ptr());
+            }
+
+            #[no_mangle]
+            #[allow(non_snake_case)]
+            pub extern "C" fn $vector(location: GLint, count: GLsizei, v: *const $type_) {
+                info!(concat!(stringify!($vector), "(location = {}, count = {}, v = {:?})"), location, count, v);
+
+                set_uniform(location, count, $components, v as *const u32);
+            }
+        )+
+    };
+}
+
+/// Raw bits of uniform values, stored as they are in the uniform block
+trait ToBits {
+    fn to_bits_u32(self) -> u32;
+}
+
+impl ToBits for GLfloat {
+    #[inline]
+    fn to_bits_u32(self) -> u32 {
+        self.to_bits()
+    }
+}
+
+impl ToBits for GLint {
+    #[inline]
+    fn to_bits_u32(self) -> u32 {
+        self as u32
+    }
+}
+
+uniform_functions! {
+    glUniform1f, glUniform1fv, GLfloat, Components::Float(1), [x];
+    glUniform2f, glUniform2fv, GLfloat, Components::Float(2), [x, y];
+    glUniform3f, glUniform3fv, GLfloat, Components::Float(3), [x, y, z];
+    glUniform4f, glUniform4fv, GLfloat, Components::Float(4), [x, y, z, w];
+    glUniform1i, glUniform1iv, GLint, Components::Int(1), [x];
+    glUniform2i, glUniform2iv, GLint, Components::Int(2), [x, y];
+    glUniform3i, glUniform3iv, GLint, Components::Int(3), [x, y, z];
+    glUniform4i, glUniform4iv, GLint, Components::Int(4), [x, y, z, w];
+}
+
+fn set_uniform_matrix(location: GLint, count: GLsizei, transpose: GLboolean, size: u8, value: *const GLfloat) {
+    if transpose != GL_FALSE {
+        error!("transpose must be GL_FALSE");
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+    set_uniform(location, count, Components::Matrix(size), value as *const u32);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glUniformMatrix2fv(location: GLint, count: GLsizei, transpose: GLboolean, value: *const GLfloat) {
+    info!(
+        "glUniformMatrix2fv(location = {}, count = {}, transpose = {}, value = {:?})",
+        location, count, transpose, value
+    );
+
+    set_uniform_matrix(location, count, transpose, 2, value);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glUniformMatrix3fv(location: GLint, count: GLsizei, transpose: GLboolean, value: *const GLfloat) {
+    info!(
+        "glUniformMatrix3fv(location = {}, count = {}, transpose = {}, value = {:?})",
+        location, count, transpose, value
+    );
+
+    set_uniform_matrix(location, count, transpose, 3, value);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glUniformMatrix4fv(location: GLint, count: GLsizei, transpose: GLboolean, value: *const GLfloat) {
+    info!(
+        "glUniformMatrix4fv(location = {}, count = {}, transpose = {}, value = {:?})",
+        location, count, transpose, value
+    );
+
+    set_uniform_matrix(location, count, transpose, 4, value);
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::*;
+    use program::tests::link_program;
+    use program::{glGetProgramiv, glUseProgram};
+    use std::ptr;
+
+    fn location(program: GLuint, name: &str) -> GLint {
+        let name = format!("{}\0", name);
+        glGetUniformLocation(program, name.as_ptr() as *const GLchar)
+    }
+
+    fn uniform_data(program: GLuint) -> Vec<u32> {
+        let mut pool_guard = HUB.program_pool.lock();
+        let data = &pool_guard.get_object_mut(program).linked.as_ref().unwrap().uniforms.data;
+        data.chunks(4)
+            .map(|bytes| bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32))
+            .collect()
+    }
+
+    #[test]
+    fn test_uniforms() {
+        let _guard = context::TEST_LOCK.lock();
+
+        let program = link_program(
+            "
+            struct Light { vec3 color; bool enabled; };
+            uniform Light u_lights[2];
+            uniform mat2 u_matrix;
+            uniform float u_unused;
+            void main() { gl_Position = vec4(u_lights[1].color * u_matrix[1].y, float(u_lights[0].enabled)); }
+            ",
+            "
+            precision mediump float;
+            uniform float u_values[3];
+            uniform sampler2D u_texture;
+            void main() { gl_FragColor = texture2D(u_texture, vec2(u_values[2])); }
+            ",
+        );
+        let mut count = 0;
+        glGetProgramiv(program, GL_ACTIVE_UNIFORMS, &mut count);
+        assert_eq!(count, 7);
+
+        let mut name = [0 as GLchar; 32];
+        let (mut size, mut type_) = (0, 0);
+        glGetActiveUniform(program, 5, 32, ptr::null_mut(), &mut size, &mut type_, name.as_mut_ptr());
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) }.to_str().unwrap();
+        assert_eq!((name, size, type_), ("u_values[0]", 3, GL_FLOAT));
+
+        assert_eq!(location(program, "u_lights[1].color"), 2);
+        assert_eq!(location(program, "u_values[2]"), 7);
+        assert_eq!(location(program, "u_values"), 5);
+        assert_eq!(location(program, "u_matrix[0]"), -1);
+        assert_eq!(location(program, "u_unused"), -1);
+
+        glUseProgram(program);
+        glUniform3f(location(program, "u_lights[1].color"), 1.0, 2.0, 3.0);
+        glUniform1i(location(program, "u_lights[0].enabled"), 5);
+        glUniformMatrix2fv(location(program, "u_matrix"), 1, GL_FALSE, [1.0f32, 2.0, 3.0, 4.0].as_ptr());
+        glUniform1fv(location(program, "u_values[1]"), 3, [5.0f32, 6.0, 7.0].as_ptr());
+        // mismatched types are rejected
+        glUniform1i(location(program, "u_values"), 1);
+        glUniform1f(location(program, "u_texture"), 1.0);
+        glUniform1i(location(program, "u_texture"), 3);
+        // so are texture units outside of the implementation's range
+        glUniform1i(location(program, "u_texture"), -1);
+        glUseProgram(0);
+
+        let data = uniform_data(program);
+        let float = |value: f32| value.to_bits();
+        assert_eq!(&data[..4], &[0, 0, 0, 1]);
+        assert_eq!(&data[4..7], &[float(1.0), float(2.0), float(3.0)]);
+        assert_eq!(&data[8..10], &[float(1.0), float(2.0)]);
+        assert_eq!(&data[12..14], &[float(3.0), float(4.0)]);
+        assert_eq!(&data[20..], &[0, 0, 0, 0, float(5.0), 0, 0, 0, float(6.0), 0, 0, 0]);
+
+        let mut pool_guard = HUB.program_pool.lock();
+        let object = pool_guard.get_object_mut(program);
+        assert_eq!(object.linked.as_ref().unwrap().uniforms.sampler_units[&1], vec![3]);
+        let mut upload = UploadBuffer::default();
+        upload.push(&[0], 1);
+        assert_eq!(object.upload_uniforms(&mut upload), Some(256));
+        assert!(!object.update_descriptor_sets);
+        assert_eq!(upload.data.len(), 256 + 128);
+        assert_eq!(object.upload_uniforms(&mut upload), Some(256));
+        upload.reset();
+        assert_eq!(object.upload_uniforms(&mut upload), Some(0));
+    }
+}
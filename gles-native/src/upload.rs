@@ -0,0 +1,215 @@
+use std::ops::Range;
+use std::slice;
+
+use gl_sys::{GLenum, GLuint, GL_BYTE, GL_FIXED, GL_FLOAT, GL_SHORT, GL_UNSIGNED_BYTE, GL_UNSIGNED_SHORT};
+
+use vertex_attribute::VertexAttribute;
+
+/// Transient buffer client data is copied into, reset once the commands using it completed
+#[derive(Debug, Default)]
+pub(crate) struct UploadBuffer {
+    pub data: Vec<u8>,
+    /// Incremented on every reset, offsets taken before are stale
+    pub generation: u64,
+}
+
+impl UploadBuffer {
+    /// Append `bytes` aligned to `alignment`, returning their offset
+    pub fn push(&mut self, bytes: &[u8], alignment: usize) -> usize {
+        let offset = (self.data.len() + alignment - 1) / alignment * alignment;
+        self.data.resize(offset, 0);
+        self.data.extend_from_slice(bytes);
+        offset
+    }
+
+    /// Append `count` elements of `element_size` bytes read every `stride` bytes from `pointer`
+    pub fn push_strided(&mut self, pointer: *const u8, element_size: usize, stride: usize, count: usize) -> usize {
+        if stride == element_size {
+            let bytes = unsafe { slice::from_raw_parts(pointer, element_size * count) };
+            return self.push(bytes, 4);
+        }
+
+        let offset = self.push(&[], 4);
+        self.data.reserve(element_size * count);
+        for index in 0..count {
+            let element = unsafe { slice::from_raw_parts(pointer.add(index * stride), element_size) };
+            self.data.extend_from_slice(element);
+        }
+        offset
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        self.data.clear();
+        self.generation += 1;
+    }
+}
+
+/// Where the data of a vertex or index binding lives
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum DataSource {
+    Buffer(GLuint),
+    Upload,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct VertexBinding {
+    pub location: GLuint,
+    pub source: DataSource,
+    pub offset: usize,
+    /// 0 for constant attributes
+    pub stride: usize,
+}
+
+/// Vertex buffers of a draw, rebased so that vertex `first_vertex` is at the start of each binding
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct VertexInput {
+    pub bindings: Vec<VertexBinding>,
+    pub first_vertex: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct IndexData {
+    pub source: DataSource,
+    pub offset: usize,
+    pub type_: GLenum,
+}
+
+#[inline]
+pub(crate) fn index_size(type_: GLenum) -> usize {
+    match type_ {
+        GL_UNSIGNED_BYTE => 1,
+        GL_UNSIGNED_SHORT => 2,
+        _ => 4,
+    }
+}
+
+#[inline]
+pub(crate) fn component_size(type_: GLenum) -> usize {
+    match type_ {
+        GL_BYTE | GL_UNSIGNED_BYTE => 1,
+        GL_SHORT | GL_UNSIGNED_SHORT => 2,
+        GL_FIXED | GL_FLOAT => 4,
+        _ => unreachable!(),
+    }
+}
+
+/// Smallest and largest index of `indices`, the range of vertices they reference
+pub(crate) fn index_range(type_: GLenum, indices: &[u8]) -> Range<u32> {
+    let (min, max) = if type_ == GL_UNSIGNED_BYTE {
+        indices.iter().fold((u32::max_value(), 0), |(min, max), &index| {
+            (min.min(index as u32), max.max(index as u32))
+        })
+    } else {
+        indices.chunks(2).fold((u32::max_value(), 0), |(min, max), index| {
+            let index = index[0] as u32 | (index[1] as u32) << 8;
+            (min.min(index), max.max(index))
+        })
+    };
+    if min > max {
+        0..0
+    } else {
+        min..max + 1
+    }
+}
+
+/// Bind the vertex data of `locations` for the vertices in `range`, copying client arrays and the values of
+/// disabled arrays to `upload`
+pub(crate) fn prepare_vertices(
+    attributes: &[VertexAttribute],
+    locations: &[GLuint],
+    range: Range<u32>,
+    upload: &mut UploadBuffer,
+) -> VertexInput {
+    let count = (range.end - range.start) as usize;
+    let bindings = locations
+        .iter()
+        .map(|&location| {
+            let attribute = &attributes[location as usize];
+            if !attribute.is_enabled {
+                let value = unsafe { slice::from_raw_parts(attribute.current_value.as_ptr() as *const u8, 16) };
+                return VertexBinding {
+                    location,
+                    source: DataSource::Upload,
+                    offset: upload.push(value, 4),
+                    stride: 0,
+                };
+            }
+
+            let stride = attribute.effective_stride();
+            let start = attribute.pointer + range.start as usize * stride;
+            if attribute.buffer != 0 {
+                VertexBinding {
+                    location,
+                    source: DataSource::Buffer(attribute.buffer),
+                    offset: start,
+                    stride,
+                }
+            } else {
+                let element_size = attribute.size as usize * component_size(attribute.type_);
+                VertexBinding {
+                    location,
+                    source: DataSource::Upload,
+                    offset: upload.push_strided(start as *const u8, element_size, stride, count),
+                    stride: element_size,
+                }
+            }
+        })
+        .collect();
+
+    VertexInput {
+        bindings,
+        first_vertex: range.start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_arrays() {
+        let indices = [7u16, 5, 6, 5];
+        let bytes = unsafe { slice::from_raw_parts(indices.as_ptr() as *const u8, 8) };
+        assert_eq!(index_range(GL_UNSIGNED_SHORT, bytes), 5..8);
+        assert_eq!(index_range(GL_UNSIGNED_BYTE, &[3, 1, 2]), 1..4);
+
+        // interleaved positions and colors, only the color array is client memory
+        let vertices = (0..8u8).flat_map(|index| vec![index; 12]).collect::<Vec<_>>();
+        let mut attributes = [VertexAttribute::default(); 3];
+        attributes[0] = VertexAttribute {
+            is_enabled: true,
+            size: 3,
+            type_: GL_FLOAT,
+            stride: 0,
+            buffer: 1,
+            ..VertexAttribute::default()
+        };
+        attributes[1] = VertexAttribute {
+            is_enabled: true,
+            size: 4,
+            type_: GL_UNSIGNED_BYTE,
+            stride: 12,
+            pointer: vertices.as_ptr() as usize + 4,
+            ..VertexAttribute::default()
+        };
+
+        let mut upload = UploadBuffer::default();
+        upload.push(&[1], 1);
+        let input = prepare_vertices(&attributes, &[0, 1, 2], 5..8, &mut upload);
+        assert_eq!(input.first_vertex, 5);
+        assert_eq!(
+            input.bindings[0],
+            VertexBinding {
+                location: 0,
+                source: DataSource::Buffer(1),
+                offset: 60,
+                stride: 12
+            }
+        );
+        assert_eq!((input.bindings[1].offset, input.bindings[1].stride), (4, 4));
+        assert_eq!(&upload.data[4..16], &[5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 7]);
+        assert_eq!((input.bindings[2].offset, input.bindings[2].stride), (16, 0));
+        assert_eq!(&upload.data[28..32], &[0, 0, 0x80, 0x3f]);
+    }
+}
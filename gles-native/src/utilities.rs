@@ -1,9 +1,18 @@
+use std::slice;
+
 use gl_sys::{
-    GLboolean, GLenum, GLubyte, GL_EXTENSIONS, GL_FALSE, GL_INVALID_ENUM, GL_NO_ERROR, GL_RENDERER, GL_SHADING_LANGUAGE_VERSION,
-    GL_TRUE, GL_VENDOR, GL_VERSION,
+    GLboolean, GLclampf, GLenum, GLfloat, GLint, GLubyte, GLuint, GL_EXTENSIONS, GL_FALSE, GL_INVALID_ENUM, GL_NO_ERROR,
+    GL_RENDERER, GL_SHADING_LANGUAGE_VERSION, GL_TRUE, GL_VENDOR, GL_VERSION,
 };
 
-use context::{self, HUB};
+use active_object;
+use context::{self, AsGlBoolValue, HUB};
+use depth_stencil_state;
+use fragment_state;
+use limits;
+use pixel_operations;
+use rasterization_state;
+use viewport_transformation;
 
 #[derive(Debug)]
 pub(crate) struct Error {
@@ -16,6 +25,21 @@ impl Default for Error {
     }
 }
 
+/// Implementation hints, `GL_GENERATE_MIPMAP_HINT` is the only hint of OpenGL ES 2.0
+#[derive(Debug)]
+pub(crate) struct Hints {
+    pub generate_mipmap: GLenum,
+}
+
+impl Default for Hints {
+    fn default() -> Self {
+        use gl_sys::GL_DONT_CARE;
+        Self {
+            generate_mipmap: GL_DONT_CARE,
+        }
+    }
+}
+
 // todo refactor to record_error(err, msg)?
 
 //#[cfg(feature = "Release")]
@@ -36,6 +60,267 @@ pub(crate) fn record_error(error: GLenum) {
 //    }
 //}
 
+/// Context state in its natural type, converted by `glGet*v` following the GL conversion rules
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StateValue {
+    Booleans(Vec<bool>),
+    Integers(Vec<GLint>),
+    Floats(Vec<GLfloat>),
+    /// Colors and depth values, `glGetIntegerv` maps [-1, 1] linearly onto the whole integer range
+    NormalizedFloats(Vec<GLfloat>),
+}
+
+impl StateValue {
+    pub fn to_booleans(&self) -> Vec<GLboolean> {
+        match *self {
+            StateValue::Booleans(ref values) => values.iter().map(|value| value.as_gl_bool_value()).collect(),
+            StateValue::Integers(ref values) => values.iter().map(|&value| (value != 0).as_gl_bool_value()).collect(),
+            StateValue::Floats(ref values) | StateValue::NormalizedFloats(ref values) => {
+                values.iter().map(|&value| (value != 0.0).as_gl_bool_value()).collect()
+            }
+        }
+    }
+
+    pub fn to_integers(&self) -> Vec<GLint> {
+        match *self {
+            StateValue::Booleans(ref values) => values.iter().map(|&value| value as GLint).collect(),
+            StateValue::Integers(ref values) => values.clone(),
+            StateValue::Floats(ref values) => values.iter().map(|&value| value.round() as GLint).collect(),
+            StateValue::NormalizedFloats(ref values) => values
+                .iter()
+                .map(|&value| {
+                    let value = (value.max(-1.0).min(1.0) as f64 * 4294967295.0 - 1.0) / 2.0;
+                    // round halves up, so 0.0 maps to 0 rather than -1
+                    (value + 0.5).floor() as GLint
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_floats(&self) -> Vec<GLfloat> {
+        match *self {
+            StateValue::Booleans(ref values) => values.iter().map(|&value| if value { 1.0 } else { 0.0 }).collect(),
+            StateValue::Integers(ref values) => values.iter().map(|&value| value as GLfloat).collect(),
+            StateValue::Floats(ref values) | StateValue::NormalizedFloats(ref values) => values.clone(),
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn clamp_to_unit_range(value: GLclampf) -> GLclampf {
+    value.max(0.0).min(1.0)
+}
+
+pub(crate) fn query_state(pname: GLenum) -> Option<StateValue> {
+    use gl_sys::{
+        GL_ACTIVE_TEXTURE, GL_ALPHA_BITS, GL_ARRAY_BUFFER, GL_ARRAY_BUFFER_BINDING, GL_BLUE_BITS, GL_COLOR_CLEAR_VALUE,
+        GL_COMPRESSED_TEXTURE_FORMATS, GL_CULL_FACE_MODE, GL_CURRENT_PROGRAM, GL_DEPTH_BITS, GL_DEPTH_CLEAR_VALUE,
+        GL_ELEMENT_ARRAY_BUFFER, GL_ELEMENT_ARRAY_BUFFER_BINDING, GL_FRAMEBUFFER_BINDING, GL_FRONT_FACE, GL_GENERATE_MIPMAP_HINT,
+        GL_GREEN_BITS, GL_IMPLEMENTATION_COLOR_READ_FORMAT, GL_IMPLEMENTATION_COLOR_READ_TYPE, GL_LINE_WIDTH,
+        GL_NUM_COMPRESSED_TEXTURE_FORMATS, GL_NUM_SHADER_BINARY_FORMATS, GL_POLYGON_OFFSET_FACTOR, GL_POLYGON_OFFSET_UNITS,
+        GL_RED_BITS, GL_RENDERBUFFER_BINDING, GL_RGBA, GL_SAMPLES, GL_SAMPLE_BUFFERS, GL_SHADER_BINARY_FORMATS,
+        GL_SHADER_COMPILER, GL_STENCIL_BITS, GL_STENCIL_CLEAR_VALUE, GL_SUBPIXEL_BITS, GL_TEXTURE_2D, GL_TEXTURE_BINDING_2D,
+        GL_TEXTURE_BINDING_CUBE_MAP, GL_TEXTURE_CUBE_MAP, GL_UNSIGNED_BYTE,
+    };
+
+    if let Some(is_enabled) = is_capability_enabled(pname) {
+        return Some(StateValue::Booleans(vec![is_enabled]));
+    }
+    if let Some(value) = depth_stencil_state::query_state(pname)
+        .or_else(|| fragment_state::query_state(pname))
+        .or_else(|| viewport_transformation::query_state(pname))
+        .or_else(|| pixel_operations::query_state(pname))
+        .or_else(|| limits::query_state(pname))
+    {
+        return Some(value);
+    }
+
+    let name = |name: GLuint| StateValue::Integers(vec![name as GLint]);
+    let integer = |value: GLint| StateValue::Integers(vec![value]);
+    let value = match pname {
+        GL_ACTIVE_TEXTURE => integer(HUB.active_texture_unit.lock().raw as GLint),
+        GL_ARRAY_BUFFER_BINDING => name(context::get_active_buffer(GL_ARRAY_BUFFER).name),
+        GL_ELEMENT_ARRAY_BUFFER_BINDING => name(context::get_active_buffer(GL_ELEMENT_ARRAY_BUFFER).name),
+        GL_TEXTURE_BINDING_2D => name(context::get_active_texture(GL_TEXTURE_2D).name),
+        GL_TEXTURE_BINDING_CUBE_MAP => name(context::get_active_texture(GL_TEXTURE_CUBE_MAP).name),
+        GL_FRAMEBUFFER_BINDING => name(HUB.active_frame_buffer.lock().name),
+        GL_RENDERBUFFER_BINDING => name(context::get_active_render_buffer().name),
+        GL_CURRENT_PROGRAM => name(HUB.active_program.lock().name),
+        GL_COLOR_CLEAR_VALUE => StateValue::NormalizedFloats(HUB.clear_values.lock().color.to_vec()),
+        GL_DEPTH_CLEAR_VALUE => StateValue::NormalizedFloats(vec![HUB.clear_values.lock().depth]),
+        GL_STENCIL_CLEAR_VALUE => integer(HUB.clear_values.lock().stencil),
+        GL_CULL_FACE_MODE => integer(HUB.rasterization_state.lock().cull_face as GLint),
+        GL_FRONT_FACE => integer(HUB.rasterization_state.lock().front_face as GLint),
+        GL_LINE_WIDTH => StateValue::Floats(vec![HUB.rasterization_state.lock().line_width]),
+        GL_POLYGON_OFFSET_FACTOR => StateValue::Floats(vec![HUB.rasterization_state.lock().polygon_offset_factor]),
+        GL_POLYGON_OFFSET_UNITS => StateValue::Floats(vec![HUB.rasterization_state.lock().polygon_offset_units]),
+        GL_GENERATE_MIPMAP_HINT => integer(HUB.hints.lock().generate_mipmap as GLint),
+        GL_RED_BITS | GL_GREEN_BITS | GL_BLUE_BITS | GL_ALPHA_BITS | GL_DEPTH_BITS | GL_STENCIL_BITS => {
+            let mut active_object = context::get_active_frame_buffer();
+            let bits = active_object::get_object_mut(&mut active_object).unwrap().format_bits();
+            integer(match pname {
+                GL_RED_BITS => bits.red,
+                GL_GREEN_BITS => bits.green,
+                GL_BLUE_BITS => bits.blue,
+                GL_ALPHA_BITS => bits.alpha,
+                GL_DEPTH_BITS => bits.depth,
+                _ => bits.stencil,
+            })
+        }
+        // frame buffers are single sampled, with 4 bits of sub-pixel precision as GL requires at least
+        GL_SUBPIXEL_BITS => integer(4),
+        GL_SAMPLE_BUFFERS | GL_SAMPLES => integer(0),
+        GL_IMPLEMENTATION_COLOR_READ_FORMAT => integer(GL_RGBA as GLint),
+        GL_IMPLEMENTATION_COLOR_READ_TYPE => integer(GL_UNSIGNED_BYTE as GLint),
+        GL_SHADER_COMPILER => StateValue::Booleans(vec![true]),
+        GL_NUM_COMPRESSED_TEXTURE_FORMATS | GL_NUM_SHADER_BINARY_FORMATS => integer(0),
+        GL_COMPRESSED_TEXTURE_FORMATS | GL_SHADER_BINARY_FORMATS => StateValue::Integers(Vec::new()),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// State owning each OpenGL ES 2.0 capability
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CapabilityState {
+    Rasterization,
+    DepthStencil,
+    Fragment,
+    Viewport,
+}
+
+fn capability_state(cap: GLenum) -> Option<CapabilityState> {
+    use gl_sys::{
+        GL_BLEND, GL_CULL_FACE, GL_DEPTH_TEST, GL_DITHER, GL_POLYGON_OFFSET_FILL, GL_SAMPLE_ALPHA_TO_COVERAGE,
+        GL_SAMPLE_COVERAGE, GL_SCISSOR_TEST, GL_STENCIL_TEST,
+    };
+
+    match cap {
+        GL_CULL_FACE | GL_POLYGON_OFFSET_FILL => Some(CapabilityState::Rasterization),
+        GL_DEPTH_TEST | GL_STENCIL_TEST => Some(CapabilityState::DepthStencil),
+        GL_BLEND | GL_DITHER | GL_SAMPLE_ALPHA_TO_COVERAGE | GL_SAMPLE_COVERAGE => Some(CapabilityState::Fragment),
+        GL_SCISSOR_TEST => Some(CapabilityState::Viewport),
+        _ => None,
+    }
+}
+
+/// Value of capability `cap`, `None` if `cap` is not a capability
+fn is_capability_enabled(cap: GLenum) -> Option<bool> {
+    let is_enabled = match capability_state(cap)? {
+        CapabilityState::Rasterization => rasterization_state::is_capability_enabled(cap),
+        CapabilityState::DepthStencil => depth_stencil_state::is_capability_enabled(cap),
+        CapabilityState::Fragment => fragment_state::is_capability_enabled(cap),
+        CapabilityState::Viewport => viewport_transformation::is_capability_enabled(cap),
+    };
+    Some(is_enabled)
+}
+
+/// Enable or disable `cap`, only the piece of pipeline state it belongs to is marked dirty
+fn set_capability(cap: GLenum, is_enabled: bool) {
+    match capability_state(cap) {
+        Some(CapabilityState::Rasterization) => rasterization_state::set_capability(cap, is_enabled),
+        Some(CapabilityState::DepthStencil) => depth_stencil_state::set_capability(cap, is_enabled),
+        Some(CapabilityState::Fragment) => fragment_state::set_capability(cap, is_enabled),
+        Some(CapabilityState::Viewport) => viewport_transformation::set_capability(cap, is_enabled),
+        None => {
+            error!("invalid capability: {}", cap);
+            record_error(GL_INVALID_ENUM);
+        }
+    }
+}
+
+fn get_state<T, CVT>(pname: GLenum, params: *mut T, convert: CVT)
+where
+    T: Copy,
+    CVT: Fn(&StateValue) -> Vec<T>,
+{
+    if context::is_nullptr(params, "params is nullptr") {
+        return;
+    }
+
+    match query_state(pname) {
+        Some(value) => {
+            let values = convert(&value);
+            let params = unsafe { slice::from_raw_parts_mut(params, values.len()) };
+            params.copy_from_slice(&values);
+        }
+        None => {
+            error!("invalid pname: {}", pname);
+            record_error(GL_INVALID_ENUM);
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetBooleanv(pname: GLenum, params: *mut GLboolean) {
+    info!("glGetBooleanv(pname = {:?}, params = {:p})", pname, params);
+
+    get_state(pname, params, StateValue::to_booleans);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetFloatv(pname: GLenum, params: *mut GLfloat) {
+    info!("glGetFloatv(pname = {:?}, params = {:p})", pname, params);
+
+    get_state(pname, params, StateValue::to_floats);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetIntegerv(pname: GLenum, params: *mut GLint) {
+    info!("glGetIntegerv(pname = {:?}, params = {:p})", pname, params);
+
+    get_state(pname, params, StateValue::to_integers);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glEnable(cap: GLenum) {
+    info!("glEnable(cap = {:?})", cap);
+
+    set_capability(cap, true);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glDisable(cap: GLenum) {
+    info!("glDisable(cap = {:?})", cap);
+
+    set_capability(cap, false);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glIsEnabled(cap: GLenum) -> GLboolean {
+    info!("glIsEnabled(cap = {:?})", cap);
+
+    match is_capability_enabled(cap) {
+        Some(is_enabled) => is_enabled.as_gl_bool_value(),
+        None => {
+            error!("invalid capability: {}", cap);
+            record_error(GL_INVALID_ENUM);
+            GL_FALSE
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glHint(target: GLenum, mode: GLenum) {
+    use gl_sys::{GL_DONT_CARE, GL_FASTEST, GL_GENERATE_MIPMAP_HINT, GL_NICEST};
+
+    info!("glHint(target = {:?}, mode = {:?})", target, mode);
+
+    if !context::validate_invalid_enum(target, &[GL_GENERATE_MIPMAP_HINT], "invalid hint target")
+        || !context::validate_invalid_enum(mode, &[GL_FASTEST, GL_NICEST, GL_DONT_CARE], "invalid hint mode")
+    {
+        return;
+    }
+    HUB.hints.lock().generate_mipmap = mode;
+}
+
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "C" fn glGetError() -> GLenum {
@@ -57,7 +342,7 @@ pub extern "C" fn glGetString(name: GLenum) -> *const GLubyte {
         "OpenGL ES 2.0 Over Hood\0",
         "OpenGL ES 2.0\0",
         "OpenGL ES GLSL ES 1.00\0",
-        "GL_OES_get_program_binary\0",
+        "GL_OES_get_program_binary GL_OES_packed_depth_stencil GL_OES_depth24 GL_OES_depth32 GL_EXT_blend_minmax\0",
     ];
 
     match name {
@@ -74,3 +359,56 @@ pub extern "C" fn glGetString(name: GLenum) -> *const GLubyte {
     }
     .as_ptr()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::{
+        GLuint, GL_ACTIVE_TEXTURE, GL_FASTEST, GL_GENERATE_MIPMAP_HINT, GL_RED_BITS, GL_TEXTURE0, GL_TEXTURE3, GL_TEXTURE_2D,
+        GL_TEXTURE_BINDING_2D, GL_UNPACK_ALIGNMENT,
+    };
+    use pixel_operations::glPixelStorei;
+    use texture::{glActiveTexture, glBindTexture, glDeleteTextures, glGenTextures};
+
+    fn get_integer(pname: GLenum) -> GLint {
+        let mut value = 0;
+        glGetIntegerv(pname, &mut value);
+        value
+    }
+
+    #[test]
+    fn test_query_state() {
+        let _guard = context::TEST_LOCK.lock();
+        let mut texture = 0 as GLuint;
+        glGenTextures(1, &mut texture);
+        let unit0_texture = get_integer(GL_TEXTURE_BINDING_2D);
+        glActiveTexture(GL_TEXTURE3);
+        glBindTexture(GL_TEXTURE_2D, texture);
+        assert_eq!(get_integer(GL_ACTIVE_TEXTURE), GL_TEXTURE3 as GLint);
+        assert_eq!(get_integer(GL_TEXTURE_BINDING_2D), texture as GLint);
+        glActiveTexture(GL_TEXTURE0);
+        assert_eq!(get_integer(GL_TEXTURE_BINDING_2D), unit0_texture);
+
+        glPixelStorei(GL_UNPACK_ALIGNMENT, 1);
+        let mut is_set = GL_FALSE;
+        glGetBooleanv(GL_UNPACK_ALIGNMENT, &mut is_set);
+        assert_eq!(is_set, GL_TRUE);
+        let mut alignment = 0.0;
+        glGetFloatv(GL_UNPACK_ALIGNMENT, &mut alignment);
+        assert_eq!(alignment, 1.0);
+
+        // normalized values map onto the whole integer range
+        assert_eq!(
+            StateValue::NormalizedFloats(vec![1.0, -1.0]).to_integers(),
+            vec![GLint::max_value(), GLint::min_value()]
+        );
+        assert_eq!(get_integer(GL_RED_BITS), 8);
+
+        glHint(GL_GENERATE_MIPMAP_HINT, GL_FASTEST);
+        assert_eq!(get_integer(GL_GENERATE_MIPMAP_HINT), GL_FASTEST as GLint);
+
+        glHint(GL_GENERATE_MIPMAP_HINT, Hints::default().generate_mipmap);
+        glPixelStorei(GL_UNPACK_ALIGNMENT, 4);
+        glDeleteTextures(1, &texture);
+    }
+}
@@ -0,0 +1,513 @@
+use std::ffi::CStr;
+use std::slice;
+
+use gl_sys::{
+    GLboolean, GLchar, GLenum, GLfloat, GLint, GLsizei, GLuint, GLvoid, GL_ARRAY_BUFFER, GL_BYTE, GL_FALSE, GL_FIXED, GL_FLOAT,
+    GL_FLOAT_MAT2, GL_FLOAT_MAT3, GL_FLOAT_MAT4, GL_FLOAT_VEC2, GL_FLOAT_VEC3, GL_FLOAT_VEC4, GL_INVALID_OPERATION,
+    GL_INVALID_VALUE, GL_SHORT, GL_UNSIGNED_BYTE, GL_UNSIGNED_SHORT,
+};
+
+use context::{self, is_nullptr, AsGlBoolValue, HUB};
+use glsl::{BaseType, Symbol};
+use limits::LIMITS;
+use program::{self, Program};
+use utilities::record_error;
+
+/// Number of generic vertex attributes the context stores, Vulkan guarantees at least 16 vertex input attributes.
+/// `GL_MAX_VERTEX_ATTRIBS` may be lower on adapters with fewer.
+pub(crate) const MAX_VERTEX_ATTRIBS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct VertexAttribute {
+    pub is_enabled: bool,
+    pub size: GLint,
+    pub type_: GLenum,
+    pub normalized: bool,
+    pub stride: GLsizei,
+    /// Offset into `buffer`, or the address of client memory if `buffer` is 0
+    pub pointer: usize,
+    /// `GL_ARRAY_BUFFER` binding when the pointer was set
+    pub buffer: GLuint,
+    /// Value used when the array is disabled
+    pub current_value: [GLfloat; 4],
+}
+
+impl Default for VertexAttribute {
+    fn default() -> Self {
+        Self {
+            is_enabled: false,
+            size: 4,
+            type_: GL_FLOAT,
+            normalized: false,
+            stride: 0,
+            pointer: 0,
+            buffer: 0,
+            current_value: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+impl VertexAttribute {
+    /// Bytes between consecutive elements, a stride of 0 means tightly packed
+    pub fn effective_stride(&self) -> usize {
+        if self.stride != 0 {
+            return self.stride as usize;
+        }
+        let component_size = match self.type_ {
+            GL_BYTE | GL_UNSIGNED_BYTE => 1,
+            GL_SHORT | GL_UNSIGNED_SHORT => 2,
+            _ => 4,
+        };
+        self.size as usize * component_size
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct VertexAttributeState {
+    pub attributes: [VertexAttribute; MAX_VERTEX_ATTRIBS],
+}
+
+fn validate_index(index: GLuint) -> bool {
+    if index >= LIMITS.max_vertex_attribs as GLuint {
+        error!("index {} is not less than GL_MAX_VERTEX_ATTRIBS", index);
+        record_error(GL_INVALID_VALUE);
+        return false;
+    }
+    true
+}
+
+fn attribute_type(symbol: &Symbol) -> GLenum {
+    match symbol.ty.base {
+        BaseType::Vector(_, 2) => GL_FLOAT_VEC2,
+        BaseType::Vector(_, 3) => GL_FLOAT_VEC3,
+        BaseType::Vector(..) => GL_FLOAT_VEC4,
+        BaseType::Matrix(2) => GL_FLOAT_MAT2,
+        BaseType::Matrix(3) => GL_FLOAT_MAT3,
+        BaseType::Matrix(_) => GL_FLOAT_MAT4,
+        _ => GL_FLOAT,
+    }
+}
+
+fn set_current_value(index: GLuint, value: [GLfloat; 4]) {
+    if !validate_index(index) {
+        return;
+    }
+    HUB.vertex_attribute_state.lock().attributes[index as usize].current_value = value;
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glBindAttribLocation(program: GLuint, index: GLuint, name: *const GLchar) {
+    info!(
+        "glBindAttribLocation(program = {}, index = {}, name = {:?})",
+        program, index, name
+    );
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !validate_index(index)
+        || !program::validate_program_name(program, &program_pool_guard, &shader_pool_guard)
+        || is_nullptr(name as *mut GLchar, "name is nullptr")
+    {
+        return;
+    }
+
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    if name.starts_with("gl_") {
+        error!("{} is a reserved name", name);
+        record_error(GL_INVALID_OPERATION);
+        return;
+    }
+    // takes effect on the next link
+    program_pool_guard
+        .get_object_mut(program)
+        .attribute_bindings
+        .insert(name, index);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glDisableVertexAttribArray(index: GLuint) {
+    info!("glDisableVertexAttribArray(index = {})", index);
+
+    if validate_index(index) {
+        HUB.vertex_attribute_state.lock().attributes[index as usize].is_enabled = false;
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glEnableVertexAttribArray(index: GLuint) {
+    info!("glEnableVertexAttribArray(index = {})", index);
+
+    if validate_index(index) {
+        HUB.vertex_attribute_state.lock().attributes[index as usize].is_enabled = true;
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetActiveAttrib(
+    program: GLuint,
+    index: GLuint,
+    bufsize: GLsizei,
+    length: *mut GLsizei,
+    size: *mut GLint,
+    type_: *mut GLenum,
+    name: *mut GLchar,
+) {
+    info!(
+        "glGetActiveAttrib(program = {}, index = {}, bufsize = {}, length = {:?}, size = {:?}, type = {:?}, name = {:?})",
+        program, index, bufsize, length, size, type_, name
+    );
+
+    if bufsize < 0 {
+        error!("invalid bufsize {}", bufsize);
+        record_error(GL_INVALID_VALUE);
+        return;
+    }
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !program::validate_linked_program(program, &mut program_pool_guard, &shader_pool_guard) {
+        return;
+    }
+
+    let object = program_pool_guard.get_object_mut(program);
+    let symbol = object.linked.as_ref().and_then(|linked| {
+        linked
+            .vertex
+            .attributes()
+            .filter(|&(_, symbol)| symbol.is_used)
+            .nth(index as usize)
+            .map(|(_, symbol)| symbol)
+    });
+    let symbol = match symbol {
+        Some(symbol) => symbol,
+        None => {
+            error!("invalid attribute index {}", index);
+            record_error(GL_INVALID_VALUE);
+            return;
+        }
+    };
+
+    context::copy_to_gl_string(&symbol.name, bufsize, length, name);
+    if !size.is_null() {
+        unsafe { *size = 1 };
+    }
+    if !type_.is_null() {
+        unsafe { *type_ = attribute_type(symbol) };
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetAttribLocation(program: GLuint, name: *const GLchar) -> GLint {
+    info!("glGetAttribLocation(program = {}, name = {:?})", program, name);
+
+    let shader_pool_guard = HUB.shader_pool.lock();
+    let mut program_pool_guard = HUB.program_pool.lock();
+    if !program::validate_linked_program(program, &mut program_pool_guard, &shader_pool_guard)
+        || is_nullptr(name as *mut GLchar, "name is nullptr")
+    {
+        return -1;
+    }
+
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    let linked = match program_pool_guard.get_object_mut(program).linked {
+        Some(ref linked) => linked,
+        None => return -1,
+    };
+    let is_active = linked
+        .vertex
+        .attributes()
+        .any(|(_, symbol)| symbol.name == name && symbol.is_used);
+    match linked.layout.attribute_locations.get(&*name) {
+        Some(&location) if is_active => location as GLint,
+        _ => -1,
+    }
+}
+
+/// Query a vertex attribute parameter as up to 4 floats
+fn vertex_attribute_parameter(index: GLuint, pname: GLenum) -> Option<[GLfloat; 4]> {
+    use gl_sys::{
+        GL_CURRENT_VERTEX_ATTRIB, GL_VERTEX_ATTRIB_ARRAY_BUFFER_BINDING, GL_VERTEX_ATTRIB_ARRAY_ENABLED,
+        GL_VERTEX_ATTRIB_ARRAY_NORMALIZED, GL_VERTEX_ATTRIB_ARRAY_SIZE, GL_VERTEX_ATTRIB_ARRAY_STRIDE,
+        GL_VERTEX_ATTRIB_ARRAY_TYPE,
+    };
+
+    if !validate_index(index)
+        || !context::validate_pname(
+            pname,
+            &[
+                GL_VERTEX_ATTRIB_ARRAY_BUFFER_BINDING,
+                GL_VERTEX_ATTRIB_ARRAY_ENABLED,
+                GL_VERTEX_ATTRIB_ARRAY_SIZE,
+                GL_VERTEX_ATTRIB_ARRAY_STRIDE,
+                GL_VERTEX_ATTRIB_ARRAY_TYPE,
+                GL_VERTEX_ATTRIB_ARRAY_NORMALIZED,
+                GL_CURRENT_VERTEX_ATTRIB,
+            ],
+        )
+    {
+        return None;
+    }
+
+    let attribute = HUB.vertex_attribute_state.lock().attributes[index as usize];
+    let value = match pname {
+        GL_VERTEX_ATTRIB_ARRAY_BUFFER_BINDING => attribute.buffer as GLfloat,
+        GL_VERTEX_ATTRIB_ARRAY_ENABLED => attribute.is_enabled.as_gl_bool_value() as GLfloat,
+        GL_VERTEX_ATTRIB_ARRAY_SIZE => attribute.size as GLfloat,
+        GL_VERTEX_ATTRIB_ARRAY_STRIDE => attribute.stride as GLfloat,
+        GL_VERTEX_ATTRIB_ARRAY_TYPE => attribute.type_ as GLfloat,
+        GL_VERTEX_ATTRIB_ARRAY_NORMALIZED => attribute.normalized.as_gl_bool_value() as GLfloat,
+        GL_CURRENT_VERTEX_ATTRIB => return Some(attribute.current_value),
+        _ => unreachable!(),
+    };
+    Some([value, 0.0, 0.0, 0.0])
+}
+
+/// Number of values written by `glGetVertexAttrib*` for `pname`
+#[inline]
+fn parameter_count(pname: GLenum) -> usize {
+    use gl_sys::GL_CURRENT_VERTEX_ATTRIB;
+
+    if pname == GL_CURRENT_VERTEX_ATTRIB {
+        4
+    } else {
+        1
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetVertexAttribfv(index: GLuint, pname: GLenum, params: *mut GLfloat) {
+    info!(
+        "glGetVertexAttribfv(index = {}, pname = {:?}, params = {:?})",
+        index, pname, params
+    );
+
+    if let Some(values) = vertex_attribute_parameter(index, pname) {
+        if !is_nullptr(params, "params is nullptr") {
+            let params = unsafe { slice::from_raw_parts_mut(params, parameter_count(pname)) };
+            params.copy_from_slice(&values[..params.len()]);
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetVertexAttribiv(index: GLuint, pname: GLenum, params: *mut GLint) {
+    info!(
+        "glGetVertexAttribiv(index = {}, pname = {:?}, params = {:?})",
+        index, pname, params
+    );
+
+    if let Some(values) = vertex_attribute_parameter(index, pname) {
+        if !is_nullptr(params, "params is nullptr") {
+            let params = unsafe { slice::from_raw_parts_mut(params, parameter_count(pname)) };
+            for (param, value) in params.iter_mut().zip(&values) {
+                *param = *value as GLint;
+            }
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glGetVertexAttribPointerv(index: GLuint, pname: GLenum, pointer: *mut *mut GLvoid) {
+    info!(
+        "glGetVertexAttribPointerv(index = {}, pname = {:?}, pointer = {:?})",
+        index, pname, pointer
+    );
+
+    use gl_sys::GL_VERTEX_ATTRIB_ARRAY_POINTER;
+
+    if !validate_index(index)
+        || !context::validate_pname(pname, &[GL_VERTEX_ATTRIB_ARRAY_POINTER])
+        || is_nullptr(pointer, "pointer is nullptr")
+    {
+        return;
+    }
+
+    unsafe { *pointer = HUB.vertex_attribute_state.lock().attributes[index as usize].pointer as *mut GLvoid };
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib1f(index: GLuint, x: GLfloat) {
+    info!("glVertexAttrib1f(index = {}, x = {})", index, x);
+
+    set_current_value(index, [x, 0.0, 0.0, 1.0]);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib1fv(index: GLuint, values: *const GLfloat) {
+    info!("glVertexAttrib1fv(index = {}, values = {:?})", index, values);
+
+    if !is_nullptr(values as *mut GLfloat, "values is nullptr") {
+        let v = unsafe { slice::from_raw_parts(values, 1) };
+        set_current_value(index, [v[0], 0.0, 0.0, 1.0]);
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib2f(index: GLuint, x: GLfloat, y: GLfloat) {
+    info!("glVertexAttrib2f(index = {}, x = {}, y = {})", index, x, y);
+
+    set_current_value(index, [x, y, 0.0, 1.0]);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib2fv(index: GLuint, values: *const GLfloat) {
+    info!("glVertexAttrib2fv(index = {}, values = {:?})", index, values);
+
+    if !is_nullptr(values as *mut GLfloat, "values is nullptr") {
+        let v = unsafe { slice::from_raw_parts(values, 2) };
+        set_current_value(index, [v[0], v[1], 0.0, 1.0]);
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib3f(index: GLuint, x: GLfloat, y: GLfloat, z: GLfloat) {
+    info!("glVertexAttrib3f(index = {}, x = {}, y = {}, z = {})", index, x, y, z);
+
+    set_current_value(index, [x, y, z, 1.0]);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib3fv(index: GLuint, values: *const GLfloat) {
+    info!("glVertexAttrib3fv(index = {}, values = {:?})", index, values);
+
+    if !is_nullptr(values as *mut GLfloat, "values is nullptr") {
+        let v = unsafe { slice::from_raw_parts(values, 3) };
+        set_current_value(index, [v[0], v[1], v[2], 1.0]);
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib4f(index: GLuint, x: GLfloat, y: GLfloat, z: GLfloat, w: GLfloat) {
+    info!(
+        "glVertexAttrib4f(index = {}, x = {}, y = {}, z = {}, w = {})",
+        index, x, y, z, w
+    );
+
+    set_current_value(index, [x, y, z, w]);
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttrib4fv(index: GLuint, values: *const GLfloat) {
+    info!("glVertexAttrib4fv(index = {}, values = {:?})", index, values);
+
+    if !is_nullptr(values as *mut GLfloat, "values is nullptr") {
+        let v = unsafe { slice::from_raw_parts(values, 4) };
+        set_current_value(index, [v[0], v[1], v[2], v[3]]);
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn glVertexAttribPointer(
+    index: GLuint,
+    size: GLint,
+    type_: GLenum,
+    normalized: GLboolean,
+    stride: GLsizei,
+    ptr: *const GLvoid,
+) {
+    info!(
+        "glVertexAttribPointer(index = {}, size = {}, type = {:?}, normalized = {}, stride = {}, ptr = {:?})",
+        index, size, type_, normalized, stride, ptr
+    );
+
+    if !validate_index(index)
+        || !context::validate_invalid_value(size, |&size| size < 1 || size > 4, "invalid size")
+        || !context::validate_invalid_value(stride, |&stride| stride < 0, "invalid stride")
+        || !context::validate_invalid_enum(
+            type_,
+            &[GL_BYTE, GL_UNSIGNED_BYTE, GL_SHORT, GL_UNSIGNED_SHORT, GL_FIXED, GL_FLOAT],
+            "invalid type",
+        )
+    {
+        return;
+    }
+
+    let buffer = context::get_active_buffer(GL_ARRAY_BUFFER).name;
+    let attribute = &mut HUB.vertex_attribute_state.lock().attributes[index as usize];
+    attribute.size = size;
+    attribute.type_ = type_;
+    attribute.normalized = normalized != GL_FALSE;
+    attribute.stride = stride;
+    attribute.pointer = ptr as usize;
+    attribute.buffer = buffer;
+}
+
+#[allow(non_snake_case)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gl_sys::*;
+    use program::glLinkProgram;
+    use program::tests::link_program;
+    use std::ptr;
+
+    #[test]
+    fn test_vertex_attribute() {
+        let _guard = context::TEST_LOCK.lock();
+        let index = LIMITS.max_vertex_attribs as GLuint - 1;
+        glVertexAttribPointer(index, 3, GL_UNSIGNED_SHORT, GL_TRUE, 0, 8 as *const GLvoid);
+        glEnableVertexAttribArray(index);
+        glVertexAttrib2f(index, 1.0, 2.0);
+
+        let attribute = HUB.vertex_attribute_state.lock().attributes[index as usize];
+        assert!(attribute.is_enabled && attribute.normalized);
+        assert_eq!((attribute.pointer, attribute.effective_stride()), (8, 6));
+
+        let mut values = [0 as GLint; 4];
+        glGetVertexAttribiv(index, GL_VERTEX_ATTRIB_ARRAY_TYPE, values.as_mut_ptr());
+        assert_eq!(values[0], GL_UNSIGNED_SHORT as GLint);
+        glGetVertexAttribiv(index, GL_CURRENT_VERTEX_ATTRIB, values.as_mut_ptr());
+        assert_eq!(values, [1, 2, 0, 1]);
+        let mut pointer = ptr::null_mut();
+        glGetVertexAttribPointerv(index, GL_VERTEX_ATTRIB_ARRAY_POINTER, &mut pointer);
+        assert_eq!(pointer as usize, 8);
+
+        // invalid parameters leave the state unchanged
+        glVertexAttribPointer(index, 5, GL_FLOAT, GL_FALSE, 0, ptr::null());
+        glVertexAttribPointer(index, 2, GL_UNSIGNED_INT, GL_FALSE, 0, ptr::null());
+        assert_eq!(HUB.vertex_attribute_state.lock().attributes[index as usize], attribute);
+    }
+
+    #[test]
+    fn test_attribute_locations() {
+        let _guard = context::TEST_LOCK.lock();
+        let vertex = "
+            attribute vec4 a_position;
+            attribute mat2 a_matrix;
+            attribute vec2 a_uv;
+            attribute float a_unused;
+            void main() { gl_Position = a_position + vec4(a_matrix[1] * a_uv, 0.0, 0.0); }
+        ";
+        let fragment = "void main() { gl_FragColor = vec4(1.0); }";
+        let program = link_program(vertex, fragment);
+        let location = |name: &str| {
+            let name = format!("{}\0", name);
+            glGetAttribLocation(program, name.as_ptr() as *const GLchar)
+        };
+        assert_eq!((location("a_position"), location("a_matrix"), location("a_uv")), (0, 1, 3));
+        assert_eq!(location("a_unused"), -1);
+
+        glBindAttribLocation(program, 0, b"a_uv\0".as_ptr() as *const GLchar);
+        glBindAttribLocation(program, 2, b"a_position\0".as_ptr() as *const GLchar);
+        assert_eq!(location("a_uv"), 3);
+        glLinkProgram(program);
+        assert_eq!((location("a_position"), location("a_matrix"), location("a_uv")), (2, 3, 0));
+    }
+}
@@ -1,35 +1,261 @@
 #![allow(non_snake_case)]
 
-use gl_sys::{GLclampf, GLfloat, GLint, GLsizei, GL_INVALID_VALUE};
+use hal::pso;
 
+use gl_sys::{
+    GLclampf, GLenum, GLfloat, GLint, GLsizei, GL_DEPTH_RANGE, GL_INVALID_VALUE, GL_SCISSOR_BOX, GL_SCISSOR_TEST, GL_VIEWPORT,
+};
+
+use context::HUB;
 use frame_buffer::Rect;
-use utilities::record_error;
+use glsl::FLIP_Y;
+use limits::LIMITS;
+use utilities::{clamp_to_unit_range, record_error, StateValue};
 
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub(crate) struct DepthRange {
     pub min: GLfloat,
     pub max: GLfloat,
 }
 
+impl Default for DepthRange {
+    fn default() -> Self {
+        Self { min: 0.0, max: 1.0 }
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct ViewportTransformation {
     pub depth_range: DepthRange,
     pub viewport_rectangle: Rect,
     pub viewport_count: u32,
     pub scissor_count: u32,
+    pub scissor_box: Rect,
+    pub is_scissor_test_enabled: bool,
+}
+
+impl ViewportTransformation {
+    /// Viewport of drawing to a frame buffer of `dimensions`, mirrored to the upper-left origin of hal. Vertex
+    /// shaders negating y already mirror the image, otherwise the viewport does with a negative height
+    pub fn viewport(&self, dimensions: &Rect) -> pso::Viewport {
+        let rect = flip(&self.viewport_rectangle, dimensions.height);
+        pso::Viewport {
+            rect: if FLIP_Y {
+                hal_rect(&rect)
+            } else {
+                hal_rect(&Rect::new(rect.x, rect.y + rect.height, rect.width, -rect.height))
+            },
+            depth: self.depth_range.min..self.depth_range.max,
+        }
+    }
+
+    /// Scissor rectangle of drawing to a frame buffer of `dimensions`, the whole frame buffer without scissor test
+    pub fn scissor(&self, dimensions: &Rect) -> pso::Rect {
+        if self.is_scissor_test_enabled {
+            hal_rect(&flip(&self.scissor_box, dimensions.height))
+        } else {
+            hal_rect(dimensions)
+        }
+    }
+
+    /// Area `glClear` is limited to in a frame buffer of `dimensions`, `None` without scissor test
+    pub fn clear_rect(&self, dimensions: &Rect) -> Option<Rect> {
+        if !self.is_scissor_test_enabled {
+            return None;
+        }
+        Some(flip(&self.scissor_box, dimensions.height))
+    }
+}
+
+/// Set the viewport and the scissor box to a window surface of `dimensions` the context is made current to for the
+/// first time
+pub(crate) fn initialize(dimensions: &Rect) {
+    let mut state = HUB.viewport_state.lock();
+    state.viewport_rectangle = *dimensions;
+    state.scissor_box = *dimensions;
+}
+
+/// Mirror `rect` from the lower-left origin of a frame buffer `height` pixels high to the upper-left origin, the
+/// rows OpenGL addresses as `rect` in the vertically mirrored images of hal
+fn flip(rect: &Rect, height: i32) -> Rect {
+    Rect::new(rect.x, height - rect.y - rect.height, rect.width, rect.height)
+}
+
+/// `rect` clamped to the `i16` rectangles of hal
+pub(crate) fn hal_rect(rect: &Rect) -> pso::Rect {
+    let clamp = |value: i32| value.max(i16::min_value() as i32).min(i16::max_value() as i32) as i16;
+    pso::Rect {
+        x: clamp(rect.x),
+        y: clamp(rect.y),
+        w: clamp(rect.width),
+        h: clamp(rect.height),
+    }
+}
+
+/// `width` and `height` clamped to `GL_MAX_VIEWPORT_DIMS`, `None` if either is negative
+fn clamped_rect(x: GLint, y: GLint, width: GLsizei, height: GLsizei) -> Option<Rect> {
+    if width < 0 || height < 0 {
+        error!("negative size {}x{}", width, height);
+        record_error(GL_INVALID_VALUE);
+        return None;
+    }
+    Some(Rect::new(
+        x,
+        y,
+        width.min(LIMITS.max_viewport_dims[0]),
+        height.min(LIMITS.max_viewport_dims[1]),
+    ))
+}
+
+/// The scissor rectangle is dynamic state, toggling the scissor test leaves pipelines alone
+pub(crate) fn set_capability(cap: GLenum, is_enabled: bool) {
+    match cap {
+        GL_SCISSOR_TEST => HUB.viewport_state.lock().is_scissor_test_enabled = is_enabled,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn is_capability_enabled(cap: GLenum) -> bool {
+    match cap {
+        GL_SCISSOR_TEST => HUB.viewport_state.lock().is_scissor_test_enabled,
+        _ => unreachable!(),
+    }
+}
+
+pub(crate) fn query_state(pname: GLenum) -> Option<StateValue> {
+    let state = HUB.viewport_state.lock();
+    let rect = |rect: &Rect| Some(StateValue::Integers(vec![rect.x, rect.y, rect.width, rect.height]));
+    match pname {
+        GL_VIEWPORT => rect(&state.viewport_rectangle),
+        GL_SCISSOR_BOX => rect(&state.scissor_box),
+        GL_DEPTH_RANGE => Some(StateValue::NormalizedFloats(vec![
+            state.depth_range.min,
+            state.depth_range.max,
+        ])),
+        _ => None,
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn glDepthRangef(z_near: GLclampf, z_far: GLclampf) {
     info!("glDepthRangef(z_near = {}, z_far = {}", z_near, z_far);
+
+    HUB.viewport_state.lock().depth_range = DepthRange {
+        min: clamp_to_unit_range(z_near),
+        max: clamp_to_unit_range(z_far),
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn glScissor(x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
+    info!("glScissor(x = {}, y = {}, width = {}, height = {})", x, y, width, height);
+
+    if let Some(rect) = clamped_rect(x, y, width, height) {
+        HUB.viewport_state.lock().scissor_box = rect;
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn glViewport(x: GLint, y: GLint, width: GLsizei, height: GLsizei) {
     info!("glViewport(x = {}, y = {}, width = {}, height = {})", x, y, width, height);
 
-    if width < 0 || height < 0 {
-        record_error(GL_INVALID_VALUE);
-        return;
+    if let Some(rect) = clamped_rect(x, y, width, height) {
+        HUB.viewport_state.lock().viewport_rectangle = rect;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use glsl::clip_space_position;
+
+    fn rect(x: i16, y: i16, w: i16, h: i16) -> pso::Rect {
+        pso::Rect { x, y, w, h }
+    }
+
+    /// Window coordinates, from the upper-left origin, and depth hal rasterizes `gl_Position` at with `viewport`
+    pub(crate) fn window_position(viewport: &pso::Viewport, position: [f32; 4]) -> [f32; 3] {
+        let [x, y, z, w] = clip_space_position(position);
+        let (rect, depth) = (viewport.rect, &viewport.depth);
+        [
+            rect.x as f32 + rect.w as f32 * 0.5 * (1.0 + x / w),
+            rect.y as f32 + rect.h as f32 * 0.5 * (1.0 + y / w),
+            depth.start + z / w * (depth.end - depth.start),
+        ]
+    }
+
+    #[test]
+    fn test_viewport_flip() {
+        let dimensions = Rect::new(0, 0, 64, 32);
+        let mut state = ViewportTransformation::default();
+        state.viewport_rectangle = dimensions;
+        let height = if FLIP_Y { 32 } else { -32 };
+        assert_eq!(state.viewport(&dimensions).rect.h, height);
+
+        // pixels are mirrored: a row `y` of OpenGL is the row `32 - y` counted from the top
+        state.viewport_rectangle = Rect::new(8, 4, 16, 8);
+        let viewport = state.viewport(&dimensions);
+        assert_eq!(window_position(&viewport, [-1.0, 1.0, 0.0, 1.0])[..2], [8.0, 32.0 - 12.0]);
+        assert_eq!(window_position(&viewport, [1.0, -1.0, 0.0, 1.0])[..2], [24.0, 32.0 - 4.0]);
+        assert_eq!(window_position(&viewport, [0.5, 0.25, 0.0, 2.0])[..2], [18.0, 32.0 - 8.5]);
+
+        // scissors and clears address the same mirrored rows
+        state.scissor_box = Rect::new(0, 0, 10, 10);
+        assert_eq!(state.scissor(&dimensions), rect(0, 0, 64, 32));
+        assert_eq!(state.clear_rect(&dimensions), None);
+        state.is_scissor_test_enabled = true;
+        assert_eq!(state.scissor(&dimensions), rect(0, 22, 10, 10));
+        assert_eq!(state.clear_rect(&dimensions), Some(Rect::new(0, 22, 10, 10)));
+        let bottom = window_position(&viewport, [0.0, -1.0, 0.0, 1.0])[1];
+        assert!(22.0 <= bottom && bottom < 32.0);
+    }
+
+    #[test]
+    fn test_make_current() {
+        use context;
+        use hal_registry;
+        use utilities::glGetIntegerv;
+
+        let _guard = context::TEST_LOCK.lock();
+        let get_rect = |pname: GLenum| {
+            let mut rect = [0 as GLint; 4];
+            glGetIntegerv(pname, rect.as_mut_ptr());
+            rect
+        };
+        // the first window surface sets the viewport and the scissor box, later ones leave them alone
+        *HUB.is_initialized.lock() = false;
+        assert_eq!(context::make_current(64, 32), hal_registry::hal_device_available());
+        assert_eq!(get_rect(GL_VIEWPORT), [0, 0, 64, 32]);
+        assert_eq!(get_rect(GL_SCISSOR_BOX), [0, 0, 64, 32]);
+        glViewport(8, 4, 16, 8);
+        context::make_current(128, 128);
+        assert_eq!(get_rect(GL_VIEWPORT), [8, 4, 16, 8]);
+        assert_eq!(get_rect(GL_SCISSOR_BOX), [0, 0, 64, 32]);
+    }
+
+    #[test]
+    fn test_depth_range() {
+        use context;
+        use utilities::glGetFloatv;
+
+        let _guard = context::TEST_LOCK.lock();
+        glDepthRangef(-1.0, 0.5);
+        let mut range = [0.0 as GLfloat; 2];
+        glGetFloatv(GL_DEPTH_RANGE, range.as_mut_ptr());
+        assert_eq!(range, [0.0, 0.5]);
+        let viewport = HUB.viewport_state.lock().viewport(&Rect::new(0, 0, 1, 1));
+        assert_eq!(viewport.depth, 0.0..0.5);
+
+        // clip space z of -w lands on the near value, w on the far value, even with a reversed range
+        assert_eq!(window_position(&viewport, [0.0, 0.0, -2.0, 2.0])[2], 0.0);
+        assert_eq!(window_position(&viewport, [0.0, 0.0, 2.0, 2.0])[2], 0.5);
+        glDepthRangef(0.75, 0.25);
+        let viewport = HUB.viewport_state.lock().viewport(&Rect::new(0, 0, 1, 1));
+        assert_eq!(window_position(&viewport, [0.0, 0.0, -1.0, 1.0])[2], 0.75);
+        assert_eq!(window_position(&viewport, [0.0, 0.0, 0.0, 1.0])[2], 0.5);
+        assert_eq!(window_position(&viewport, [0.0, 0.0, 1.0, 1.0])[2], 0.25);
+
+        glDepthRangef(0.0, 1.0);
+        assert_eq!(HUB.viewport_state.lock().depth_range, DepthRange::default());
     }
 }